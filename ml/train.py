@@ -0,0 +1,104 @@
+"""Trains the predicate keep model from training tables exported by the explorer.
+
+    cargo run -- --dataset Book --wdc --start-with Book --export-features ml/book.csv
+    python ml/train.py ml/book.csv [ml/movie.csv ...] [--output ml/model.onnx]
+
+Only labelled rows are used. The model is the one of ml_training.ipynb; it is exported with a free batch dimension and
+the `features` and `keep_output` metadata read by the explorer.
+"""
+
+import argparse
+import json
+from pathlib import Path
+
+import onnx
+import pandas as pd
+import torch
+import torch.nn as nn
+from torch.utils.data import DataLoader, TensorDataset
+
+FEATURES = ["frequency", "uniqueness", "entropy", "quality", "edge_rank"]
+
+
+def load_table(path):
+    if path.suffix == ".json":
+        return pd.DataFrame(json.loads(path.read_text()))
+    return pd.read_csv(path, low_memory=False)
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__, formatter_class=argparse.RawDescriptionHelpFormatter)
+    parser.add_argument("tables", nargs="+", type=Path, help="exported training tables (.csv or .json)")
+    parser.add_argument("--output", type=Path, default=Path(__file__).parent / "model.onnx")
+    parser.add_argument("--epochs", type=int, default=30)
+    parser.add_argument("--recall-bias", type=float, default=2.0)
+    args = parser.parse_args()
+
+    df = pd.concat([load_table(p) for p in args.tables], ignore_index=True, sort=False)
+    df = df[df["label"].notna()]
+    if df.empty:
+        raise SystemExit("No labelled predicate in the tables")
+
+    X = torch.tensor(df[FEATURES].fillna(0.0).values, dtype=torch.float32)
+    y = torch.tensor(df["label"].astype(int).values, dtype=torch.long)
+    pos = int((y == 1).sum())
+    neg = int((y == 0).sum())
+    print(f"{len(y)} labelled predicates: keep={pos}, drop={neg}")
+
+    if pos == 0 or neg == 0:
+        loss_fn = nn.CrossEntropyLoss()
+    else:
+        # inverse-frequency weights, favouring the recall of the kept predicates
+        total = pos + neg
+        weights = torch.tensor([total / (2 * neg), total / (2 * pos) * args.recall_bias], dtype=torch.float32)
+        loss_fn = nn.CrossEntropyLoss(weight=weights)
+
+    model = nn.Sequential(
+        nn.Linear(len(FEATURES), 16),
+        nn.Linear(16, 8),
+        nn.Linear(8, 2),
+        nn.Softmax(dim=1),
+    )
+    opt = torch.optim.Adam(model.parameters(), lr=1e-3)
+
+    # 50/50 split to verify that we do not overfit
+    perm = torch.randperm(len(X))
+    split = max(1, len(X) // 2)
+    train_idx, val_idx = perm[:split], perm[split:]
+    train_loader = DataLoader(TensorDataset(X[train_idx], y[train_idx]), batch_size=32, shuffle=True)
+    val_loader = DataLoader(TensorDataset(X[val_idx], y[val_idx]), batch_size=64)
+
+    for epoch in range(1, args.epochs + 1):
+        model.train()
+        for xb, yb in train_loader:
+            opt.zero_grad()
+            loss = loss_fn(model[:-1](xb), yb)
+            loss.backward()
+            opt.step()
+
+        model.eval()
+        correct, seen = 0, 0
+        with torch.no_grad():
+            for xb, yb in val_loader:
+                correct += (model[:-1](xb).argmax(dim=1) == yb).sum().item()
+                seen += len(yb)
+        if seen:
+            print(f"Epoch {epoch:02d} | val_acc: {correct / seen:.4f}")
+
+    torch.onnx.export(
+        model,
+        torch.randn(1, len(FEATURES)),
+        str(args.output),
+        input_names=["input"],
+        output_names=["output"],
+        dynamic_axes={"input": {0: "batch"}, "output": {0: "batch"}},
+        opset_version=11,
+    )
+    exported = onnx.load(str(args.output))
+    onnx.helper.set_model_props(exported, {"features": ",".join(FEATURES), "keep_output": "1"})
+    onnx.save(exported, str(args.output))
+    print(f"Model written to {args.output}")
+
+
+if __name__ == "__main__":
+    main()
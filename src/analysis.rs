@@ -0,0 +1,358 @@
+//! # Analysis reports
+//!
+//! Builds the reports of the analysis pages (class relations graph, class analysis and predicate analysis)
+//! on top of the statistics computed by the store. Reports are plain serde structs, rendered as HTML by the
+//! web UI and returned as is by the JSON API.
+
+use std::collections::{ BTreeSet, HashMap, HashSet };
+use std::str::FromStr;
+
+use oxigraph::model::NamedNode;
+use oxigraph::model::Term::Literal;
+use petgraph::Direction::Outgoing;
+use serde::Serialize;
+
+use crate::jobs;
+use crate::page_rank::{ page_rank, PageRankOptions };
+use crate::store::{ StoreError, KG };
+use crate::utils::calculate_probabilities_for_graph;
+
+/// A class reached while traversing the class relations graph.
+#[derive(Serialize)]
+pub struct ClassNode {
+    pub class: String,
+    pub count: u64,
+    pub depth: usize,
+}
+
+/// A relation between two classes of the class relations graph.
+#[derive(Serialize)]
+pub struct ClassEdge {
+    pub from: String,
+    pub to: String,
+    pub predicate: String,
+}
+
+/// Classes reachable from the starting class, in traversal order, and the relations between them.
+#[derive(Serialize)]
+pub struct ClassGraph {
+    pub nodes: Vec<ClassNode>,
+    pub edges: Vec<ClassEdge>,
+}
+
+/// Scores of a class computed by `KG::stat_anal_types`.
+#[derive(Serialize)]
+pub struct ClassScore {
+    pub class: String,
+    pub count: f64,
+    pub depth: f64,
+    pub page_rank: f64,
+    pub reverse_page_rank: f64,
+    pub elimination_round: i32,
+    pub keep: bool,
+    pub score: f64,
+}
+
+/// Classes to keep and to drop, proposed by the class analysis and reviewed before being applied.
+///
+/// ## Fields
+/// * `start_with` - Class the analysis started from.
+/// * `keep` - Classes to keep, best first: an entity with several of them keeps the first one as its type.
+/// * `drop` - Classes reviewed and dropped. Like them, every class not in `keep` loses its `rdf:type` statements.
+#[derive(Serialize, Clone, Debug)]
+pub struct ClassPlan {
+    pub start_with: String,
+    pub keep: Vec<String>,
+    pub drop: Vec<String>,
+}
+
+impl ClassPlan {
+    /// Plan proposed by the analysis: keeps the classes that survived every elimination round.
+    pub fn proposed(start_with: &str, scores: &[ClassScore]) -> ClassPlan {
+        let mut kept = scores
+            .iter()
+            .filter(|s| s.keep)
+            .collect::<Vec<_>>();
+        kept.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.class.cmp(&b.class)));
+        ClassPlan {
+            start_with: start_with.to_string(),
+            keep: kept
+                .into_iter()
+                .map(|s| s.class.clone())
+                .collect(),
+            drop: scores
+                .iter()
+                .filter(|s| !s.keep)
+                .map(|s| s.class.clone())
+                .collect(),
+        }
+    }
+
+    /// SPARQL comments describing the plan, recorded in the history along with its update.
+    fn describe(&self) -> String {
+        format!(
+            "# Class plan from schema:{}\n# Keep: {}\n# Drop: {}",
+            self.start_with,
+            if self.keep.is_empty() { "-".to_string() } else { self.keep.join(" ") },
+            if self.drop.is_empty() { "-".to_string() } else { self.drop.join(" ") }
+        )
+    }
+}
+
+/// Result of the class analysis: the scores of the classes and the plan they lead to.
+///
+/// ## Fields
+/// * `page_rank` - Description of the PageRank options used.
+/// * `classes` - Scores of the classes, the survivors first.
+/// * `plan` - Proposed plan, to review and pass to `KG::apply_class_plan`.
+#[derive(Serialize)]
+pub struct ClassAnalysis {
+    pub page_rank: String,
+    pub classes: Vec<ClassScore>,
+    pub plan: ClassPlan,
+}
+
+/// Statistics of a predicate of a class and the resulting keep decisions.
+///
+/// ## Fields
+/// * `stats` - Statistics computed by `KG::stat_anal_predicates` (frequency, uniqueness, entropy, ...).
+/// * `nn_keep` - Decision of the neural network.
+/// * `score_keep` - Decision based on the cumulated score.
+/// * `keep` - Hybrid decision combining both.
+/// * `label` - Decision of the curators, if any (see `predicate_labels`).
+/// * `decision` - Final decision: the label, or the hybrid decision without one.
+#[derive(Serialize)]
+pub struct PredicateScore {
+    pub predicate: String,
+    pub stats: HashMap<String, f64>,
+    pub nn_keep: bool,
+    pub score_keep: bool,
+    pub keep: bool,
+    pub label: Option<bool>,
+    pub decision: bool,
+}
+
+/// Predicate analysis of a single class.
+#[derive(Serialize)]
+pub struct ClassPredicates {
+    pub class: String,
+    pub count: u64,
+    pub good_entities: i64,
+    pub predicates: Vec<PredicateScore>,
+}
+
+impl KG {
+    /// Traverses the class relations graph breadth first, starting from `schema:{start_with}`.
+    ///
+    /// - (Re)computes the class relations if needed (see `KG::class_relations`).
+    /// - Follows the relations to the classes that have relations of their own.
+    /// - Counts the entities of every reached class.
+    pub fn class_graph(&self, start_with: &str) -> ClassGraph {
+        let (_, relations) = self.class_relations();
+        let relations = relations
+            .into_iter()
+            .filter(|(_, _, object, _)| object != "Literal")
+            .collect::<Vec<_>>();
+        let sources = relations
+            .iter()
+            .map(|(class, _, _, _)| class.clone())
+            .collect::<HashSet<_>>();
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut items = vec![(format!("<http://schema.org/{}>", start_with), 0)];
+        let mut nodes = vec![];
+        let mut edges = vec![];
+
+        while !items.is_empty() {
+            let (ent, depth) = items.remove(0);
+            if seen.contains(&ent) {
+                continue;
+            }
+            seen.insert(ent.clone());
+            jobs::log(format!("Visiting {ent}"));
+
+            let count_query = format!(
+                r#"
+SELECT (COUNT(DISTINCT ?s) as ?cnt)
+WHERE {{
+    ?s a {ent}.
+
+}}
+"#
+            );
+            let count = match self.query(&count_query) {
+                Ok(res) => {
+                    match res.first().and_then(|r| r.get("cnt")) {
+                        Some(Literal(l)) => l.value().parse::<u64>().unwrap_or(0),
+                        _ => 0,
+                    }
+                }
+                Err(_) => panic!("Failed to fetch use count"),
+            };
+
+            nodes.push(ClassNode { class: ent.clone(), count, depth });
+            let connections = relations
+                .iter()
+                .filter(|(class, _, object, _)| *class == ent && sources.contains(object))
+                .map(|(_, predicate, object, _)| (predicate.clone(), object.clone()))
+                .collect::<BTreeSet<_>>();
+            for (predicate, cur) in connections {
+                edges.push(ClassEdge {
+                    from: ent.clone(),
+                    to: cur.clone(),
+                    predicate,
+                });
+                items.push((cur, depth + 1));
+            }
+        }
+        ClassGraph { nodes, edges }
+    }
+
+    /// Scores the classes reachable from `schema:{start_with}` (see `stat_anal_types`), ranking them with `options`,
+    /// and proposes a plan. The store is left untouched.
+    pub fn class_analysis(&self, start_with: &str, options: &PageRankOptions) -> ClassAnalysis {
+        let classes = self
+            .stat_anal_types(start_with, options)
+            .into_iter()
+            .map(|(class, (count, depth, pr, rpr, round, keep, score))| ClassScore {
+                class,
+                count,
+                depth,
+                page_rank: pr,
+                reverse_page_rank: rpr,
+                elimination_round: round,
+                keep,
+                score,
+            })
+            .collect::<Vec<_>>();
+        let plan = ClassPlan::proposed(start_with, &classes);
+        ClassAnalysis { page_rank: options.describe(), classes, plan }
+    }
+
+    /// Applies a reviewed class plan: removes the `rdf:type` statements of the classes that are not kept, the
+    /// entities left without a type, and moves the secondary types of the remaining entities to
+    /// `schema:additionalType`.
+    ///
+    /// The plan is written in the history, as comments of its first update.
+    ///
+    /// # Errors
+    /// - `StoreError::EvaluationError` if a class is not an IRI or if no class is kept.
+    /// - The `StoreError` of a failed update.
+    pub fn apply_class_plan(&self, plan: &ClassPlan) -> Result<(), StoreError> {
+        if plan.keep.is_empty() {
+            return Err(StoreError::EvaluationError("The plan keeps no class".to_string()));
+        }
+        let keep = plan.keep
+            .iter()
+            .map(|class| {
+                let iri = if class.starts_with('<') { class.clone() } else { format!("<{class}>") };
+                NamedNode::from_str(&iri)
+                    .map(|node| node.to_string())
+                    .map_err(|_| StoreError::EvaluationError(format!("Invalid class: {class}")))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        jobs::log(format!("Keeping {} classes, dropping {}", keep.len(), plan.drop.len()));
+        self.keep_types(&keep, &plan.describe())?;
+
+        // The first classes of the plan take precedence
+        let scores = keep
+            .iter()
+            .enumerate()
+            .map(|(i, class)| (class.clone(), (keep.len() - i) as f64))
+            .collect::<HashMap<_, _>>();
+        jobs::log("Moving secondary types to schema:additionalType".to_string());
+        self.fix_types(&scores)
+    }
+
+    /// Analyses the predicates of every class reachable from `schema:{start_with}`, deepest classes first.
+    ///
+    /// A predicate is kept when the neural network says so, or when it is within the first 60% of the
+    /// cumulated score and the confidence of the network weighted by its score reaches 0.5, unless the curators
+    /// labelled it otherwise.
+    pub fn predicate_scores(&self, start_with: &str) -> Vec<ClassPredicates> {
+        let labels = self
+            .predicate_labels()
+            .into_iter()
+            .map(|l| ((l.class, l.predicate), l.keep))
+            .collect::<HashMap<_, _>>();
+        let mut result = vec![];
+        for (ClassNode { class, count, .. }, data) in self.predicate_statistics(start_with) {
+            let mut thres = 60.0;
+            let mut mean_passed_score = 0.0;
+            let mut passed_count = 0;
+            for (_, stats) in &data {
+                if thres > 0.0 {
+                    mean_passed_score += stats["score"];
+                    passed_count += 1;
+                }
+                thres -= stats["score"];
+            }
+            mean_passed_score /= passed_count as f64;
+
+            thres = 60.0;
+            let mut predicates = vec![];
+            for (predicate, stats) in data {
+                let nn_keep = stats["keep"] > 0.5;
+                let score_keep = thres > 0.0;
+                let keep =
+                    nn_keep ||
+                    (score_keep &&
+                        stats["keep"] + (stats["keep"] * stats["score"]) / mean_passed_score >= 0.5);
+                thres -= stats["score"];
+                let label = labels.get(&(class.clone(), predicate.clone())).copied();
+                let decision = label.unwrap_or(keep);
+                predicates.push(PredicateScore { predicate, stats, nn_keep, score_keep, keep, label, decision });
+            }
+
+            let good_entities = self.analyse_objects(&class);
+            result.push(ClassPredicates { class, count, good_entities, predicates });
+        }
+        result
+    }
+
+    /// Statistics of the predicates (see `stat_anal_predicates`) of every class reachable from
+    /// `schema:{start_with}`, deepest classes first.
+    pub fn predicate_statistics(
+        &self,
+        start_with: &str
+    ) -> Vec<(ClassNode, Vec<(String, HashMap<String, f64>)>)> {
+        let mut order = self.class_graph(start_with).nodes;
+        order.reverse();
+
+        let (mut graph, node_map) = self.calculate_class_relations_graph();
+        calculate_probabilities_for_graph(&mut graph);
+        let mut node_counts: HashMap<String, f64> = HashMap::new();
+
+        for node in node_map.keys() {
+            if node == "Literal" {
+                continue;
+            }
+            let q = format!(
+                r#"
+            SELECT (COUNT(?s) as ?cnt) WHERE {{
+                ?s a {node}.
+            }}
+            "#
+            );
+            let cnt = *self.get_counts(&q, "cnt").first().unwrap();
+            node_counts.insert(node.clone(), cnt);
+        }
+
+        let (_, edge_rank) = page_rank(&graph, &node_counts, Outgoing, &PageRankOptions::default());
+        let empty = HashMap::new();
+
+        let mut result = vec![];
+        let total = order.len() as u64;
+        for (i, node) in order.into_iter().enumerate() {
+            jobs::progress(i as u64, total);
+            jobs::log(format!("Analysing the predicates of {}", node.class));
+            let data = self
+                .stat_anal_predicates(&node.class, edge_rank.get(&node.class).unwrap_or(&empty))
+                .unwrap_or_default();
+            result.push((node, data));
+        }
+        jobs::progress(total, total);
+        result
+    }
+}
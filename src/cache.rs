@@ -0,0 +1,686 @@
+//! # Caches of the analyses
+//!
+//! The class relations graph and the predicate statistics take one query per class and several per predicate, so they
+//! are cached per dataset in `./data/<dataset>/cache/`, and only the parts an update may have changed are recomputed.
+//!
+//! - Every update run through `KG::update` is analysed: `ChangeSet::from_update` lists the classes whose instances it
+//!   may add or remove, and the predicates whose values it may change, along with the classes of their subjects when
+//!   the `WHERE` clause gives them (`?s a schema:Book`, possibly through `VALUES`).
+//! - Change sets are appended to the change log, `changes.jsonl`, each one numbered with the next generation.
+//! - Cache files start with a header: the schema of the caches (`CACHE_SCHEMA`), the dataset, the change log they
+//!   follow (`epoch`) and the generation they were computed at. `KG::load_cache` returns them with the changes logged
+//!   since, for the analyses to recompute only what these touch (see `KG::touched_predicates`).
+//! - Updates whose effect cannot be bounded (a variable predicate or class, clearing or loading the default graph),
+//!   reloads and reverts invalidate every cache, as does a new change log or a new `CACHE_SCHEMA`.
+//!
+//! Updates of named graphs (archives, inferred triples, `<urn:class_relations>`) change nothing the analyses read,
+//! and history lines that are not updates (dumps, comments) no longer invalidate anything.
+
+use std::collections::{ BTreeSet, BTreeMap, HashMap };
+use std::fs::{ File, OpenOptions };
+use std::io::{ BufRead, BufReader, BufWriter, Write };
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use serde::de::DeserializeOwned;
+use serde::{ Deserialize, Serialize };
+use spargebra::algebra::{ GraphPattern, GraphTarget };
+use spargebra::term::{ GraphName, GraphNamePattern, GroundTerm, GroundTermPattern, NamedNodePattern, Term, TermPattern };
+use spargebra::{ GraphUpdateOperation, Update };
+
+use crate::store::KG;
+
+/// Version of the layout of the cache files; caches written with another one are recomputed.
+pub const CACHE_SCHEMA: u32 = 1;
+
+/// Change sets kept in the log; once reached, the older half is dropped, and caches older than the log recomputed.
+const MAX_LOGGED_CHANGES: usize = 10_000;
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+
+/// In-memory copy of the change log of the dataset, loaded on first use.
+static CHANGE_LOG: Mutex<Option<ChangeLog>> = Mutex::new(None);
+
+/// What an update may have changed in the default graph. Classes and predicates are in their SPARQL form.
+///
+/// ## Fields
+/// * `everything` - The update cannot be bounded: every cache is invalid.
+/// * `types` - Classes whose instances may have been added or removed.
+/// * `predicates` - Predicates whose values may have changed, with the classes of their subjects.
+/// * `anywhere` - Predicates whose values may have changed, on subjects of any class.
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq)]
+pub struct ChangeSet {
+    #[serde(default)]
+    pub everything: bool,
+    #[serde(default)]
+    pub types: BTreeSet<String>,
+    #[serde(default)]
+    pub predicates: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    pub anywhere: BTreeSet<String>,
+}
+
+/// A cache and the changes logged since it was computed.
+pub struct Cached<T> {
+    pub data: T,
+    pub changes: ChangeSet,
+}
+
+/// Position in the change log, saved with the caches.
+///
+/// ## Fields
+/// * `epoch` - Creation time of the change log, in nanoseconds.
+/// * `generation` - Number of change sets logged since.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CacheVersion {
+    pub epoch: u64,
+    pub generation: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile<T> {
+    schema: u32,
+    dataset: String,
+    epoch: u64,
+    generation: u64,
+    data: T,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogHeader {
+    schema: u32,
+    dataset: String,
+    epoch: u64,
+    first: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LogEntry {
+    generation: u64,
+    changes: ChangeSet,
+}
+
+/// The change log: change sets `first + 1` to `first + entries.len()` of the epoch.
+struct ChangeLog {
+    path: String,
+    epoch: u64,
+    first: u64,
+    entries: Vec<ChangeSet>,
+}
+
+impl ChangeLog {
+    fn generation(&self) -> u64 {
+        self.first + (self.entries.len() as u64)
+    }
+
+    /// Reads the log of `dataset`, or starts a new epoch if it is missing, invalid or of another schema.
+    fn open(path: &str, dataset: &str) -> ChangeLog {
+        let read = || -> Option<ChangeLog> {
+            let mut lines = BufReader::new(File::open(path).ok()?).lines();
+            let header: LogHeader = serde_json::from_str(&lines.next()?.ok()?).ok()?;
+            if header.schema != CACHE_SCHEMA || header.dataset != dataset {
+                return None;
+            }
+            let mut log = ChangeLog { path: path.to_string(), epoch: header.epoch, first: header.first, entries: vec![] };
+            for line in lines {
+                let entry: LogEntry = serde_json::from_str(&line.ok()?).ok()?;
+                if entry.generation != log.generation() + 1 {
+                    return None;
+                }
+                log.entries.push(entry.changes);
+            }
+            Some(log)
+        };
+        read().unwrap_or_else(|| {
+            let epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            let log = ChangeLog { path: path.to_string(), epoch, first: 0, entries: vec![] };
+            if let Err(e) = log.rewrite(dataset) {
+                eprintln!("Failed to write the change log {path}: {e}");
+            }
+            log
+        })
+    }
+
+    fn rewrite(&self, dataset: &str) -> std::io::Result<()> {
+        if let Some(dir) = Path::new(&self.path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut writer = BufWriter::new(File::create(&self.path)?);
+        let header = LogHeader {
+            schema: CACHE_SCHEMA,
+            dataset: dataset.to_string(),
+            epoch: self.epoch,
+            first: self.first,
+        };
+        writeln!(writer, "{}", serde_json::to_string(&header)?)?;
+        for (i, changes) in self.entries.iter().enumerate() {
+            let entry = LogEntry { generation: self.first + (i as u64) + 1, changes: changes.clone() };
+            writeln!(writer, "{}", serde_json::to_string(&entry)?)?;
+        }
+        writer.flush()
+    }
+
+    fn append(&mut self, changes: ChangeSet, dataset: &str) -> std::io::Result<()> {
+        if changes.everything {
+            // Every cache is invalid: the log restarts from this generation
+            self.first = self.generation() + 1;
+            self.entries.clear();
+            return self.rewrite(dataset);
+        }
+        let entry = LogEntry { generation: self.generation() + 1, changes };
+        self.entries.push(entry.changes.clone());
+        if self.entries.len() > MAX_LOGGED_CHANGES {
+            let dropped = self.entries.len() / 2;
+            self.entries.drain(..dropped);
+            self.first += dropped as u64;
+            return self.rewrite(dataset);
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)
+    }
+
+    /// Changes logged after `generation`, `None` if the log does not go back that far.
+    fn since(&self, generation: u64) -> Option<ChangeSet> {
+        if generation < self.first || generation > self.generation() {
+            return None;
+        }
+        let mut changes = ChangeSet::default();
+        for entry in &self.entries[(generation - self.first) as usize..] {
+            changes.merge(entry);
+        }
+        Some(changes)
+    }
+}
+
+/// Bindings of the `WHERE` clause of an update that bound the classes of its subjects.
+///
+/// ## Fields
+/// * `classes` - Constant classes of the variables (`?s a <C>`).
+/// * `class_variables` - Variable classes of the variables (`?s a ?c`).
+/// * `values` - IRIs a `VALUES` block binds a variable to; `None` if it may be unbound or bound to another term.
+#[derive(Default)]
+struct Bindings {
+    classes: HashMap<String, BTreeSet<String>>,
+    class_variables: HashMap<String, BTreeSet<String>>,
+    values: HashMap<String, Option<BTreeSet<String>>>,
+}
+
+impl Bindings {
+    /// Collects the bindings of the parts of `pattern` every solution matches (not `OPTIONAL`, `UNION` or `MINUS`).
+    fn collect(&mut self, pattern: &GraphPattern) {
+        match pattern {
+            GraphPattern::Bgp { patterns } => {
+                for triple in patterns {
+                    let (NamedNodePattern::NamedNode(p), TermPattern::Variable(s)) = (&triple.predicate, &triple.subject) else {
+                        continue;
+                    };
+                    if p.to_string() != RDF_TYPE {
+                        continue;
+                    }
+                    match &triple.object {
+                        TermPattern::NamedNode(class) => {
+                            self.classes.entry(s.as_str().to_string()).or_default().insert(class.to_string());
+                        }
+                        TermPattern::Variable(class) => {
+                            self.class_variables
+                                .entry(s.as_str().to_string())
+                                .or_default()
+                                .insert(class.as_str().to_string());
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            GraphPattern::Join { left, right } => {
+                self.collect(left);
+                self.collect(right);
+            }
+            GraphPattern::LeftJoin { left, .. } | GraphPattern::Minus { left, .. } => self.collect(left),
+            GraphPattern::Filter { inner, .. }
+            | GraphPattern::Extend { inner, .. }
+            | GraphPattern::OrderBy { inner, .. }
+            | GraphPattern::Project { inner, .. }
+            | GraphPattern::Distinct { inner }
+            | GraphPattern::Reduced { inner }
+            | GraphPattern::Slice { inner, .. }
+            | GraphPattern::Group { inner, .. } => self.collect(inner),
+            GraphPattern::Values { variables, bindings } => {
+                for (i, variable) in variables.iter().enumerate() {
+                    let iris = bindings
+                        .iter()
+                        .map(|row| {
+                            match row.get(i) {
+                                Some(Some(GroundTerm::NamedNode(iri))) => Some(iri.to_string()),
+                                _ => None,
+                            }
+                        })
+                        .collect::<Option<BTreeSet<_>>>();
+                    let entry = self.values.entry(variable.as_str().to_string()).or_insert(None);
+                    if let Some(iris) = iris {
+                        entry.get_or_insert_with(BTreeSet::new).extend(iris);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// IRIs a variable is bound to by `VALUES`, if it is bound to IRIs only.
+    fn values_of(&self, variable: &str) -> Option<&BTreeSet<String>> {
+        self.values.get(variable)?.as_ref()
+    }
+
+    /// Classes every binding of the subject variable belongs to (empty if unknown).
+    fn classes_of(&self, variable: &str) -> BTreeSet<String> {
+        let mut classes = self.classes.get(variable).cloned().unwrap_or_default();
+        for class in self.class_variables.get(variable).into_iter().flatten() {
+            if let Some(iris) = self.values_of(class) {
+                classes.extend(iris.iter().cloned());
+            }
+        }
+        classes
+    }
+}
+
+impl ChangeSet {
+    /// A change that invalidates every cache.
+    pub fn everything() -> ChangeSet {
+        ChangeSet { everything: true, ..ChangeSet::default() }
+    }
+
+    /// Whether the update changed nothing the caches depend on.
+    pub fn is_empty(&self) -> bool {
+        !self.everything && self.types.is_empty() && self.predicates.is_empty() && self.anywhere.is_empty()
+    }
+
+    /// Adds the changes of `other`.
+    pub fn merge(&mut self, other: &ChangeSet) {
+        self.everything |= other.everything;
+        self.types.extend(other.types.iter().cloned());
+        for (predicate, classes) in &other.predicates {
+            self.predicates.entry(predicate.clone()).or_default().extend(classes.iter().cloned());
+        }
+        self.anywhere.extend(other.anywhere.iter().cloned());
+    }
+
+    /// Lists what a SPARQL update may change in the default graph, without running it.
+    ///
+    /// Updates that cannot be parsed change everything, to be safe (the store rejects them anyway).
+    pub fn from_update(update: &str) -> ChangeSet {
+        let Ok(update) = Update::parse(update, None) else {
+            return ChangeSet::everything();
+        };
+        let mut changes = ChangeSet::default();
+        for operation in &update.operations {
+            match operation {
+                GraphUpdateOperation::InsertData { data } => {
+                    for quad in data.iter().filter(|q| q.graph_name == GraphName::DefaultGraph) {
+                        let object = match &quad.object {
+                            Term::NamedNode(iri) => Some(iri.to_string()),
+                            _ => None,
+                        };
+                        changes.add_data(&quad.predicate.to_string(), object);
+                    }
+                }
+                GraphUpdateOperation::DeleteData { data } => {
+                    for quad in data.iter().filter(|q| q.graph_name == GraphName::DefaultGraph) {
+                        let object = match &quad.object {
+                            GroundTerm::NamedNode(iri) => Some(iri.to_string()),
+                            _ => None,
+                        };
+                        changes.add_data(&quad.predicate.to_string(), object);
+                    }
+                }
+                GraphUpdateOperation::DeleteInsert { delete, insert, using, pattern } => {
+                    let mut bindings = Bindings::default();
+                    // With USING, the WHERE clause does not read the default graph
+                    if using.is_none() {
+                        bindings.collect(pattern);
+                    }
+                    for quad in delete {
+                        // Quads of a variable graph may be in the default one
+                        if matches!(quad.graph_name, GraphNamePattern::NamedNode(_)) {
+                            continue;
+                        }
+                        let subject = match &quad.subject {
+                            GroundTermPattern::Variable(v) => Some(v.as_str()),
+                            _ => None,
+                        };
+                        let object = match &quad.object {
+                            GroundTermPattern::NamedNode(iri) => Some(Ok(iri.to_string())),
+                            GroundTermPattern::Variable(v) => Some(Err(v.as_str())),
+                            _ => None,
+                        };
+                        changes.add_template(&bindings, &quad.predicate, subject, object);
+                    }
+                    for quad in insert {
+                        if matches!(quad.graph_name, GraphNamePattern::NamedNode(_)) {
+                            continue;
+                        }
+                        let subject = match &quad.subject {
+                            TermPattern::Variable(v) => Some(v.as_str()),
+                            _ => None,
+                        };
+                        let object = match &quad.object {
+                            TermPattern::NamedNode(iri) => Some(Ok(iri.to_string())),
+                            TermPattern::Variable(v) => Some(Err(v.as_str())),
+                            _ => None,
+                        };
+                        changes.add_template(&bindings, &quad.predicate, subject, object);
+                    }
+                }
+                GraphUpdateOperation::Load { destination, .. } => {
+                    if *destination == GraphName::DefaultGraph {
+                        changes.everything = true;
+                    }
+                }
+                GraphUpdateOperation::Clear { graph, .. } | GraphUpdateOperation::Drop { graph, .. } => {
+                    if matches!(graph, GraphTarget::DefaultGraph | GraphTarget::AllGraphs) {
+                        changes.everything = true;
+                    }
+                }
+                GraphUpdateOperation::Create { .. } => (),
+            }
+        }
+        changes
+    }
+
+    /// Adds a triple of `INSERT DATA` or `DELETE DATA`, whose subject has an unknown class.
+    fn add_data(&mut self, predicate: &str, object: Option<String>) {
+        if predicate == RDF_TYPE {
+            self.types.extend(object);
+        } else {
+            self.anywhere.insert(predicate.to_string());
+        }
+    }
+
+    /// Adds a quad of a `DELETE` or `INSERT` template.
+    ///
+    /// `subject` is the subject variable, if any; `object` the object IRI (`Ok`) or variable (`Err`), if any.
+    fn add_template(
+        &mut self,
+        bindings: &Bindings,
+        predicate: &NamedNodePattern,
+        subject: Option<&str>,
+        object: Option<Result<String, &str>>
+    ) {
+        let predicates = match predicate {
+            NamedNodePattern::NamedNode(iri) => BTreeSet::from([iri.to_string()]),
+            NamedNodePattern::Variable(v) =>
+                match bindings.values_of(v.as_str()) {
+                    Some(iris) => iris.clone(),
+                    None => {
+                        self.everything = true;
+                        return;
+                    }
+                }
+        };
+        let classes = subject.map(|s| bindings.classes_of(s)).unwrap_or_default();
+        for predicate in predicates {
+            if predicate == RDF_TYPE {
+                match &object {
+                    Some(Ok(class)) => {
+                        self.types.insert(class.clone());
+                    }
+                    Some(Err(variable)) =>
+                        match bindings.values_of(variable) {
+                            Some(iris) => self.types.extend(iris.iter().cloned()),
+                            None => {
+                                self.everything = true;
+                            }
+                        }
+                    None => (),
+                }
+            } else if classes.is_empty() {
+                self.anywhere.insert(predicate);
+            } else {
+                self.predicates.entry(predicate).or_default().extend(classes.iter().cloned());
+            }
+        }
+    }
+}
+
+/// Name of the cache file of a class or predicate (`<iri>`), without its extension.
+pub fn cache_file_name(iri: &str) -> String {
+    iri.trim_start_matches('<')
+        .trim_end_matches('>')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+impl KG {
+    /// Directory of the caches of the dataset.
+    pub fn cache_dir(&self) -> String {
+        format!("./data/{}/cache", self.get_name())
+    }
+
+    fn with_change_log<T>(&self, f: impl FnOnce(&mut ChangeLog) -> T) -> T {
+        let path = format!("{}/changes.jsonl", self.cache_dir());
+        let mut log = CHANGE_LOG.lock().unwrap_or_else(|e| e.into_inner());
+        if log.as_ref().is_none_or(|l| l.path != path) {
+            *log = Some(ChangeLog::open(&path, &self.get_name()));
+        }
+        f(log.as_mut().unwrap())
+    }
+
+    /// Logs the changes of an update (nothing if it changed nothing the caches depend on).
+    pub fn record_changes(&self, changes: ChangeSet) {
+        if changes.is_empty() {
+            return;
+        }
+        let dataset = self.get_name();
+        if let Err(e) = self.with_change_log(|log| log.append(changes, &dataset)) {
+            eprintln!("Failed to log the changes of an update: {e}");
+        }
+    }
+
+    /// Invalidates every cache, after the store was reloaded or reverted.
+    pub fn invalidate_caches(&self) {
+        self.record_changes(ChangeSet::everything());
+    }
+
+    /// Current position in the change log, to save with a cache computed from now on.
+    pub fn cache_version(&self) -> CacheVersion {
+        self.with_change_log(|log| CacheVersion { epoch: log.epoch, generation: log.generation() })
+    }
+
+    /// Reads the cache `name` (a path relative to `cache_dir`), with the changes logged since it was computed.
+    ///
+    /// Returns `None` if it is missing, invalid, of another schema, dataset or epoch, or older than the change log.
+    pub fn load_cache<T: DeserializeOwned>(&self, name: &str) -> Option<Cached<T>> {
+        let file = File::open(format!("{}/{name}", self.cache_dir())).ok()?;
+        let cache: CacheFile<T> = serde_json::from_reader(BufReader::new(file)).ok()?;
+        if cache.schema != CACHE_SCHEMA || cache.dataset != self.get_name() {
+            return None;
+        }
+        let changes = self.with_change_log(|log| {
+            if log.epoch == cache.epoch { log.since(cache.generation) } else { None }
+        })?;
+        Some(Cached { data: cache.data, changes })
+    }
+
+    /// Saves the cache `name`, computed from the store at `version`.
+    pub fn save_cache<T: Serialize>(&self, name: &str, version: CacheVersion, data: &T) {
+        let path = format!("{}/{name}", self.cache_dir());
+        let save = || -> std::io::Result<()> {
+            if let Some(dir) = Path::new(&path).parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let cache = CacheFile {
+                schema: CACHE_SCHEMA,
+                dataset: self.get_name(),
+                epoch: version.epoch,
+                generation: version.generation,
+                data,
+            };
+            serde_json::to_writer(BufWriter::new(File::create(&path)?), &cache)?;
+            Ok(())
+        };
+        if let Err(e) = save() {
+            eprintln!("Failed to save the cache {path}: {e}");
+        }
+    }
+
+    /// Predicates of `class` whose statistics the changes may have changed, `predicates` being its known ones.
+    ///
+    /// Returns `None` if its instances may have changed, and every statistic must be recomputed. A predicate changed
+    /// on the instances of another class counts when that class shares instances with `class`.
+    pub fn touched_predicates(
+        &self,
+        changes: &ChangeSet,
+        class: &str,
+        predicates: &BTreeSet<String>
+    ) -> Option<BTreeSet<String>> {
+        if changes.everything || changes.types.contains(class) {
+            return None;
+        }
+        let mut touched = BTreeSet::new();
+        if !changes.predicates.is_empty() {
+            let query = format!("SELECT DISTINCT ?t WHERE {{ ?s a {class} . ?s a ?t }}");
+            let shared = self
+                .query(&query)
+                .ok()?
+                .iter()
+                .filter_map(|row| row.get("t").map(|t| t.to_string()))
+                .collect::<BTreeSet<_>>();
+            for (predicate, classes) in &changes.predicates {
+                if classes.iter().any(|c| c == class || shared.contains(c)) {
+                    touched.insert(predicate.clone());
+                }
+            }
+        }
+        for predicate in &changes.anywhere {
+            if touched.contains(predicate) {
+                continue;
+            }
+            // Known predicates, or ones the class may have gained
+            if predicates.contains(predicate) || self.has_predicate(class, predicate) {
+                touched.insert(predicate.clone());
+            }
+        }
+        Some(touched)
+    }
+
+    fn has_predicate(&self, class: &str, predicate: &str) -> bool {
+        self.query(&format!("SELECT ?s WHERE {{ ?s a {class} . ?s {predicate} ?o }} LIMIT 1"))
+            .map(|rows| !rows.is_empty())
+            .unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(update: &str) -> ChangeSet {
+        ChangeSet::from_update(&format!("PREFIX schema: <http://schema.org/>\n{update}"))
+    }
+
+    fn schema(name: &str) -> String {
+        format!("<http://schema.org/{name}>")
+    }
+
+    #[test]
+    fn data_updates() {
+        let changes = changes(
+            r#"INSERT DATA {
+                <urn:a> a schema:Book ; schema:name "Dune" .
+                GRAPH <urn:archive> { <urn:b> a schema:Movie ; schema:duration "PT2H" }
+            } ;
+            DELETE DATA { <urn:c> schema:author <urn:d> }"#
+        );
+        assert_eq!(changes.types, BTreeSet::from([schema("Book")]));
+        assert_eq!(changes.anywhere, BTreeSet::from([schema("name"), schema("author")]));
+        assert!(changes.predicates.is_empty());
+        assert!(!changes.everything);
+    }
+
+    #[test]
+    fn classes_of_the_subjects() {
+        let changes = changes(
+            "DELETE { ?s schema:isbn ?o } INSERT { ?s schema:identifier ?o }
+            WHERE { ?s a schema:Book ; schema:isbn ?o }"
+        );
+        assert!(changes.types.is_empty() && changes.anywhere.is_empty());
+        assert_eq!(
+            changes.predicates,
+            BTreeMap::from([
+                (schema("isbn"), BTreeSet::from([schema("Book")])),
+                (schema("identifier"), BTreeSet::from([schema("Book")])),
+            ])
+        );
+    }
+
+    #[test]
+    fn values_bind_classes_and_predicates() {
+        let changes = changes(
+            "DELETE { ?s ?p ?o } WHERE {
+                VALUES ?c { schema:Book schema:Movie }
+                VALUES ?p { schema:name schema:url }
+                ?s a ?c ; ?p ?o
+            }"
+        );
+        let classes = BTreeSet::from([schema("Book"), schema("Movie")]);
+        assert_eq!(
+            changes.predicates,
+            BTreeMap::from([(schema("name"), classes.clone()), (schema("url"), classes)])
+        );
+
+        let changes = self::changes(
+            "DELETE { ?s a ?c } INSERT { ?s a schema:CreativeWork } WHERE { VALUES ?c { schema:Book } ?s a ?c }"
+        );
+        assert_eq!(changes.types, BTreeSet::from([schema("Book"), schema("CreativeWork")]));
+        assert!(changes.predicates.is_empty() && !changes.everything);
+    }
+
+    #[test]
+    fn unbound_subjects() {
+        // Neither an optional class nor the classes of another graph bind the subject
+        let changes = changes(
+            "DELETE { ?s schema:name ?o } WHERE { ?s schema:name ?o OPTIONAL { ?s a schema:Book } }"
+        );
+        assert_eq!(changes.anywhere, BTreeSet::from([schema("name")]));
+        let changes = self::changes(
+            "DELETE { ?s schema:name ?o } USING <urn:g> WHERE { ?s a schema:Book ; schema:name ?o }"
+        );
+        assert_eq!(changes.anywhere, BTreeSet::from([schema("name")]));
+        assert!(changes.predicates.is_empty());
+    }
+
+    #[test]
+    fn unbounded_updates() {
+        assert!(changes("DELETE { ?s ?p ?o } WHERE { ?s a schema:Book ; ?p ?o }").everything);
+        assert!(changes("DELETE { ?s a ?c } WHERE { ?s a ?c }").everything);
+        assert!(changes("CLEAR DEFAULT").everything);
+        assert!(changes("DROP ALL").everything);
+        assert!(changes("LOAD <http://example.org/data.nt>").everything);
+        assert!(changes("not an update").everything);
+    }
+
+    #[test]
+    fn named_graph_updates() {
+        assert!(changes("DROP SILENT GRAPH <urn:archive>").is_empty());
+        assert!(changes("CREATE GRAPH <urn:archive>").is_empty());
+        assert!(
+            changes(
+                "DELETE { GRAPH <urn:inferred> { ?s a ?c } } INSERT { GRAPH <urn:archive> { ?s ?p ?o } }
+                WHERE { ?s ?p ?o }"
+            ).is_empty()
+        );
+    }
+
+    #[test]
+    fn merged_changes() {
+        let mut merged = changes("INSERT DATA { <urn:a> a schema:Book }");
+        merged.merge(&changes("DELETE { ?s schema:name ?o } WHERE { ?s a schema:Movie ; schema:name ?o }"));
+        assert_eq!(merged.types, BTreeSet::from([schema("Book")]));
+        assert_eq!(merged.predicates, BTreeMap::from([(schema("name"), BTreeSet::from([schema("Movie")]))]));
+        merged.merge(&ChangeSet::everything());
+        assert!(merged.everything && !merged.is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+//! # Domain/range conformance
+//!
+//! Measures how the data uses the schema.org predicates against their `schema:domainIncludes` and
+//! `schema:rangeIncludes`, on top of the class relations graph (`KG::calculate_class_relations_graph`), which already
+//! counts the objects of every class-predicate pair per type.
+//!
+//! - Objects without a type are split into literals and untyped IRIs with one query per class.
+//! - Non-conforming values can be fixed in one click: dropped, turned into a literal (their name, or their IRI) or
+//!   wrapped into an entity of an expected class. Every fix is recorded in the history.
+
+use std::collections::{ BTreeSet, HashMap };
+use std::str::FromStr;
+
+use oxigraph::model::NamedNode;
+use petgraph::visit::EdgeRef;
+use serde::Serialize;
+
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+use crate::utils::extract_literal;
+use crate::vocabulary::{ self, TermKind, Vocabulary };
+
+/// Node of the class relations graph gathering the objects without a type.
+const UNTYPED: &str = "Literal";
+
+/// Observed range of the literals.
+pub const LITERAL: &str = "Literal";
+
+/// Observed range of the IRIs without a type.
+pub const UNTYPED_IRI: &str = "IRI";
+
+/// Number of expected classes offered as targets of a conversion to an entity.
+const MAX_ENTITY_FIXES: usize = 3;
+
+/// # One-click fixes of non-conforming values.
+///
+/// ## Variants:
+/// * `Drop`: Deletes the triples.
+/// * `ToLiteral`: Replaces the objects by their `schema:name`, or by their IRI as a string.
+/// * `ToEntity(class)`: Replaces the literals by an entity of `class` named after them; equal literals share the
+///   same entity.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConformanceFix {
+    Drop,
+    ToLiteral,
+    ToEntity(String),
+}
+
+impl ConformanceFix {
+    /// Parses the form of `as_param` (`drop`, `literal`, `entity:<class>`).
+    pub fn parse(fix: &str) -> Option<ConformanceFix> {
+        match fix {
+            "drop" => Some(ConformanceFix::Drop),
+            "literal" => Some(ConformanceFix::ToLiteral),
+            _ => fix
+                .strip_prefix("entity:")
+                .and_then(|class| NamedNode::from_str(class).ok())
+                .map(|class| ConformanceFix::ToEntity(class.to_string())),
+        }
+    }
+
+    pub fn as_param(&self) -> String {
+        match self {
+            ConformanceFix::Drop => "drop".to_string(),
+            ConformanceFix::ToLiteral => "literal".to_string(),
+            ConformanceFix::ToEntity(class) => format!("entity:{class}"),
+        }
+    }
+
+    /// Human readable description, for buttons.
+    pub fn describe(&self) -> String {
+        match self {
+            ConformanceFix::Drop => "Drop".to_string(),
+            ConformanceFix::ToLiteral => "Convert to text".to_string(),
+            ConformanceFix::ToEntity(class) =>
+                format!("Wrap in a {}", vocabulary::local_name(class).unwrap_or(class)),
+        }
+    }
+}
+
+/// Objects of a class-predicate pair of one kind.
+///
+/// ## Fields
+/// * `range` - Class of the objects, or `LITERAL`, or `UNTYPED_IRI`.
+/// * `count` - Number of triples.
+/// * `conforms` - Whether the range is one of the expected ranges (or a subclass of one).
+/// * `fixes` - Fixes offered for non-conforming objects.
+#[derive(Serialize, Clone)]
+pub struct ObservedRange {
+    pub range: String,
+    pub count: u64,
+    pub conforms: bool,
+    pub fixes: Vec<ConformanceFix>,
+}
+
+/// Conformance of a predicate used by the instances of a class.
+///
+/// ## Fields
+/// * `class`, `predicate` - The pair, as IRIs.
+/// * `domain_conforms` - Whether the class is one of the `schema:domainIncludes` of the predicate (or a subclass).
+/// * `expected_domains`, `expected_ranges` - Local names from the vocabulary.
+/// * `observed` - Observed ranges, most used first.
+/// * `total`, `non_conforming` - Number of triples, and of triples with a non-conforming range.
+#[derive(Serialize, Clone)]
+pub struct PredicateConformance {
+    pub class: String,
+    pub predicate: String,
+    pub domain_conforms: bool,
+    pub expected_domains: Vec<String>,
+    pub expected_ranges: Vec<String>,
+    pub observed: Vec<ObservedRange>,
+    pub total: u64,
+    pub non_conforming: u64,
+}
+
+/// Conformance of the whole dataset.
+///
+/// ## Fields
+/// * `pairs` - Class-predicate pairs using schema.org predicates, the most non-conforming first.
+/// * `skipped_predicates` - Predicates that are not schema.org properties, and so cannot be checked.
+#[derive(Serialize, Clone)]
+pub struct ConformanceReport {
+    pub pairs: Vec<PredicateConformance>,
+    pub skipped_predicates: Vec<String>,
+}
+
+/// Whether an observed range is accepted by the expected ranges of a predicate.
+fn range_conforms(vocabulary: &Vocabulary, ranges: &[String], observed: &str) -> bool {
+    match observed {
+        LITERAL => ranges.iter().any(|r| vocabulary.is_data_type(r)),
+        // An IRI stands for a URL or a reference to an entity
+        UNTYPED_IRI =>
+            ranges
+                .iter()
+                .any(|r| vocabulary.is_a(r, "URL") || !vocabulary.is_data_type(r)),
+        class => ranges.iter().any(|r| vocabulary.is_a(class, r)),
+    }
+}
+
+/// Fixes offered for a non-conforming observed range.
+fn fixes(vocabulary: &Vocabulary, ranges: &[String], observed: &str) -> Vec<ConformanceFix> {
+    let mut fixes = vec![];
+    let expects_text = ranges.iter().any(|r| vocabulary.is_a(r, "Text"));
+    match observed {
+        LITERAL =>
+            fixes.extend(
+                ranges
+                    .iter()
+                    .filter(|r| !vocabulary.is_data_type(r))
+                    .take(MAX_ENTITY_FIXES)
+                    .map(|r| ConformanceFix::ToEntity(vocabulary::schema_iri(r)))
+            ),
+        _ if expects_text => fixes.push(ConformanceFix::ToLiteral),
+        _ => {}
+    }
+    fixes.push(ConformanceFix::Drop);
+    fixes
+}
+
+/// Parses an IRI given in its SPARQL form, so that it can be inserted in a query.
+fn sparql_iri(iri: &str) -> Result<String, StoreError> {
+    NamedNode::from_str(iri)
+        .map(|node| node.to_string())
+        .map_err(|_| StoreError::EvaluationError(format!("Invalid IRI: {iri}")))
+}
+
+impl KG {
+    /// Checks every class-predicate pair of the class relations graph against the vocabulary.
+    pub fn conformance_report(&self, vocabulary: &Vocabulary) -> ConformanceReport {
+        let (graph, node_map) = self.calculate_class_relations_graph();
+
+        let classes = node_map
+            .keys()
+            .filter(|c| c.as_str() != UNTYPED)
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut pairs = vec![];
+        let mut skipped = BTreeSet::new();
+
+        for (i, class) in classes.iter().enumerate() {
+            jobs::progress(i as u64, classes.len() as u64);
+            jobs::log(format!("Checking {class}"));
+
+            // Observed ranges of every predicate of the class
+            let mut observed: HashMap<String, Vec<(String, u64)>> = HashMap::new();
+            for edge in graph.edges(node_map[class]) {
+                let (predicate, count, _, _) = edge.weight();
+                observed
+                    .entry(predicate.clone())
+                    .or_default()
+                    .push((graph[edge.target()].clone(), *count as u64));
+            }
+            if observed.is_empty() {
+                continue;
+            }
+
+            // Untyped objects are literals or IRIs
+            let literals = self
+                .query(
+                    &format!(
+                        "SELECT ?p (COUNT(?o) AS ?cnt) WHERE {{ ?s a {class} . ?s ?p ?o . FILTER(isLiteral(?o)) }} GROUP BY ?p"
+                    )
+                )
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|row| {
+                    let count = extract_literal(row.get("cnt"))?.parse::<u64>().ok()?;
+                    Some((row.get("p")?.to_string(), count))
+                })
+                .collect::<HashMap<_, _>>();
+
+            for (predicate, ranges) in observed {
+                let Some(term) = vocabulary.term(&predicate).filter(|t| t.kind == TermKind::Property) else {
+                    skipped.insert(predicate);
+                    continue;
+                };
+                let mut split = vec![];
+                for (range, count) in ranges {
+                    if range == UNTYPED {
+                        let literal_count = literals.get(&predicate).copied().unwrap_or(0).min(count);
+                        split.push((LITERAL.to_string(), literal_count));
+                        split.push((UNTYPED_IRI.to_string(), count - literal_count));
+                    } else {
+                        split.push((range, count));
+                    }
+                }
+                let mut observed = split
+                    .into_iter()
+                    .filter(|(_, count)| *count > 0)
+                    .map(|(range, count)| {
+                        let conforms = range_conforms(vocabulary, &term.ranges, &range);
+                        ObservedRange {
+                            fixes: if conforms { vec![] } else { fixes(vocabulary, &term.ranges, &range) },
+                            range,
+                            count,
+                            conforms,
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                observed.sort_by(|a, b| b.count.cmp(&a.count));
+
+                pairs.push(PredicateConformance {
+                    class: class.clone(),
+                    domain_conforms: term.domains.iter().any(|d| vocabulary.is_a(class, d)),
+                    expected_domains: term.domains.clone(),
+                    expected_ranges: term.ranges.clone(),
+                    total: observed.iter().map(|o| o.count).sum(),
+                    non_conforming: observed
+                        .iter()
+                        .filter(|o| !o.conforms)
+                        .map(|o| o.count)
+                        .sum(),
+                    predicate,
+                    observed,
+                });
+            }
+        }
+        jobs::progress(classes.len() as u64, classes.len() as u64);
+
+        pairs.sort_by(|a, b| {
+            b.non_conforming
+                .cmp(&a.non_conforming)
+                .then_with(|| a.class.cmp(&b.class))
+                .then_with(|| a.predicate.cmp(&b.predicate))
+        });
+        ConformanceReport { pairs, skipped_predicates: skipped.into_iter().collect() }
+    }
+
+    /// Applies a fix to the objects of `predicate` of the instances of `class` whose observed range is `range`.
+    ///
+    /// Objects that also have a conforming type are left alone. The update is recorded in the history.
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if an IRI is invalid, the predicate is not a schema.org property or
+    /// the update fails.
+    pub fn apply_conformance_fix(
+        &self,
+        vocabulary: &Vocabulary,
+        class: &str,
+        predicate: &str,
+        range: &str,
+        fix: &ConformanceFix
+    ) -> Result<(), StoreError> {
+        let class = sparql_iri(class)?;
+        let predicate = sparql_iri(predicate)?;
+        let term = vocabulary
+            .term(&predicate)
+            .ok_or_else(|| StoreError::EvaluationError(format!("Not a schema.org property: {predicate}")))?;
+
+        let filter = match range {
+            LITERAL => "FILTER(isLiteral(?o))".to_string(),
+            UNTYPED_IRI => "FILTER(isIRI(?o) && NOT EXISTS { ?o a ?any })".to_string(),
+            range => {
+                // Classes of the dataset accepted by the predicate
+                let accepted = self
+                    .class_counts()
+                    .into_iter()
+                    .map(|(c, _)| c)
+                    .filter(|c| range_conforms(vocabulary, &term.ranges, c))
+                    .collect::<Vec<_>>();
+                let exception = if accepted.is_empty() {
+                    String::new()
+                } else {
+                    format!("FILTER NOT EXISTS {{ ?o a ?ok . FILTER(?ok IN ({})) }}", accepted.join(", "))
+                };
+                format!("?o a {} . {exception}", sparql_iri(range)?)
+            }
+        };
+
+        let q = match fix {
+            ConformanceFix::Drop =>
+                format!(
+                    r#"
+            DELETE {{ ?s {predicate} ?o . }}
+            WHERE {{
+                ?s a {class} .
+                ?s {predicate} ?o .
+                {filter}
+            }}
+        "#
+                ),
+            ConformanceFix::ToLiteral =>
+                format!(
+                    r#"
+            DELETE {{ ?s {predicate} ?o . }}
+            INSERT {{ ?s {predicate} ?value . }}
+            WHERE {{
+                ?s a {class} .
+                ?s {predicate} ?o .
+                {filter}
+                OPTIONAL {{ ?o {} ?name . }}
+                BIND(COALESCE(STR(?name), STR(?o)) AS ?value)
+            }}
+        "#,
+                    vocabulary::schema_iri("name")
+                ),
+            ConformanceFix::ToEntity(target) => {
+                if range != LITERAL {
+                    return Err(StoreError::EvaluationError("Only literals can be wrapped in an entity".to_string()));
+                }
+                let target = sparql_iri(target)?;
+                format!(
+                    r#"
+            DELETE {{ ?s {predicate} ?o . }}
+            INSERT {{ ?s {predicate} ?entity . ?entity a {target} . ?entity {} ?o . }}
+            WHERE {{
+                ?s a {class} .
+                ?s {predicate} ?o .
+                {filter}
+                BIND(IRI(CONCAT("urn:coerced:", MD5(CONCAT(STR({target}), STR(?o))))) AS ?entity)
+            }}
+        "#,
+                    vocabulary::schema_iri("name")
+                )
+            }
+        };
+        jobs::log(format!("{} {range} objects of {predicate} for {class}", fix.describe()));
+        self.update(&q)?;
+        self.write_to_history(format!("```sparql\n{}\n```", q));
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+//! # Faceted exploration
+//!
+//! Filters, sorts and counts the instances of a class for the explore page and its API counterpart.
+//!
+//! - Facets are computed with one aggregate query per kind (predicates, values, datatypes, languages), so each
+//!   is a single pass over the filtered instances.
+//! - Facets are cached in memory per set of filters until the store changes (see `KG::history_len`).
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex, OnceLock };
+
+use oxigraph::model::{ NamedNode, Term };
+use oxigraph::sparql::QuerySolution;
+use serde::{ Deserialize, Serialize };
+
+use crate::store::KG;
+use crate::utils::extract_literal;
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+
+/// Predicates with at most this many distinct values get a "top values" facet.
+const LOW_CARDINALITY: u64 = 25;
+
+/// Number of predicates for which top values are computed.
+const MAX_VALUE_FACETS: usize = 8;
+
+/// Number of top values listed per predicate.
+const TOP_VALUES: usize = 10;
+
+/// Number of facet sets kept in the cache.
+const MAX_CACHED_FACETS: usize = 64;
+
+/// Filters applied to the instances of a class.
+///
+/// ## Fields
+/// * `class` - Class whose instances are explored (`<iri>`).
+/// * `has` - Predicates the instances must have.
+/// * `values` - `(predicate, object)` pairs the instances must have, objects in N-Triples syntax.
+/// * `datatype` - Datatype of a literal the instances must have.
+/// * `lang` - Language of a literal the instances must have.
+#[derive(Clone, Default)]
+pub struct ExploreFilters {
+    pub class: String,
+    pub has: Vec<String>,
+    pub values: Vec<(String, String)>,
+    pub datatype: Option<String>,
+    pub lang: Option<String>,
+}
+
+/// Parses an IRI, with or without its brackets, into its SPARQL form.
+///
+/// # Errors
+/// Returns a message if the IRI is invalid.
+pub fn parse_iri(raw: &str) -> Result<String, String> {
+    let raw = raw.trim();
+    let iri = if raw.starts_with('<') { raw.to_string() } else { format!("<{raw}>") };
+    NamedNode::from_str(&iri)
+        .map(|node| node.to_string())
+        .map_err(|_| format!("Invalid IRI: {raw}"))
+}
+
+impl ExploreFilters {
+    /// Validates raw filters, as sent by the explore page.
+    ///
+    /// `values` are given as `<predicate> object`, e.g. `<http://schema.org/addressCountry> "US"`.
+    ///
+    /// # Errors
+    /// Returns a message if an IRI, an object or a language tag is invalid.
+    pub fn parse(
+        class: &str,
+        has: Vec<String>,
+        values: Vec<String>,
+        datatype: Option<String>,
+        lang: Option<String>
+    ) -> Result<ExploreFilters, String> {
+        let class = parse_iri(&class.replace("%3C", "<").replace("%3E", ">"))?;
+        let has = has
+            .iter()
+            .map(|p| parse_iri(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        let values = values
+            .iter()
+            .map(|v| {
+                let (predicate, object) = v
+                    .trim()
+                    .split_once("> ")
+                    .ok_or_else(|| format!("Invalid value filter: {v}"))?;
+                let object = Term::from_str(object.trim()).map_err(|_|
+                    format!("Invalid value: {object}")
+                )?;
+                Ok((parse_iri(&format!("{predicate}>"))?, object.to_string()))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let datatype = datatype
+            .filter(|d| !d.is_empty())
+            .map(|d| parse_iri(&d))
+            .transpose()?;
+        let lang = lang
+            .filter(|l| !l.is_empty())
+            .map(|l| {
+                if l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    Ok(l.to_lowercase())
+                } else {
+                    Err(format!("Invalid language: {l}"))
+                }
+            })
+            .transpose()?;
+        Ok(ExploreFilters { class, has, values, datatype, lang })
+    }
+
+    /// Whether any filter besides the class is set.
+    pub fn is_filtered(&self) -> bool {
+        !self.has.is_empty() || !self.values.is_empty() || self.datatype.is_some() || self.lang.is_some()
+    }
+
+    /// SPARQL patterns binding `?s` to the matching instances.
+    fn patterns(&self) -> String {
+        let mut patterns = format!("?s a {} .\n", self.class);
+        for p in &self.has {
+            patterns += &format!("FILTER EXISTS {{ ?s {p} ?has_object }}\n");
+        }
+        for (p, o) in &self.values {
+            patterns += &format!("?s {p} {o} .\n");
+        }
+        if let Some(datatype) = &self.datatype {
+            patterns += &format!(
+                "FILTER EXISTS {{ ?s ?dt_p ?dt_o . FILTER(isLiteral(?dt_o) && DATATYPE(?dt_o) = {datatype}) }}\n"
+            );
+        }
+        if let Some(lang) = &self.lang {
+            patterns += &format!("FILTER EXISTS {{ ?s ?lang_p ?lang_o . FILTER(LANG(?lang_o) = \"{lang}\") }}\n");
+        }
+        patterns
+    }
+
+    fn cache_key(&self) -> String {
+        format!("{}|{:?}|{:?}|{:?}|{:?}", self.class, self.has, self.values, self.datatype, self.lang)
+    }
+}
+
+/// # Orders of the explored instances.
+///
+/// ## Variants:
+/// * `Default`: Store order, the cheapest.
+/// * `Name`: By `schema:name`, instances without a name last.
+/// * `Triples`: By number of outgoing triples, the most described first.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Default,
+    Name,
+    Triples,
+}
+
+impl SortOrder {
+    pub fn parse(sort: &str) -> SortOrder {
+        match sort {
+            "name" => SortOrder::Name,
+            "triples" => SortOrder::Triples,
+            _ => SortOrder::Default,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortOrder::Default => "",
+            SortOrder::Name => "name",
+            SortOrder::Triples => "triples",
+        }
+    }
+}
+
+/// A value of a facet and the number of instances having it.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: u64,
+}
+
+/// Presence of a predicate among the instances.
+///
+/// ## Fields
+/// * `count` - Number of instances having the predicate.
+/// * `distinct_values` - Number of distinct objects of the predicate.
+/// * `top_values` - Most frequent objects, only for low cardinality predicates.
+#[derive(Serialize, Clone)]
+pub struct PredicateFacet {
+    pub predicate: String,
+    pub count: u64,
+    pub distinct_values: u64,
+    pub top_values: Vec<ValueCount>,
+}
+
+/// Facets of the filtered instances of a class.
+#[derive(Serialize, Clone)]
+pub struct Facets {
+    pub total: u64,
+    pub predicates: Vec<PredicateFacet>,
+    pub datatypes: Vec<ValueCount>,
+    pub languages: Vec<ValueCount>,
+}
+
+type FacetCache = Mutex<HashMap<String, (usize, Arc<Facets>)>>;
+
+fn facet_cache() -> &'static FacetCache {
+    static CACHE: OnceLock<FacetCache> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn count(row: &QuerySolution, var: &str) -> u64 {
+    extract_literal(row.get(var))
+        .and_then(|c| c.parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+impl KG {
+    /// Computes the facets of the instances matching `filters`.
+    pub fn explore_facets(&self, filters: &ExploreFilters) -> Arc<Facets> {
+        let version = self.history_len();
+        let key = filters.cache_key();
+        if let Some((v, facets)) = facet_cache().lock().unwrap().get(&key) {
+            if *v == version {
+                return facets.clone();
+            }
+        }
+
+        let patterns = filters.patterns();
+        let total = self
+            .query(&format!("SELECT (COUNT(DISTINCT ?s) AS ?cnt) WHERE {{ {patterns} }}"))
+            .unwrap_or_default()
+            .first()
+            .map(|row| count(row, "cnt"))
+            .unwrap_or(0);
+
+        let mut predicates = self
+            .query(
+                &format!(
+                    "SELECT ?p (COUNT(DISTINCT ?s) AS ?cnt) (COUNT(DISTINCT ?o) AS ?values)
+                    WHERE {{ {patterns} ?s ?p ?o . }}
+                    GROUP BY ?p ORDER BY DESC(?cnt)"
+                )
+            )
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| {
+                Some(PredicateFacet {
+                    predicate: row.get("p")?.to_string(),
+                    count: count(row, "cnt"),
+                    distinct_values: count(row, "values"),
+                    top_values: vec![],
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // Top values of the most used low cardinality predicates (addressCountry, inLanguage, ...)
+        for facet in predicates
+            .iter_mut()
+            .filter(|f| f.predicate != RDF_TYPE && f.distinct_values > 1 && f.distinct_values <= LOW_CARDINALITY)
+            .take(MAX_VALUE_FACETS) {
+            facet.top_values = self.value_counts(
+                &format!(
+                    "SELECT ?value (COUNT(DISTINCT ?s) AS ?cnt)
+                    WHERE {{ {patterns} ?s {} ?value . }}
+                    GROUP BY ?value ORDER BY DESC(?cnt) LIMIT {TOP_VALUES}",
+                    facet.predicate
+                )
+            );
+        }
+
+        let datatypes = self.value_counts(
+            &format!(
+                "SELECT ?value (COUNT(DISTINCT ?s) AS ?cnt)
+                WHERE {{ {patterns} ?s ?p ?o . FILTER(isLiteral(?o)) }}
+                GROUP BY (DATATYPE(?o) AS ?value) ORDER BY DESC(?cnt)"
+            )
+        );
+        let languages = self
+            .value_counts(
+                &format!(
+                    "SELECT ?value (COUNT(DISTINCT ?s) AS ?cnt)
+                    WHERE {{ {patterns} ?s ?p ?o . FILTER(isLiteral(?o) && LANG(?o) != \"\") }}
+                    GROUP BY (LCASE(LANG(?o)) AS ?value) ORDER BY DESC(?cnt)"
+                )
+            )
+            .into_iter()
+            // Languages are literals, only their text is needed
+            .map(|v| ValueCount { value: v.value.trim_matches('"').to_string(), count: v.count })
+            .collect();
+
+        let facets = Arc::new(Facets { total, predicates, datatypes, languages });
+        let mut cache = facet_cache().lock().unwrap();
+        if cache.len() >= MAX_CACHED_FACETS {
+            cache.retain(|_, (v, _)| *v == version);
+            if cache.len() >= MAX_CACHED_FACETS {
+                cache.clear();
+            }
+        }
+        cache.insert(key, (version, facets.clone()));
+        facets
+    }
+
+    fn value_counts(&self, query: &str) -> Vec<ValueCount> {
+        self.query(query)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| {
+                Some(ValueCount { value: row.get("value")?.to_string(), count: count(row, "cnt") })
+            })
+            .collect()
+    }
+
+    /// Returns a page of the instances matching `filters`, in the given order.
+    pub fn explore_entities(
+        &self,
+        filters: &ExploreFilters,
+        sort: SortOrder,
+        limit: u32,
+        offset: u32
+    ) -> Vec<String> {
+        let patterns = filters.patterns();
+        let query = match sort {
+            SortOrder::Default =>
+                format!("SELECT DISTINCT ?s WHERE {{ {patterns} }} LIMIT {limit} OFFSET {offset}"),
+            SortOrder::Name =>
+                format!(
+                    "SELECT ?s (SAMPLE(?n) AS ?name)
+                    WHERE {{ {patterns} OPTIONAL {{ ?s <http://schema.org/name> ?n }} }}
+                    GROUP BY ?s ORDER BY (!BOUND(?name)) LCASE(STR(?name)) ?s
+                    LIMIT {limit} OFFSET {offset}"
+                ),
+            SortOrder::Triples =>
+                format!(
+                    "SELECT ?s (COUNT(*) AS ?n)
+                    WHERE {{ {patterns} ?s ?sort_p ?sort_o . }}
+                    GROUP BY ?s ORDER BY DESC(?n) ?s
+                    LIMIT {limit} OFFSET {offset}"
+                ),
+        };
+        self.query(&query)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| row.get("s").map(|s| s.to_string()))
+            .collect()
+    }
+}
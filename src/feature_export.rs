@@ -0,0 +1,196 @@
+//! # Training table of the predicate keep model
+//!
+//! Exports, for every class reachable from a starting class and every one of its predicates, the statistics computed
+//! by `KG::stat_anal_predicates` and the curator label of the pair (see `predicate_labels`), so that `ml/train.py`
+//! can retrain the model in a single command.
+//!
+//! - `ExportFormat::Csv`: one row per class-predicate pair; `label` is `1` (keep), `0` (drop) or empty.
+//! - `ExportFormat::Columns`: a JSON object of columns (`{"class": [...], "frequency": [...], ...}`), which
+//!   `pandas.DataFrame` reads as is.
+//!
+//! Labelled predicates that are no longer in the data (the dropped ones) are exported with the statistics saved along
+//! with their label.
+
+use std::collections::{ HashMap, HashSet };
+
+use serde::Serialize;
+
+use crate::store::KG;
+
+/// Statistics exported as features, in the order of the columns.
+pub const FEATURE_COLUMNS: [&str; 6] = ["frequency", "uniqueness", "entropy", "quality", "edge_rank", "score"];
+
+/// # Formats of the training table.
+///
+/// ## Variants:
+/// * `Csv`: Comma separated values, with a header.
+/// * `Columns`: JSON object of columns.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Columns,
+}
+
+impl ExportFormat {
+    /// Parses a format name (`csv` or `columns`).
+    pub fn parse(name: &str) -> Option<ExportFormat> {
+        match name {
+            "csv" => Some(ExportFormat::Csv),
+            "columns" | "json" => Some(ExportFormat::Columns),
+            _ => None,
+        }
+    }
+
+    /// Format of an output file, from its extension (`.json` for columns, CSV otherwise).
+    pub fn from_path(path: &str) -> ExportFormat {
+        if path.ends_with(".json") { ExportFormat::Columns } else { ExportFormat::Csv }
+    }
+
+    /// `Content-Type` of the exported table.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=UTF-8",
+            ExportFormat::Columns => "application/json",
+        }
+    }
+}
+
+/// A class-predicate pair of the training table.
+///
+/// ## Fields
+/// * `features` - Values of the `FEATURE_COLUMNS` (0 when a statistic is missing).
+/// * `model_keep` - Probability to keep returned by the current model, if known.
+/// * `label` - Curator decision, if any.
+pub struct FeatureRow {
+    pub class: String,
+    pub predicate: String,
+    pub features: [f64; FEATURE_COLUMNS.len()],
+    pub model_keep: Option<f64>,
+    pub label: Option<bool>,
+}
+
+impl FeatureRow {
+    fn new(class: String, predicate: String, stats: &HashMap<String, f64>, label: Option<bool>) -> FeatureRow {
+        FeatureRow {
+            features: FEATURE_COLUMNS.map(|column| stats.get(column).copied().unwrap_or(0.0)),
+            model_keep: stats.get("keep").copied(),
+            class,
+            predicate,
+            label,
+        }
+    }
+}
+
+/// Columns of `ExportFormat::Columns`.
+#[derive(Serialize)]
+struct Columns<'a> {
+    class: Vec<&'a str>,
+    predicate: Vec<&'a str>,
+    #[serde(flatten)]
+    features: HashMap<&'static str, Vec<f64>>,
+    model_keep: Vec<Option<f64>>,
+    label: Vec<Option<u8>>,
+}
+
+impl KG {
+    /// Builds the training table of the classes reachable from `schema:{start_with}`, followed by the labelled
+    /// predicates missing from it.
+    pub fn training_table(&self, start_with: &str) -> Vec<FeatureRow> {
+        let labels = self
+            .predicate_labels()
+            .into_iter()
+            .map(|l| ((l.class.clone(), l.predicate.clone()), l))
+            .collect::<HashMap<_, _>>();
+
+        let mut rows = vec![];
+        let mut seen = HashSet::new();
+        for (node, data) in self.predicate_statistics(start_with) {
+            for (predicate, stats) in data {
+                let key = (node.class.clone(), predicate.clone());
+                let label = labels.get(&key).map(|l| l.keep);
+                rows.push(FeatureRow::new(node.class.clone(), predicate, &stats, label));
+                seen.insert(key);
+            }
+        }
+
+        let mut missing = labels
+            .into_iter()
+            .filter(|(key, label)| !seen.contains(key) && !label.stats.is_empty())
+            .map(|(_, label)| label)
+            .collect::<Vec<_>>();
+        missing.sort_by(|a, b| a.class.cmp(&b.class).then_with(|| a.predicate.cmp(&b.predicate)));
+        for label in missing {
+            rows.push(FeatureRow::new(label.class, label.predicate, &label.stats, Some(label.keep)));
+        }
+        rows
+    }
+}
+
+/// Writes the training table in `format`.
+///
+/// # Errors
+/// Returns a message if the table cannot be serialized.
+pub fn export_training_table(rows: &[FeatureRow], format: ExportFormat) -> Result<String, String> {
+    match format {
+        ExportFormat::Csv => Ok(to_csv(rows)),
+        ExportFormat::Columns => {
+            let columns = Columns {
+                class: rows
+                    .iter()
+                    .map(|r| r.class.as_str())
+                    .collect(),
+                predicate: rows
+                    .iter()
+                    .map(|r| r.predicate.as_str())
+                    .collect(),
+                features: FEATURE_COLUMNS.iter()
+                    .enumerate()
+                    .map(|(i, column)| (
+                        *column,
+                        rows
+                            .iter()
+                            .map(|r| r.features[i])
+                            .collect(),
+                    ))
+                    .collect(),
+                model_keep: rows
+                    .iter()
+                    .map(|r| r.model_keep)
+                    .collect(),
+                label: rows
+                    .iter()
+                    .map(|r| r.label.map(u8::from))
+                    .collect(),
+            };
+            serde_json::to_string(&columns).map_err(|e| format!("Failed to serialize the table: {e}"))
+        }
+    }
+}
+
+fn to_csv(rows: &[FeatureRow]) -> String {
+    let mut csv = format!("class,predicate,{},model_keep,label\n", FEATURE_COLUMNS.join(","));
+    for row in rows {
+        let features = row.features
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        csv += &format!(
+            "{},{},{},{},{}\n",
+            csv_field(&row.class),
+            csv_field(&row.predicate),
+            features.join(","),
+            row.model_keep.map(|k| k.to_string()).unwrap_or_default(),
+            row.label.map(|l| u8::from(l).to_string()).unwrap_or_default()
+        );
+    }
+    csv
+}
+
+/// Quotes a CSV field when it contains a separator, a quote or a line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,459 @@
+//! # Flattened class export
+//!
+//! Flattens the instances of a class into a table, one row per entity (`Book`) and one column per predicate path:
+//! `name`, or nested paths following IRI values such as `author/name`. The first column holds the IRI of the
+//! entity.
+//!
+//! - Path segments are schema.org local names (any casing) or bracketed IRIs, resolved as the filters of the Neo4j
+//!   export (see `vocabulary::resolve_term`).
+//! - `MultiValue` decides what becomes of the cells holding several values: the first one is kept, they are joined,
+//!   or the row is exploded into one row per combination of values.
+//! - The default paths come from the predicate analysis of the class when it was run (kept predicates, or those the
+//!   curators labelled so), from its profile otherwise (predicates filled for at least half the entities); predicates
+//!   whose objects have a `schema:name` are suggested as `predicate/name`.
+//!
+//! The table is written as CSV, JSON Lines or Parquet (optional UTF-8 columns).
+
+use std::collections::{ HashMap, HashSet };
+use std::sync::Arc;
+
+use oxigraph::model::Term;
+use parquet::basic::{ ConvertedType, Repetition, Type as PhysicalType };
+use parquet::data_type::{ ByteArray, ByteArrayType };
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde_json::{ Map, Value };
+
+use crate::feature_export::csv_field;
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+use crate::vocabulary::{ self, resolve_term, short_name };
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+
+/// Predicate suggested after the predicates whose objects are entities.
+const NAME_PREDICATE: &str = "<http://schema.org/name>";
+
+/// Fill rate above which the profile suggests a predicate, when the predicate analysis was not run.
+const SUGGESTED_FILL_RATE: f64 = 0.5;
+
+/// Separator of the values of `MultiValue::Join`.
+const JOIN_SEPARATOR: &str = " | ";
+
+/// Name of the column holding the IRI of the entities.
+pub const IRI_COLUMN: &str = "iri";
+
+/// # Formats of the flattened table.
+///
+/// ## Variants:
+/// * `Csv`: Comma separated values, with a header.
+/// * `JsonLines`: A JSON object per row, `null` for the empty cells.
+/// * `Parquet`: Parquet file of optional UTF-8 columns.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FlattenFormat {
+    Csv,
+    JsonLines,
+    Parquet,
+}
+
+impl FlattenFormat {
+    /// Parses a format name (`csv`, `jsonl` or `parquet`).
+    pub fn parse(name: &str) -> Option<FlattenFormat> {
+        match name {
+            "csv" => Some(FlattenFormat::Csv),
+            "jsonl" | "json_lines" => Some(FlattenFormat::JsonLines),
+            "parquet" => Some(FlattenFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    /// Format of an output file, from its extension (`.jsonl`, `.parquet`, CSV otherwise).
+    pub fn from_path(path: &str) -> FlattenFormat {
+        match path.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("jsonl") => FlattenFormat::JsonLines,
+            Some("parquet") => FlattenFormat::Parquet,
+            _ => FlattenFormat::Csv,
+        }
+    }
+
+    /// Extension of the files of the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FlattenFormat::Csv => "csv",
+            FlattenFormat::JsonLines => "jsonl",
+            FlattenFormat::Parquet => "parquet",
+        }
+    }
+
+    /// `Content-Type` of the table.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FlattenFormat::Csv => "text/csv; charset=UTF-8",
+            FlattenFormat::JsonLines => "application/jsonl",
+            FlattenFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+/// # Handling of the cells holding several values.
+///
+/// ## Variants:
+/// * `First`: The first value only.
+/// * `Join`: The values joined into a single string.
+/// * `Explode`: A row per combination of the values of the entity.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MultiValue {
+    First,
+    Join,
+    Explode,
+}
+
+impl MultiValue {
+    /// Parses a strategy name (`first`, `join` or `explode`).
+    pub fn parse(name: &str) -> Option<MultiValue> {
+        match name {
+            "first" => Some(MultiValue::First),
+            "join" => Some(MultiValue::Join),
+            "explode" => Some(MultiValue::Explode),
+            _ => None,
+        }
+    }
+}
+
+/// A column of the table: the predicates followed from the entity to its values.
+///
+/// ## Fields
+/// * `column` - Name of the column, the local names of the predicates joined with `/`.
+/// * `steps` - Predicates, in their SPARQL form.
+#[derive(Clone, Debug)]
+pub struct PredicatePath {
+    pub column: String,
+    pub steps: Vec<String>,
+}
+
+impl PredicatePath {
+    /// Parses a path such as `author/name` or `<http://example.org/p>/name`.
+    ///
+    /// # Errors
+    /// Returns a message if the path is empty or one of its segments is an invalid IRI.
+    pub fn parse(path: &str) -> Result<PredicatePath, String> {
+        // Slashes of bracketed IRIs do not separate segments
+        let mut segments = vec![String::new()];
+        let mut in_iri = false;
+        for c in path.trim().chars() {
+            match c {
+                '<' => in_iri = true,
+                '>' => in_iri = false,
+                '/' if !in_iri => {
+                    segments.push(String::new());
+                    continue;
+                }
+                _ => {}
+            }
+            if let Some(segment) = segments.last_mut() {
+                segment.push(c);
+            }
+        }
+        if segments.iter().any(|s| s.trim().is_empty()) {
+            return Err(format!("Invalid path: {path}"));
+        }
+        let steps = segments
+            .iter()
+            .map(|s| resolve_term(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        let column = steps
+            .iter()
+            .map(|s| short_name(s))
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok(PredicatePath { column, steps })
+    }
+}
+
+/// Writes a predicate as a path segment: its local name for schema.org terms, bracketed IRI otherwise.
+fn path_segment(predicate: &str) -> String {
+    match vocabulary::local_name(predicate) {
+        Some(_) => short_name(predicate),
+        None => predicate.to_string(),
+    }
+}
+
+/// What to flatten.
+///
+/// ## Fields
+/// * `class` - Class of the entities, in its SPARQL form.
+/// * `paths` - Columns following the IRI of the entities.
+/// * `multi_value` - Handling of the cells holding several values.
+#[derive(Clone, Debug)]
+pub struct FlattenOptions {
+    pub class: String,
+    pub paths: Vec<PredicatePath>,
+    pub multi_value: MultiValue,
+}
+
+impl FlattenOptions {
+    /// Reads the options of the command line or of the API: the class, the paths (separated by commas or line
+    /// breaks) and the name of the multi-value strategy (`first` by default).
+    ///
+    /// # Errors
+    /// Returns a message if the class or a path is invalid, no path is given or the strategy is unknown.
+    pub fn parse(class: &str, paths: &[String], multi_value: Option<&str>) -> Result<FlattenOptions, String> {
+        let class = resolve_term(class)?;
+        let paths = paths
+            .iter()
+            .flat_map(|p| p.split([',', '\n']))
+            .filter(|p| !p.trim().is_empty())
+            .map(PredicatePath::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            return Err("No predicate path to flatten".to_string());
+        }
+        let multi_value = match multi_value.filter(|m| !m.is_empty()) {
+            None => MultiValue::First,
+            Some(name) => MultiValue::parse(name).ok_or_else(|| format!("Unknown multi-value strategy: {name}"))?,
+        };
+        Ok(FlattenOptions { class, paths, multi_value })
+    }
+}
+
+/// A flattened table: its columns (`iri` first) and rows, `None` for the empty cells.
+pub struct FlatTable {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Option<String>>>,
+}
+
+/// Value of a cell: the literal value, or the IRI without brackets.
+fn cell_value(term: &Term) -> String {
+    match term {
+        Term::Literal(literal) => literal.value().to_string(),
+        Term::NamedNode(node) => node.as_str().to_string(),
+        other => other.to_string(),
+    }
+}
+
+impl KG {
+    /// Flattens the instances of `options.class`, ordered by IRI.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of a failed query.
+    pub fn flatten_class(&self, options: &FlattenOptions) -> Result<FlatTable, StoreError> {
+        let class = &options.class;
+        jobs::log(format!("Listing the instances of {class}"));
+        let entities = self
+            .query(&format!("SELECT DISTINCT ?s WHERE {{ ?s a {class} }} ORDER BY ?s"))?
+            .iter()
+            .filter_map(|row| row.get("s").map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+
+        // Values of every path, per entity, without duplicates
+        let mut cells: Vec<HashMap<String, Vec<String>>> = vec![];
+        let total = options.paths.len() as u64;
+        for (i, path) in options.paths.iter().enumerate() {
+            jobs::progress(i as u64, total);
+            jobs::log(format!("Following {}", path.column));
+            let mut patterns = String::new();
+            for (j, step) in path.steps.iter().enumerate() {
+                let subject = if j == 0 { "?s".to_string() } else { format!("?o{}", j - 1) };
+                let object = if j + 1 == path.steps.len() { "?v".to_string() } else { format!("?o{j}") };
+                patterns += &format!(" {subject} {step} {object} .");
+            }
+            let mut values: HashMap<String, Vec<String>> = HashMap::new();
+            let mut seen = HashSet::new();
+            // Ordered, so that `MultiValue::First` keeps the same value on every export
+            let query = format!("SELECT ?s ?v WHERE {{ ?s a {class} .{patterns} }} ORDER BY ?s ?v");
+            for row in self.query(&query)? {
+                let (Some(s), Some(v)) = (row.get("s"), row.get("v")) else {
+                    continue;
+                };
+                let (s, v) = (s.to_string(), cell_value(v));
+                if seen.insert((s.clone(), v.clone())) {
+                    values.entry(s).or_default().push(v);
+                }
+            }
+            cells.push(values);
+        }
+        jobs::progress(total, total);
+
+        let mut columns = vec![IRI_COLUMN.to_string()];
+        columns.extend(options.paths.iter().map(|p| p.column.clone()));
+        let mut rows = vec![];
+        for entity in entities {
+            let values = cells
+                .iter_mut()
+                .map(|c| c.remove(&entity).unwrap_or_default())
+                .collect::<Vec<_>>();
+            let iri = Some(entity.trim_start_matches('<').trim_end_matches('>').to_string());
+            match options.multi_value {
+                MultiValue::First | MultiValue::Join => {
+                    let mut row = vec![iri];
+                    row.extend(
+                        values.into_iter().map(|v| {
+                            match options.multi_value {
+                                MultiValue::Join if !v.is_empty() => Some(v.join(JOIN_SEPARATOR)),
+                                _ => v.into_iter().next(),
+                            }
+                        })
+                    );
+                    rows.push(row);
+                }
+                MultiValue::Explode => {
+                    let mut exploded = vec![vec![iri]];
+                    for column in values {
+                        if column.is_empty() {
+                            exploded.iter_mut().for_each(|row| row.push(None));
+                            continue;
+                        }
+                        exploded = exploded
+                            .into_iter()
+                            .flat_map(|row| {
+                                column.iter().map(move |value| {
+                                    let mut row = row.clone();
+                                    row.push(Some(value.clone()));
+                                    row
+                                })
+                            })
+                            .collect();
+                    }
+                    rows.extend(exploded);
+                }
+            }
+        }
+        Ok(FlatTable { columns, rows })
+    }
+
+    /// Default paths of the flattened table of `class`, the most relevant first.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of a failed query.
+    pub fn suggested_flatten_paths(&self, class: &str) -> Result<Vec<String>, StoreError> {
+        let labels = self
+            .predicate_labels()
+            .into_iter()
+            .filter(|l| l.class == class)
+            .map(|l| (l.predicate, l.keep))
+            .collect::<HashMap<_, _>>();
+        let predicates = match self.cached_predicate_analysis(class) {
+            Some(analysis) =>
+                analysis
+                    .into_iter()
+                    .filter(|(predicate, stats)| {
+                        labels
+                            .get(predicate)
+                            .copied()
+                            .unwrap_or_else(|| stats.get("keep").is_some_and(|keep| *keep > 0.5))
+                    })
+                    .map(|(predicate, _)| predicate)
+                    .collect::<Vec<_>>(),
+            None =>
+                self
+                    .class_profile(class)?
+                    .predicates
+                    .into_iter()
+                    .filter(|p| labels.get(&p.predicate).copied().unwrap_or(p.fill_rate >= SUGGESTED_FILL_RATE))
+                    .map(|p| p.predicate)
+                    .collect(),
+        };
+
+        let named = self
+            .query(
+                &format!("SELECT DISTINCT ?p WHERE {{ ?s a {class} . ?s ?p ?o . ?o {NAME_PREDICATE} ?n }}")
+            )?
+            .iter()
+            .filter_map(|row| row.get("p").map(|p| p.to_string()))
+            .collect::<HashSet<_>>();
+        Ok(
+            predicates
+                .iter()
+                .filter(|p| *p != RDF_TYPE)
+                .map(|p| {
+                    if named.contains(p) {
+                        format!("{}/{}", path_segment(p), path_segment(NAME_PREDICATE))
+                    } else {
+                        path_segment(p)
+                    }
+                })
+                .collect()
+        )
+    }
+}
+
+impl FlatTable {
+    /// Writes the table in `format`.
+    ///
+    /// # Errors
+    /// Returns a message if the Parquet file cannot be written.
+    pub fn export(&self, format: FlattenFormat) -> Result<Vec<u8>, String> {
+        match format {
+            FlattenFormat::Csv => Ok(self.to_csv().into_bytes()),
+            FlattenFormat::JsonLines => Ok(self.to_json_lines().into_bytes()),
+            FlattenFormat::Parquet => self.to_parquet().map_err(|e| format!("Failed to write the Parquet file: {e}")),
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = self.columns
+            .iter()
+            .map(|c| csv_field(c))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv.push('\n');
+        for row in &self.rows {
+            csv += &row
+                .iter()
+                .map(|cell| csv_field(cell.as_deref().unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .join(",");
+            csv.push('\n');
+        }
+        csv
+    }
+
+    fn to_json_lines(&self) -> String {
+        let mut lines = String::new();
+        for row in &self.rows {
+            let object = self.columns
+                .iter()
+                .zip(row)
+                .map(|(column, cell)| (column.clone(), cell.clone().map(Value::String).unwrap_or(Value::Null)))
+                .collect::<Map<_, _>>();
+            lines += &Value::Object(object).to_string();
+            lines.push('\n');
+        }
+        lines
+    }
+
+    fn to_parquet(&self) -> parquet::errors::Result<Vec<u8>> {
+        let fields = self.columns
+            .iter()
+            .map(|column| {
+                Type::primitive_type_builder(column, PhysicalType::BYTE_ARRAY)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .with_converted_type(ConvertedType::UTF8)
+                    .build()
+                    .map(Arc::new)
+            })
+            .collect::<parquet::errors::Result<Vec<_>>>()?;
+        let schema = Arc::new(Type::group_type_builder("schema").with_fields(fields).build()?);
+        let mut writer = SerializedFileWriter::new(vec![], schema, Arc::new(WriterProperties::builder().build()))?;
+
+        let mut row_group = writer.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            // Definition level 1 for the present values, 0 for the empty cells
+            let values = self.rows
+                .iter()
+                .filter_map(|row| row[index].as_deref().map(ByteArray::from))
+                .collect::<Vec<_>>();
+            let levels = self.rows
+                .iter()
+                .map(|row| i16::from(row[index].is_some()))
+                .collect::<Vec<_>>();
+            column.typed::<ByteArrayType>().write_batch(&values, Some(&levels), None)?;
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        writer.into_inner()
+    }
+}
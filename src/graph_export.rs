@@ -0,0 +1,288 @@
+//! # Class relations graph export
+//!
+//! Exports the class relations graph built by `KG::calculate_class_relations_graph`, for tools such as Gephi and
+//! NetworkX. Every class (and the `Literal` node gathering the literals) carries its instance count and its
+//! PageRank and reverse PageRank; every relation its predicate, its triple count, its forward and backward
+//! probabilities (its share of the relations leaving its source and reaching its target, see
+//! `calculate_probabilities_for_graph`) and the edge rank of its predicate.
+//!
+//! - `GraphFormat::GraphMl`: GraphML, with typed attribute keys (Gephi, yEd, NetworkX `read_graphml`).
+//! - `GraphFormat::Dot`: Graphviz DOT, the attributes as quoted properties of the nodes and edges.
+//! - `GraphFormat::NodeLink`: JSON node-link data, as read by NetworkX `node_link_graph` (a directed multigraph
+//!   whose links are keyed by predicate).
+//!
+//! Classes are identified by their IRI, without brackets.
+
+use std::collections::HashMap;
+
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{ Incoming, Outgoing };
+use serde::Serialize;
+
+use crate::page_rank::{ page_rank, PageRankOptions };
+use crate::store::KG;
+use crate::utils::calculate_probabilities_for_graph;
+
+/// # Formats of the exported graph.
+///
+/// ## Variants:
+/// * `GraphMl`: GraphML document.
+/// * `Dot`: Graphviz directed graph.
+/// * `NodeLink`: JSON node-link data.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GraphFormat {
+    GraphMl,
+    Dot,
+    NodeLink,
+}
+
+impl GraphFormat {
+    /// Parses a format name (`graphml`, `dot` or `json`).
+    pub fn parse(name: &str) -> Option<GraphFormat> {
+        match name {
+            "graphml" => Some(GraphFormat::GraphMl),
+            "dot" | "gv" => Some(GraphFormat::Dot),
+            "json" | "node_link" => Some(GraphFormat::NodeLink),
+            _ => None,
+        }
+    }
+
+    /// Format of an output file, from its extension (`.graphml`, `.dot` or `.gv`, JSON otherwise).
+    pub fn from_path(path: &str) -> GraphFormat {
+        match path.rsplit_once('.').map(|(_, extension)| extension) {
+            Some("graphml") => GraphFormat::GraphMl,
+            Some("dot" | "gv") => GraphFormat::Dot,
+            _ => GraphFormat::NodeLink,
+        }
+    }
+
+    /// `Content-Type` of the exported graph.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            GraphFormat::GraphMl => "application/graphml+xml",
+            GraphFormat::Dot => "text/vnd.graphviz",
+            GraphFormat::NodeLink => "application/json",
+        }
+    }
+}
+
+/// A class of the exported graph.
+///
+/// ## Fields
+/// * `id` - IRI of the class, or `Literal`.
+/// * `instances` - Number of instances of the class (0 for `Literal`).
+/// * `page_rank`, `reverse_page_rank` - Ranks of the class following the relations forward and backward.
+#[derive(Serialize)]
+pub struct GraphNode {
+    pub id: String,
+    pub instances: u64,
+    pub page_rank: f64,
+    pub reverse_page_rank: f64,
+}
+
+/// A relation of the exported graph.
+///
+/// ## Fields
+/// * `source`, `target` - Classes of the subjects and of the objects.
+/// * `key` - Predicate of the relation.
+/// * `count` - Number of triples of the relation.
+/// * `forward` - Share of the triples of the relations of `source` going through this one.
+/// * `backward` - Share of the triples of the relations reaching `target` going through this one.
+/// * `edge_rank` - Edge rank of the predicate among the relations of `source`.
+#[derive(Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub key: String,
+    pub count: f64,
+    pub forward: f64,
+    pub backward: f64,
+    pub edge_rank: f64,
+}
+
+/// The class relations graph, with the scores of its classes and relations.
+#[derive(Serialize)]
+pub struct ClassRelationsExport {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+/// Node-link data, the layout NetworkX reads.
+#[derive(Serialize)]
+struct NodeLink<'a> {
+    directed: bool,
+    multigraph: bool,
+    graph: HashMap<&'static str, String>,
+    nodes: &'a [GraphNode],
+    links: &'a [GraphEdge],
+}
+
+fn id(term: &str) -> String {
+    term.trim_start_matches('<').trim_end_matches('>').to_string()
+}
+
+impl KG {
+    /// Builds the class relations graph with the probabilities of its relations and the ranks computed with
+    /// `options`.
+    pub fn class_relations_export(&self, options: &PageRankOptions) -> ClassRelationsExport {
+        let (mut graph, _) = self.calculate_class_relations_graph();
+        calculate_probabilities_for_graph(&mut graph);
+
+        let node_counts = self
+            .class_counts()
+            .into_iter()
+            .map(|(class, count)| (class, count as f64))
+            .collect::<HashMap<_, _>>();
+        let (ranks, edge_ranks) = page_rank(&graph, &node_counts, Outgoing, options);
+        let (reverse_ranks, _) = page_rank(&graph, &node_counts, Incoming, options);
+
+        let nodes = graph
+            .node_indices()
+            .map(|n| {
+                let class = &graph[n];
+                GraphNode {
+                    id: id(class),
+                    instances: node_counts.get(class).copied().unwrap_or(0.0) as u64,
+                    page_rank: ranks.get(class).copied().unwrap_or(0.0),
+                    reverse_page_rank: reverse_ranks.get(class).copied().unwrap_or(0.0),
+                }
+            })
+            .collect();
+        let edges = graph
+            .edge_references()
+            .map(|e| {
+                let (predicate, count, forward, backward) = e.weight();
+                let source = &graph[e.source()];
+                GraphEdge {
+                    source: id(source),
+                    target: id(&graph[e.target()]),
+                    key: id(predicate),
+                    count: *count,
+                    forward: forward.unwrap_or(0.0),
+                    backward: backward.unwrap_or(0.0),
+                    edge_rank: edge_ranks
+                        .get(source)
+                        .and_then(|ranks| ranks.get(predicate))
+                        .copied()
+                        .unwrap_or(0.0),
+                }
+            })
+            .collect();
+        ClassRelationsExport { nodes, edges }
+    }
+}
+
+/// Writes the graph in `format`, `options` being described in its metadata.
+///
+/// # Errors
+/// Returns a message if the node-link data cannot be serialized.
+pub fn export_class_graph(
+    graph: &ClassRelationsExport,
+    format: GraphFormat,
+    options: &PageRankOptions
+) -> Result<String, String> {
+    match format {
+        GraphFormat::GraphMl => Ok(to_graphml(graph, options)),
+        GraphFormat::Dot => Ok(to_dot(graph, options)),
+        GraphFormat::NodeLink => {
+            let data = NodeLink {
+                directed: true,
+                multigraph: true,
+                graph: HashMap::from([
+                    ("name", "class relations".to_string()),
+                    ("page_rank", options.describe()),
+                ]),
+                nodes: &graph.nodes,
+                links: &graph.edges,
+            };
+            serde_json::to_string(&data).map_err(|e| format!("Failed to serialize the graph: {e}"))
+        }
+    }
+}
+
+/// Escapes the XML special characters, quotes included.
+fn xml_escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn to_graphml(graph: &ClassRelationsExport, options: &PageRankOptions) -> String {
+    let mut xml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="instances" for="node" attr.name="instances" attr.type="long"/>
+  <key id="page_rank" for="node" attr.name="page_rank" attr.type="double"/>
+  <key id="reverse_page_rank" for="node" attr.name="reverse_page_rank" attr.type="double"/>
+  <key id="predicate" for="edge" attr.name="predicate" attr.type="string"/>
+  <key id="count" for="edge" attr.name="count" attr.type="double"/>
+  <key id="forward" for="edge" attr.name="forward" attr.type="double"/>
+  <key id="backward" for="edge" attr.name="backward" attr.type="double"/>
+  <key id="edge_rank" for="edge" attr.name="edge_rank" attr.type="double"/>
+"#
+    );
+    xml += &format!(
+        "  <graph id=\"class_relations\" edgedefault=\"directed\">\n    <desc>PageRank: {}</desc>\n",
+        xml_escape(&options.describe())
+    );
+    for node in &graph.nodes {
+        xml += &format!(
+            "    <node id=\"{}\">\n      <data key=\"instances\">{}</data>\n      <data key=\"page_rank\">{}</data>\n      <data key=\"reverse_page_rank\">{}</data>\n    </node>\n",
+            xml_escape(&node.id),
+            node.instances,
+            node.page_rank,
+            node.reverse_page_rank
+        );
+    }
+    for (i, edge) in graph.edges.iter().enumerate() {
+        xml += &format!(
+            "    <edge id=\"e{i}\" source=\"{}\" target=\"{}\">\n      <data key=\"predicate\">{}</data>\n      <data key=\"count\">{}</data>\n      <data key=\"forward\">{}</data>\n      <data key=\"backward\">{}</data>\n      <data key=\"edge_rank\">{}</data>\n    </edge>\n",
+            xml_escape(&edge.source),
+            xml_escape(&edge.target),
+            xml_escape(&edge.key),
+            edge.count,
+            edge.forward,
+            edge.backward,
+            edge.edge_rank
+        );
+    }
+    xml += "  </graph>\n</graphml>\n";
+    xml
+}
+
+/// Quotes a DOT identifier.
+fn dot_id(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn to_dot(graph: &ClassRelationsExport, options: &PageRankOptions) -> String {
+    let mut dot = format!("digraph class_relations {{\n  comment={};\n", dot_id(&format!("PageRank: {}", options.describe())));
+    for node in &graph.nodes {
+        dot += &format!(
+            "  {} [label={}, instances={}, page_rank={}, reverse_page_rank={}];\n",
+            dot_id(&node.id),
+            dot_id(node.id.rsplit(['/', '#']).next().unwrap_or(&node.id)),
+            node.instances,
+            node.page_rank,
+            node.reverse_page_rank
+        );
+    }
+    for edge in &graph.edges {
+        dot += &format!(
+            "  {} -> {} [label={}, predicate={}, count={}, forward={}, backward={}, edge_rank={}];\n",
+            dot_id(&edge.source),
+            dot_id(&edge.target),
+            dot_id(edge.key.rsplit(['/', '#']).next().unwrap_or(&edge.key)),
+            dot_id(&edge.key),
+            edge.count,
+            edge.forward,
+            edge.backward,
+            edge.edge_rank
+        );
+    }
+    dot += "}\n";
+    dot
+}
@@ -0,0 +1,500 @@
+//! # RDFS / schema.org inference
+//!
+//! Materializes the entailments of the class and property hierarchies into the named graph `INFERRED_GRAPH`, so
+//! that a query for every `CreativeWork` also finds the `Book`s and `Movie`s:
+//!
+//! - `rdfs:subClassOf`: `?s a ?c` entails `?s a ?super` for every super class of `?c`. The classes moved to
+//!   `schema:additionalType` by `fix_types` count as types of the entity too.
+//! - `rdfs:subPropertyOf`: `?s ?p ?o` entails `?s ?super ?o` for every super property of `?p`.
+//! - `schema:inverseOf` / `owl:inverseOf`: `?s ?p ?o` entails `?o ?inverse ?s` when `?o` is an IRI.
+//!
+//! The hierarchies come from the schema.org vocabulary, if loaded, and from the `rdfs:subClassOf`,
+//! `rdfs:subPropertyOf` and inverse statements of the dataset itself. Triples already asserted are not repeated, and
+//! blank nodes are ignored (WDC datasets are skolemized).
+//!
+//! Inference is optional: it is enabled by creating the graph and stays enabled, across restarts, until the graph is
+//! dropped. Every change recorded in the history marks the graph stale; it is refreshed on the next query including
+//! it. That refresh only re-evaluates the rules of the classes and predicates the change log (see `cache`) lists since
+//! the previous one, and inserts and deletes the triples whose entailment changed. Changes to the hierarchies
+//! themselves, or that the log cannot bound, recompute every rule, as enabling inference and refreshing it explicitly
+//! do.
+//!
+//! The default graph keeps the asserted triples only, so analyses, dumps and updates ignore the inferred ones;
+//! queries opt in with `KG::query_with_inferred`.
+
+use std::collections::{ BTreeSet, HashMap, HashSet, VecDeque };
+
+use serde::Serialize;
+
+use crate::cache::ChangeSet;
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+use crate::utils::extract_literal;
+use crate::vocabulary::{ self, SCHEMA_NS };
+
+/// Named graph holding the inferred triples.
+pub const INFERRED_GRAPH: &str = "urn:kg-explorer:inferred";
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+const RDFS_SUBCLASS_OF: &str = "<http://www.w3.org/2000/01/rdf-schema#subClassOf>";
+const RDFS_SUBPROPERTY_OF: &str = "<http://www.w3.org/2000/01/rdf-schema#subPropertyOf>";
+const OWL_INVERSE_OF: &str = "<http://www.w3.org/2002/07/owl#inverseOf>";
+
+/// Rules given to a single query, to keep the `VALUES` blocks small.
+const RULES_PER_QUERY: usize = 200;
+
+/// Triples per `INSERT DATA` or `DELETE DATA` on the inferred graph.
+const TRIPLES_PER_UPDATE: usize = 1000;
+
+/// Cache recording the position in the change log of the last refresh.
+const INFERENCE_CACHE: &str = "inference.json";
+
+/// State of the inferred graph.
+///
+/// ## Fields
+/// * `enabled` - Whether inference is enabled (the inferred graph exists).
+/// * `stale` - Whether the store changed since the graph was last refreshed.
+/// * `graph` - IRI of the inferred graph.
+/// * `triples` - Number of inferred triples.
+#[derive(Serialize)]
+pub struct InferenceStatus {
+    pub enabled: bool,
+    pub stale: bool,
+    pub graph: String,
+    pub triples: u64,
+}
+
+/// Outcome of a refresh of the inferred graph.
+///
+/// ## Fields
+/// * `incremental` - Whether only the rules of the classes and predicates changed since the last refresh were applied.
+/// * `class_rules`, `property_rules`, `inverse_rules` - Number of (term, entailed term) pairs applied to the
+///   classes and predicates of the dataset.
+/// * `inserted`, `deleted` - Triples added to and removed from the inferred graph.
+/// * `triples` - Number of inferred triples after the refresh.
+#[derive(Serialize)]
+pub struct InferenceReport {
+    pub incremental: bool,
+    pub class_rules: usize,
+    pub property_rules: usize,
+    pub inverse_rules: usize,
+    pub inserted: usize,
+    pub deleted: usize,
+    pub triples: usize,
+}
+
+/// Super terms and inverses declared in the dataset, in SPARQL form; the vocabulary is looked up on the fly.
+#[derive(Default)]
+struct Hierarchy {
+    parents: HashMap<String, BTreeSet<String>>,
+    inverses: HashMap<String, BTreeSet<String>>,
+}
+
+impl Hierarchy {
+    /// Direct super classes (or super properties) of a term.
+    fn parents(&self, term: &str) -> BTreeSet<String> {
+        let mut parents = self.parents.get(term).cloned().unwrap_or_default();
+        if let Some(term) = vocabulary::get().and_then(|v| v.term(term)) {
+            parents.extend(term.parents.iter().map(|p| vocabulary::schema_iri(p)));
+        }
+        parents
+    }
+
+    /// Every ancestor of a term, closest first, without the term itself.
+    fn ancestors(&self, term: &str) -> Vec<String> {
+        let mut ancestors = vec![];
+        let mut seen = HashSet::from([term.to_string()]);
+        let mut queue = self.parents(term).into_iter().collect::<VecDeque<_>>();
+        while let Some(parent) = queue.pop_front() {
+            if seen.insert(parent.clone()) {
+                queue.extend(self.parents(&parent));
+                ancestors.push(parent);
+            }
+        }
+        ancestors
+    }
+
+    /// Inverse properties of a property.
+    fn inverses(&self, property: &str) -> BTreeSet<String> {
+        let mut inverses = self.inverses.get(property).cloned().unwrap_or_default();
+        if let Some(term) = vocabulary::get().and_then(|v| v.term(property)) {
+            inverses.extend(term.inverse.iter().map(|i| vocabulary::schema_iri(i)));
+        }
+        inverses
+    }
+}
+
+/// Entailed classes and predicates whose triples a refresh recomputes.
+struct Scope {
+    classes: BTreeSet<String>,
+    predicates: BTreeSet<String>,
+}
+
+impl Scope {
+    /// Entailed classes and predicates the changes may have affected, `None` if every rule must be re-evaluated.
+    ///
+    /// A rule is affected when the term it applies to, or the term it entails (whose asserted triples are not
+    /// repeated), changed. Every rule entailing an affected term is re-evaluated, so that the triples of the inferred
+    /// graph with that term can be compared with the new entailments.
+    fn of(
+        changes: &ChangeSet,
+        class_rules: &[(String, String)],
+        property_rules: &[(String, String)],
+        inverse_rules: &[(String, String)]
+    ) -> Option<Scope> {
+        let changed = changes.predicates.keys().chain(&changes.anywhere).cloned().collect::<BTreeSet<_>>();
+        let hierarchy = [
+            RDFS_SUBCLASS_OF.to_string(),
+            RDFS_SUBPROPERTY_OF.to_string(),
+            OWL_INVERSE_OF.to_string(),
+            format!("<{SCHEMA_NS}inverseOf>"),
+            format!("<{SCHEMA_NS}additionalType>"),
+        ];
+        if changes.everything || hierarchy.iter().any(|p| changed.contains(p)) {
+            return None;
+        }
+        let mut classes = changes.types.clone();
+        classes.extend(
+            class_rules
+                .iter()
+                .filter(|(class, _)| changes.types.contains(class))
+                .map(|(_, entailed)| entailed.clone())
+        );
+        let mut predicates = changed.clone();
+        predicates.extend(
+            property_rules
+                .iter()
+                .chain(inverse_rules)
+                .filter(|(predicate, _)| changed.contains(predicate))
+                .map(|(_, entailed)| entailed.clone())
+        );
+        // The class rules entail `rdf:type` triples too
+        if predicates.contains(RDF_TYPE) {
+            return None;
+        }
+        Some(Scope { classes, predicates })
+    }
+}
+
+impl KG {
+    /// Whether inference is enabled, i.e. the inferred graph exists.
+    pub fn inference_enabled(&self) -> bool {
+        self.has_named_graph(INFERRED_GRAPH)
+    }
+
+    /// State of the inferred graph.
+    pub fn inference_status(&self) -> InferenceStatus {
+        let query = format!("SELECT (COUNT(*) AS ?count) WHERE {{ GRAPH <{INFERRED_GRAPH}> {{ ?s ?p ?o }} }}");
+        let triples = self
+            .query(&query)
+            .ok()
+            .and_then(|solutions| solutions.first().and_then(|s| extract_literal(s.get("count"))))
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0);
+        InferenceStatus {
+            enabled: self.inference_enabled(),
+            stale: self.inferences_stale(),
+            graph: INFERRED_GRAPH.to_string(),
+            triples,
+        }
+    }
+
+    /// Enables inference: creates the inferred graph and materializes the entailments of the current data.
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if a query or an update fails.
+    pub fn enable_inference(&self) -> Result<InferenceReport, StoreError> {
+        self.with_inference_lock(|| {
+            self.update(&format!("CREATE SILENT GRAPH <{INFERRED_GRAPH}>"))?;
+            self.take_inferences_stale();
+            self.materialize_inferences(true)
+        })
+    }
+
+    /// Disables inference: drops the inferred graph.
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if the graph cannot be dropped.
+    pub fn disable_inference(&self) -> Result<(), StoreError> {
+        self.with_inference_lock(|| self.update(&format!("DROP SILENT GRAPH <{INFERRED_GRAPH}>")))
+    }
+
+    /// Refreshes the inferred graph, whether the store changed or not, re-evaluating every rule (the vocabulary may
+    /// have changed).
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if inference is not enabled, or if a query or an update fails.
+    pub fn refresh_inferences(&self) -> Result<InferenceReport, StoreError> {
+        self.with_inference_lock(|| {
+            if !self.inference_enabled() {
+                return Err(StoreError::EvaluationError("Inference is not enabled".to_string()));
+            }
+            self.take_inferences_stale();
+            self.materialize_inferences(true)
+        })
+    }
+
+    /// Refreshes the inferred graph if inference is enabled and the store changed since the last refresh, re-evaluating
+    /// the rules of the changed classes and predicates only.
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if a query or an update fails; the graph stays stale.
+    pub fn refresh_inferences_if_stale(&self) -> Result<(), StoreError> {
+        self.with_inference_lock(|| {
+            if self.inference_enabled() && self.take_inferences_stale() {
+                if let Err(e) = self.materialize_inferences(false) {
+                    self.mark_inferences_stale();
+                    return Err(e);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Computes the entailments of the current data and applies the difference to the inferred graph.
+    ///
+    /// Unless `full`, only the rules affected by the changes logged since the last refresh are re-evaluated, if the
+    /// change log goes back that far.
+    fn materialize_inferences(&self, full: bool) -> Result<InferenceReport, StoreError> {
+        let version = self.cache_version();
+        let changes = if full { None } else { self.load_cache::<()>(INFERENCE_CACHE).map(|cached| cached.changes) };
+        let additional_type = format!("<{SCHEMA_NS}additionalType>");
+        jobs::log("Collecting the hierarchies, classes and predicates".to_string());
+        let hierarchy = self.declared_hierarchy()?;
+        let classes = self.distinct_terms(
+            &format!(
+                "SELECT DISTINCT ?t WHERE {{ {{ ?s a ?t }} UNION {{ ?s {additional_type} ?t }} FILTER(isIRI(?t)) }}"
+            )
+        )?;
+        let predicates = self.distinct_terms("SELECT DISTINCT ?t WHERE { ?s ?t ?o }")?;
+
+        let mut class_rules = vec![];
+        for class in &classes {
+            class_rules.extend(
+                hierarchy
+                    .ancestors(class)
+                    .into_iter()
+                    .map(|ancestor| (class.clone(), ancestor))
+            );
+        }
+        let mut property_rules = vec![];
+        let mut inverse_rules = vec![];
+        for predicate in predicates.iter().filter(|p| *p != RDF_TYPE) {
+            let ancestors = hierarchy.ancestors(predicate);
+            for property in std::iter::once(predicate).chain(&ancestors) {
+                inverse_rules.extend(
+                    hierarchy
+                        .inverses(property)
+                        .into_iter()
+                        .map(|inverse| (predicate.clone(), inverse))
+                );
+            }
+            property_rules.extend(ancestors.into_iter().map(|ancestor| (predicate.clone(), ancestor)));
+        }
+        let scope = changes.and_then(|changes| Scope::of(&changes, &class_rules, &property_rules, &inverse_rules));
+        if let Some(scope) = &scope {
+            class_rules.retain(|(_, class)| scope.classes.contains(class));
+            property_rules.retain(|(_, predicate)| scope.predicates.contains(predicate));
+            inverse_rules.retain(|(_, predicate)| scope.predicates.contains(predicate));
+            jobs::log(
+                format!(
+                    "Re-evaluating the rules of {} classes and {} predicates changed since the last refresh",
+                    scope.classes.len(),
+                    scope.predicates.len()
+                )
+            );
+        }
+
+        let mut entailed = HashSet::new();
+        jobs::log(format!("Applying {} class rules", class_rules.len()));
+        jobs::progress(0, 4);
+        self.entailments(
+            &format!(
+                "SELECT DISTINCT ?s ?p ?o WHERE {{
+                    VALUES (?c ?o) {{ {{values}} }}
+                    {{ ?s a ?c }} UNION {{ ?s {additional_type} ?c }}
+                    BIND({RDF_TYPE} AS ?p)
+                    FILTER(!isBlank(?s))
+                    FILTER NOT EXISTS {{ ?s a ?o }}
+                }}"
+            ),
+            &class_rules,
+            &mut entailed
+        )?;
+        // The classes given as `schema:additionalType` are types themselves
+        self.collect_for_terms(
+            &format!(
+                "SELECT DISTINCT ?s ?p ?o WHERE {{
+                    {{values}}
+                    ?s {additional_type} ?o
+                    BIND({RDF_TYPE} AS ?p)
+                    FILTER(isIRI(?o) && !isBlank(?s))
+                    FILTER NOT EXISTS {{ ?s a ?o }}
+                }}"
+            ),
+            "o",
+            scope.as_ref().map(|scope| &scope.classes),
+            &mut entailed
+        )?;
+        jobs::log(format!("Applying {} property rules", property_rules.len()));
+        jobs::progress(1, 4);
+        self.entailments(
+            "SELECT DISTINCT ?s ?p ?o WHERE {
+                VALUES (?q ?p) { {values} }
+                ?s ?q ?o
+                FILTER(!isBlank(?s) && !isBlank(?o))
+                FILTER NOT EXISTS { ?s ?p ?o }
+            }",
+            &property_rules,
+            &mut entailed
+        )?;
+        jobs::log(format!("Applying {} inverse rules", inverse_rules.len()));
+        jobs::progress(2, 4);
+        self.entailments(
+            "SELECT DISTINCT ?s ?p ?o WHERE {
+                VALUES (?q ?p) { {values} }
+                ?o ?q ?s
+                FILTER(isIRI(?s) && !isBlank(?o))
+                FILTER NOT EXISTS { ?s ?p ?o }
+            }",
+            &inverse_rules,
+            &mut entailed
+        )?;
+
+        jobs::progress(3, 4);
+        // The inferred triples the re-evaluated rules may entail
+        let mut current = HashSet::new();
+        let inferred = format!("SELECT ?s ?p ?o WHERE {{ {{values}} GRAPH <{INFERRED_GRAPH}> {{ ?s ?p ?o }} }}");
+        match &scope {
+            None => self.collect_for_terms(&inferred, "p", None, &mut current)?,
+            Some(scope) => {
+                let types = format!(
+                    "SELECT ?s ?p ?o WHERE {{
+                        {{values}} GRAPH <{INFERRED_GRAPH}> {{ ?s ?p ?o }} FILTER(?p = {RDF_TYPE})
+                    }}"
+                );
+                self.collect_for_terms(&types, "o", Some(&scope.classes), &mut current)?;
+                self.collect_for_terms(&inferred, "p", Some(&scope.predicates), &mut current)?;
+            }
+        }
+        let inserted = entailed.difference(&current).collect::<Vec<_>>();
+        let deleted = current.difference(&entailed).collect::<Vec<_>>();
+        jobs::log(format!("Inserting {} and deleting {} inferred triples", inserted.len(), deleted.len()));
+        self.update_inferred_graph("DELETE", &deleted)?;
+        self.update_inferred_graph("INSERT", &inserted)?;
+        self.save_cache(INFERENCE_CACHE, version, &());
+        jobs::progress(4, 4);
+
+        Ok(InferenceReport {
+            incremental: scope.is_some(),
+            class_rules: class_rules.len(),
+            property_rules: property_rules.len(),
+            inverse_rules: inverse_rules.len(),
+            inserted: inserted.len(),
+            deleted: deleted.len(),
+            triples: self.inference_status().triples as usize,
+        })
+    }
+
+    /// Reads the `rdfs:subClassOf`, `rdfs:subPropertyOf` and inverse statements of the dataset.
+    fn declared_hierarchy(&self) -> Result<Hierarchy, StoreError> {
+        let query = format!(
+            "SELECT ?a ?r ?b WHERE {{
+                VALUES ?r {{ {RDFS_SUBCLASS_OF} {RDFS_SUBPROPERTY_OF} {OWL_INVERSE_OF} <{SCHEMA_NS}inverseOf> }}
+                ?a ?r ?b
+                FILTER(isIRI(?a) && isIRI(?b))
+            }}"
+        );
+        let mut hierarchy = Hierarchy::default();
+        for solution in self.query(&query)? {
+            let (Some(a), Some(r), Some(b)) = (solution.get("a"), solution.get("r"), solution.get("b")) else {
+                continue;
+            };
+            let (a, b) = (a.to_string(), b.to_string());
+            match r.to_string().as_str() {
+                RDFS_SUBCLASS_OF | RDFS_SUBPROPERTY_OF => {
+                    hierarchy.parents.entry(a).or_default().insert(b);
+                }
+                // Inverses hold both ways, whichever side declares them
+                _ => {
+                    hierarchy.inverses.entry(a.clone()).or_default().insert(b.clone());
+                    hierarchy.inverses.entry(b).or_default().insert(a);
+                }
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    /// Runs a query selecting `?t` and returns the distinct terms, in SPARQL form.
+    fn distinct_terms(&self, query: &str) -> Result<Vec<String>, StoreError> {
+        Ok(
+            self
+                .query(query)?
+                .iter()
+                .filter_map(|s| s.get("t").map(|t| t.to_string()))
+                .collect()
+        )
+    }
+
+    /// Runs `template` for every chunk of `rules`, replacing `{values}` by the pairs of the chunk (see
+    /// `collect_entailed`).
+    fn entailments(
+        &self,
+        template: &str,
+        rules: &[(String, String)],
+        entailed: &mut HashSet<String>
+    ) -> Result<(), StoreError> {
+        for chunk in rules.chunks(RULES_PER_QUERY) {
+            let values = chunk
+                .iter()
+                .map(|(term, entailed)| format!("({term} {entailed})"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            self.collect_entailed(&template.replace("{values}", &values), entailed)?;
+        }
+        Ok(())
+    }
+
+    /// Runs `template` with `{values}` replaced by `VALUES` blocks binding `variable` to chunks of `terms`, or once
+    /// with nothing if `terms` is `None`, and adds the solutions to `triples` (see `collect_entailed`).
+    fn collect_for_terms(
+        &self,
+        template: &str,
+        variable: &str,
+        terms: Option<&BTreeSet<String>>,
+        triples: &mut HashSet<String>
+    ) -> Result<(), StoreError> {
+        let Some(terms) = terms else {
+            return self.collect_entailed(&template.replace("{values}", ""), triples);
+        };
+        let terms = terms.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+        for chunk in terms.chunks(RULES_PER_QUERY) {
+            let values = format!("VALUES ?{variable} {{ {} }}", chunk.join(" "));
+            self.collect_entailed(&template.replace("{values}", &values), triples)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the `?s ?p ?o` solutions of `query` to `entailed`, as N-Triples lines.
+    fn collect_entailed(&self, query: &str, entailed: &mut HashSet<String>) -> Result<(), StoreError> {
+        for solution in self.query(query)? {
+            if let (Some(s), Some(p), Some(o)) = (solution.get("s"), solution.get("p"), solution.get("o")) {
+                entailed.insert(format!("{s} {p} {o} ."));
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts or deletes (`operation`) N-Triples lines in the inferred graph, in batches.
+    fn update_inferred_graph(&self, operation: &str, triples: &[&String]) -> Result<(), StoreError> {
+        for chunk in triples.chunks(TRIPLES_PER_UPDATE) {
+            let triples = chunk
+                .iter()
+                .map(|t| t.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            self.update(&format!("{operation} DATA {{ GRAPH <{INFERRED_GRAPH}> {{ {triples} }} }}"))?;
+        }
+        Ok(())
+    }
+}
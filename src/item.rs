@@ -39,6 +39,11 @@ impl Item {
         }
     }
 
+    /// Returns the identifier of the item as it appears in SPARQL queries (`<iri>` or `_:id`).
+    pub fn id(&self) -> String {
+        self.node.to_string()
+    }
+
     /// Generates an HTML representation of the `Item`.
     ///
     /// This method:
@@ -0,0 +1,401 @@
+//! # Background jobs
+//!
+//! Long operations (analyses, routines, reverts, history replays, iterative updates) run as jobs instead of inside
+//! the HTTP request. Every job has an ID, a status, a progress, logs and, once finished, a result (the HTML page the
+//! operation produced).
+//!
+//! - Jobs run on a fixed pool of `MAX_RUNNING_JOBS` threads; the others wait in a queue of at most
+//!   `MAX_QUEUED_JOBS` jobs, beyond which new jobs fail right away.
+//! - A job started with a key while another job of the same key is queued or running is not started again: the
+//!   ID of the existing job is returned instead, so that refreshing an analysis page does not scan the store twice.
+//!   Jobs without a key are the ones modifying the store.
+//! - `JobManager::shutdown` stops starting jobs and waits for the running jobs that modify the store, so that the
+//!   server never stops in the middle of an update.
+//!
+//! Code running inside a job reports through the free functions `progress` and `log`, which do nothing when
+//! called outside of a job. Watchers are woken up through `JobManager::wait_for_change`.
+
+use std::cell::RefCell;
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::mpsc::{ self, Receiver, SyncSender, TrySendError };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::thread;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use serde::Serialize;
+
+use crate::store;
+
+/// Number of finished jobs kept in memory.
+const MAX_FINISHED_JOBS: usize = 100;
+
+/// Number of log lines kept per job.
+const MAX_LOG_LINES: usize = 1000;
+
+/// Number of jobs running at once.
+const MAX_RUNNING_JOBS: usize = 4;
+
+/// Number of jobs waiting for a free thread.
+const MAX_QUEUED_JOBS: usize = 64;
+
+/// # State of a job.
+///
+/// ## Variants:
+/// * `Queued`: The job waits for a free thread.
+/// * `Running`: The job is still running.
+/// * `Succeeded`: The job finished and its result is available.
+/// * `Failed`: The job returned an error or panicked.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Progress of a job, e.g. rows processed by an iterative update.
+#[derive(Serialize, Clone, Copy)]
+pub struct Progress {
+    pub done: u64,
+    pub total: u64,
+}
+
+/// Public description of a job.
+///
+/// ## Fields
+/// * `id` - Identifier of the job, increasing from 1.
+/// * `title` - Human readable description of the operation.
+/// * `user` - User that started the job, if authenticated.
+/// * `status` - Current state of the job.
+/// * `progress` - Last progress reported by the job, if any.
+/// * `logs` - Log lines reported by the job (the last `MAX_LOG_LINES`).
+/// * `error` - Error message of a failed job.
+/// * `started_at`, `finished_at` - UNIX timestamps in seconds.
+#[derive(Serialize, Clone)]
+pub struct JobInfo {
+    pub id: u64,
+    pub title: String,
+    pub user: Option<String>,
+    pub status: JobStatus,
+    pub progress: Option<Progress>,
+    pub logs: Vec<String>,
+    pub error: Option<String>,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+}
+
+struct Job {
+    info: JobInfo,
+    /// Identifies the identical jobs, see `JobManager::spawn`.
+    key: Option<String>,
+    result: Option<String>,
+    /// Bumped on every change, so that watchers know when to send an update.
+    version: u64,
+}
+
+struct Jobs {
+    list: Vec<Job>,
+    next_id: u64,
+    /// Set by `JobManager::shutdown`: no job starts anymore.
+    closed: bool,
+}
+
+/// Work of a queued job.
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Keeps track of the running and finished jobs.
+pub struct JobManager {
+    jobs: Mutex<Jobs>,
+    changed: Condvar,
+    queue: SyncSender<Task>,
+}
+
+thread_local! {
+    static CURRENT_JOB: RefCell<Option<(Arc<JobManager>, u64)>> = const { RefCell::new(None) };
+}
+
+/// Reports the progress of the job running on the current thread.
+///
+/// Watchers are only woken up when the percentage changes, so this can be called for every row.
+pub fn progress(done: u64, total: u64) {
+    with_current_job(|manager, id| {
+        manager.update(id, |job| {
+            let percent = |p: Option<Progress>| p.map(|p| (p.done * 100) / p.total.max(1));
+            let previous = percent(job.info.progress);
+            job.info.progress = Some(Progress { done, total });
+            previous != percent(job.info.progress) || done == total
+        })
+    });
+}
+
+/// Appends a line to the logs of the job running on the current thread.
+pub fn log(message: String) {
+    with_current_job(|manager, id| {
+        manager.update(id, |job| {
+            job.info.logs.push(message);
+            if job.info.logs.len() > MAX_LOG_LINES {
+                job.info.logs.remove(0);
+            }
+            true
+        })
+    });
+}
+
+fn with_current_job(f: impl FnOnce(&JobManager, u64)) {
+    CURRENT_JOB.with(|current| {
+        if let Some((manager, id)) = current.borrow().as_ref() {
+            f(manager, *id);
+        }
+    });
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl Default for JobManager {
+    fn default() -> Self {
+        JobManager::new()
+    }
+}
+
+impl JobManager {
+    /// Creates the manager and starts its `MAX_RUNNING_JOBS` threads.
+    pub fn new() -> JobManager {
+        let (queue, tasks) = mpsc::sync_channel::<Task>(MAX_QUEUED_JOBS);
+        let tasks = Arc::new(Mutex::new(tasks));
+        for _ in 0..MAX_RUNNING_JOBS {
+            let tasks = tasks.clone();
+            thread::spawn(move || Self::run_tasks(&tasks));
+        }
+        JobManager {
+            jobs: Mutex::new(Jobs { list: vec![], next_id: 1, closed: false }),
+            changed: Condvar::new(),
+            queue,
+        }
+    }
+
+    /// Runs the queued tasks until the manager is dropped.
+    fn run_tasks(tasks: &Mutex<Receiver<Task>>) {
+        loop {
+            // The lock is released before running the task, so that the other threads can take the next ones
+            let task = tasks.lock().unwrap().recv();
+            match task {
+                Ok(task) => task(),
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Queues `work` and returns the ID of the job.
+    ///
+    /// - If a job started with the same `key` is still queued or running, its ID is returned and `work` is dropped.
+    ///   Work modifying the store must be started without a key.
+    /// - History entries written by the job are attributed to `user`.
+    /// - `work` returns the HTML page of the result, or an error message.
+    /// - A panic inside `work` marks the job as failed, and so does a full queue or a shutdown.
+    pub fn spawn<F>(self: &Arc<Self>, title: String, key: Option<String>, user: Option<String>, work: F) -> u64
+        where F: FnOnce() -> Result<String, String> + Send + 'static
+    {
+        let (id, closed) = {
+            let mut jobs = self.jobs.lock().unwrap();
+            let existing = key.as_ref().and_then(|key| {
+                jobs.list.iter().find(|job| {
+                    job.key.as_ref() == Some(key) &&
+                        matches!(job.info.status, JobStatus::Queued | JobStatus::Running)
+                })
+            });
+            if let Some(job) = existing {
+                return job.info.id;
+            }
+            let id = jobs.next_id;
+            jobs.next_id += 1;
+            jobs.list.push(Job {
+                info: JobInfo {
+                    id,
+                    title: title.clone(),
+                    user: user.clone(),
+                    status: JobStatus::Queued,
+                    progress: None,
+                    logs: vec![],
+                    error: None,
+                    started_at: now(),
+                    finished_at: None,
+                },
+                key,
+                result: None,
+                version: 0,
+            });
+            Self::prune(&mut jobs.list);
+            (id, jobs.closed)
+        };
+        if closed {
+            self.fail(id, "The server is shutting down");
+            return id;
+        }
+        println!("Job {id} queued: {title}");
+
+        let manager = self.clone();
+        let task: Task = Box::new(move || {
+            // A job failed by a shutdown while it was queued does not start
+            let mut started = false;
+            manager.update(id, |job| {
+                started = job.info.status == JobStatus::Queued;
+                if started {
+                    job.info.status = JobStatus::Running;
+                }
+                started
+            });
+            if !started {
+                return;
+            }
+            CURRENT_JOB.with(|current| *current.borrow_mut() = Some((manager.clone(), id)));
+            store::set_acting_user(user);
+
+            let outcome = panic
+                ::catch_unwind(AssertUnwindSafe(work))
+                .unwrap_or_else(|_| Err("The job panicked".to_string()));
+
+            CURRENT_JOB.with(|current| *current.borrow_mut() = None);
+            store::set_acting_user(None);
+
+            manager.update(id, |job| {
+                job.info.finished_at = Some(now());
+                match outcome {
+                    Ok(result) => {
+                        job.info.status = JobStatus::Succeeded;
+                        job.result = Some(result);
+                    }
+                    Err(e) => {
+                        job.info.status = JobStatus::Failed;
+                        job.info.error = Some(e);
+                    }
+                }
+                true
+            });
+            println!("Job {id} finished");
+        });
+        match self.queue.try_send(task) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => self.fail(id, "Too many jobs are waiting, try again later"),
+            Err(TrySendError::Disconnected(_)) => self.fail(id, "The job threads are stopped"),
+        }
+        id
+    }
+
+    /// Marks a job that could not be queued as failed.
+    fn fail(&self, id: u64, error: &str) {
+        self.update(id, |job| {
+            job.fail(error);
+            true
+        });
+    }
+
+    /// Stops starting jobs, and waits for the running jobs that modify the store (the jobs without a key).
+    ///
+    /// Queued jobs and the jobs spawned afterwards fail; running read-only jobs are not waited for.
+    pub fn shutdown(&self) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.closed = true;
+        for job in jobs.list.iter_mut().filter(|job| job.info.status == JobStatus::Queued) {
+            job.fail("The server is shutting down");
+            job.version += 1;
+        }
+        self.changed.notify_all();
+
+        let updating = |jobs: &mut Jobs| {
+            jobs.list.iter().any(|job| job.key.is_none() && job.info.status == JobStatus::Running)
+        };
+        if updating(&mut jobs) {
+            println!("Waiting for the running jobs to finish their updates...");
+        }
+        let _jobs = self.changed.wait_while(jobs, |jobs| updating(jobs)).unwrap();
+    }
+
+    /// Returns the description of every job, most recent first.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.list
+            .iter()
+            .rev()
+            .map(|job| job.info.clone())
+            .collect()
+    }
+
+    /// Returns the description of a job.
+    pub fn get(&self, id: u64) -> Option<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.list
+            .iter()
+            .find(|job| job.info.id == id)
+            .map(|job| job.info.clone())
+    }
+
+    /// Returns the result of a succeeded job.
+    pub fn result(&self, id: u64) -> Option<String> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.list
+            .iter()
+            .find(|job| job.info.id == id)
+            .and_then(|job| job.result.clone())
+    }
+
+    /// Waits until the job changes after `version`, or `timeout` elapses.
+    ///
+    /// Returns the description and version of the job, or `None` if it does not exist.
+    pub fn wait_for_change(&self, id: u64, version: u64, timeout: Duration) -> Option<(JobInfo, u64)> {
+        let jobs = self.jobs.lock().unwrap();
+        let (jobs, _) = self.changed
+            .wait_timeout_while(jobs, timeout, |jobs| {
+                jobs.list.iter().any(|job| job.info.id == id && job.version == version)
+            })
+            .unwrap();
+        jobs.list
+            .iter()
+            .find(|job| job.info.id == id)
+            .map(|job| (job.info.clone(), job.version))
+    }
+
+    /// Applies `change` to a job; watchers are woken up when it returns `true`.
+    fn update(&self, id: u64, change: impl FnOnce(&mut Job) -> bool) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.list.iter_mut().find(|job| job.info.id == id) {
+            if change(job) {
+                job.version += 1;
+                self.changed.notify_all();
+            }
+        }
+    }
+
+    /// Drops the oldest finished jobs beyond `MAX_FINISHED_JOBS`.
+    fn prune(list: &mut Vec<Job>) {
+        let finished = list
+            .iter()
+            .filter(|job| job.is_finished())
+            .count();
+        let mut to_remove = finished.saturating_sub(MAX_FINISHED_JOBS);
+        list.retain(|job| {
+            if to_remove > 0 && job.is_finished() {
+                to_remove -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+impl Job {
+    fn is_finished(&self) -> bool {
+        matches!(self.info.status, JobStatus::Succeeded | JobStatus::Failed)
+    }
+
+    fn fail(&mut self, error: &str) {
+        self.info.status = JobStatus::Failed;
+        self.info.error = Some(error.to_string());
+        self.info.finished_at = Some(now());
+    }
+}
@@ -0,0 +1,186 @@
+//! # Predicate keep model
+//!
+//! Decides whether a predicate of a class is worth keeping, from the statistics of the predicate analysis
+//! (`KG::stat_anal_predicates`). The decision is the probability to keep returned by an ONNX classifier
+//! (`./ml/model.onnx` by default, trained in `ml/ml_training.ipynb`), loaded and optimized once at startup and
+//! shared by every thread.
+//!
+//! - The features fed to the model are the analysis columns named in its `features` metadata property (comma
+//!   separated), `frequency,uniqueness,entropy,quality,edge_rank` when it is absent.
+//! - The `keep_output` metadata property gives the output column holding the probability to keep (1 by default).
+//! - The batch dimension of the input is left free, so that all of the predicates of a class are scored in one run.
+//! - Without a model file, predicates are scored by a fallback heuristic: √frequency × mean(entropy, quality).
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use tract_onnx::prelude::*;
+
+/// Model loaded when none is given on the command line, if it exists.
+pub const DEFAULT_MODEL_PATH: &str = "./ml/model.onnx";
+
+/// Features of models without a `features` metadata property, in the order of their input columns.
+pub const DEFAULT_FEATURES: [&str; 5] = ["frequency", "uniqueness", "entropy", "quality", "edge_rank"];
+
+/// Metadata property listing the features of the model.
+const FEATURES_KEY: &str = "features";
+
+/// Metadata property giving the output column holding the probability to keep.
+const KEEP_OUTPUT_KEY: &str = "keep_output";
+
+/// Output column holding the probability to keep when the model does not say.
+const DEFAULT_KEEP_OUTPUT: usize = 1;
+
+static MODEL: OnceLock<KeepModel> = OnceLock::new();
+
+type Plan = RunnableModel<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+/// # Scorers of the predicates.
+///
+/// ## Variants:
+/// * `Onnx`: ONNX classifier read from `path`, fed with the `features` columns; `keep_output` is the output column
+///   holding the probability to keep.
+/// * `Fallback`: Heuristic used when no model is available.
+pub enum KeepModel {
+    Onnx {
+        path: String,
+        features: Vec<String>,
+        keep_output: usize,
+        plan: Plan,
+    },
+    Fallback,
+}
+
+/// Loads the model used by the rest of the explorer. Only the first call has an effect.
+///
+/// # Errors
+/// Returns a message if the model cannot be read, or its metadata is invalid.
+pub fn init(path: &str) -> Result<(), String> {
+    let model = KeepModel::load(path)?;
+    println!("Predicate keep model: {}", model.describe());
+    let _ = MODEL.set(model);
+    Ok(())
+}
+
+/// Returns the model used by the predicate analysis.
+///
+/// If `init` was not called, loads `DEFAULT_MODEL_PATH` on first use, or falls back to the heuristic when it is
+/// missing or invalid.
+pub fn get() -> &'static KeepModel {
+    MODEL.get_or_init(|| {
+        if !Path::new(DEFAULT_MODEL_PATH).exists() {
+            println!("No model found at {DEFAULT_MODEL_PATH}: predicates are scored by the fallback heuristic");
+            return KeepModel::Fallback;
+        }
+        KeepModel::load(DEFAULT_MODEL_PATH).unwrap_or_else(|e| {
+            eprintln!("{e}: predicates are scored by the fallback heuristic");
+            KeepModel::Fallback
+        })
+    })
+}
+
+/// Probability to keep a predicate according to the fallback heuristic: predicates used by most entities, with
+/// varied values, on good quality entities. Missing statistics count as 0.
+pub fn fallback_score(stats: &HashMap<String, f64>) -> f64 {
+    let stat = |name: &str| stats.get(name).copied().unwrap_or(0.0).clamp(0.0, 1.0);
+    stat("frequency").sqrt() * (stat("entropy") + stat("quality")) / 2.0
+}
+
+impl KeepModel {
+    /// Reads an ONNX model and its metadata, and optimizes it for batches of any size.
+    ///
+    /// # Errors
+    /// Returns a message if the file cannot be read or is not a valid model, or if its metadata is invalid.
+    pub fn load(path: &str) -> Result<KeepModel, String> {
+        let fail = |e: TractError| format!("Cannot load the model {path}: {e}");
+        let onnx = tract_onnx::onnx();
+        let proto = onnx.proto_model_for_path(path).map_err(fail)?;
+        let metadata = proto.metadata_props
+            .iter()
+            .map(|p| (p.key.as_str(), p.value.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let features = match metadata.get(FEATURES_KEY) {
+            Some(list) =>
+                list
+                    .split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect::<Vec<_>>(),
+            None =>
+                DEFAULT_FEATURES.iter()
+                    .map(|f| f.to_string())
+                    .collect(),
+        };
+        if features.is_empty() {
+            return Err(format!("The model {path} lists no feature"));
+        }
+        let keep_output = match metadata.get(KEEP_OUTPUT_KEY) {
+            Some(index) =>
+                index
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid {KEEP_OUTPUT_KEY} in the model {path}: {index}"))?,
+            None => DEFAULT_KEEP_OUTPUT,
+        };
+
+        let model = onnx.model_for_proto_model(&proto).map_err(fail)?;
+        let batch = model.symbols.sym("N");
+        let plan = model
+            .with_input_fact(0, f32::fact([TDim::Sym(batch), features.len().to_dim()]).into())
+            .and_then(|model| model.into_optimized())
+            .and_then(|model| model.into_runnable())
+            .map_err(fail)?;
+        Ok(KeepModel::Onnx { path: path.to_string(), features, keep_output, plan })
+    }
+
+    /// Human readable summary, shown at startup.
+    pub fn describe(&self) -> String {
+        match self {
+            KeepModel::Onnx { path, features, .. } => format!("{path} ({})", features.join(", ")),
+            KeepModel::Fallback => "fallback heuristic (√frequency × mean(entropy, quality))".to_string(),
+        }
+    }
+
+    /// Probability to keep each predicate, given its statistics, in a single run of the model.
+    ///
+    /// # Errors
+    /// Returns a message if the model fails to run or its output does not have one row per predicate.
+    pub fn predict(&self, rows: &[&HashMap<String, f64>]) -> Result<Vec<f64>, String> {
+        let (features, keep_output, plan) = match self {
+            KeepModel::Onnx { features, keep_output, plan, .. } => (features, *keep_output, plan),
+            KeepModel::Fallback => {
+                return Ok(
+                    rows
+                        .iter()
+                        .map(|stats| fallback_score(stats))
+                        .collect()
+                );
+            }
+        };
+        if rows.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let input = rows
+            .iter()
+            .flat_map(|stats| features.iter().map(|f| stats.get(f).copied().unwrap_or(0.0) as f32))
+            .collect::<Vec<_>>();
+        let input = Tensor::from_shape(&[rows.len(), features.len()], &input).map_err(|e| e.to_string())?;
+        let outputs = plan.run(tvec!(input.into())).map_err(|e| format!("The model failed: {e}"))?;
+        let output = outputs[0].as_slice::<f32>().map_err(|e| e.to_string())?;
+
+        let width = output.len() / rows.len();
+        if width == 0 || output.len() % rows.len() != 0 {
+            return Err(format!("The model returned {} values for {} predicates", output.len(), rows.len()));
+        }
+        let column = keep_output.min(width - 1);
+        Ok(
+            output
+                .chunks(width)
+                .map(|row| row[column] as f64)
+                .collect()
+        )
+    }
+}
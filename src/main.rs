@@ -1,11 +1,33 @@
 mod store;
 mod utils;
 mod item;
-
+mod routine;
+mod analysis;
+mod jobs;
+mod search;
+mod explore;
+mod page_rank;
+mod inference;
+mod conformance;
+mod shacl;
+mod shape_inference;
+mod vocabulary;
+mod keep_model;
+mod predicate_labels;
+mod feature_export;
+mod graph_export;
+mod predicate_plan;
+mod cache;
+mod profiling;
+mod void;
+mod neo4j_export;
+mod flatten;
 mod web_ui;
 
 use dotenv::dotenv;
 use clap::Parser;
+use oxigraph::io::RdfFormat;
+use web_ui::auth::Auth;
 use web_ui::server::WebServer;
 
 use crate::{ store::KG };
@@ -25,21 +47,315 @@ struct Args {
     /// Number of parts (default = 1)
     #[arg(long, default_value_t = 1)]
     nb_parts: u32,
+
+    /// Number of worker threads handling web requests (default = 4)
+    #[arg(long, default_value_t = 4)]
+    workers: usize,
+
+    /// Serve the explorer in read-only mode, rejecting every operation modifying the store
+    #[arg(long)]
+    read_only: bool,
+
+    /// File listing the users and API tokens allowed to use the explorer, with their roles
+    /// (authentication is disabled without it)
+    #[arg(long)]
+    auth_file: Option<String>,
+
+    /// Local copy of the schema.org vocabulary (.nt, .ttl or .rdf)
+    /// (default = ./data/schemaorg-current-https.nt, if it exists)
+    #[arg(long)]
+    vocabulary: Option<String>,
+
+    /// ONNX model deciding which predicates to keep in the predicate analysis
+    /// (default = ./ml/model.onnx, if it exists; a heuristic is used without a model)
+    #[arg(long)]
+    model: Option<String>,
+
+    /// Write the training table of the predicate keep model (features and curator labels of the predicates of the
+    /// classes reachable from --start-with) to this file instead of serving the dataset (.json for columns, CSV otherwise)
+    #[arg(long)]
+    export_features: Option<String>,
+
+    /// Write the VoID description of the dataset (Turtle) to this file instead of serving the dataset
+    #[arg(long)]
+    export_void: Option<String>,
+
+    /// Write the class relations graph, with the probabilities of its relations and the PageRank of its classes, to
+    /// this file instead of serving the dataset (.graphml for GraphML, .dot or .gv for Graphviz, JSON node-link otherwise)
+    #[arg(long)]
+    export_class_graph: Option<String>,
+
+    /// Write the dataset as a property graph for Neo4j to this directory instead of serving it (nodes.csv and
+    /// relationships.csv for neo4j-admin import, or import.cypher with --neo4j-format cypher)
+    #[arg(long)]
+    export_neo4j: Option<String>,
+
+    /// Format of --export-neo4j: csv or cypher
+    #[arg(long, default_value = "csv")]
+    neo4j_format: String,
+
+    /// Comma separated classes whose instances --export-neo4j exports, with their subclasses, e.g. Book,Person
+    /// (default = every entity)
+    #[arg(long)]
+    export_types: Option<String>,
+
+    /// Comma separated predicates --export-neo4j exports, e.g. name,author (default = every predicate)
+    #[arg(long)]
+    export_predicates: Option<String>,
+
+    /// Comma separated namespaces of the predicates and types --export-neo4j exports, e.g. http://schema.org/
+    #[arg(long)]
+    export_namespaces: Option<String>,
+
+    /// Write the instances of --flatten-class as a table, one row per entity, to this file instead of serving the
+    /// dataset (.jsonl for JSON Lines, .parquet for Parquet, CSV otherwise)
+    #[arg(long)]
+    flatten: Option<String>,
+
+    /// Class flattened by --flatten, e.g. Book
+    #[arg(long)]
+    flatten_class: Option<String>,
+
+    /// Comma separated predicate paths of the columns of --flatten, e.g. name,author/name,isbn
+    /// (default = the paths suggested by the predicate analysis or the profile of the class)
+    #[arg(long)]
+    flatten_paths: Option<String>,
+
+    /// Handling of the values sharing a property or cell: array, first or join for --export-neo4j (default array),
+    /// first, join or explode for --flatten (default first)
+    #[arg(long)]
+    multi_value: Option<String>,
+
+    /// Class the analyses start from, e.g. Book (required by --export-features)
+    #[arg(long)]
+    start_with: Option<String>,
+
+    /// Validate the dataset against the SHACL shapes of this file (repeatable) instead of serving it;
+    /// exits with status 1 if the data does not conform
+    #[arg(long)]
+    shacl: Vec<String>,
+
+    /// Named graph of the store holding SHACL shapes to validate the dataset against (same as --shacl)
+    #[arg(long)]
+    shacl_graph: Option<String>,
+
+    /// File to write the SHACL validation report to (Turtle, or N-Triples for .nt), instead of the standard output
+    #[arg(long)]
+    shacl_report: Option<String>,
 }
 
 fn main() {
     dotenv().ok();
     let args = Args::parse();
-    if args.wdc {
+    let auth = args.auth_file.as_deref().map(|path| {
+        Auth::load(path).unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        })
+    });
+    // The vocabulary is needed before loading, to canonicalize the terms of new datasets
+    let vocabulary_path = args.vocabulary
+        .clone()
+        .unwrap_or_else(|| vocabulary::DEFAULT_VOCABULARY_PATH.to_string());
+    if args.vocabulary.is_some() || std::path::Path::new(&vocabulary_path).exists() {
+        if let Err(e) = vocabulary::init(&vocabulary_path) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    } else {
+        println!("No schema.org vocabulary found at {vocabulary_path}: terms are kept as found in the dataset");
+    }
+    match &args.model {
+        Some(path) => {
+            if let Err(e) = keep_model::init(path) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+        }
+        None => {
+            println!("Predicate keep model: {}", keep_model::get().describe());
+        }
+    }
+    let kg = if args.wdc {
         // If wdc flag is there, download and load from web data commons
-
-        let kg = KG::from_wdc(&args.dataset, args.nb_parts);
-        let w = WebServer::new(kg, 8080);
-        w.serve();
+        KG::from_wdc(&args.dataset, args.nb_parts)
     } else {
         // Otherwise load from the filepath specified as the dataset
-        let kg = KG::from_file(&args.dataset);
-        let w = WebServer::new(kg, 8080);
-        w.serve();
+        KG::from_file(&args.dataset)
+    };
+    if let Some(path) = &args.export_features {
+        let code = match export_features(&kg, path, args.start_with.as_deref()) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
+    }
+    if let Some(path) = &args.export_void {
+        let code = match kg.export_void(path) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
+    }
+    if let Some(path) = &args.export_class_graph {
+        let code = match export_class_graph(&kg, path) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
     }
+    if let Some(directory) = &args.export_neo4j {
+        let code = match export_neo4j(&kg, directory, &args) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
+    }
+    if let Some(path) = &args.flatten {
+        let code = match flatten_class(&kg, path, &args) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("{e}");
+                1
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
+    }
+    if !args.shacl.is_empty() || args.shacl_graph.is_some() {
+        let code = match validate(&kg, &args) {
+            Ok(true) => 0,
+            Ok(false) => 1,
+            Err(e) => {
+                eprintln!("{e}");
+                2
+            }
+        };
+        kg.flush();
+        std::process::exit(code);
+    }
+    let w = WebServer::new(kg, 8080, args.workers, args.read_only, auth);
+    w.serve();
+}
+
+/// Validates the dataset against the SHACL shapes given on the command line and writes the report.
+///
+/// Returns whether the data conforms, so that cleanup pipelines can gate on the exit status.
+fn validate(kg: &KG, args: &Args) -> Result<bool, String> {
+    let shapes = shacl::Shapes::load(kg, &args.shacl, args.shacl_graph.as_deref())?;
+    let report = kg.validate_shacl(&shapes)?;
+    let format = match args.shacl_report.as_deref() {
+        Some(path) if path.ends_with(".nt") => RdfFormat::NTriples,
+        _ => RdfFormat::Turtle,
+    };
+    let rdf = report.to_rdf(format)?;
+    match &args.shacl_report {
+        Some(path) => std::fs::write(path, rdf).map_err(|e| format!("Cannot write the report to {path}: {e}"))?,
+        None => print!("{rdf}"),
+    }
+    eprintln!(
+        "{} shapes, {} focus nodes: {} violations, {} warnings, {} infos",
+        report.shapes,
+        report.focus_nodes,
+        report.count(shacl::Severity::Violation),
+        report.count(shacl::Severity::Warning),
+        report.count(shacl::Severity::Info)
+    );
+    Ok(report.conforms)
+}
+
+/// Writes the training table of the predicate keep model to `path`.
+fn export_features(kg: &KG, path: &str, start_with: Option<&str>) -> Result<(), String> {
+    let start_with = start_with.ok_or("--export-features needs --start-with")?;
+    let rows = kg.training_table(start_with);
+    let table = feature_export::export_training_table(&rows, feature_export::ExportFormat::from_path(path))?;
+    std::fs::write(path, table).map_err(|e| format!("Cannot write the features to {path}: {e}"))?;
+    eprintln!(
+        "{} predicates exported to {path}, {} labelled",
+        rows.len(),
+        rows.iter().filter(|r| r.label.is_some()).count()
+    );
+    Ok(())
+}
+
+/// Writes the class relations graph to `path`, ranked with the default PageRank options.
+fn export_class_graph(kg: &KG, path: &str) -> Result<(), String> {
+    let options = page_rank::PageRankOptions::default();
+    let graph = kg.class_relations_export(&options);
+    let data = graph_export::export_class_graph(&graph, graph_export::GraphFormat::from_path(path), &options)?;
+    std::fs::write(path, data).map_err(|e| format!("Cannot write the class graph to {path}: {e}"))?;
+    eprintln!("{} classes and {} relations exported to {path}", graph.nodes.len(), graph.edges.len());
+    Ok(())
+}
+
+/// Writes the property graph selected by the export filters to `directory`, in the format of `--neo4j-format`.
+fn export_neo4j(kg: &KG, directory: &str, args: &Args) -> Result<(), String> {
+    let format = neo4j_export::Neo4jFormat::parse(&args.neo4j_format).ok_or_else(||
+        format!("Unknown Neo4j export format: {}", args.neo4j_format)
+    )?;
+    let options = neo4j_export::Neo4jOptions::parse(
+        args.export_types.as_deref(),
+        args.export_predicates.as_deref(),
+        args.export_namespaces.as_deref(),
+        args.multi_value.as_deref()
+    )?;
+    let graph = kg.property_graph(&options).map_err(|e| e.to_string())?;
+    std::fs::create_dir_all(directory).map_err(|e| format!("Cannot create {directory}: {e}"))?;
+    let files = match format {
+        neo4j_export::Neo4jFormat::AdminImport =>
+            vec![
+                ("nodes.csv", graph.nodes_csv(options.multi_value)),
+                ("relationships.csv", graph.relationships_csv())
+            ],
+        neo4j_export::Neo4jFormat::Cypher => vec![("import.cypher", graph.cypher(options.multi_value))],
+    };
+    for (name, data) in files {
+        let path = std::path::Path::new(directory).join(name);
+        std::fs::write(&path, data).map_err(|e| format!("Cannot write {}: {e}", path.display()))?;
+    }
+    eprintln!("{} nodes and {} relationships exported to {directory}", graph.nodes.len(), graph.relationships.len());
+    Ok(())
+}
+
+/// Writes the instances of `--flatten-class` to `path`, in the format of the file extension.
+fn flatten_class(kg: &KG, path: &str, args: &Args) -> Result<(), String> {
+    let class = args.flatten_class.as_deref().ok_or("--flatten needs --flatten-class")?;
+    let paths = match &args.flatten_paths {
+        Some(paths) => vec![paths.clone()],
+        None => {
+            let class = vocabulary::resolve_term(class)?;
+            kg.suggested_flatten_paths(&class).map_err(|e| e.to_string())?
+        }
+    };
+    let options = flatten::FlattenOptions::parse(class, &paths, args.multi_value.as_deref())?;
+    let table = kg.flatten_class(&options).map_err(|e| e.to_string())?;
+    let data = table.export(flatten::FlattenFormat::from_path(path))?;
+    std::fs::write(path, data).map_err(|e| format!("Cannot write the table to {path}: {e}"))?;
+    eprintln!(
+        "{} rows of {} columns exported to {path} ({})",
+        table.rows.len(),
+        table.columns.len(),
+        options.paths
+            .iter()
+            .map(|p| p.column.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
 }
@@ -0,0 +1,476 @@
+//! # Property graph export
+//!
+//! Converts the entities of the store into a property graph, for Neo4j and other openCypher databases.
+//!
+//! - Every subject becomes a node labelled `Resource` and with the local names of its `rdf:type`s, its IRI in the
+//!   `iri` property.
+//! - Literal values become node properties, keyed by the local name of their predicate. With
+//!   `MultiValue::Array`, properties having several values on some node become arrays; `MultiValue::First` keeps the
+//!   first value, and `MultiValue::Join` joins them into a single string.
+//! - IRI values become relationships typed by the local name of their predicate, when the object is an exported node,
+//!   and `iri` properties otherwise (so that no relationship points to a missing node).
+//!
+//! Local names are the canonical names of the schema.org terms (see `vocabulary`), and the last segment of the IRI
+//! of other terms; values of predicates sharing a local name go to the same property.
+//!
+//! `Neo4jFormat::AdminImport` writes `nodes.csv` and `relationships.csv` in the layout of `neo4j-admin database
+//! import` (default `;` array delimiter), `Neo4jFormat::Cypher` a script of `CREATE` statements.
+
+use std::collections::{ BTreeMap, BTreeSet, HashMap, HashSet };
+
+use oxigraph::model::Term;
+
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+use crate::vocabulary::{ self, resolve_term, short_name };
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// Label of every exported node, carrying the unique `iri` property.
+pub const RESOURCE_LABEL: &str = "Resource";
+
+/// Separator of the values of `MultiValue::Join`.
+const JOIN_SEPARATOR: &str = " | ";
+
+/// # Formats of the export.
+///
+/// ## Variants:
+/// * `AdminImport`: `nodes.csv` and `relationships.csv`, for `neo4j-admin database import`.
+/// * `Cypher`: openCypher `CREATE` statements.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Neo4jFormat {
+    AdminImport,
+    Cypher,
+}
+
+impl Neo4jFormat {
+    /// Parses a format name (`csv` or `cypher`).
+    pub fn parse(name: &str) -> Option<Neo4jFormat> {
+        match name {
+            "csv" | "admin_import" => Some(Neo4jFormat::AdminImport),
+            "cypher" => Some(Neo4jFormat::Cypher),
+            _ => None,
+        }
+    }
+}
+
+/// # Handling of the properties having several values on a node.
+///
+/// ## Variants:
+/// * `Array`: An array of every value.
+/// * `First`: The first value only.
+/// * `Join`: The values joined into a single string.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MultiValue {
+    Array,
+    First,
+    Join,
+}
+
+impl MultiValue {
+    /// Parses a strategy name (`array`, `first` or `join`).
+    pub fn parse(name: &str) -> Option<MultiValue> {
+        match name {
+            "array" => Some(MultiValue::Array),
+            "first" => Some(MultiValue::First),
+            "join" => Some(MultiValue::Join),
+            _ => None,
+        }
+    }
+}
+
+/// Selection of the exported data. Empty lists select everything.
+///
+/// ## Fields
+/// * `types` - Classes whose instances are exported (their schema.org subclasses too), in their SPARQL form.
+/// * `predicates` - Predicates exported as properties and relationships.
+/// * `namespaces` - Namespaces of the exported predicates and labels (`http://schema.org/` also matches the https
+///   terms).
+/// * `multi_value` - Handling of the multi-valued properties.
+#[derive(Clone, Debug)]
+pub struct Neo4jOptions {
+    pub types: Vec<String>,
+    pub predicates: Vec<String>,
+    pub namespaces: Vec<String>,
+    pub multi_value: MultiValue,
+}
+
+impl Neo4jOptions {
+    /// Reads the filters of the command line or of the API: comma separated lists of types, predicates and
+    /// namespaces, and the name of the multi-value strategy (`array` by default).
+    ///
+    /// # Errors
+    /// Returns a message if a term is invalid or the strategy unknown.
+    pub fn parse(
+        types: Option<&str>,
+        predicates: Option<&str>,
+        namespaces: Option<&str>,
+        multi_value: Option<&str>
+    ) -> Result<Neo4jOptions, String> {
+        let terms = |list: Option<&str>| {
+            list.unwrap_or_default()
+                .split(',')
+                .filter(|t| !t.trim().is_empty())
+                .map(resolve_term)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let namespaces = namespaces
+            .unwrap_or_default()
+            .split(',')
+            .map(|n| n.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+            .filter(|n| !n.is_empty())
+            .collect();
+        let multi_value = match multi_value.filter(|m| !m.is_empty()) {
+            None => MultiValue::Array,
+            Some(name) => MultiValue::parse(name).ok_or_else(|| format!("Unknown multi-value strategy: {name}"))?,
+        };
+        Ok(Neo4jOptions { types: terms(types)?, predicates: terms(predicates)?, namespaces, multi_value })
+    }
+
+    /// Whether a predicate or class (in its SPARQL form) is in one of the namespaces.
+    fn in_namespaces(&self, term: &str) -> bool {
+        let iri = term.trim_start_matches('<').trim_end_matches('>');
+        self.namespaces.is_empty() ||
+            self.namespaces.iter().any(|ns| {
+                iri.starts_with(ns.as_str()) ||
+                    (ns.trim_end_matches('/').ends_with("schema.org") && vocabulary::local_name(iri).is_some())
+            })
+    }
+
+    fn exports_predicate(&self, predicate: &str) -> bool {
+        (self.predicates.is_empty() || self.predicates.iter().any(|p| p == predicate)) && self.in_namespaces(predicate)
+    }
+
+    /// The types to export and their schema.org subclasses.
+    fn expanded_types(&self) -> BTreeSet<String> {
+        let mut types = BTreeSet::new();
+        let mut queue = self.types.clone();
+        while let Some(class) = queue.pop() {
+            if !types.insert(class.clone()) {
+                continue;
+            }
+            if let Some(vocabulary) = vocabulary::get() {
+                queue.extend(
+                    vocabulary
+                        .children(&class)
+                        .iter()
+                        .map(|c| c.iri())
+                );
+            }
+        }
+        types
+    }
+}
+
+/// # Types of the property values.
+///
+/// ## Variants:
+/// * `Long`, `Double`, `Boolean`: Literals of the matching XSD datatypes.
+/// * `String`: Any other literal, and IRIs.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ValueType {
+    Long,
+    Double,
+    Boolean,
+    String,
+}
+
+impl ValueType {
+    fn of(term: &Term) -> ValueType {
+        let Term::Literal(literal) = term else {
+            return ValueType::String;
+        };
+        match literal.datatype().as_str().strip_prefix(XSD).unwrap_or_default() {
+            "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger" | "positiveInteger" | "unsignedInt" =>
+                ValueType::Long,
+            "decimal" | "double" | "float" => ValueType::Double,
+            "boolean" => ValueType::Boolean,
+            _ => ValueType::String,
+        }
+    }
+
+    /// Type of a property whose values have the types `self` and `other`.
+    fn merge(self, other: ValueType) -> ValueType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (ValueType::Long, ValueType::Double) | (ValueType::Double, ValueType::Long) => ValueType::Double,
+            _ => ValueType::String,
+        }
+    }
+
+    fn csv_name(&self) -> &'static str {
+        match self {
+            ValueType::Long => "long",
+            ValueType::Double => "double",
+            ValueType::Boolean => "boolean",
+            ValueType::String => "string",
+        }
+    }
+}
+
+/// Value of a property, as written in the dataset (literal value, or IRI without brackets).
+fn value_of(term: &Term) -> String {
+    match term {
+        Term::Literal(literal) => literal.value().to_string(),
+        Term::NamedNode(node) => node.as_str().to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// A node of the property graph.
+pub struct PgNode {
+    pub iri: String,
+    pub labels: BTreeSet<String>,
+    pub properties: BTreeMap<String, Vec<String>>,
+}
+
+/// A relationship of the property graph, between the IRIs of two nodes.
+pub struct PgRelationship {
+    pub start: String,
+    pub end: String,
+    pub kind: String,
+    pub predicate: String,
+}
+
+/// A property of the nodes, its type and whether it is an array.
+pub struct PgProperty {
+    pub name: String,
+    pub value_type: ValueType,
+    pub array: bool,
+}
+
+/// The property graph built from the store.
+pub struct PropertyGraph {
+    pub nodes: Vec<PgNode>,
+    pub relationships: Vec<PgRelationship>,
+    pub properties: Vec<PgProperty>,
+}
+
+impl KG {
+    /// Builds the property graph of the entities selected by `options`.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of the query listing the triples.
+    pub fn property_graph(&self, options: &Neo4jOptions) -> Result<PropertyGraph, StoreError> {
+        let types = options.expanded_types();
+        let query = if types.is_empty() {
+            "SELECT ?s ?p ?o WHERE { ?s ?p ?o }".to_string()
+        } else {
+            let values = types.iter().cloned().collect::<Vec<_>>().join(" ");
+            format!("SELECT DISTINCT ?s ?p ?o WHERE {{ VALUES ?t {{ {values} }} ?s a ?t . ?s ?p ?o }}")
+        };
+        jobs::log("Listing the triples of the exported entities".to_string());
+        let rows = self.query(&query)?;
+
+        // Subjects and their triples, in the order they were first met
+        let mut order = vec![];
+        let mut triples: HashMap<String, Vec<(String, Term)>> = HashMap::new();
+        for row in &rows {
+            let (Some(s), Some(p), Some(o)) = (row.get("s"), row.get("p"), row.get("o")) else {
+                continue;
+            };
+            let subject = value_of(s);
+            triples
+                .entry(subject.clone())
+                .or_insert_with(|| {
+                    order.push(subject);
+                    vec![]
+                })
+                .push((p.to_string(), o.clone()));
+        }
+        let exported = order.iter().cloned().collect::<HashSet<_>>();
+
+        jobs::log(format!("Converting {} entities", order.len()));
+        let mut nodes = vec![];
+        let mut relationships = vec![];
+        let mut property_types: BTreeMap<String, (ValueType, bool)> = BTreeMap::new();
+        let total = order.len() as u64;
+        for (i, iri) in order.into_iter().enumerate() {
+            if i % 10_000 == 0 {
+                jobs::progress(i as u64, total);
+            }
+            let mut node = PgNode {
+                iri: iri.clone(),
+                labels: BTreeSet::from([RESOURCE_LABEL.to_string()]),
+                properties: BTreeMap::new(),
+            };
+            for (predicate, object) in triples.remove(&iri).unwrap_or_default() {
+                if predicate == RDF_TYPE {
+                    if let Term::NamedNode(class) = &object {
+                        if options.in_namespaces(&object.to_string()) {
+                            node.labels.insert(short_name(class.as_str()));
+                        }
+                    }
+                    continue;
+                }
+                if !options.exports_predicate(&predicate) {
+                    continue;
+                }
+                let name = short_name(&predicate);
+                if let Term::NamedNode(target) = &object {
+                    if exported.contains(target.as_str()) {
+                        relationships.push(PgRelationship {
+                            start: iri.clone(),
+                            end: target.as_str().to_string(),
+                            kind: name,
+                            predicate: predicate.trim_matches(['<', '>']).to_string(),
+                        });
+                        continue;
+                    }
+                }
+                let value_type = ValueType::of(&object);
+                let values = node.properties.entry(name.clone()).or_default();
+                values.push(value_of(&object));
+                let entry = property_types.entry(name).or_insert((value_type, false));
+                entry.0 = entry.0.merge(value_type);
+                entry.1 |= values.len() > 1;
+            }
+            nodes.push(node);
+        }
+        jobs::progress(total, total);
+
+        let properties = property_types
+            .into_iter()
+            .filter(|(name, _)| name != "iri")
+            .map(|(name, (value_type, multi))| {
+                // Joined values are strings
+                let value_type = if multi && options.multi_value == MultiValue::Join { ValueType::String } else { value_type };
+                PgProperty { name, value_type, array: multi && options.multi_value == MultiValue::Array }
+            })
+            .collect();
+        Ok(PropertyGraph { nodes, relationships, properties })
+    }
+}
+
+/// Quotes a CSV field when it contains a separator, a quote or a line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Quotes a Cypher string.
+fn cypher_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'").replace('\n', "\\n").replace('\r', "\\r"))
+}
+
+/// Quotes a Cypher label, property key or relationship type.
+fn cypher_name(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
+
+impl PropertyGraph {
+    /// Values of a property of a node, reduced according to `multi_value`.
+    fn values<'a>(&self, node: &'a PgNode, property: &PgProperty, multi_value: MultiValue) -> Option<Vec<&'a str>> {
+        let values = node.properties.get(&property.name)?;
+        Some(match multi_value {
+            MultiValue::Array => values.iter().map(String::as_str).collect(),
+            _ => values.iter().take(1).map(String::as_str).collect(),
+        })
+    }
+
+    fn joined(&self, node: &PgNode, property: &PgProperty, multi_value: MultiValue) -> Option<String> {
+        match multi_value {
+            MultiValue::Join => node.properties.get(&property.name).map(|values| values.join(JOIN_SEPARATOR)),
+            _ => None,
+        }
+    }
+
+    /// `nodes.csv` of `neo4j-admin database import`.
+    pub fn nodes_csv(&self, multi_value: MultiValue) -> String {
+        let mut header = vec!["iri:ID".to_string(), ":LABEL".to_string()];
+        for property in &self.properties {
+            header.push(
+                csv_field(
+                    &format!("{}:{}{}", property.name, property.value_type.csv_name(), if property.array { "[]" } else { "" })
+                )
+            );
+        }
+        let mut csv = header.join(",") + "\n";
+        for node in &self.nodes {
+            let mut fields = vec![csv_field(&node.iri), csv_field(&node.labels.iter().cloned().collect::<Vec<_>>().join(";"))];
+            for property in &self.properties {
+                let value = match self.joined(node, property, multi_value) {
+                    Some(joined) => joined,
+                    None =>
+                        self
+                            .values(node, property, multi_value)
+                            .map(|values| values.join(";"))
+                            .unwrap_or_default(),
+                };
+                fields.push(csv_field(&value));
+            }
+            csv += &(fields.join(",") + "\n");
+        }
+        csv
+    }
+
+    /// `relationships.csv` of `neo4j-admin database import`.
+    pub fn relationships_csv(&self) -> String {
+        let mut csv = ":START_ID,:END_ID,:TYPE,predicate\n".to_string();
+        for relationship in &self.relationships {
+            csv += &format!(
+                "{},{},{},{}\n",
+                csv_field(&relationship.start),
+                csv_field(&relationship.end),
+                csv_field(&relationship.kind),
+                csv_field(&relationship.predicate)
+            );
+        }
+        csv
+    }
+
+    /// openCypher script creating the nodes, then the relationships (matched on the unique `iri` of the nodes).
+    pub fn cypher(&self, multi_value: MultiValue) -> String {
+        let literal = |value: &str, value_type: ValueType| {
+            match value_type {
+                ValueType::Long if value.trim().parse::<i64>().is_ok() => value.trim().to_string(),
+                ValueType::Double if value.trim().parse::<f64>().is_ok_and(|v| v.is_finite()) => value.trim().to_string(),
+                ValueType::Boolean if value == "true" || value == "false" => value.to_string(),
+                _ => cypher_string(value),
+            }
+        };
+
+        let mut script = format!(
+            "CREATE CONSTRAINT IF NOT EXISTS FOR (n:{RESOURCE_LABEL}) REQUIRE n.iri IS UNIQUE;\n"
+        );
+        for node in &self.nodes {
+            let labels = node.labels
+                .iter()
+                .map(|l| format!(":{}", cypher_name(l)))
+                .collect::<String>();
+            let mut properties = vec![format!("iri: {}", cypher_string(&node.iri))];
+            for property in &self.properties {
+                let value = match self.joined(node, property, multi_value) {
+                    Some(joined) => cypher_string(&joined),
+                    None => {
+                        let Some(values) = self.values(node, property, multi_value) else {
+                            continue;
+                        };
+                        let values = values
+                            .iter()
+                            .map(|v| literal(v, property.value_type))
+                            .collect::<Vec<_>>();
+                        if property.array { format!("[{}]", values.join(", ")) } else { values.join("") }
+                    }
+                };
+                properties.push(format!("{}: {value}", cypher_name(&property.name)));
+            }
+            script += &format!("CREATE (n{labels} {{{}}});\n", properties.join(", "));
+        }
+        for relationship in &self.relationships {
+            script += &format!(
+                "MATCH (a:{RESOURCE_LABEL} {{iri: {}}}), (b:{RESOURCE_LABEL} {{iri: {}}}) CREATE (a)-[:{} {{predicate: {}}}]->(b);\n",
+                cypher_string(&relationship.start),
+                cypher_string(&relationship.end),
+                cypher_name(&relationship.kind),
+                cypher_string(&relationship.predicate)
+            );
+        }
+        script
+    }
+}
+
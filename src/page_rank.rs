@@ -0,0 +1,348 @@
+//! # PageRank of the class relations graph
+//!
+//! Ranks the classes of the class relations graph for the class analysis (`KG::stat_anal_types`), and their
+//! predicates (edge ranks) for the predicate analysis.
+//!
+//! The rank is personalized: walks start at a class picked in proportion to its number of instances, follow one of
+//! its relations with the probability `damping` (picked by its share of the relations of the class, see
+//! `calculate_probabilities_for_graph`), and restart otherwise, as well as when they reach a literal or a class
+//! without relations. The rank of a class is the share of the visits it gets, and the edge rank of a predicate the
+//! share of the transitions going through it, per class.
+//!
+//! - `PageRankMethod::PowerIteration` computes the ranks exactly, iterating until they move by less than the
+//!   tolerance (L1 norm).
+//! - `PageRankMethod::Sampling` estimates them with random walks drawn from a seeded generator, so that runs with
+//!   the same seed give the same ranks.
+
+use std::collections::HashMap;
+
+use petgraph::graph::{ EdgeIndex, NodeIndex };
+use petgraph::visit::EdgeRef;
+use petgraph::Direction::{ self, Outgoing };
+use petgraph::Graph;
+use rand::rngs::StdRng;
+use rand::{ Rng, SeedableRng };
+use serde::Serialize;
+
+use crate::jobs;
+use crate::utils::choice;
+
+/// Damping factor when none is given.
+pub const DEFAULT_DAMPING: f64 = 0.85;
+
+/// Convergence tolerance of the power iteration when none is given.
+pub const DEFAULT_TOLERANCE: f64 = 1e-9;
+
+/// Random walks of the sampling mode when no number is given.
+pub const DEFAULT_WALKS: usize = 10_000;
+
+/// Seed of the sampling mode when none is given.
+pub const DEFAULT_SEED: u64 = 42;
+
+/// Iterations after which the power iteration stops, converged or not.
+const MAX_ITERATIONS: usize = 1000;
+
+/// Steps after which a random walk stops, in case the damping is close to 1.
+const MAX_WALK_LENGTH: usize = 1000;
+
+/// Node of the class relations graph gathering the literals.
+const LITERAL: &str = "Literal";
+
+/// Class relations graph: classes, and relations weighted by (predicate, count, outgoing share, incoming share).
+type RelationGraph = Graph<String, (String, f64, Option<f64>, Option<f64>)>;
+
+/// # Ways of computing the ranks.
+///
+/// ## Variants:
+/// * `PowerIteration`: Exact ranks, by power iteration.
+/// * `Sampling`: Estimate from `walks` random walks, drawn from a generator seeded with `seed`.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum PageRankMethod {
+    PowerIteration,
+    Sampling {
+        walks: usize,
+        seed: u64,
+    },
+}
+
+/// Parameters of the PageRank.
+///
+/// ## Fields
+/// * `method` - Exact computation or sampling.
+/// * `damping` - Probability to follow a relation rather than restart, in `[0, 1)`.
+/// * `tolerance` - The power iteration stops once the ranks move by less than this.
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct PageRankOptions {
+    #[serde(flatten)]
+    pub method: PageRankMethod,
+    pub damping: f64,
+    pub tolerance: f64,
+}
+
+impl Default for PageRankOptions {
+    fn default() -> Self {
+        PageRankOptions {
+            method: PageRankMethod::PowerIteration,
+            damping: DEFAULT_DAMPING,
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+}
+
+impl PageRankOptions {
+    /// Human readable summary, shown next to the results.
+    pub fn describe(&self) -> String {
+        match self.method {
+            PageRankMethod::PowerIteration =>
+                format!("power iteration, damping {}, tolerance {:e}", self.damping, self.tolerance),
+            PageRankMethod::Sampling { walks, seed } =>
+                format!("{walks} random walks (seed {seed}), damping {}", self.damping),
+        }
+    }
+}
+
+/// Ranks of the classes, and edge ranks of their predicates (class → predicate → rank).
+pub type PageRanks = (HashMap<String, f64>, HashMap<String, HashMap<String, f64>>);
+
+/// Computes the ranks of the classes of `graph`, following the relations in `direction` (`Incoming` for the
+/// reverse PageRank).
+///
+/// `node_counts` gives the number of instances of every class; the edge shares must have been computed by
+/// `calculate_probabilities_for_graph`.
+pub fn page_rank(
+    graph: &RelationGraph,
+    node_counts: &HashMap<String, f64>,
+    direction: Direction,
+    options: &PageRankOptions
+) -> PageRanks {
+    match options.method {
+        PageRankMethod::PowerIteration => power_iteration(graph, node_counts, direction, options),
+        PageRankMethod::Sampling { walks, seed } =>
+            sampling(graph, node_counts, direction, options.damping, walks, seed),
+    }
+}
+
+/// Share of the relation `edge` among the relations of its class, in `direction`.
+fn share(graph: &RelationGraph, edge: EdgeIndex, direction: Direction) -> f64 {
+    let (_, _, outgoing, incoming) = &graph[edge];
+    (if direction == Outgoing { *outgoing } else { *incoming }).unwrap_or(0.0)
+}
+
+/// Class reached by following `edge` in `direction`.
+fn follow(graph: &RelationGraph, edge: EdgeIndex, direction: Direction) -> NodeIndex {
+    let (source, target) = graph.edge_endpoints(edge).unwrap();
+    if direction == Outgoing { target } else { source }
+}
+
+fn power_iteration(
+    graph: &RelationGraph,
+    node_counts: &HashMap<String, f64>,
+    direction: Direction,
+    options: &PageRankOptions
+) -> PageRanks {
+    let literal = graph.node_indices().find(|n| graph[*n] == LITERAL);
+
+    // Where the walks (re)start, in proportion to the instance counts
+    let mut restart = graph
+        .node_indices()
+        .map(|n| if Some(n) == literal { 0.0 } else { node_counts.get(&graph[n]).copied().unwrap_or(0.0) })
+        .collect::<Vec<_>>();
+    let total = restart.iter().sum::<f64>();
+    if total > 0.0 {
+        restart.iter_mut().for_each(|r| *r /= total);
+    }
+
+    // (from, to, probability) of every transition, in the order of the edges
+    let transitions = graph
+        .edge_indices()
+        .map(|e| {
+            let from = follow(graph, e, direction.opposite());
+            let to = follow(graph, e, direction);
+            (from.index(), to, share(graph, e, direction))
+        })
+        .collect::<Vec<_>>();
+
+    let mut rank = restart.clone();
+    let mut iterations = 0;
+    let mut delta = f64::INFINITY;
+    while iterations < MAX_ITERATIONS && delta >= options.tolerance {
+        let mut next = vec![0.0; rank.len()];
+        let mut followed = 0.0;
+        for (from, to, probability) in &transitions {
+            if Some(*to) != literal {
+                let flow = options.damping * rank[*from] * probability;
+                next[to.index()] += flow;
+                followed += flow;
+            }
+        }
+        // Walks that stop (restart, dead ends, literals) start over
+        let restarted = rank.iter().sum::<f64>() - followed;
+        for (r, start) in next.iter_mut().zip(&restart) {
+            *r += restarted * start;
+        }
+        delta = next
+            .iter()
+            .zip(&rank)
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        rank = next;
+        iterations += 1;
+    }
+    jobs::log(format!("PageRank: {iterations} iterations, last change {delta:e}"));
+
+    let mut edge_rank: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    let mut total_flow = 0.0;
+    for (e, (from, _, probability)) in graph.edge_indices().zip(&transitions) {
+        let flow = options.damping * rank[*from] * probability;
+        let class = &graph[NodeIndex::new(*from)];
+        *edge_rank.entry(class.clone()).or_default().entry(graph[e].0.clone()).or_default() += flow;
+        total_flow += flow;
+    }
+    if total_flow > 0.0 {
+        edge_rank
+            .values_mut()
+            .flat_map(|ranks| ranks.values_mut())
+            .for_each(|r| *r /= total_flow);
+    }
+
+    let page_rank = graph
+        .node_indices()
+        .map(|n| (graph[n].clone(), rank[n.index()]))
+        .collect();
+    (page_rank, edge_rank)
+}
+
+fn sampling(
+    graph: &RelationGraph,
+    node_counts: &HashMap<String, f64>,
+    direction: Direction,
+    damping: f64,
+    walks: usize,
+    seed: u64
+) -> PageRanks {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let literal = graph.node_indices().find(|n| graph[*n] == LITERAL);
+    let starts = graph
+        .node_indices()
+        .filter(|n| Some(*n) != literal)
+        .filter_map(|n| node_counts.get(&graph[n]).map(|count| (n, *count)))
+        .collect::<HashMap<_, _>>();
+
+    let mut visits: HashMap<NodeIndex, f64> = HashMap::new();
+    let mut transitions: HashMap<(NodeIndex, String), f64> = HashMap::new();
+    for _ in 0..walks {
+        let Some(mut current) = choice(&starts, &mut rng) else {
+            break;
+        };
+        for _ in 0..MAX_WALK_LENGTH {
+            *visits.entry(current).or_default() += 1.0;
+            if rng.random::<f64>() >= damping {
+                break;
+            }
+            let edges = graph
+                .edges_directed(current, direction)
+                .map(|e| (e.id(), share(graph, e.id(), direction)))
+                .collect::<HashMap<_, _>>();
+            if edges.is_empty() {
+                break;
+            }
+            let Some(edge) = choice(&edges, &mut rng) else {
+                break;
+            };
+            *transitions.entry((current, graph[edge].0.clone())).or_default() += 1.0;
+            current = follow(graph, edge, direction);
+            if Some(current) == literal {
+                break;
+            }
+        }
+    }
+
+    let total_visits = visits.values().sum::<f64>().max(1.0);
+    let page_rank = graph
+        .node_indices()
+        .map(|n| (graph[n].clone(), visits.get(&n).copied().unwrap_or(0.0) / total_visits))
+        .collect();
+    let total_transitions = transitions.values().sum::<f64>().max(1.0);
+    let mut edge_rank: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    for ((class, predicate), count) in transitions {
+        edge_rank
+            .entry(graph[class].clone())
+            .or_default()
+            .insert(predicate, count / total_transitions);
+    }
+    (page_rank, edge_rank)
+}
+
+#[cfg(test)]
+mod tests {
+    use petgraph::Direction::Incoming;
+
+    use super::*;
+    use crate::utils::calculate_probabilities_for_graph;
+
+    /// Classes `A` and `B` pointing to each other, `A` having a literal too, with 3 instances of `A` for 1 of `B`.
+    fn graph(with_literal: bool) -> (RelationGraph, HashMap<String, f64>) {
+        let mut graph = RelationGraph::new();
+        let a = graph.add_node("A".to_string());
+        let b = graph.add_node("B".to_string());
+        graph.add_edge(a, b, ("knows".to_string(), 2.0, None, None));
+        graph.add_edge(b, a, ("knows".to_string(), 1.0, None, None));
+        if with_literal {
+            let literal = graph.add_node(LITERAL.to_string());
+            graph.add_edge(a, literal, ("name".to_string(), 2.0, None, None));
+        }
+        calculate_probabilities_for_graph(&mut graph);
+        (graph, HashMap::from([("A".to_string(), 3.0), ("B".to_string(), 1.0)]))
+    }
+
+    fn sampling_options(seed: u64) -> PageRankOptions {
+        PageRankOptions { method: PageRankMethod::Sampling { walks: 20_000, seed }, ..PageRankOptions::default() }
+    }
+
+    #[test]
+    fn power_iteration_converges() {
+        let (graph, counts) = graph(false);
+        let options = PageRankOptions { tolerance: 1e-12, ..PageRankOptions::default() };
+        let (ranks, edge_ranks) = page_rank(&graph, &counts, Outgoing, &options);
+        // r_A = d r_B + (1 - d) 3/4 and r_B = d r_A + (1 - d) 1/4
+        let d = options.damping;
+        let a = (0.25 * d + 0.75) / (1.0 + d);
+        assert!((ranks["A"] - a).abs() < 1e-9);
+        assert!((ranks["B"] - (1.0 - a)).abs() < 1e-9);
+        // Every transition goes through `knows`, in proportion to the rank of its class
+        assert!((edge_ranks["A"]["knows"] - a).abs() < 1e-9);
+        assert!((edge_ranks["B"]["knows"] - (1.0 - a)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn literals_restart_the_walks() {
+        let (graph, counts) = graph(true);
+        for direction in [Outgoing, Incoming] {
+            let (ranks, edge_ranks) = page_rank(&graph, &counts, direction, &PageRankOptions::default());
+            assert_eq!(ranks[LITERAL], 0.0);
+            assert!((ranks.values().sum::<f64>() - 1.0).abs() < 1e-6);
+            assert!((edge_ranks.values().flat_map(|r| r.values()).sum::<f64>() - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn sampling_is_deterministic() {
+        let (graph, counts) = graph(true);
+        let first = page_rank(&graph, &counts, Outgoing, &sampling_options(7));
+        for _ in 0..3 {
+            assert_eq!(page_rank(&graph, &counts, Outgoing, &sampling_options(7)), first);
+        }
+        assert_ne!(page_rank(&graph, &counts, Outgoing, &sampling_options(8)), first);
+    }
+
+    #[test]
+    fn sampling_estimates_the_power_iteration() {
+        let (graph, counts) = graph(true);
+        let (exact, _) = page_rank(&graph, &counts, Outgoing, &PageRankOptions::default());
+        let (estimate, _) = page_rank(&graph, &counts, Outgoing, &sampling_options(DEFAULT_SEED));
+        for class in ["A", "B", LITERAL] {
+            assert!((exact[class] - estimate[class]).abs() < 0.02, "{class}: {} vs {}", exact[class], estimate[class]);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! # Predicate labels
+//!
+//! Keep/drop decisions taken by curators on the predicates of a class, stored per dataset in
+//! `./data/<dataset>/predicate_labels.json`. They are the labels of the training table of the predicate keep model
+//! (see `feature_export`).
+//!
+//! - Curators label predicates from the predicate analysis page (or `/api/v1/analysis/labels`), and deleting a
+//!   predicate of a class (`KG::delete_predicate`) records a `drop` label.
+//! - Labels override the automatic decisions of the predicate analysis (`PredicateScore::decision`).
+//! - A label keeps the statistics of the predicate at the time of the decision, so that dropped predicates, gone from
+//!   the data, still make it to the training table.
+//! - `label_report` measures how often the model and the hybrid decision agree with the curators, and suggests the
+//!   predicates to label next: the unlabelled ones the model is the least sure about.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter };
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::{ Deserialize, Serialize };
+
+use crate::analysis::ClassPredicates;
+use crate::store::KG;
+
+/// Serializes the updates of the labels file, written by concurrent requests.
+static LABELS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Predicates suggested for labelling by `label_report`.
+pub const SUGGESTIONS: usize = 10;
+
+/// A curator decision on a predicate of a class.
+///
+/// ## Fields
+/// * `class` - Class, in its SPARQL form.
+/// * `predicate` - Predicate, in its SPARQL form.
+/// * `keep` - Whether the predicate is worth keeping for the class.
+/// * `stats` - Statistics of the predicate when the decision was taken (empty if it had not been analysed).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PredicateLabel {
+    pub class: String,
+    pub predicate: String,
+    pub keep: bool,
+    #[serde(default)]
+    pub stats: HashMap<String, f64>,
+}
+
+/// Agreement of automatic decisions with the curators, over the labelled predicates they could be compared on.
+///
+/// ## Fields
+/// * `compared` - Labelled predicates with an automatic decision.
+/// * `agreed` - Predicates on which both decisions are the same.
+/// * `rate` - `agreed / compared` (0 when nothing was compared).
+/// * `kept_by_both`, `dropped_by_both` - Agreements, per decision.
+/// * `kept_by_curator_only`, `kept_by_model_only` - Disagreements, per decision of the curator.
+#[derive(Serialize, Default)]
+pub struct Agreement {
+    pub compared: usize,
+    pub agreed: usize,
+    pub rate: f64,
+    pub kept_by_both: usize,
+    pub dropped_by_both: usize,
+    pub kept_by_curator_only: usize,
+    pub kept_by_model_only: usize,
+}
+
+impl Agreement {
+    fn add(&mut self, automatic: bool, curator: bool) {
+        self.compared += 1;
+        match (automatic, curator) {
+            (true, true) => self.kept_by_both += 1,
+            (false, false) => self.dropped_by_both += 1,
+            (false, true) => self.kept_by_curator_only += 1,
+            (true, false) => self.kept_by_model_only += 1,
+        }
+        self.agreed = self.kept_by_both + self.dropped_by_both;
+        self.rate = (self.agreed as f64) / (self.compared as f64);
+    }
+}
+
+/// An unlabelled predicate worth labelling.
+///
+/// ## Fields
+/// * `model_keep` - Probability to keep returned by the model, closest to 0.5 first.
+#[derive(Serialize)]
+pub struct LabelSuggestion {
+    pub class: String,
+    pub predicate: String,
+    pub model_keep: f64,
+}
+
+/// Labels of the dataset compared to the automatic decisions.
+///
+/// ## Fields
+/// * `labels` - Number of labels of the dataset.
+/// * `model` - Agreement of the model (probability to keep above 0.5), on every label with a known probability.
+/// * `hybrid` - Agreement of the hybrid decision, on the labelled predicates of the analysis.
+/// * `suggestions` - Unlabelled predicates of the analysis the model is the least sure about.
+#[derive(Serialize)]
+pub struct LabelReport {
+    pub labels: usize,
+    pub model: Agreement,
+    pub hybrid: Agreement,
+    pub suggestions: Vec<LabelSuggestion>,
+}
+
+/// Compares the labels to the decisions of a predicate analysis (see `KG::predicate_scores`).
+///
+/// Labelled predicates missing from the analysis are compared to the model with the probability saved with the label.
+pub fn label_report(classes: &[ClassPredicates], labels: &[PredicateLabel]) -> LabelReport {
+    let mut analysed: HashMap<(&str, &str), (f64, bool)> = HashMap::new();
+    let mut suggestions = vec![];
+    for class in classes {
+        for p in &class.predicates {
+            let model_keep = p.stats.get("keep").copied().unwrap_or(0.5);
+            analysed.insert((&class.class, &p.predicate), (model_keep, p.keep));
+            if p.label.is_none() {
+                suggestions.push(LabelSuggestion {
+                    class: class.class.clone(),
+                    predicate: p.predicate.clone(),
+                    model_keep,
+                });
+            }
+        }
+    }
+
+    let mut model = Agreement::default();
+    let mut hybrid = Agreement::default();
+    for label in labels {
+        match analysed.get(&(label.class.as_str(), label.predicate.as_str())) {
+            Some((model_keep, hybrid_keep)) => {
+                model.add(*model_keep > 0.5, label.keep);
+                hybrid.add(*hybrid_keep, label.keep);
+            }
+            None => {
+                if let Some(model_keep) = label.stats.get("keep") {
+                    model.add(*model_keep > 0.5, label.keep);
+                }
+            }
+        }
+    }
+
+    suggestions.sort_by(|a, b| {
+        (a.model_keep - 0.5)
+            .abs()
+            .total_cmp(&(b.model_keep - 0.5).abs())
+            .then_with(|| a.class.cmp(&b.class))
+            .then_with(|| a.predicate.cmp(&b.predicate))
+    });
+    suggestions.truncate(SUGGESTIONS);
+    LabelReport { labels: labels.len(), model, hybrid, suggestions }
+}
+
+impl KG {
+    /// File holding the predicate labels of the dataset.
+    pub fn predicate_labels_path(&self) -> String {
+        format!("./data/{}/predicate_labels.json", self.get_name())
+    }
+
+    /// Labels of the dataset, in the order they were recorded (none if the file is missing or invalid).
+    pub fn predicate_labels(&self) -> Vec<PredicateLabel> {
+        File::open(self.predicate_labels_path())
+            .ok()
+            .and_then(|file| serde_json::from_reader(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    /// Records a label, replacing the previous one of the same class-predicate pair.
+    ///
+    /// Without `stats`, the statistics of the cached predicate analysis of the class are used, if any.
+    ///
+    /// # Errors
+    /// Returns the error of writing the labels file.
+    pub fn record_predicate_label(&self, mut label: PredicateLabel) -> std::io::Result<()> {
+        if label.stats.is_empty() {
+            if let Some(data) = self.cached_predicate_analysis(&label.class) {
+                if let Some((_, stats)) = data.into_iter().find(|(p, _)| *p == label.predicate) {
+                    label.stats = stats;
+                }
+            }
+        }
+
+        let _guard = LABELS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut labels = self.predicate_labels();
+        labels.retain(|l| !(l.class == label.class && l.predicate == label.predicate));
+        labels.push(label);
+        self.save_predicate_labels(&labels)
+    }
+
+    /// Removes the label of a class-predicate pair, giving the decision back to the analysis.
+    ///
+    /// Returns whether there was a label to remove.
+    ///
+    /// # Errors
+    /// Returns the error of writing the labels file.
+    pub fn remove_predicate_label(&self, class: &str, predicate: &str) -> std::io::Result<bool> {
+        let _guard = LABELS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut labels = self.predicate_labels();
+        let before = labels.len();
+        labels.retain(|l| !(l.class == class && l.predicate == predicate));
+        if labels.len() == before {
+            return Ok(false);
+        }
+        self.save_predicate_labels(&labels)?;
+        Ok(true)
+    }
+
+    fn save_predicate_labels(&self, labels: &[PredicateLabel]) -> std::io::Result<()> {
+        let path = self.predicate_labels_path();
+        if let Some(dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer(writer, labels)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+//! # Predicate deletion plans
+//!
+//! Deletes the values of many class-predicate pairs at once, as proposed by the predicate analysis.
+//!
+//! - `KG::predicate_plan_impact` counts, before anything changes, the triples and entities every pair would lose,
+//!   and the triples lost overall (a triple shared by two pairs counts once).
+//! - `KG::apply_predicate_plan` moves them to a named graph of the plan (`urn:kg-explorer:archive:plan-<id>`), out
+//!   of the default graph that queries and analyses see, with a single `DELETE ... INSERT` update recorded as one
+//!   history entry. The triples of the graph are then saved to `./data/<dataset>/plans/plan-<id>.nt`: they are
+//!   exactly the removed ones, whatever ran meanwhile. Without `archive`, the graph is dropped afterwards (a second
+//!   history entry).
+//! - `KG::undo_predicate_plan` puts the saved triples back and drops the archive graph. Its history entry only
+//!   references the plan and its file, not the triples.
+//! - The pairs of every applied plan are listed in `./data/<dataset>/plans/index.json`. Whether a plan is applied
+//!   and undone is read from the history, so that reverting the store or replaying the history is reflected.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Write };
+use std::sync::Mutex;
+
+use serde::{ Deserialize, Serialize };
+
+use crate::jobs;
+use crate::predicate_labels::PredicateLabel;
+use crate::store::{ HistoryEntry, StoreError, KG, PLAN_UNDO_PREFIX };
+use crate::utils::extract_literal;
+
+/// Prefix of the archive graphs of the plans.
+pub const ARCHIVE_GRAPH_PREFIX: &str = "urn:kg-explorer:archive:";
+
+/// First line of the update applying a plan, followed by its id, which identifies it in the history.
+const PLAN_COMMENT: &str = "# Predicate plan ";
+
+/// Serializes the applications and undos of plans, which update the plan index.
+static PLANS_LOCK: Mutex<()> = Mutex::new(());
+
+/// A class-predicate pair of a plan, both in their SPARQL form.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PlanPair {
+    pub class: String,
+    pub predicate: String,
+}
+
+impl PlanPair {
+    /// Parses a pair sent by the plan form: `<class> <predicate>`.
+    pub fn parse(value: &str) -> Option<PlanPair> {
+        let (class, predicate) = value.trim().split_once(char::is_whitespace)?;
+        let (class, predicate) = (class.trim(), predicate.trim());
+        let is_iri = |iri: &str| iri.starts_with('<') && iri.ends_with('>') && !iri[1..iri.len() - 1].contains(['<', '>', ' ']);
+        if is_iri(class) && is_iri(predicate) {
+            Some(PlanPair { class: class.to_string(), predicate: predicate.to_string() })
+        } else {
+            None
+        }
+    }
+
+    /// Value of the pair in the plan form.
+    pub fn to_param(&self) -> String {
+        format!("{} {}", self.class, self.predicate)
+    }
+}
+
+/// Triples and entities a pair of the plan would lose.
+#[derive(Serialize)]
+pub struct PairImpact {
+    pub class: String,
+    pub predicate: String,
+    pub triples: u64,
+    pub entities: u64,
+}
+
+/// Impact of a plan, before it is applied.
+///
+/// ## Fields
+/// * `pairs` - Impact of every pair, in the order of the plan.
+/// * `triples` - Triples removed overall.
+/// * `entities` - Entities losing at least one triple.
+#[derive(Serialize)]
+pub struct PlanImpact {
+    pub pairs: Vec<PairImpact>,
+    pub triples: u64,
+    pub entities: u64,
+}
+
+/// A plan applied to the store.
+///
+/// ## Fields
+/// * `id` - Number of the plan, in the order they were applied.
+/// * `pairs` - Class-predicate pairs whose values were removed.
+/// * `triples` - Triples removed.
+/// * `archive` - Named graph holding the removed triples, if they were archived.
+/// * `undone` - Whether the plan was undone, according to the history.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AppliedPlan {
+    pub id: u32,
+    pub pairs: Vec<PlanPair>,
+    pub triples: u64,
+    pub archive: Option<String>,
+    pub undone: bool,
+}
+
+/// `VALUES` block binding `?c` and `?p` to the pairs of a plan.
+fn values(pairs: &[PlanPair]) -> String {
+    let rows = pairs
+        .iter()
+        .map(|pair| format!("({} {})", pair.class, pair.predicate))
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("VALUES (?c ?p) {{ {rows} }}")
+}
+
+impl KG {
+    fn plans_dir(&self) -> String {
+        format!("./data/{}/plans", self.get_name())
+    }
+
+    /// Plans of the index, oldest first, including the ones whose application is no longer in the history.
+    fn indexed_predicate_plans(&self) -> Vec<AppliedPlan> {
+        File::open(format!("{}/index.json", self.plans_dir()))
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, Vec<AppliedPlan>>(BufReader::new(file)).ok())
+            .unwrap_or_default()
+    }
+
+    /// Plans applied to the dataset, oldest first.
+    ///
+    /// Only the plans whose application is in the history are listed (a revert to an older version drops the
+    /// newer ones), undone if the history holds their undo after it.
+    pub fn predicate_plans(&self) -> Vec<AppliedPlan> {
+        let mut undone: HashMap<u32, bool> = HashMap::new();
+        for entry in self.history_entries() {
+            match entry {
+                HistoryEntry::Sparql { query, .. } => {
+                    let id = query
+                        .lines()
+                        .find_map(|line| line.trim().strip_prefix(PLAN_COMMENT))
+                        .and_then(|rest| rest.split(':').next())
+                        .and_then(|id| id.trim().parse().ok());
+                    if let Some(id) = id {
+                        undone.insert(id, false);
+                    }
+                }
+                HistoryEntry::PlanUndo { plan, .. } => {
+                    if let Some(undone) = undone.get_mut(&plan) {
+                        *undone = true;
+                    }
+                }
+                _ => (),
+            }
+        }
+        self.indexed_predicate_plans()
+            .into_iter()
+            .filter_map(|plan| Some(AppliedPlan { undone: *undone.get(&plan.id)?, ..plan }))
+            .collect()
+    }
+
+    fn save_predicate_plans(&self, plans: &[AppliedPlan]) -> Result<(), StoreError> {
+        let fail = |e: std::io::Error| StoreError::EvaluationError(format!("Cannot save the plans: {e}"));
+        let dir = self.plans_dir();
+        std::fs::create_dir_all(&dir).map_err(fail)?;
+        let writer = BufWriter::new(File::create(format!("{dir}/index.json")).map_err(fail)?);
+        serde_json::to_writer(writer, plans).map_err(|e| fail(e.into()))
+    }
+
+    fn count(&self, query: &str) -> Result<u64, StoreError> {
+        Ok(
+            self
+                .query(query)?
+                .first()
+                .and_then(|row| extract_literal(row.get("cnt")))
+                .and_then(|cnt| cnt.parse().ok())
+                .unwrap_or(0)
+        )
+    }
+
+    /// Counts the triples and entities the plan would remove, per pair and overall.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of a failed count.
+    pub fn predicate_plan_impact(&self, pairs: &[PlanPair]) -> Result<PlanImpact, StoreError> {
+        let mut impact = vec![];
+        for (i, pair) in pairs.iter().enumerate() {
+            jobs::progress(i as u64, pairs.len() as u64);
+            let PlanPair { class, predicate } = pair;
+            let triples = self.count(
+                &format!("SELECT (COUNT(*) AS ?cnt) WHERE {{ SELECT DISTINCT ?s ?o WHERE {{ ?s a {class} . ?s {predicate} ?o }} }}")
+            )?;
+            let entities = self.count(
+                &format!("SELECT (COUNT(DISTINCT ?s) AS ?cnt) WHERE {{ ?s a {class} . ?s {predicate} ?o }}")
+            )?;
+            impact.push(PairImpact { class: class.clone(), predicate: predicate.clone(), triples, entities });
+        }
+        jobs::progress(pairs.len() as u64, pairs.len() as u64);
+
+        let (triples, entities) = if pairs.is_empty() {
+            (0, 0)
+        } else {
+            let values = values(pairs);
+            (
+                self.count(
+                    &format!("SELECT (COUNT(*) AS ?cnt) WHERE {{ SELECT DISTINCT ?s ?p ?o WHERE {{ {values} ?s a ?c . ?s ?p ?o }} }}")
+                )?,
+                self.count(&format!("SELECT (COUNT(DISTINCT ?s) AS ?cnt) WHERE {{ {values} ?s a ?c . ?s ?p ?o }}"))?,
+            )
+        };
+        Ok(PlanImpact { pairs: impact, triples, entities })
+    }
+
+    /// Removes the values of every pair of the plan in one update, moving them to an archive graph if `archive`.
+    ///
+    /// The pairs are labelled as dropped (see `predicate_labels`).
+    ///
+    /// # Errors
+    /// - `StoreError::EvaluationError` if the plan is empty or the removed triples cannot be saved.
+    /// - The `StoreError` of the update, in which case nothing is removed.
+    pub fn apply_predicate_plan(&self, pairs: &[PlanPair], archive: bool) -> Result<AppliedPlan, StoreError> {
+        if pairs.is_empty() {
+            return Err(StoreError::EvaluationError("The plan has no class-predicate pair".to_string()));
+        }
+        let _guard = PLANS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let mut plans = self.indexed_predicate_plans();
+        let id =
+            plans
+                .iter()
+                .map(|p| p.id)
+                .max()
+                .unwrap_or(0) + 1;
+        let values = values(pairs);
+
+        // The removed triples are moved to the archive graph by the same operation, so that it holds exactly them
+        let graph = format!("{ARCHIVE_GRAPH_PREFIX}plan-{id}");
+        let update = format!(
+            "{PLAN_COMMENT}{id}: {} class-predicate pairs\nDROP SILENT GRAPH <{graph}> ;\n\
+            DELETE {{ ?s ?p ?o }}\nINSERT {{ GRAPH <{graph}> {{ ?s ?p ?o }} }}\nWHERE {{ {values} ?s a ?c . ?s ?p ?o }}",
+            pairs.len()
+        );
+        jobs::log("Removing the triples".to_string());
+        self.update(&update)?;
+        self.write_to_history(format!("```sparql\n{}\n```", update));
+
+        // Saves the removed triples, to be able to undo the plan
+        jobs::log("Saving the removed triples".to_string());
+        let removed = self.query(&format!("SELECT ?s ?p ?o WHERE {{ GRAPH <{graph}> {{ ?s ?p ?o }} }}"))?;
+        let fail = |e: std::io::Error| StoreError::EvaluationError(format!("Cannot save the removed triples: {e}"));
+        std::fs::create_dir_all(self.plans_dir()).map_err(fail)?;
+        let mut file = BufWriter::new(File::create(format!("{}/plan-{id}.nt", self.plans_dir())).map_err(fail)?);
+        for row in &removed {
+            if let (Some(s), Some(p), Some(o)) = (row.get("s"), row.get("p"), row.get("o")) {
+                writeln!(file, "{s} {p} {o} .").map_err(fail)?;
+            }
+        }
+        file.flush().map_err(fail)?;
+        jobs::log(format!("Removed {} triples", removed.len()));
+
+        if !archive {
+            let drop = format!("DROP SILENT GRAPH <{graph}>");
+            self.update(&drop)?;
+            self.write_to_history(format!("```sparql\n{}\n```", drop));
+        }
+
+        for pair in pairs {
+            let label = PredicateLabel {
+                class: pair.class.clone(),
+                predicate: pair.predicate.clone(),
+                keep: false,
+                stats: Default::default(),
+            };
+            if let Err(e) = self.record_predicate_label(label) {
+                eprintln!("Failed to record the label of {} for {}: {e}", pair.predicate, pair.class);
+            }
+        }
+
+        let archive = archive.then_some(graph);
+        let plan = AppliedPlan { id, pairs: pairs.to_vec(), triples: removed.len() as u64, archive, undone: false };
+        plans.push(plan.clone());
+        self.save_predicate_plans(&plans)?;
+        Ok(plan)
+    }
+
+    /// Inserts back the triples saved by a plan in `plan-<id>.nt`, and drops its archive graph if any.
+    ///
+    /// Shared by `undo_predicate_plan` and the replay of its history entry, which only references the file.
+    /// Returns the path of the file.
+    ///
+    /// # Errors
+    /// - `StoreError::EvaluationError` if the triples cannot be read.
+    /// - The `StoreError` of the update.
+    pub(crate) fn restore_predicate_plan(&self, id: u32) -> Result<String, StoreError> {
+        let path = format!("{}/plan-{id}.nt", self.plans_dir());
+        let triples = std::fs
+            ::read_to_string(&path)
+            .map_err(|e| StoreError::EvaluationError(format!("Cannot read {path}: {e}")))?;
+        let mut operations = vec![];
+        if !triples.trim().is_empty() {
+            operations.push(format!("INSERT DATA {{\n{triples}}}"));
+        }
+        operations.push(format!("DROP SILENT GRAPH <{ARCHIVE_GRAPH_PREFIX}plan-{id}>"));
+        self.update(&operations.join(" ;\n"))?;
+        Ok(path)
+    }
+
+    /// Puts back the triples removed by a plan, and drops its archive graph.
+    ///
+    /// The history only records the plan and the file of its triples (`Undoing predicate plan <id> from <path>`),
+    /// which the replay reads back.
+    ///
+    /// # Errors
+    /// - `StoreError::EvaluationError` if the plan does not exist, was already undone or its triples cannot be read.
+    /// - The `StoreError` of the update.
+    pub fn undo_predicate_plan(&self, id: u32) -> Result<AppliedPlan, StoreError> {
+        let _guard = PLANS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let plans = self.predicate_plans();
+        let Some(plan) = plans.into_iter().find(|p| p.id == id) else {
+            return Err(StoreError::EvaluationError(format!("No predicate plan {id}")));
+        };
+        if plan.undone {
+            return Err(StoreError::EvaluationError(format!("The predicate plan {id} was already undone")));
+        }
+
+        jobs::log(format!("Restoring {} triples", plan.triples));
+        let path = self.restore_predicate_plan(id)?;
+        self.write_to_history(format!("{PLAN_UNDO_PREFIX}{id} from {path}"));
+        Ok(AppliedPlan { undone: true, ..plan })
+    }
+}
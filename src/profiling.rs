@@ -0,0 +1,330 @@
+//! # Class profiles
+//!
+//! Profiles the predicates of a class for the profile page and its API counterpart: fill rate, distinct values, top
+//! values, kinds of objects, datatypes and language tags, lengths of the strings, numeric and date ranges, and
+//! example entities.
+//!
+//! - Every metric comes from a single query listing the triples of the instances of the class, aggregated in one
+//!   pass, instead of one query per metric and predicate.
+//! - Frequency, uniqueness and entropy are those of the predicate analysis (see `KG::usage_stats`).
+//! - Profiles are cached (see `cache`), and recomputed once an update may have changed the class.
+
+use std::collections::{ BTreeSet, HashMap, HashSet };
+
+use oxigraph::model::Term;
+use serde::{ Deserialize, Serialize };
+
+use crate::cache::{ cache_file_name, Cached };
+use crate::explore::ValueCount;
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+
+/// Number of top values, datatypes and languages listed per predicate.
+const TOP_VALUES: usize = 10;
+
+/// Number of example entities listed per predicate.
+const EXAMPLES: usize = 3;
+
+/// Upper bounds of the buckets of the string length histogram; longer strings go to a last bucket.
+const LENGTH_BUCKETS: [usize; 6] = [0, 10, 50, 100, 500, 1000];
+
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// Lowest and highest value of a predicate.
+///
+/// ## Fields
+/// * `min`, `max` - Bounds, as written in the dataset.
+/// * `count` - Number of values in the range.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ValueRange {
+    pub min: String,
+    pub max: String,
+    pub count: u64,
+}
+
+/// Profile of a predicate of a class.
+///
+/// ## Fields
+/// * `entities` - Instances having the predicate.
+/// * `fill_rate` - Share of the instances having the predicate.
+/// * `values` - Triples of the predicate.
+/// * `distinct` - Distinct objects of the predicate.
+/// * `uniqueness`, `entropy` - As in the predicate analysis.
+/// * `top_values` - Most used objects, in N-Triples syntax.
+/// * `kinds` - Number of IRIs, blank nodes and literals.
+/// * `datatypes`, `languages` - Most used datatypes and language tags of the literals.
+/// * `lengths` - Histogram of the lengths of the literals, in characters.
+/// * `numeric` - Range of the numeric literals (numeric datatypes, or strings holding a number).
+/// * `dates` - Range of the date literals (`xsd:date` and `xsd:dateTime`, or strings starting with `YYYY-MM-DD`).
+/// * `examples` - Some instances having the predicate.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PredicateProfile {
+    pub predicate: String,
+    pub entities: u64,
+    pub fill_rate: f64,
+    pub values: u64,
+    pub distinct: u64,
+    pub uniqueness: f64,
+    pub entropy: f64,
+    pub top_values: Vec<ValueCount>,
+    pub kinds: Vec<ValueCount>,
+    pub datatypes: Vec<ValueCount>,
+    pub languages: Vec<ValueCount>,
+    pub lengths: Vec<ValueCount>,
+    pub numeric: Option<ValueRange>,
+    pub dates: Option<ValueRange>,
+    pub examples: Vec<String>,
+}
+
+/// Profile of a class: its instance count and the profile of each of its predicates, the most used first.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClassProfile {
+    pub class: String,
+    pub entities: u64,
+    pub predicates: Vec<PredicateProfile>,
+}
+
+/// Running range of the values of a predicate, compared with `key`.
+struct RangeAcc<K> {
+    min: Option<(K, String)>,
+    max: Option<(K, String)>,
+    count: u64,
+}
+
+impl<K: PartialOrd + Clone> RangeAcc<K> {
+    fn new() -> Self {
+        RangeAcc { min: None, max: None, count: 0 }
+    }
+
+    fn add(&mut self, key: K, value: &str) {
+        self.count += 1;
+        if self.min.as_ref().is_none_or(|(min, _)| key < *min) {
+            self.min = Some((key.clone(), value.to_string()));
+        }
+        if self.max.as_ref().is_none_or(|(max, _)| key > *max) {
+            self.max = Some((key, value.to_string()));
+        }
+    }
+
+    fn range(self) -> Option<ValueRange> {
+        let (Some((_, min)), Some((_, max))) = (self.min, self.max) else {
+            return None;
+        };
+        Some(ValueRange { min, max, count: self.count })
+    }
+}
+
+/// Metrics of a predicate, accumulated over its triples.
+struct PredicateAcc {
+    subjects: HashSet<String>,
+    examples: Vec<String>,
+    values: HashMap<String, u64>,
+    iris: u64,
+    blank_nodes: u64,
+    literals: u64,
+    datatypes: HashMap<String, u64>,
+    languages: HashMap<String, u64>,
+    lengths: [u64; LENGTH_BUCKETS.len() + 1],
+    numeric: RangeAcc<f64>,
+    dates: RangeAcc<String>,
+}
+
+impl PredicateAcc {
+    fn new() -> Self {
+        PredicateAcc {
+            subjects: HashSet::new(),
+            examples: vec![],
+            values: HashMap::new(),
+            iris: 0,
+            blank_nodes: 0,
+            literals: 0,
+            datatypes: HashMap::new(),
+            languages: HashMap::new(),
+            lengths: [0; LENGTH_BUCKETS.len() + 1],
+            numeric: RangeAcc::new(),
+            dates: RangeAcc::new(),
+        }
+    }
+
+    fn add(&mut self, subject: &Term, object: &Term) {
+        let subject = subject.to_string();
+        if !self.subjects.contains(&subject) {
+            if self.examples.len() < EXAMPLES {
+                self.examples.push(subject.clone());
+            }
+            self.subjects.insert(subject);
+        }
+        *self.values.entry(object.to_string()).or_default() += 1;
+
+        match object {
+            Term::NamedNode(_) => {
+                self.iris += 1;
+            }
+            Term::BlankNode(_) => {
+                self.blank_nodes += 1;
+            }
+            Term::Literal(literal) => {
+                self.literals += 1;
+                let datatype = literal.datatype().as_str();
+                *self.datatypes.entry(format!("<{datatype}>")).or_default() += 1;
+                if let Some(lang) = literal.language() {
+                    *self.languages.entry(lang.to_string()).or_default() += 1;
+                }
+
+                let value = literal.value();
+                let length = value.chars().count();
+                let bucket = LENGTH_BUCKETS.iter()
+                    .position(|max| length <= *max)
+                    .unwrap_or(LENGTH_BUCKETS.len());
+                self.lengths[bucket] += 1;
+
+                let local = datatype.strip_prefix(XSD).unwrap_or_default();
+                if is_numeric_datatype(local) || local == "string" {
+                    if let Ok(number) = value.trim().parse::<f64>() {
+                        if number.is_finite() {
+                            self.numeric.add(number, value);
+                        }
+                    }
+                }
+                if local == "date" || local == "dateTime" || (local == "string" && is_date(value)) {
+                    self.dates.add(value.trim().to_string(), value);
+                }
+            }
+            Term::Triple(_) => {}
+        }
+    }
+
+    fn profile(self, predicate: String, object_count: u64) -> PredicateProfile {
+        let entities = self.subjects.len() as u64;
+        let value_counts = self.values
+            .values()
+            .map(|c| *c as f64)
+            .collect::<Vec<_>>();
+        let stats = KG::usage_stats(object_count as f64, entities as f64, &value_counts);
+
+        let kinds = [("IRI", self.iris), ("Blank node", self.blank_nodes), ("Literal", self.literals)]
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(value, count)| ValueCount { value: value.to_string(), count })
+            .collect();
+        let lengths = if self.literals == 0 {
+            vec![]
+        } else {
+            self.lengths
+                .iter()
+                .enumerate()
+                .map(|(i, count)| ValueCount { value: length_bucket(i), count: *count })
+                .collect()
+        };
+
+        PredicateProfile {
+            predicate,
+            entities,
+            fill_rate: stats["frequency"],
+            values: value_counts.iter().sum::<f64>() as u64,
+            distinct: value_counts.len() as u64,
+            uniqueness: stats["uniqueness"],
+            entropy: stats["entropy"],
+            top_values: top(self.values),
+            kinds,
+            datatypes: top(self.datatypes),
+            languages: top(self.languages),
+            lengths,
+            numeric: self.numeric.range(),
+            dates: self.dates.range(),
+            examples: self.examples,
+        }
+    }
+}
+
+fn is_numeric_datatype(local: &str) -> bool {
+    matches!(
+        local,
+        "integer" | "decimal" | "double" | "float" | "int" | "long" | "short" | "byte" | "nonNegativeInteger" |
+            "positiveInteger" | "negativeInteger" | "nonPositiveInteger" | "unsignedInt" | "unsignedLong"
+    )
+}
+
+/// Whether a string starts with a `YYYY-MM-DD` date.
+fn is_date(value: &str) -> bool {
+    let bytes = value.trim().as_bytes();
+    bytes.len() >= 10 &&
+        bytes[..10]
+            .iter()
+            .enumerate()
+            .all(|(i, b)| if i == 4 || i == 7 { *b == b'-' } else { b.is_ascii_digit() })
+}
+
+/// Label of the `i`-th bucket of the length histogram.
+fn length_bucket(i: usize) -> String {
+    match (i.checked_sub(1).map(|j| LENGTH_BUCKETS[j]), LENGTH_BUCKETS.get(i)) {
+        (None, Some(max)) => format!("{max}"),
+        (Some(min), Some(max)) => format!("{}-{max}", min + 1),
+        (Some(min), None) => format!("> {min}"),
+        (None, None) => String::new(),
+    }
+}
+
+/// The `TOP_VALUES` most used values, the most used first.
+fn top(counts: HashMap<String, u64>) -> Vec<ValueCount> {
+    let mut counts = counts
+        .into_iter()
+        .map(|(value, count)| ValueCount { value, count })
+        .collect::<Vec<_>>();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(TOP_VALUES);
+    counts
+}
+
+impl KG {
+    /// Profiles the predicates of `class` (`<iri>`), or loads its cached profile if no update changed the class since.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of the query listing the triples of the class.
+    pub fn class_profile(&self, class: &str) -> Result<ClassProfile, StoreError> {
+        let name = format!("profiles/{}.json", cache_file_name(class));
+        let version = self.cache_version();
+        let cached = self
+            .load_cache::<ClassProfile>(&name)
+            .filter(|cached| cached.data.class == class)
+            .and_then(|Cached { data, changes }| {
+                let known = data.predicates
+                    .iter()
+                    .map(|p| p.predicate.clone())
+                    .collect::<BTreeSet<_>>();
+                self.touched_predicates(&changes, class, &known).filter(|touched| touched.is_empty()).map(|_| data)
+            });
+        if let Some(profile) = cached {
+            return Ok(profile);
+        }
+
+        jobs::log(format!("Listing the triples of {class}"));
+        let rows = self.query(&format!("SELECT ?s ?p ?o WHERE {{ ?s a {class} . ?s ?p ?o }}"))?;
+
+        let mut entities = HashSet::new();
+        let mut predicates: HashMap<String, PredicateAcc> = HashMap::new();
+        let total = rows.len() as u64;
+        for (i, row) in rows.iter().enumerate() {
+            if i % 10_000 == 0 {
+                jobs::progress(i as u64, total);
+            }
+            let (Some(s), Some(p), Some(o)) = (row.get("s"), row.get("p"), row.get("o")) else {
+                continue;
+            };
+            entities.insert(s.to_string());
+            predicates.entry(p.to_string()).or_insert_with(PredicateAcc::new).add(s, o);
+        }
+        jobs::progress(total, total);
+
+        let entities = entities.len() as u64;
+        let mut predicates = predicates
+            .into_iter()
+            .map(|(predicate, acc)| acc.profile(predicate, entities))
+            .collect::<Vec<_>>();
+        predicates.sort_by(|a, b| b.entities.cmp(&a.entities).then_with(|| a.predicate.cmp(&b.predicate)));
+
+        let profile = ClassProfile { class: class.to_string(), entities, predicates };
+        self.save_cache(&name, version, &profile);
+        Ok(profile)
+    }
+}
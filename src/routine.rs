@@ -0,0 +1,93 @@
+//! # Routine files
+//!
+//! Routines are `.sparql` files stored in the `routines/` directory.
+//! - The first line (`### description`) describes the file; a description ending with `@hidden` hides it from the UI.
+//! - Each procedure starts with a `## name` line followed by its SPARQL. Procedures whose name ends with
+//!   `@advanced` are iterative updates: a `SELECT`, a `#` line, then the update template.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// A single procedure of a routine file.
+#[derive(Serialize, Clone)]
+pub struct Procedure {
+    pub name: String,
+    pub query: String,
+    pub advanced: bool,
+}
+
+/// A parsed routine file.
+///
+/// ## Fields
+/// * `file` - File name inside the `routines/` directory.
+/// * `description` - Description taken from the first line.
+/// * `hidden` - Whether the file is hidden from the UI.
+/// * `procedures` - Procedures in the order of the file.
+#[derive(Serialize, Clone)]
+pub struct Routine {
+    pub file: String,
+    pub description: String,
+    pub hidden: bool,
+    pub procedures: Vec<Procedure>,
+}
+
+impl Routine {
+    /// Parses the content of a routine file.
+    pub fn parse(file: &str, content: &str) -> Routine {
+        let mut lines = content.lines();
+        let description = lines.next().unwrap_or("").trim_start_matches("###").trim();
+        let hidden = description.ends_with("@hidden");
+
+        let mut procedures = vec![];
+        let mut current: Option<Procedure> = None;
+        for line in lines {
+            if line.starts_with("##") {
+                if let Some(p) = current.take() {
+                    procedures.push(p);
+                }
+                let name = line.trim_start_matches("##").trim().to_string();
+                current = Some(Procedure {
+                    advanced: name.ends_with("@advanced"),
+                    name,
+                    query: String::new(),
+                });
+            } else if let Some(p) = current.as_mut() {
+                p.query.push_str(line);
+                p.query.push('\n');
+            }
+        }
+        if let Some(p) = current {
+            procedures.push(p);
+        }
+
+        Routine {
+            file: file.to_string(),
+            description: description.to_string(),
+            hidden,
+            procedures,
+        }
+    }
+}
+
+/// Lists all of the routine files of the `routines/` directory, sorted by file name.
+pub fn list_routines() -> Vec<Routine> {
+    let mut routines = vec![];
+    if let Ok(entries) = fs::read_dir("routines") {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "sparql") {
+                if let Ok(content) = fs::read_to_string(&path) {
+                    routines.push(Routine::parse(&file_name(&path), &content));
+                }
+            }
+        }
+    }
+    routines.sort_by(|a, b| a.file.cmp(&b.file));
+    routines
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
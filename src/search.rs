@@ -0,0 +1,434 @@
+//! # Full-text search
+//!
+//! In-memory inverted index over the literal values of the store, ranked with BM25.
+//!
+//! - Every string literal (plain or language tagged) of a named entity is indexed; `schema:name` and
+//!   `schema:description` weigh more than the other predicates.
+//! - The last term of a query also matches as a prefix, so partial input already yields suggestions.
+//! - Results can be filtered by class and by literal language.
+//! - `SearchEngine` keeps the index in sync with the store: when an update was logged in the change log since the
+//!   index was built (see `cache`), a background job rebuilds it while the previous index keeps serving queries.
+//!   History lines that are not updates (dumps, users, analyses) and updates of named graphs do not count.
+
+use std::collections::{ BTreeMap, HashMap };
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Arc, RwLock };
+
+use oxigraph::model::{ Subject, Term };
+use serde::Serialize;
+
+use crate::cache::CacheVersion;
+use crate::jobs::{ self, JobManager };
+use crate::store::KG;
+use crate::utils::escape_html;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const XSD_STRING: &str = "http://www.w3.org/2001/XMLSchema#string";
+
+/// Weight of the terms found in a literal of `predicate`.
+fn field_weight(predicate: &str) -> f32 {
+    match predicate.to_lowercase().as_str() {
+        "http://schema.org/name" => 3.0,
+        "http://schema.org/alternatename" | "http://schema.org/headline" => 2.0,
+        "http://schema.org/description" => 1.5,
+        _ => 1.0,
+    }
+}
+
+/// BM25 parameters.
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// Weight of a term matched as a prefix rather than exactly.
+const PREFIX_WEIGHT: f32 = 0.8;
+
+/// Splits a text into lowercase alphanumeric terms.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && t.chars().count() <= 40)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// An occurrence of a term in a literal of an entity.
+struct Posting {
+    doc: u32,
+    lang: u16,
+    weight: f32,
+}
+
+struct Doc {
+    subject: String,
+    name: Option<String>,
+    classes: Vec<u32>,
+    len: f32,
+}
+
+/// A search request.
+///
+/// ## Fields
+/// * `text` - Text typed by the user.
+/// * `class` - Only return instances of this class (`<iri>`).
+/// * `lang` - Only match literals in this language (`en`, `fr`, ...).
+/// * `limit`, `offset` - Page of the results to return.
+pub struct SearchQuery {
+    pub text: String,
+    pub class: Option<String>,
+    pub lang: Option<String>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+/// An entity matching a search, most relevant first.
+#[derive(Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub name: Option<String>,
+    pub score: f32,
+}
+
+/// A page of search results.
+#[derive(Serialize)]
+pub struct SearchResults {
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+/// Inverted index of the literals of the store.
+///
+/// ## Fields
+/// * `terms` - Postings of every term, sorted to allow prefix lookups.
+/// * `classes`, `langs` - Interned classes and languages (`""` for literals without language).
+/// * `version` - Position in the change log when the index was built (see `KG::cache_version`).
+pub struct SearchIndex {
+    docs: Vec<Doc>,
+    terms: BTreeMap<String, Vec<Posting>>,
+    classes: Vec<String>,
+    langs: Vec<String>,
+    avg_len: f32,
+    pub version: CacheVersion,
+}
+
+impl SearchIndex {
+    /// Builds the index with a single scan of the default graph.
+    pub fn build(kg: &KG) -> SearchIndex {
+        let version = kg.cache_version();
+        let total = kg.count_lines();
+
+        let mut index = SearchIndex {
+            docs: vec![],
+            terms: BTreeMap::new(),
+            classes: vec![],
+            langs: vec![String::new()],
+            avg_len: 0.0,
+            version,
+        };
+        let mut doc_ids: HashMap<String, u32> = HashMap::new();
+        let mut class_ids: HashMap<String, u32> = HashMap::new();
+        let mut lang_ids: HashMap<String, u16> = HashMap::from([(String::new(), 0)]);
+        let mut scanned = 0u64;
+
+        kg.for_each_triple(|quad| {
+            scanned += 1;
+            if scanned % 100_000 == 0 {
+                jobs::progress(scanned, total);
+            }
+            let Subject::NamedNode(subject) = &quad.subject else {
+                return;
+            };
+            let predicate = quad.predicate.as_str();
+            let is_type = predicate == RDF_TYPE;
+            let literal = match &quad.object {
+                Term::Literal(l) if
+                    !is_type && (l.language().is_some() || l.datatype().as_str() == XSD_STRING)
+                => l,
+                Term::NamedNode(_) if is_type => {
+                    let doc = index.doc_id(&mut doc_ids, subject.to_string());
+                    let class = quad.object.to_string();
+                    let next = class_ids.len() as u32;
+                    let class = *class_ids.entry(class.clone()).or_insert_with(|| {
+                        index.classes.push(class);
+                        next
+                    });
+                    let classes = &mut index.docs[doc as usize].classes;
+                    if !classes.contains(&class) {
+                        classes.push(class);
+                    }
+                    return;
+                }
+                _ => {
+                    return;
+                }
+            };
+
+            let doc = index.doc_id(&mut doc_ids, subject.to_string());
+            let lang = literal.language().unwrap_or_default().to_lowercase();
+            let next = lang_ids.len() as u16;
+            let lang = *lang_ids.entry(lang.clone()).or_insert_with(|| {
+                index.langs.push(lang);
+                next
+            });
+            let weight = field_weight(predicate);
+            if weight >= 3.0 && index.docs[doc as usize].name.is_none() {
+                index.docs[doc as usize].name = Some(literal.value().to_string());
+            }
+
+            let terms = tokenize(literal.value());
+            index.docs[doc as usize].len += terms.len() as f32;
+            for term in terms {
+                index.terms.entry(term).or_default().push(Posting { doc, lang, weight });
+            }
+        });
+        jobs::progress(total, total);
+
+        let total_len: f32 = index.docs
+            .iter()
+            .map(|d| d.len)
+            .sum();
+        index.avg_len = total_len / (index.docs.len().max(1) as f32);
+        index
+    }
+
+    fn doc_id(&mut self, ids: &mut HashMap<String, u32>, subject: String) -> u32 {
+        if let Some(id) = ids.get(&subject) {
+            return *id;
+        }
+        let id = self.docs.len() as u32;
+        ids.insert(subject.clone(), id);
+        self.docs.push(Doc { subject, name: None, classes: vec![], len: 0.0 });
+        id
+    }
+
+    /// Number of indexed entities.
+    pub fn len(&self) -> usize {
+        self.docs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.docs.is_empty()
+    }
+
+    /// Returns the entities matching every term of the query, best first.
+    pub fn search(&self, query: &SearchQuery) -> SearchResults {
+        let terms = tokenize(&query.text);
+        if terms.is_empty() {
+            return SearchResults { total: 0, hits: vec![] };
+        }
+        let class = query.class.as_ref().and_then(|c| self.classes.iter().position(|k| k == c));
+        if query.class.is_some() && class.is_none() {
+            return SearchResults { total: 0, hits: vec![] };
+        }
+        let lang = match &query.lang {
+            Some(l) =>
+                match self.langs.iter().position(|k| k == &l.to_lowercase()) {
+                    Some(id) => Some(id as u16),
+                    None => {
+                        return SearchResults { total: 0, hits: vec![] };
+                    }
+                }
+            None => None,
+        };
+
+        let nb_docs = self.docs.len() as f32;
+        let mut scores: HashMap<u32, (f32, usize)> = HashMap::new();
+        for (i, term) in terms.iter().enumerate() {
+            let is_last = i == terms.len() - 1;
+            let mut tf: HashMap<u32, f32> = HashMap::new();
+            for (key, postings) in self.terms.range(term.clone()..) {
+                let factor = if key == term {
+                    1.0
+                } else if is_last && key.starts_with(term.as_str()) {
+                    PREFIX_WEIGHT
+                } else {
+                    break;
+                };
+                for p in postings.iter().filter(|p| lang.is_none_or(|l| p.lang == l)) {
+                    *tf.entry(p.doc).or_default() += p.weight * factor;
+                }
+            }
+
+            let df = tf.len() as f32;
+            let idf = (1.0 + (nb_docs - df + 0.5) / (df + 0.5)).ln();
+            for (doc, tf) in tf {
+                let len = self.docs[doc as usize].len;
+                let bm25 = (idf * tf * (K1 + 1.0)) / (tf + K1 * (1.0 - B + (B * len) / self.avg_len.max(1.0)));
+                let entry = scores.entry(doc).or_default();
+                entry.0 += bm25;
+                entry.1 += 1;
+            }
+        }
+
+        let mut matches = scores
+            .into_iter()
+            .filter(|(doc, (_, matched))| {
+                *matched == terms.len() &&
+                    class.is_none_or(|c| self.docs[*doc as usize].classes.contains(&(c as u32)))
+            })
+            .collect::<Vec<_>>();
+        matches.sort_by(|(da, (sa, _)), (db, (sb, _))| {
+            sb.total_cmp(sa).then_with(|| self.docs[*da as usize].subject.cmp(&self.docs[*db as usize].subject))
+        });
+
+        let total = matches.len();
+        let hits = matches
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit)
+            .map(|(doc, (score, _))| {
+                let doc = &self.docs[doc as usize];
+                SearchHit { id: doc.subject.clone(), name: doc.name.clone(), score }
+            })
+            .collect();
+        SearchResults { total, hits }
+    }
+}
+
+/// Escapes `text` and wraps the words starting with one of the query terms in `<mark>`.
+///
+/// With `max_len`, only a window of about `max_len` characters around the first match is kept.
+pub fn highlight(text: &str, query: &str, max_len: Option<usize>) -> String {
+    let terms = tokenize(query);
+    let matches = |word: &str| {
+        let word = word.to_lowercase();
+        terms.iter().any(|t| word.starts_with(t.as_str()))
+    };
+
+    // Words of the text, as byte ranges
+    let mut words = vec![];
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => {
+                start = Some(i);
+            }
+            (false, Some(s)) => {
+                words.push((s, i));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        words.push((s, text.len()));
+    }
+
+    let (from, to) = match max_len {
+        Some(max_len) if text.chars().count() > max_len => {
+            let first = words
+                .iter()
+                .find(|(s, e)| matches(&text[*s..*e]))
+                .map(|(s, _)| *s)
+                .unwrap_or(0);
+            let from = text[..first]
+                .char_indices()
+                .rev()
+                .nth(max_len / 4)
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            let to = text[from..]
+                .char_indices()
+                .nth(max_len)
+                .map(|(i, _)| from + i)
+                .unwrap_or(text.len());
+            (from, to)
+        }
+        _ => (0, text.len()),
+    };
+
+    let mut result = String::new();
+    if from > 0 {
+        result.push('…');
+    }
+    let mut last = from;
+    for (s, e) in words.into_iter().filter(|(s, e)| *s >= from && *e <= to) {
+        if matches(&text[s..e]) {
+            result += &escape_html(&text[last..s].to_string());
+            result += &format!("<mark>{}</mark>", escape_html(&text[s..e].to_string()));
+            last = e;
+        }
+    }
+    result += &escape_html(&text[last..to].to_string());
+    if to < text.len() {
+        result.push('…');
+    }
+    result
+}
+
+/// Serves searches from the latest index and rebuilds it when the store changes.
+pub struct SearchEngine {
+    kg: Arc<KG>,
+    jobs: Arc<JobManager>,
+    index: RwLock<Option<Arc<SearchIndex>>>,
+    building: AtomicBool,
+}
+
+impl SearchEngine {
+    pub fn new(kg: Arc<KG>, jobs: Arc<JobManager>) -> SearchEngine {
+        SearchEngine { kg, jobs, index: RwLock::new(None), building: AtomicBool::new(false) }
+    }
+
+    /// Returns the latest index, `None` while the first one is being built.
+    ///
+    /// Starts a rebuild job if the store changed since the index was built.
+    pub fn index(self: &Arc<Self>) -> Option<Arc<SearchIndex>> {
+        let index = self.index.read().unwrap().clone();
+        let version = self.kg.cache_version();
+        if index.as_ref().is_none_or(|i| i.version != version) {
+            self.rebuild();
+        }
+        index
+    }
+
+    /// Starts building a new index, unless one is already being built.
+    fn rebuild(self: &Arc<Self>) {
+        if self.building.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let engine = self.clone();
+        // The key marks the job as read-only, see `JobManager::shutdown`
+        let key = Some("search-index".to_string());
+        self.jobs.spawn("Build the search index".to_string(), key, None, move || {
+            let built = panic::catch_unwind(AssertUnwindSafe(|| SearchIndex::build(&engine.kg)));
+            engine.building.store(false, Ordering::SeqCst);
+            let index = built.map_err(|_| "Failed to build the search index".to_string())?;
+
+            let message = format!("Indexed {} entities", index.len());
+            jobs::log(message.clone());
+            *engine.index.write().unwrap() = Some(Arc::new(index));
+            Ok(message)
+        });
+    }
+}
+
+/// Returns the highlighted excerpt of the literal of `id` that best matches the query.
+///
+/// `schema:name` is left out since it is displayed on its own.
+pub fn snippet(kg: &KG, id: &str, query: &str, max_len: usize) -> Option<String> {
+    let terms = tokenize(query);
+    let q = format!(
+        r#"SELECT ?o WHERE {{
+            {id} ?p ?o .
+            FILTER(isLiteral(?o) && LCASE(STR(?p)) != "http://schema.org/name")
+        }}"#
+    );
+    kg.query(&q)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|row| {
+            match row.get("o") {
+                Some(Term::Literal(l)) => Some(l.value().to_string()),
+                _ => None,
+            }
+        })
+        .map(|value| {
+            let score = tokenize(&value)
+                .iter()
+                .filter(|word| terms.iter().any(|t| word.starts_with(t.as_str())))
+                .count();
+            (score, value)
+        })
+        .filter(|(score, _)| *score > 0)
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, value)| highlight(&value, query, Some(max_len)))
+}
@@ -0,0 +1,1461 @@
+//! # SHACL validation
+//!
+//! Validates the store against [SHACL Core](https://www.w3.org/TR/shacl/) shapes, loaded from RDF files (the
+//! `shapes/` directory in the UI) and/or from a named graph of the store.
+//!
+//! - Focus nodes and value nodes are fetched with one SPARQL query per shape and path rather than per node; nested
+//!   shapes (`sh:node`, `sh:not`, `sh:and`, `sh:or`, `sh:xone`, `sh:qualifiedValueShape`) are checked on all of the
+//!   value nodes at once, by chunks of `NODES_PER_QUERY`.
+//! - Recursive shapes are cut: nodes are assumed to conform to a shape that is already being checked.
+//! - Blank nodes cannot be sent back to the store, so blank value nodes are not checked against nested shapes or
+//!   `sh:class` (the WDC datasets are skolemized and have none).
+//! - SPARQL-based constraints and `sh:qualifiedValueShapesDisjoint` are not supported.
+
+use std::cmp::Ordering;
+use std::collections::{ HashMap, HashSet };
+use std::fmt;
+use std::fs::{ self, File };
+use std::io::BufReader;
+use std::path::Path as FilePath;
+use std::str::FromStr;
+
+use oxigraph::io::{ RdfFormat, RdfParser, RdfSerializer };
+use oxigraph::model::{ BlankNode, Literal, NamedNode, Term, Triple };
+use oxigraph::sparql::QuerySolution;
+use regex::Regex;
+use serde::{ Serialize, Serializer };
+
+use crate::jobs;
+use crate::store::KG;
+
+/// SHACL namespace.
+pub const SH: &str = "http://www.w3.org/ns/shacl#";
+
+/// Directory of the shape files offered by the UI.
+pub const SHAPES_DIR: &str = "shapes";
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+const RDFS_CLASS: &str = "http://www.w3.org/2000/01/rdf-schema#Class";
+const RDFS_SUBCLASS_OF: &str = "http://www.w3.org/2000/01/rdf-schema#subClassOf";
+const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+
+/// Number of nodes bound by a single `VALUES` clause.
+const NODES_PER_QUERY: usize = 500;
+
+/// Datatypes compared as numbers by the range constraints.
+const NUMERIC_TYPES: &[&str] = &[
+    "integer",
+    "decimal",
+    "double",
+    "float",
+    "int",
+    "long",
+    "short",
+    "byte",
+    "nonNegativeInteger",
+    "positiveInteger",
+    "nonPositiveInteger",
+    "negativeInteger",
+    "unsignedLong",
+    "unsignedInt",
+    "unsignedShort",
+    "unsignedByte",
+];
+
+/// Full IRI of a term of the SHACL namespace.
+fn sh(name: &str) -> String {
+    format!("{SH}{name}")
+}
+
+fn sh_node(name: &str) -> NamedNode {
+    NamedNode::new_unchecked(sh(name))
+}
+
+/// # Severity of a validation result.
+///
+/// ## Variants:
+/// * `Info`, `Warning`, `Violation`: `sh:Info`, `sh:Warning` and `sh:Violation`, the default.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Violation,
+}
+
+impl Severity {
+    fn parse(iri: &str) -> Option<Severity> {
+        match iri.strip_prefix(SH)? {
+            "Info" => Some(Severity::Info),
+            "Warning" => Some(Severity::Warning),
+            "Violation" => Some(Severity::Violation),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Info => "Info",
+            Severity::Warning => "Warning",
+            Severity::Violation => "Violation",
+        }
+    }
+}
+
+/// # A SHACL property path.
+///
+/// ## Variants:
+/// * `Predicate(iri)`: A single predicate.
+/// * `Inverse`, `ZeroOrMore`, `OneOrMore`, `ZeroOrOne`: `sh:inversePath`, `sh:zeroOrMorePath`, ...
+/// * `Sequence`, `Alternative`: An RDF list of paths, or `sh:alternativePath` of one.
+///
+/// Displayed (and serialized) as a SPARQL property path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Path {
+    Predicate(String),
+    Inverse(Box<Path>),
+    Sequence(Vec<Path>),
+    Alternative(Vec<Path>),
+    ZeroOrMore(Box<Path>),
+    OneOrMore(Box<Path>),
+    ZeroOrOne(Box<Path>),
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |paths: &[Path], separator: &str| {
+            paths
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        match self {
+            Path::Predicate(iri) => write!(f, "<{iri}>"),
+            Path::Inverse(path) => write!(f, "^({path})"),
+            Path::Sequence(paths) => write!(f, "({})", join(paths, "/")),
+            Path::Alternative(paths) => write!(f, "({})", join(paths, "|")),
+            Path::ZeroOrMore(path) => write!(f, "({path})*"),
+            Path::OneOrMore(path) => write!(f, "({path})+"),
+            Path::ZeroOrOne(path) => write!(f, "({path})?"),
+        }
+    }
+}
+
+impl Serialize for Path {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl Path {
+    /// Writes the SHACL form of the path, returning the node standing for it.
+    fn to_rdf(&self, triples: &mut Vec<Triple>) -> Term {
+        let wrap = |predicate: &str, path: &Path, triples: &mut Vec<Triple>| -> Term {
+            let node = BlankNode::default();
+            let inner = path.to_rdf(triples);
+            triples.push(Triple::new(node.clone(), sh_node(predicate), inner));
+            node.into()
+        };
+        match self {
+            Path::Predicate(iri) => NamedNode::new_unchecked(iri.clone()).into(),
+            Path::Inverse(path) => wrap("inversePath", path, triples),
+            Path::ZeroOrMore(path) => wrap("zeroOrMorePath", path, triples),
+            Path::OneOrMore(path) => wrap("oneOrMorePath", path, triples),
+            Path::ZeroOrOne(path) => wrap("zeroOrOnePath", path, triples),
+            Path::Sequence(paths) => {
+                let items = paths
+                    .iter()
+                    .map(|p| p.to_rdf(triples))
+                    .collect();
+                rdf_list(items, triples)
+            }
+            Path::Alternative(paths) => {
+                let items = paths
+                    .iter()
+                    .map(|p| p.to_rdf(triples))
+                    .collect();
+                let node = BlankNode::default();
+                let list = rdf_list(items, triples);
+                triples.push(Triple::new(node.clone(), sh_node("alternativePath"), list));
+                node.into()
+            }
+        }
+    }
+}
+
+/// Writes an RDF list, returning its head.
+fn rdf_list(items: Vec<Term>, triples: &mut Vec<Triple>) -> Term {
+    let mut head: Term = NamedNode::new_unchecked(RDF_NIL).into();
+    for item in items.into_iter().rev() {
+        let node = BlankNode::default();
+        triples.push(Triple::new(node.clone(), NamedNode::new_unchecked(RDF_FIRST), item));
+        triples.push(Triple::new(node.clone(), NamedNode::new_unchecked(RDF_REST), head));
+        head = node.into();
+    }
+    head
+}
+
+/// # Targets of a shape.
+enum Target {
+    Class(String),
+    Node(Term),
+    SubjectsOf(String),
+    ObjectsOf(String),
+}
+
+/// # SHACL Core constraint components.
+///
+/// Shapes referred to by a constraint are given by their index in `Shapes`.
+enum Constraint {
+    Class(String),
+    Datatype(String),
+    NodeKind(String),
+    MinCount(usize),
+    MaxCount(usize),
+    MinExclusive(Term),
+    MinInclusive(Term),
+    MaxExclusive(Term),
+    MaxInclusive(Term),
+    MinLength(usize),
+    MaxLength(usize),
+    Pattern(Regex, String),
+    LanguageIn(Vec<String>),
+    UniqueLang,
+    Equals(String),
+    Disjoint(String),
+    LessThan(String),
+    LessThanOrEquals(String),
+    Not(usize),
+    And(Vec<usize>),
+    Or(Vec<usize>),
+    Xone(Vec<usize>),
+    Node(usize),
+    Property(usize),
+    QualifiedMinCount(usize, usize),
+    QualifiedMaxCount(usize, usize),
+    Closed(HashSet<String>),
+    HasValue(Term),
+    In(Vec<Term>),
+}
+
+impl Constraint {
+    /// Local name of the constraint component, in the SHACL namespace.
+    fn component(&self) -> &'static str {
+        match self {
+            Constraint::Class(_) => "ClassConstraintComponent",
+            Constraint::Datatype(_) => "DatatypeConstraintComponent",
+            Constraint::NodeKind(_) => "NodeKindConstraintComponent",
+            Constraint::MinCount(_) => "MinCountConstraintComponent",
+            Constraint::MaxCount(_) => "MaxCountConstraintComponent",
+            Constraint::MinExclusive(_) => "MinExclusiveConstraintComponent",
+            Constraint::MinInclusive(_) => "MinInclusiveConstraintComponent",
+            Constraint::MaxExclusive(_) => "MaxExclusiveConstraintComponent",
+            Constraint::MaxInclusive(_) => "MaxInclusiveConstraintComponent",
+            Constraint::MinLength(_) => "MinLengthConstraintComponent",
+            Constraint::MaxLength(_) => "MaxLengthConstraintComponent",
+            Constraint::Pattern(..) => "PatternConstraintComponent",
+            Constraint::LanguageIn(_) => "LanguageInConstraintComponent",
+            Constraint::UniqueLang => "UniqueLangConstraintComponent",
+            Constraint::Equals(_) => "EqualsConstraintComponent",
+            Constraint::Disjoint(_) => "DisjointConstraintComponent",
+            Constraint::LessThan(_) => "LessThanConstraintComponent",
+            Constraint::LessThanOrEquals(_) => "LessThanOrEqualsConstraintComponent",
+            Constraint::Not(_) => "NotConstraintComponent",
+            Constraint::And(_) => "AndConstraintComponent",
+            Constraint::Or(_) => "OrConstraintComponent",
+            Constraint::Xone(_) => "XoneConstraintComponent",
+            Constraint::Node(_) => "NodeConstraintComponent",
+            Constraint::Property(_) => "PropertyConstraintComponent",
+            Constraint::QualifiedMinCount(..) => "QualifiedMinCountConstraintComponent",
+            Constraint::QualifiedMaxCount(..) => "QualifiedMaxCountConstraintComponent",
+            Constraint::Closed(_) => "ClosedConstraintComponent",
+            Constraint::HasValue(_) => "HasValueConstraintComponent",
+            Constraint::In(_) => "InConstraintComponent",
+        }
+    }
+}
+
+/// A node or property shape.
+///
+/// ## Fields
+/// * `id` - Node of the shape in the shapes graph.
+/// * `label` - Name shown in reports: the IRI of the shape, or its `sh:name`, or its parent and path.
+/// * `path` - Path of a property shape, `None` for node shapes.
+/// * `targets`, `constraints` - Parsed from the shapes graph.
+/// * `severity`, `message`, `deactivated` - `sh:severity`, `sh:message` and `sh:deactivated`.
+pub struct Shape {
+    pub id: Term,
+    pub label: String,
+    pub path: Option<Path>,
+    targets: Vec<Target>,
+    constraints: Vec<Constraint>,
+    severity: Severity,
+    message: Option<String>,
+    deactivated: bool,
+}
+
+/// Triples of a shapes graph, indexed by subject and predicate.
+#[derive(Default)]
+struct ShapesGraph {
+    objects: HashMap<(Term, String), Vec<Term>>,
+    subjects: Vec<Term>,
+    seen: HashSet<Term>,
+}
+
+impl ShapesGraph {
+    fn insert(&mut self, subject: Term, predicate: String, object: Term) {
+        if self.seen.insert(subject.clone()) {
+            self.subjects.push(subject.clone());
+        }
+        self.objects.entry((subject, predicate)).or_default().push(object);
+    }
+
+    fn objects(&self, subject: &Term, predicate: &str) -> &[Term] {
+        self.objects
+            .get(&(subject.clone(), predicate.to_string()))
+            .map(|o| o.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn object(&self, subject: &Term, predicate: &str) -> Option<&Term> {
+        self.objects(subject, predicate).first()
+    }
+
+    /// Items of the RDF list starting at `head`.
+    fn list(&self, head: &Term) -> Vec<Term> {
+        let mut items = vec![];
+        let mut node = head.clone();
+        let mut seen = HashSet::new();
+        while seen.insert(node.clone()) {
+            let Some(item) = self.object(&node, RDF_FIRST) else {
+                break;
+            };
+            items.push(item.clone());
+            match self.object(&node, RDF_REST) {
+                Some(rest) => {
+                    node = rest.clone();
+                }
+                None => {
+                    break;
+                }
+            }
+        }
+        items
+    }
+
+    fn has_type(&self, subject: &Term, class: &str) -> bool {
+        self.objects(subject, RDF_TYPE)
+            .iter()
+            .any(|t| matches!(t, Term::NamedNode(n) if n.as_str() == class))
+    }
+}
+
+fn iri(term: &Term) -> Option<String> {
+    match term {
+        Term::NamedNode(node) => Some(node.as_str().to_string()),
+        _ => None,
+    }
+}
+
+/// Lexical form of an IRI or a literal.
+fn lexical(term: &Term) -> Option<&str> {
+    match term {
+        Term::NamedNode(node) => Some(node.as_str()),
+        Term::Literal(literal) => Some(literal.value()),
+        _ => None,
+    }
+}
+
+fn is_true(term: Option<&Term>) -> bool {
+    matches!(term, Some(Term::Literal(l)) if l.value() == "true" || l.value() == "1")
+}
+
+fn count(shape: &Term, term: &Term, name: &str) -> Result<usize, String> {
+    lexical(term)
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .ok_or_else(|| format!("Invalid sh:{name} of {shape}: {term}"))
+}
+
+/// Builds shapes from a shapes graph.
+struct ShapeParser<'a> {
+    graph: &'a ShapesGraph,
+    shapes: Vec<Option<Shape>>,
+    index: HashMap<Term, usize>,
+}
+
+impl ShapeParser<'_> {
+    /// Index of the shape of `node`, parsing it on first use.
+    fn shape(&mut self, node: &Term, parent: Option<&str>) -> Result<usize, String> {
+        if let Some(i) = self.index.get(node) {
+            return Ok(*i);
+        }
+        let i = self.shapes.len();
+        self.shapes.push(None);
+        self.index.insert(node.clone(), i);
+        let shape = self.parse(node, parent)?;
+        self.shapes[i] = Some(shape);
+        Ok(i)
+    }
+
+    fn shapes(&mut self, nodes: &[Term], parent: &str) -> Result<Vec<usize>, String> {
+        nodes
+            .iter()
+            .map(|n| self.shape(n, Some(parent)))
+            .collect()
+    }
+
+    fn path(&self, node: &Term) -> Result<Path, String> {
+        if let Term::NamedNode(predicate) = node {
+            if predicate.as_str() != RDF_NIL {
+                return Ok(Path::Predicate(predicate.as_str().to_string()));
+            }
+        }
+        let g = self.graph;
+        let inner = |name: &str| g.object(node, &sh(name)).map(|p| self.path(p).map(Box::new));
+        if let Some(path) = inner("inversePath") {
+            return Ok(Path::Inverse(path?));
+        }
+        if let Some(path) = inner("zeroOrMorePath") {
+            return Ok(Path::ZeroOrMore(path?));
+        }
+        if let Some(path) = inner("oneOrMorePath") {
+            return Ok(Path::OneOrMore(path?));
+        }
+        if let Some(path) = inner("zeroOrOnePath") {
+            return Ok(Path::ZeroOrOne(path?));
+        }
+        if let Some(list) = g.object(node, &sh("alternativePath")) {
+            let paths = g
+                .list(list)
+                .iter()
+                .map(|p| self.path(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Path::Alternative(paths));
+        }
+        let items = g.list(node);
+        if !items.is_empty() {
+            let mut paths = items
+                .iter()
+                .map(|p| self.path(p))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(if paths.len() == 1 { paths.remove(0) } else { Path::Sequence(paths) });
+        }
+        Err(format!("Unsupported sh:path: {node}"))
+    }
+
+    fn parse(&mut self, node: &Term, parent: Option<&str>) -> Result<Shape, String> {
+        let g = self.graph;
+        let path = g
+            .object(node, &sh("path"))
+            .map(|p| self.path(p))
+            .transpose()?;
+        let label = match (node, g.object(node, &sh("name")).and_then(lexical), &path, parent) {
+            (Term::NamedNode(n), ..) => format!("<{}>", n.as_str()),
+            (_, Some(name), _, Some(parent)) => format!("{parent} / {name}"),
+            (_, Some(name), _, None) => name.to_string(),
+            (_, None, Some(path), Some(parent)) => format!("{parent} / {path}"),
+            (_, None, Some(path), None) => path.to_string(),
+            _ => node.to_string(),
+        };
+
+        let mut targets = vec![];
+        for t in g.objects(node, &sh("targetClass")) {
+            targets.extend(iri(t).map(Target::Class));
+        }
+        for t in g.objects(node, &sh("targetNode")) {
+            targets.push(Target::Node(t.clone()));
+        }
+        for t in g.objects(node, &sh("targetSubjectsOf")) {
+            targets.extend(iri(t).map(Target::SubjectsOf));
+        }
+        for t in g.objects(node, &sh("targetObjectsOf")) {
+            targets.extend(iri(t).map(Target::ObjectsOf));
+        }
+        // Implicit class target
+        if g.has_type(node, RDFS_CLASS) {
+            targets.extend(iri(node).map(Target::Class));
+        }
+
+        let mut constraints = vec![];
+        for (name, objects) in [
+            "class",
+            "datatype",
+            "nodeKind",
+            "minCount",
+            "maxCount",
+            "minExclusive",
+            "minInclusive",
+            "maxExclusive",
+            "maxInclusive",
+            "minLength",
+            "maxLength",
+            "pattern",
+            "languageIn",
+            "uniqueLang",
+            "equals",
+            "disjoint",
+            "lessThan",
+            "lessThanOrEquals",
+            "not",
+            "and",
+            "or",
+            "xone",
+            "node",
+            "property",
+            "closed",
+            "hasValue",
+            "in",
+        ].map(|name| (name, g.objects(node, &sh(name)))) {
+            for object in objects {
+                let object_iri = || iri(object).ok_or_else(|| format!("sh:{name} of {label} must be an IRI"));
+                let constraint = match name {
+                    "class" => Constraint::Class(object_iri()?),
+                    "datatype" => Constraint::Datatype(object_iri()?),
+                    "nodeKind" =>
+                        Constraint::NodeKind(
+                            object_iri()?.strip_prefix(SH).unwrap_or_default().to_string()
+                        ),
+                    "minCount" => Constraint::MinCount(count(node, object, name)?),
+                    "maxCount" => Constraint::MaxCount(count(node, object, name)?),
+                    "minExclusive" => Constraint::MinExclusive(object.clone()),
+                    "minInclusive" => Constraint::MinInclusive(object.clone()),
+                    "maxExclusive" => Constraint::MaxExclusive(object.clone()),
+                    "maxInclusive" => Constraint::MaxInclusive(object.clone()),
+                    "minLength" => Constraint::MinLength(count(node, object, name)?),
+                    "maxLength" => Constraint::MaxLength(count(node, object, name)?),
+                    "pattern" => {
+                        let pattern = lexical(object).unwrap_or_default().to_string();
+                        // The XPath flags supported by the regex crate
+                        let flags = g
+                            .object(node, &sh("flags"))
+                            .and_then(lexical)
+                            .unwrap_or_default()
+                            .chars()
+                            .filter(|c| matches!(c, 'i' | 'm' | 's' | 'x'))
+                            .collect::<String>();
+                        let source = if flags.is_empty() {
+                            pattern.clone()
+                        } else {
+                            format!("(?{flags}){pattern}")
+                        };
+                        let regex = Regex::new(&source).map_err(|e|
+                            format!("Invalid sh:pattern of {label}: {e}")
+                        )?;
+                        Constraint::Pattern(regex, pattern)
+                    }
+                    "languageIn" =>
+                        Constraint::LanguageIn(
+                            g
+                                .list(object)
+                                .iter()
+                                .filter_map(|l| lexical(l).map(|l| l.to_string()))
+                                .collect()
+                        ),
+                    "uniqueLang" if is_true(Some(object)) => Constraint::UniqueLang,
+                    "equals" => Constraint::Equals(object_iri()?),
+                    "disjoint" => Constraint::Disjoint(object_iri()?),
+                    "lessThan" => Constraint::LessThan(object_iri()?),
+                    "lessThanOrEquals" => Constraint::LessThanOrEquals(object_iri()?),
+                    "not" => Constraint::Not(self.shape(object, Some(&label))?),
+                    "and" => Constraint::And(self.shapes(&g.list(object), &label)?),
+                    "or" => Constraint::Or(self.shapes(&g.list(object), &label)?),
+                    "xone" => Constraint::Xone(self.shapes(&g.list(object), &label)?),
+                    "node" => Constraint::Node(self.shape(object, Some(&label))?),
+                    "property" => Constraint::Property(self.shape(object, Some(&label))?),
+                    "closed" if is_true(Some(object)) => {
+                        let mut allowed = HashSet::from([RDF_TYPE.to_string()]);
+                        for property in g.objects(node, &sh("property")) {
+                            allowed.extend(g.object(property, &sh("path")).and_then(iri));
+                        }
+                        for ignored in g.objects(node, &sh("ignoredProperties")) {
+                            allowed.extend(g.list(ignored).iter().filter_map(iri));
+                        }
+                        Constraint::Closed(allowed)
+                    }
+                    "hasValue" => Constraint::HasValue(object.clone()),
+                    "in" => Constraint::In(g.list(object)),
+                    _ => {
+                        continue;
+                    }
+                };
+                constraints.push(constraint);
+            }
+        }
+        for qualified in g.objects(node, &sh("qualifiedValueShape")) {
+            let shape = self.shape(qualified, Some(&label))?;
+            if let Some(min) = g.object(node, &sh("qualifiedMinCount")) {
+                constraints.push(Constraint::QualifiedMinCount(shape, count(node, min, "qualifiedMinCount")?));
+            }
+            if let Some(max) = g.object(node, &sh("qualifiedMaxCount")) {
+                constraints.push(Constraint::QualifiedMaxCount(shape, count(node, max, "qualifiedMaxCount")?));
+            }
+        }
+
+        Ok(Shape {
+            id: node.clone(),
+            label,
+            path,
+            targets,
+            constraints,
+            severity: g
+                .object(node, &sh("severity"))
+                .and_then(iri)
+                .and_then(|s| Severity::parse(&s))
+                .unwrap_or(Severity::Violation),
+            message: g
+                .object(node, &sh("message"))
+                .and_then(lexical)
+                .map(|m| m.to_string()),
+            deactivated: is_true(g.object(node, &sh("deactivated"))),
+        })
+    }
+}
+
+/// Parsed shapes, ready to validate the store.
+pub struct Shapes {
+    shapes: Vec<Shape>,
+}
+
+impl Shapes {
+    /// Loads the shapes of RDF files and of a named graph of the store, merged in a single shapes graph.
+    ///
+    /// # Errors
+    /// Returns a message if a file cannot be read or parsed, the graph is not a valid IRI, or a shape is invalid.
+    pub fn load(kg: &KG, files: &[String], graph: Option<&str>) -> Result<Shapes, String> {
+        let mut shapes_graph = ShapesGraph::default();
+        for path in files {
+            let format = FilePath::new(path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .and_then(RdfFormat::from_extension)
+                .ok_or_else(|| format!("Unknown RDF format of the shapes file {path}"))?;
+            let file = File::open(path).map_err(|e| format!("Cannot open the shapes file {path}: {e}"))?;
+            for quad in RdfParser::from_format(format).for_reader(BufReader::new(file)) {
+                let quad = quad.map_err(|e| format!("Invalid shapes file {path}: {e}"))?;
+                shapes_graph.insert(quad.subject.into(), quad.predicate.as_str().to_string(), quad.object);
+            }
+        }
+        if let Some(graph) = graph {
+            let graph = NamedNode::from_str(graph.trim().trim_matches(['<', '>'])).map_err(|_|
+                format!("Invalid graph IRI: {graph}")
+            )?;
+            let rows = kg
+                .query(&format!("SELECT ?s ?p ?o WHERE {{ GRAPH {graph} {{ ?s ?p ?o }} }}"))
+                .map_err(|e| e.to_string())?;
+            if rows.is_empty() {
+                return Err(format!("The graph {graph} is empty"));
+            }
+            for row in rows {
+                if let (Some(s), Some(Term::NamedNode(p)), Some(o)) = (row.get("s"), row.get("p"), row.get("o")) {
+                    shapes_graph.insert(s.clone(), p.as_str().to_string(), o.clone());
+                }
+            }
+        }
+        Shapes::parse(&shapes_graph)
+    }
+
+    /// Loads the shapes of files of `SHAPES_DIR`, given by name, and of a named graph, as selected in the UI.
+    ///
+    /// # Errors
+    /// Same as `load`, and returns a message if nothing is selected or a name is not a file of `SHAPES_DIR`.
+    pub fn load_selected(kg: &KG, names: &[String], graph: Option<&str>) -> Result<Shapes, String> {
+        if names.is_empty() && graph.is_none() {
+            return Err("No shapes selected: give shape files or a named graph".to_string());
+        }
+        let files = names
+            .iter()
+            .map(|name| shape_file_path(name).ok_or_else(|| format!("Invalid shape file: {name}")))
+            .collect::<Result<Vec<_>, _>>()?;
+        Shapes::load(kg, &files, graph)
+    }
+
+    fn parse(graph: &ShapesGraph) -> Result<Shapes, String> {
+        let is_shape = |node: &Term| {
+            graph.has_type(node, &sh("NodeShape")) ||
+                graph.has_type(node, &sh("PropertyShape")) ||
+                ["targetClass", "targetNode", "targetSubjectsOf", "targetObjectsOf"]
+                    .iter()
+                    .any(|t| graph.object(node, &sh(t)).is_some())
+        };
+        let mut parser = ShapeParser { graph, shapes: vec![], index: HashMap::new() };
+        for node in graph.subjects.iter().filter(|n| is_shape(*n)) {
+            parser.shape(node, None)?;
+        }
+        let shapes = parser.shapes.into_iter().flatten().collect::<Vec<_>>();
+        if shapes.is_empty() {
+            return Err("No SHACL shapes found".to_string());
+        }
+        Ok(Shapes { shapes })
+    }
+}
+
+/// Lists the RDF files of `SHAPES_DIR`, sorted by name.
+pub fn list_shape_files() -> Vec<String> {
+    let mut files = fs
+        ::read_dir(SHAPES_DIR)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .filter(|name| {
+                    FilePath::new(name)
+                        .extension()
+                        .and_then(|e| e.to_str())
+                        .and_then(RdfFormat::from_extension)
+                        .is_some()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+/// Path of a file of `SHAPES_DIR`, rejecting names that would leave it.
+pub fn shape_file_path(name: &str) -> Option<String> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        return None;
+    }
+    Some(format!("{SHAPES_DIR}/{name}"))
+}
+
+fn term_string<S: Serializer>(term: &Term, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(term)
+}
+
+fn optional_term_string<S: Serializer>(term: &Option<Term>, serializer: S) -> Result<S::Ok, S::Error> {
+    match term {
+        Some(term) => serializer.collect_str(term),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// A validation result (`sh:ValidationResult`).
+///
+/// ## Fields
+/// * `focus_node` - Node that does not conform.
+/// * `path` - Path of the property shape, or the offending predicate of a closed shape.
+/// * `value` - Offending value, for the constraints checking values one by one.
+/// * `shape`, `shape_id` - Label and node of the shape holding the constraint.
+/// * `component` - Local name of the constraint component (`MinCountConstraintComponent`).
+/// * `severity`, `message` - Severity and message of the result.
+#[derive(Serialize, Clone)]
+pub struct ValidationResult {
+    #[serde(serialize_with = "term_string")]
+    pub focus_node: Term,
+    pub path: Option<Path>,
+    #[serde(serialize_with = "optional_term_string")]
+    pub value: Option<Term>,
+    pub shape: String,
+    #[serde(skip)]
+    pub shape_id: Term,
+    pub component: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// A validation report (`sh:ValidationReport`).
+///
+/// ## Fields
+/// * `conforms` - Whether there is no result at all, whatever its severity.
+/// * `shapes` - Number of shapes with targets that were checked.
+/// * `focus_nodes` - Number of focus nodes of these shapes.
+/// * `results` - Validation results, grouped by shape then focus node.
+#[derive(Serialize, Clone)]
+pub struct ValidationReport {
+    pub conforms: bool,
+    pub shapes: usize,
+    pub focus_nodes: usize,
+    pub results: Vec<ValidationResult>,
+}
+
+impl ValidationReport {
+    /// Number of results of the given severity.
+    pub fn count(&self, severity: Severity) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.severity == severity)
+            .count()
+    }
+
+    /// Serializes the report in the SHACL vocabulary.
+    ///
+    /// # Errors
+    /// Returns a message if the format does not support triples.
+    pub fn to_rdf(&self, format: RdfFormat) -> Result<String, String> {
+        let mut triples = vec![];
+        let report = BlankNode::default();
+        let rdf_type = NamedNode::new_unchecked(RDF_TYPE);
+        triples.push(Triple::new(report.clone(), rdf_type.clone(), sh_node("ValidationReport")));
+        triples.push(Triple::new(report.clone(), sh_node("conforms"), Literal::from(self.conforms)));
+        for result in &self.results {
+            let node = BlankNode::default();
+            triples.push(Triple::new(report.clone(), sh_node("result"), node.clone()));
+            triples.push(Triple::new(node.clone(), rdf_type.clone(), sh_node("ValidationResult")));
+            triples.push(Triple::new(node.clone(), sh_node("focusNode"), result.focus_node.clone()));
+            if let Some(path) = &result.path {
+                let path = path.to_rdf(&mut triples);
+                triples.push(Triple::new(node.clone(), sh_node("resultPath"), path));
+            }
+            if let Some(value) = &result.value {
+                triples.push(Triple::new(node.clone(), sh_node("value"), value.clone()));
+            }
+            triples.push(Triple::new(node.clone(), sh_node("sourceShape"), result.shape_id.clone()));
+            triples.push(Triple::new(node.clone(), sh_node("sourceConstraintComponent"), sh_node(&result.component)));
+            triples.push(Triple::new(node.clone(), sh_node("resultSeverity"), sh_node(result.severity.name())));
+            triples.push(
+                Triple::new(node.clone(), sh_node("resultMessage"), Literal::new_simple_literal(&result.message))
+            );
+        }
+
+        let serializer = RdfSerializer::from_format(format)
+            .with_prefix("sh", SH)
+            .map_err(|e| e.to_string())?;
+        let mut writer = serializer.for_writer(Vec::new());
+        for triple in &triples {
+            writer.serialize_triple(triple).map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.finish().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+}
+
+/// Group pattern binding `?focus` to the given nodes (blank nodes cannot be sent to the store).
+fn values_pattern(nodes: &[Term]) -> String {
+    let nodes = nodes
+        .iter()
+        .filter(|n| matches!(n, Term::NamedNode(_) | Term::Literal(_)))
+        .map(|n| n.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("VALUES ?focus {{ {nodes} }}")
+}
+
+/// Group pattern binding `?focus` to the targets of a shape.
+fn target_pattern(targets: &[Target]) -> String {
+    targets
+        .iter()
+        .map(|t| {
+            match t {
+                Target::Class(class) =>
+                    format!("{{ ?focus <{RDF_TYPE}>/<{RDFS_SUBCLASS_OF}>* <{class}> }}"),
+                Target::Node(node) => format!("{{ {} }}", values_pattern(std::slice::from_ref(node))),
+                Target::SubjectsOf(p) => format!("{{ SELECT DISTINCT ?focus WHERE {{ ?focus <{p}> ?target }} }}"),
+                Target::ObjectsOf(p) => format!("{{ SELECT DISTINCT ?focus WHERE {{ ?target <{p}> ?focus }} }}"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ")
+}
+
+fn is_numeric(literal: &Literal) -> bool {
+    literal
+        .datatype()
+        .as_str()
+        .strip_prefix(XSD)
+        .is_some_and(|t| NUMERIC_TYPES.contains(&t))
+}
+
+/// Whether the lexical form of a literal is valid for its datatype (only checked for numbers and booleans).
+fn well_formed(literal: &Literal) -> bool {
+    let value = literal.value().trim();
+    match literal.datatype().as_str().strip_prefix(XSD) {
+        Some("double" | "float") => value.parse::<f64>().is_ok(),
+        Some("decimal") => value.parse::<f64>().is_ok() && !value.contains(['e', 'E']),
+        Some("boolean") => matches!(value, "true" | "false" | "1" | "0"),
+        Some(t) if NUMERIC_TYPES.contains(&t) => value.parse::<i128>().is_ok(),
+        _ => true,
+    }
+}
+
+/// Order of two literals: numbers by value, other literals of the same datatype by lexical form.
+fn compare(a: &Term, b: &Term) -> Option<Ordering> {
+    let (Term::Literal(a), Term::Literal(b)) = (a, b) else {
+        return None;
+    };
+    if is_numeric(a) && is_numeric(b) {
+        let a = a.value().trim().parse::<f64>().ok()?;
+        let b = b.value().trim().parse::<f64>().ok()?;
+        a.partial_cmp(&b)
+    } else if a.datatype() == b.datatype() && a.language() == b.language() && !is_numeric(a) {
+        Some(a.value().cmp(b.value()))
+    } else {
+        None
+    }
+}
+
+/// Basic language range matching (RFC 4647), as used by `sh:languageIn`.
+fn language_matches(tag: &str, range: &str) -> bool {
+    let (tag, range) = (tag.to_ascii_lowercase(), range.to_ascii_lowercase());
+    (range == "*" && !tag.is_empty()) || tag == range || tag.starts_with(&format!("{range}-"))
+}
+
+fn node_kind_matches(kind: &str, term: &Term) -> bool {
+    let iri = matches!(term, Term::NamedNode(_));
+    let blank = matches!(term, Term::BlankNode(_));
+    let literal = matches!(term, Term::Literal(_));
+    match kind {
+        "IRI" => iri,
+        "BlankNode" => blank,
+        "Literal" => literal,
+        "BlankNodeOrIRI" => blank || iri,
+        "BlankNodeOrLiteral" => blank || literal,
+        "IRIOrLiteral" => iri || literal,
+        _ => true,
+    }
+}
+
+/// Data fetched once for all of the focus nodes of a shape before checking a constraint.
+enum Prepared {
+    None,
+    Nodes(HashSet<Term>),
+    Values(HashMap<Term, Vec<Term>>),
+    Shapes(Vec<HashSet<Term>>),
+    Outgoing(HashMap<Term, Vec<(String, Term)>>),
+}
+
+/// Validation state.
+///
+/// ## Fields
+/// * `stack` - Shapes being checked on nested value nodes, to cut recursion.
+/// * `conformance` - Known conformance of nodes to nested shapes.
+struct Validator<'a> {
+    kg: &'a KG,
+    shapes: &'a [Shape],
+    stack: Vec<usize>,
+    conformance: HashMap<(usize, Term), bool>,
+}
+
+impl Validator<'_> {
+    fn select(&self, query: &str) -> Result<Vec<QuerySolution>, String> {
+        self.kg.query(query).map_err(|e| format!("{e} in {query}"))
+    }
+
+    /// Values reached from the focus nodes of `pattern` through `path`.
+    fn path_values(&self, pattern: &str, path: &Path) -> Result<HashMap<Term, Vec<Term>>, String> {
+        let rows = self.select(&format!("SELECT ?focus ?value WHERE {{ {{ {pattern} }} ?focus {path} ?value }}"))?;
+        let mut values: HashMap<Term, Vec<Term>> = HashMap::new();
+        for row in rows {
+            if let (Some(focus), Some(value)) = (row.get("focus"), row.get("value")) {
+                let entry = values.entry(focus.clone()).or_default();
+                if !entry.contains(value) {
+                    entry.push(value.clone());
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Nodes among `nodes` that are instances of `class` or of one of its subclasses.
+    fn instances_of(&self, class: &str, nodes: &[Term]) -> Result<HashSet<Term>, String> {
+        let mut instances = HashSet::new();
+        for chunk in nodes.chunks(NODES_PER_QUERY) {
+            let rows = self.select(
+                &format!(
+                    "SELECT DISTINCT ?focus WHERE {{ {} ?focus <{RDF_TYPE}>/<{RDFS_SUBCLASS_OF}>* <{class}> }}",
+                    values_pattern(chunk)
+                )
+            )?;
+            instances.extend(rows.iter().filter_map(|r| r.get("focus").cloned()));
+        }
+        Ok(instances)
+    }
+
+    /// Outgoing triples of `nodes`.
+    fn outgoing(&self, nodes: &[Term]) -> Result<HashMap<Term, Vec<(String, Term)>>, String> {
+        let mut outgoing: HashMap<Term, Vec<(String, Term)>> = HashMap::new();
+        for chunk in nodes.chunks(NODES_PER_QUERY) {
+            let rows = self.select(&format!("SELECT ?focus ?p ?o WHERE {{ {} ?focus ?p ?o }}", values_pattern(chunk)))?;
+            for row in rows {
+                if let (Some(focus), Some(Term::NamedNode(p)), Some(o)) = (row.get("focus"), row.get("p"), row.get("o")) {
+                    outgoing.entry(focus.clone()).or_default().push((p.as_str().to_string(), o.clone()));
+                }
+            }
+        }
+        Ok(outgoing)
+    }
+
+    /// Nodes among `nodes` that do not conform to the shape `shape`.
+    fn non_conforming(&mut self, shape: usize, nodes: &[Term]) -> Result<HashSet<Term>, String> {
+        let mut failing = HashSet::new();
+        if self.stack.contains(&shape) {
+            return Ok(failing);
+        }
+        let mut unknown = vec![];
+        for node in nodes {
+            match self.conformance.get(&(shape, node.clone())) {
+                Some(false) => {
+                    failing.insert(node.clone());
+                }
+                Some(true) => {}
+                None => unknown.push(node.clone()),
+            }
+        }
+        self.stack.push(shape);
+        let mut checked = Ok(());
+        for chunk in unknown.chunks(NODES_PER_QUERY) {
+            let mut results = vec![];
+            if let Err(e) = self.validate_shape(shape, &values_pattern(chunk), &mut results) {
+                checked = Err(e);
+                break;
+            }
+            let failed = results
+                .into_iter()
+                .map(|r| r.focus_node)
+                .collect::<HashSet<_>>();
+            for node in chunk {
+                let conforms = !failed.contains(node);
+                self.conformance.insert((shape, node.clone()), conforms);
+                if !conforms {
+                    failing.insert(node.clone());
+                }
+            }
+        }
+        self.stack.pop();
+        checked.map(|_| failing)
+    }
+
+    /// Checks the shape `index` on the focus nodes bound by `pattern`, appending the results to `out`.
+    ///
+    /// Returns the number of focus nodes.
+    fn validate_shape(
+        &mut self,
+        index: usize,
+        pattern: &str,
+        out: &mut Vec<ValidationResult>
+    ) -> Result<usize, String> {
+        let shapes = self.shapes;
+        let shape = &shapes[index];
+        if shape.deactivated {
+            return Ok(0);
+        }
+        let focus_nodes = self
+            .select(&format!("SELECT DISTINCT ?focus WHERE {{ {{ {pattern} }} }}"))?
+            .iter()
+            .filter_map(|r| r.get("focus").cloned())
+            .collect::<Vec<_>>();
+        if focus_nodes.is_empty() {
+            return Ok(0);
+        }
+        let path_values = match &shape.path {
+            Some(path) => Some(self.path_values(pattern, path)?),
+            None => None,
+        };
+        let values_of = |focus: &Term| -> Vec<Term> {
+            match &path_values {
+                Some(values) => values.get(focus).cloned().unwrap_or_default(),
+                None => vec![focus.clone()],
+            }
+        };
+        let mut all_values = vec![];
+        let mut seen = HashSet::new();
+        for focus in &focus_nodes {
+            for value in values_of(focus) {
+                if seen.insert(value.clone()) {
+                    all_values.push(value);
+                }
+            }
+        }
+
+        let mut prepared = vec![];
+        for constraint in &shape.constraints {
+            prepared.push(match constraint {
+                Constraint::Class(class) => Prepared::Nodes(self.instances_of(class, &all_values)?),
+                Constraint::Equals(p) |
+                Constraint::Disjoint(p) |
+                Constraint::LessThan(p) |
+                Constraint::LessThanOrEquals(p) =>
+                    Prepared::Values(self.path_values(pattern, &Path::Predicate(p.clone()))?),
+                Constraint::Not(s) |
+                Constraint::Node(s) |
+                Constraint::QualifiedMinCount(s, _) |
+                Constraint::QualifiedMaxCount(s, _) =>
+                    Prepared::Shapes(vec![self.non_conforming(*s, &all_values)?]),
+                Constraint::And(members) | Constraint::Or(members) | Constraint::Xone(members) => {
+                    let mut sets = vec![];
+                    for s in members {
+                        sets.push(self.non_conforming(*s, &all_values)?);
+                    }
+                    Prepared::Shapes(sets)
+                }
+                Constraint::Property(s) => {
+                    // Property shapes apply to the value nodes, which are the focus nodes of a node shape
+                    if shape.path.is_none() {
+                        self.validate_shape(*s, pattern, out)?;
+                    } else {
+                        for chunk in all_values.chunks(NODES_PER_QUERY) {
+                            self.validate_shape(*s, &values_pattern(chunk), out)?;
+                        }
+                    }
+                    Prepared::None
+                }
+                Constraint::Closed(_) => Prepared::Outgoing(self.outgoing(&all_values)?),
+                _ => Prepared::None,
+            });
+        }
+
+        for focus in &focus_nodes {
+            let values = values_of(focus);
+            for (constraint, prepared) in shape.constraints.iter().zip(&prepared) {
+                let mut report = |value: Option<&Term>, path: Option<Path>, message: String| {
+                    out.push(ValidationResult {
+                        focus_node: focus.clone(),
+                        path: path.or_else(|| shape.path.clone()),
+                        value: value.cloned(),
+                        shape: shape.label.clone(),
+                        shape_id: shape.id.clone(),
+                        component: constraint.component().to_string(),
+                        severity: shape.severity,
+                        message: shape.message.clone().unwrap_or(message),
+                    });
+                };
+                let mut each = |ok: &dyn Fn(&Term) -> bool, message: &dyn Fn(&Term) -> String| {
+                    for value in values.iter().filter(|v| !ok(*v)) {
+                        report(Some(value), None, message(value));
+                    }
+                };
+                let others = || -> Vec<Term> {
+                    match prepared {
+                        Prepared::Values(values) => values.get(focus).cloned().unwrap_or_default(),
+                        _ => vec![],
+                    }
+                };
+                let failing = |i: usize, value: &Term| -> bool {
+                    matches!(prepared, Prepared::Shapes(sets) if sets.get(i).is_some_and(|s| s.contains(value)))
+                };
+                match constraint {
+                    Constraint::Class(class) =>
+                        each(
+                            &(|v| matches!(prepared, Prepared::Nodes(n) if n.contains(v))),
+                            &(|_| format!("Value is not an instance of <{class}>"))
+                        ),
+                    Constraint::Datatype(datatype) =>
+                        each(
+                            &(|v| matches!(v, Term::Literal(l) if l.datatype().as_str() == datatype && well_formed(l))),
+                            &(|_| format!("Value is not a literal of datatype <{datatype}>"))
+                        ),
+                    Constraint::NodeKind(kind) =>
+                        each(
+                            &(|v| node_kind_matches(kind, v)),
+                            &(|_| format!("Value is not of node kind sh:{kind}"))
+                        ),
+                    Constraint::MinCount(min) if values.len() < *min =>
+                        report(None, None, format!("Less than {min} values ({})", values.len())),
+                    Constraint::MaxCount(max) if values.len() > *max =>
+                        report(None, None, format!("More than {max} values ({})", values.len())),
+                    Constraint::MinExclusive(bound) =>
+                        each(
+                            &(|v| compare(v, bound) == Some(Ordering::Greater)),
+                            &(|_| format!("Value is not greater than {bound}"))
+                        ),
+                    Constraint::MinInclusive(bound) =>
+                        each(
+                            &(|v| matches!(compare(v, bound), Some(Ordering::Greater | Ordering::Equal))),
+                            &(|_| format!("Value is less than {bound}"))
+                        ),
+                    Constraint::MaxExclusive(bound) =>
+                        each(
+                            &(|v| compare(v, bound) == Some(Ordering::Less)),
+                            &(|_| format!("Value is not less than {bound}"))
+                        ),
+                    Constraint::MaxInclusive(bound) =>
+                        each(
+                            &(|v| matches!(compare(v, bound), Some(Ordering::Less | Ordering::Equal))),
+                            &(|_| format!("Value is greater than {bound}"))
+                        ),
+                    Constraint::MinLength(min) =>
+                        each(
+                            &(|v| lexical(v).is_some_and(|s| s.chars().count() >= *min)),
+                            &(|_| format!("Value is shorter than {min} characters"))
+                        ),
+                    Constraint::MaxLength(max) =>
+                        each(
+                            &(|v| lexical(v).is_some_and(|s| s.chars().count() <= *max)),
+                            &(|_| format!("Value is longer than {max} characters"))
+                        ),
+                    Constraint::Pattern(regex, pattern) =>
+                        each(
+                            &(|v| lexical(v).is_some_and(|s| regex.is_match(s))),
+                            &(|_| format!("Value does not match the pattern \"{pattern}\""))
+                        ),
+                    Constraint::LanguageIn(languages) =>
+                        each(
+                            &(|v| {
+                                matches!(v, Term::Literal(l) if l.language().is_some_and(|tag| {
+                                    languages.iter().any(|range| language_matches(tag, range))
+                                }))
+                            }),
+                            &(|_| format!("Language is not one of {}", languages.join(", ")))
+                        ),
+                    Constraint::UniqueLang => {
+                        let mut counts: HashMap<&str, usize> = HashMap::new();
+                        for value in &values {
+                            if let Term::Literal(l) = value {
+                                if let Some(tag) = l.language() {
+                                    *counts.entry(tag).or_default() += 1;
+                                }
+                            }
+                        }
+                        let mut duplicated = counts
+                            .into_iter()
+                            .filter(|(_, n)| *n > 1)
+                            .map(|(tag, _)| tag.to_string())
+                            .collect::<Vec<_>>();
+                        duplicated.sort();
+                        for tag in duplicated {
+                            report(None, None, format!("Language \"{tag}\" is used by more than one value"));
+                        }
+                    }
+                    Constraint::Equals(p) => {
+                        let others = others();
+                        for value in values.iter().filter(|v| !others.contains(v)) {
+                            report(Some(value), None, format!("Value is not a value of <{p}>"));
+                        }
+                        for other in others.iter().filter(|o| !values.contains(o)) {
+                            report(Some(other), None, format!("Value of <{p}> is missing"));
+                        }
+                    }
+                    Constraint::Disjoint(p) => {
+                        let others = others();
+                        each(&(|v| !others.contains(v)), &(|_| format!("Value is also a value of <{p}>")));
+                    }
+                    Constraint::LessThan(p) => {
+                        let others = others();
+                        each(
+                            &(|v| others.iter().all(|o| compare(v, o) == Some(Ordering::Less))),
+                            &(|_| format!("Value is not less than the values of <{p}>"))
+                        );
+                    }
+                    Constraint::LessThanOrEquals(p) => {
+                        let others = others();
+                        each(
+                            &(|v| {
+                                others
+                                    .iter()
+                                    .all(|o| matches!(compare(v, o), Some(Ordering::Less | Ordering::Equal)))
+                            }),
+                            &(|_| format!("Value is greater than the values of <{p}>"))
+                        );
+                    }
+                    Constraint::Not(s) =>
+                        each(
+                            &(|v| failing(0, v)),
+                            &(|_| format!("Value conforms to {}", shapes[*s].label))
+                        ),
+                    Constraint::Node(s) =>
+                        each(
+                            &(|v| !failing(0, v)),
+                            &(|_| format!("Value does not conform to {}", shapes[*s].label))
+                        ),
+                    Constraint::And(members) =>
+                        each(
+                            &(|v| (0..members.len()).all(|i| !failing(i, v))),
+                            &(|_| "Value does not conform to all of the shapes".to_string())
+                        ),
+                    Constraint::Or(members) =>
+                        each(
+                            &(|v| (0..members.len()).any(|i| !failing(i, v))),
+                            &(|_| "Value does not conform to any of the shapes".to_string())
+                        ),
+                    Constraint::Xone(members) =>
+                        each(
+                            &(|v| (0..members.len()).filter(|i| !failing(*i, v)).count() == 1),
+                            &(|_| "Value does not conform to exactly one of the shapes".to_string())
+                        ),
+                    Constraint::QualifiedMinCount(s, min) => {
+                        let conforming = values
+                            .iter()
+                            .filter(|v| !failing(0, *v))
+                            .count();
+                        if conforming < *min {
+                            report(
+                                None,
+                                None,
+                                format!("Less than {min} values conform to {} ({conforming})", shapes[*s].label)
+                            );
+                        }
+                    }
+                    Constraint::QualifiedMaxCount(s, max) => {
+                        let conforming = values
+                            .iter()
+                            .filter(|v| !failing(0, *v))
+                            .count();
+                        if conforming > *max {
+                            report(
+                                None,
+                                None,
+                                format!("More than {max} values conform to {} ({conforming})", shapes[*s].label)
+                            );
+                        }
+                    }
+                    Constraint::Closed(allowed) => {
+                        if let Prepared::Outgoing(outgoing) = prepared {
+                            for value in &values {
+                                for (p, o) in outgoing.get(value).into_iter().flatten() {
+                                    if !allowed.contains(p) {
+                                        report(
+                                            Some(o),
+                                            Some(Path::Predicate(p.clone())),
+                                            format!("Predicate <{p}> is not allowed by the closed shape")
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Constraint::HasValue(expected) if !values.contains(expected) =>
+                        report(None, None, format!("Missing value {expected}")),
+                    Constraint::In(allowed) =>
+                        each(
+                            &(|v| allowed.contains(v)),
+                            &(|_| "Value is not one of the allowed values".to_string())
+                        ),
+                    _ => {}
+                }
+            }
+        }
+        Ok(focus_nodes.len())
+    }
+}
+
+impl KG {
+    /// Validates the store against the shapes with targets.
+    ///
+    /// # Errors
+    /// Returns a message if one of the generated queries fails.
+    pub fn validate_shacl(&self, shapes: &Shapes) -> Result<ValidationReport, String> {
+        let mut validator = Validator {
+            kg: self,
+            shapes: &shapes.shapes,
+            stack: vec![],
+            conformance: HashMap::new(),
+        };
+        let targeted = shapes.shapes
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| !s.targets.is_empty() && !s.deactivated)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+
+        let mut results = vec![];
+        let mut focus_nodes = 0;
+        for (done, index) in targeted.iter().enumerate() {
+            jobs::progress(done as u64, targeted.len() as u64);
+            let shape = &shapes.shapes[*index];
+            let before = results.len();
+            focus_nodes += validator.validate_shape(*index, &target_pattern(&shape.targets), &mut results)?;
+            jobs::log(format!("{}: {} results", shape.label, results.len() - before));
+        }
+        jobs::progress(targeted.len() as u64, targeted.len() as u64);
+
+        Ok(ValidationReport {
+            conforms: results.is_empty(),
+            shapes: targeted.len(),
+            focus_nodes,
+            results,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    const PREFIXES: &str = r#"
+        @prefix schema: <http://schema.org/> .
+        @prefix sh: <http://www.w3.org/ns/shacl#> .
+        @prefix xsd: <http://www.w3.org/2001/XMLSchema#> .
+        @prefix ex: <http://example.org/> .
+    "#;
+
+    /// Validates `data` against the shapes of `shapes`, loaded in the graph `<urn:shapes>` of the same store, and
+    /// returns the (focus node, component) pairs of the results.
+    fn validate(data: &str, shapes: &str) -> (ValidationReport, BTreeSet<(String, String)>) {
+        let kg = KG::in_memory(&format!("{PREFIXES}\n{data}\nGRAPH <urn:shapes> {{ {shapes} }}"));
+        let shapes = Shapes::load(&kg, &[], Some("urn:shapes")).unwrap();
+        let report = kg.validate_shacl(&shapes).unwrap();
+        let results = report.results
+            .iter()
+            .map(|r| (r.focus_node.to_string(), r.component.clone()))
+            .collect();
+        (report, results)
+    }
+
+    fn result(node: &str, component: &str) -> (String, String) {
+        (format!("<http://example.org/{node}>"), format!("{component}ConstraintComponent"))
+    }
+
+    #[test]
+    fn property_constraints() {
+        let (report, results) = validate(
+            r#"
+            ex:b1 a schema:Book ; schema:name "Dune" ; schema:isbn "978-0441013593" ; schema:author ex:p1 .
+            ex:b2 a schema:Book ; schema:isbn "unknown" ; schema:numberOfPages "many" ; schema:author ex:p2 .
+            ex:b3 a schema:Book ; schema:name "A", "B" ; schema:numberOfPages 12 ; schema:author ex:b1 .
+            ex:p1 a schema:Person .
+            ex:p2 a schema:Person .
+            "#,
+            r#"
+            ex:BookShape a sh:NodeShape ;
+                sh:targetClass schema:Book ;
+                sh:property [ sh:path schema:name ; sh:minCount 1 ; sh:maxCount 1 ] ;
+                sh:property [ sh:path schema:isbn ; sh:pattern "^[0-9-]+$" ] ;
+                sh:property [ sh:path schema:numberOfPages ; sh:datatype xsd:integer ] ;
+                sh:property [ sh:path schema:author ; sh:class schema:Person ] .
+            "#
+        );
+        assert!(!report.conforms);
+        assert_eq!(report.shapes, 1);
+        assert_eq!(report.focus_nodes, 3);
+        assert_eq!(
+            results,
+            BTreeSet::from([
+                result("b2", "MinCount"),
+                result("b2", "Pattern"),
+                result("b2", "Datatype"),
+                result("b3", "MaxCount"),
+                result("b3", "Class"),
+            ])
+        );
+        assert_eq!(report.count(Severity::Violation), 5);
+    }
+
+    #[test]
+    fn logical_and_value_constraints() {
+        let (report, results) = validate(
+            r#"
+            ex:p1 a schema:Person ; schema:gender "female" .
+            ex:p2 a schema:Person ; schema:gender "other" .
+            ex:b1 a schema:Book ; schema:gender "male" .
+            "#,
+            r#"
+            ex:PersonShape a sh:NodeShape ;
+                sh:targetNode ex:p1, ex:p2, ex:b1 ;
+                sh:severity sh:Warning ;
+                sh:not [ sh:class schema:Book ] ;
+                sh:property [ sh:path schema:gender ; sh:in ( "female" "male" ) ] .
+            "#
+        );
+        assert_eq!(report.focus_nodes, 3);
+        assert_eq!(results, BTreeSet::from([result("p2", "In"), result("b1", "Not")]));
+        // The severity of the node shape is not inherited by its property shapes
+        assert_eq!(report.count(Severity::Warning), 1);
+        assert_eq!(report.count(Severity::Violation), 1);
+    }
+
+    #[test]
+    fn conforming_data() {
+        let (report, results) = validate(
+            r#"ex:b1 a schema:Book ; schema:name "Dune" ."#,
+            r#"ex:BookShape sh:targetClass schema:Book ; sh:property [ sh:path schema:name ; sh:minCount 1 ] ."#
+        );
+        assert!(report.conforms);
+        assert!(results.is_empty());
+    }
+}
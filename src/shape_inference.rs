@@ -0,0 +1,379 @@
+//! # SHACL shape inference
+//!
+//! Turns the predicate analysis (`KG::predicate_scores`, built on `KG::stat_anal_predicates`) into SHACL shapes,
+//! as a starting schema for a curated dataset:
+//!
+//! - one node shape per class reachable from the starting class, targeting the class;
+//! - one property shape per predicate kept by the analysis (predicates too unique to be analysed, such as names and
+//!   identifiers, are kept too), with `sh:minCount 1` for predicates used by (almost) every instance and
+//!   `sh:maxCount 1` for predicates never used twice by the same instance;
+//! - `sh:datatype`, `sh:class` (or `sh:or` of a few classes) and `sh:nodeKind` when the observed values are dominated
+//!   by one datatype, by typed objects, or by one kind of node.
+//!
+//! The statistics of the analysis are kept in the `sh:description` of every property shape.
+
+use std::collections::HashMap;
+
+use oxigraph::io::{ RdfFormat, RdfSerializer };
+use oxigraph::model::{ BlankNode, Literal, NamedNode, Term, Triple };
+use serde::Serialize;
+
+use crate::jobs;
+use crate::shacl::SH;
+use crate::store::KG;
+use crate::utils::extract_literal;
+use crate::vocabulary;
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_FIRST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#first";
+const RDF_REST: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#rest";
+const RDF_NIL: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#nil";
+
+/// Namespace of the inferred shapes when none is given.
+pub const DEFAULT_SHAPES_NAMESPACE: &str = "http://example.org/shapes/";
+
+/// Largest number of classes combined in a `sh:or` of `sh:class` constraints.
+const MAX_OR_CLASSES: usize = 4;
+
+/// # Thresholds and switches of the inference.
+///
+/// ## Fields
+/// * `required_frequency` - Share of the instances using a predicate above which it gets `sh:minCount 1`.
+/// * `dominant_share` - Share of the values a datatype, a node kind or a set of classes must cover to become a
+///   constraint; the remaining values will be reported by the validation.
+/// * `include_dropped` - Also infer property shapes for the predicates dropped by the analysis.
+/// * `closed` - Make the node shapes `sh:closed`, so that other predicates are reported.
+/// * `namespace` - Namespace of the IRIs of the shapes.
+#[derive(Clone)]
+pub struct ShapeInferenceOptions {
+    pub required_frequency: f64,
+    pub dominant_share: f64,
+    pub include_dropped: bool,
+    pub closed: bool,
+    pub namespace: String,
+}
+
+impl Default for ShapeInferenceOptions {
+    fn default() -> Self {
+        ShapeInferenceOptions {
+            required_frequency: 0.99,
+            dominant_share: 0.95,
+            include_dropped: false,
+            closed: false,
+            namespace: DEFAULT_SHAPES_NAMESPACE.to_string(),
+        }
+    }
+}
+
+/// A property shape inferred for a predicate of a class.
+///
+/// ## Fields
+/// * `predicate` - The predicate, in its SPARQL form.
+/// * `min_count`, `max_count` - Inferred cardinality, if any.
+/// * `node_kind` - `IRI` or `Literal` when the values are dominated by one kind.
+/// * `datatype` - Dominant datatype of the literals.
+/// * `classes` - Classes covering the typed objects (`sh:class`, or `sh:or` of several).
+/// * `frequency` - Share of the instances using the predicate.
+/// * `max_per_instance` - Largest number of values of a single instance.
+/// * `stats` - Statistics of the predicate analysis, if the predicate was analysed.
+/// * `keep` - Decision of the predicate analysis, if the predicate was analysed.
+#[derive(Serialize, Clone)]
+pub struct InferredProperty {
+    pub predicate: String,
+    pub min_count: Option<u64>,
+    pub max_count: Option<u64>,
+    pub node_kind: Option<String>,
+    pub datatype: Option<String>,
+    pub classes: Vec<String>,
+    pub frequency: f64,
+    pub max_per_instance: u64,
+    pub stats: HashMap<String, f64>,
+    pub keep: Option<bool>,
+}
+
+/// A node shape inferred for a class.
+#[derive(Serialize, Clone)]
+pub struct InferredShape {
+    pub class: String,
+    pub count: u64,
+    pub properties: Vec<InferredProperty>,
+}
+
+/// Observed values of a predicate of a class.
+#[derive(Default)]
+struct Observed {
+    subjects: u64,
+    max_per_instance: u64,
+    literals: u64,
+    iris: u64,
+    datatypes: HashMap<String, u64>,
+    classes: HashMap<String, u64>,
+}
+
+fn count(row: &oxigraph::sparql::QuerySolution, name: &str) -> u64 {
+    extract_literal(row.get(name))
+        .and_then(|c| c.parse::<u64>().ok())
+        .unwrap_or(0)
+}
+
+/// Entries of `counts` covering at least `share` of `total`, most used first, if there are at most `max` of them.
+fn dominant(counts: &HashMap<String, u64>, total: u64, share: f64, max: usize) -> Vec<String> {
+    let mut sorted = counts.iter().collect::<Vec<_>>();
+    sorted.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let mut covered = 0;
+    let mut result = vec![];
+    for (value, count) in sorted.into_iter().take(max) {
+        covered += count;
+        result.push(value.clone());
+        if (covered as f64) >= share * (total as f64) {
+            return result;
+        }
+    }
+    vec![]
+}
+
+/// Local part of an IRI usable in the IRI of a shape.
+fn shape_name(iri: &str) -> String {
+    let iri = iri.trim_matches(['<', '>']);
+    vocabulary
+        ::local_name(&format!("<{iri}>"))
+        .map(|n| n.to_string())
+        .unwrap_or_else(|| iri.rsplit(['/', '#']).next().unwrap_or(iri).to_string())
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+impl KG {
+    /// Observed cardinalities, datatypes and object classes of the predicates of `class`.
+    fn observed_values(&self, class: &str) -> HashMap<String, Observed> {
+        let mut observed: HashMap<String, Observed> = HashMap::new();
+        let cardinalities = self
+            .query(
+                &format!(
+                    r#"SELECT ?p (COUNT(?s) AS ?subjects) (MAX(?n) AS ?max) WHERE {{
+                        {{ SELECT ?s ?p (COUNT(?o) AS ?n) WHERE {{ ?s a {class} . ?s ?p ?o }} GROUP BY ?s ?p }}
+                    }} GROUP BY ?p"#
+                )
+            )
+            .unwrap_or_default();
+        for row in cardinalities {
+            if let Some(p) = row.get("p") {
+                let entry = observed.entry(p.to_string()).or_default();
+                entry.subjects = count(&row, "subjects");
+                entry.max_per_instance = count(&row, "max");
+            }
+        }
+
+        let kinds = self
+            .query(
+                &format!(
+                    r#"SELECT ?p ?dt (COUNT(?o) AS ?n) WHERE {{
+                        ?s a {class} . ?s ?p ?o .
+                        BIND(IF(isLiteral(?o), STR(DATATYPE(?o)), "") AS ?dt)
+                    }} GROUP BY ?p ?dt"#
+                )
+            )
+            .unwrap_or_default();
+        for row in kinds {
+            let (Some(p), Some(datatype)) = (row.get("p"), extract_literal(row.get("dt"))) else {
+                continue;
+            };
+            let entry = observed.entry(p.to_string()).or_default();
+            let n = count(&row, "n");
+            if datatype.is_empty() {
+                entry.iris += n;
+            } else {
+                entry.literals += n;
+                *entry.datatypes.entry(datatype).or_default() += n;
+            }
+        }
+
+        let classes = self
+            .query(
+                &format!(
+                    r#"SELECT ?p ?t (COUNT(DISTINCT ?o) AS ?n) WHERE {{
+                        ?s a {class} . ?s ?p ?o . ?o a ?t .
+                    }} GROUP BY ?p ?t"#
+                )
+            )
+            .unwrap_or_default();
+        for row in classes {
+            if let (Some(p), Some(t)) = (row.get("p"), row.get("t")) {
+                let n = count(&row, "n");
+                *observed.entry(p.to_string()).or_default().classes.entry(t.to_string()).or_default() += n;
+            }
+        }
+        observed
+    }
+
+    /// Infers a node shape for every class reachable from `schema:{start_with}`, from the predicate analysis and
+    /// the observed values.
+    pub fn infer_shapes(&self, start_with: &str, options: &ShapeInferenceOptions) -> Vec<InferredShape> {
+        let analysis = self.predicate_scores(start_with);
+        let total = analysis.len() as u64;
+        let mut shapes = vec![];
+        for (i, class) in analysis.into_iter().enumerate() {
+            jobs::progress(i as u64, total);
+            jobs::log(format!("Inferring the shape of {}", class.class));
+            let scores = class.predicates
+                .into_iter()
+                .map(|p| (p.predicate.clone(), p))
+                .collect::<HashMap<_, _>>();
+
+            let mut properties = vec![];
+            let mut observed = self.observed_values(&class.class).into_iter().collect::<Vec<_>>();
+            observed.sort_by(|a, b| b.1.subjects.cmp(&a.1.subjects).then_with(|| a.0.cmp(&b.0)));
+            for (predicate, values) in observed {
+                if predicate == format!("<{RDF_TYPE}>") {
+                    continue;
+                }
+                let score = scores.get(&predicate);
+                if !options.include_dropped && score.is_some_and(|s| !s.keep) {
+                    continue;
+                }
+                let frequency = score
+                    .and_then(|s| s.stats.get("frequency").copied())
+                    .unwrap_or((values.subjects as f64) / (class.count.max(1) as f64));
+                let total_values = values.literals + values.iris;
+                let share = |n: u64| (n as f64) >= options.dominant_share * (total_values as f64);
+
+                let node_kind = if share(values.literals) {
+                    Some("Literal")
+                } else if share(values.iris) {
+                    Some("IRI")
+                } else {
+                    None
+                };
+                let datatype = match node_kind {
+                    Some("Literal") =>
+                        dominant(&values.datatypes, values.literals, options.dominant_share, 1).pop(),
+                    _ => None,
+                };
+                let classes = match node_kind {
+                    Some("IRI") => dominant(&values.classes, values.iris, options.dominant_share, MAX_OR_CLASSES),
+                    _ => vec![],
+                };
+
+                properties.push(InferredProperty {
+                    predicate,
+                    min_count: (frequency >= options.required_frequency).then_some(1),
+                    max_count: (values.max_per_instance == 1).then_some(1),
+                    node_kind: node_kind.map(|k| k.to_string()),
+                    datatype,
+                    classes,
+                    frequency,
+                    max_per_instance: values.max_per_instance,
+                    stats: score.map(|s| s.stats.clone()).unwrap_or_default(),
+                    keep: score.map(|s| s.keep),
+                });
+            }
+            shapes.push(InferredShape { class: class.class, count: class.count, properties });
+        }
+        jobs::progress(total, total);
+        shapes
+    }
+}
+
+/// Serializes inferred shapes as Turtle.
+///
+/// # Errors
+/// Returns a message if the namespace or an IRI is invalid.
+pub fn shapes_to_turtle(shapes: &[InferredShape], options: &ShapeInferenceOptions) -> Result<String, String> {
+    let sh = |name: &str| NamedNode::new_unchecked(format!("{SH}{name}"));
+    let iri = |iri: &str| NamedNode::new(iri.trim_matches(['<', '>'])).map_err(|e| format!("Invalid IRI {iri}: {e}"));
+    let rdf_type = NamedNode::new_unchecked(RDF_TYPE);
+
+    let mut triples = vec![];
+    for shape in shapes {
+        let class_name = shape_name(&shape.class);
+        let node = iri(&format!("{}{class_name}Shape", options.namespace))?;
+        triples.push(Triple::new(node.clone(), rdf_type.clone(), sh("NodeShape")));
+        triples.push(Triple::new(node.clone(), sh("targetClass"), iri(&shape.class)?));
+        triples.push(
+            Triple::new(
+                node.clone(),
+                sh("description"),
+                Literal::new_simple_literal(format!("Inferred from {} instances", shape.count))
+            )
+        );
+        if options.closed {
+            triples.push(Triple::new(node.clone(), sh("closed"), Literal::from(true)));
+            let ignored = BlankNode::default();
+            triples.push(Triple::new(ignored.clone(), NamedNode::new_unchecked(RDF_FIRST), rdf_type.clone()));
+            triples.push(Triple::new(ignored.clone(), NamedNode::new_unchecked(RDF_REST), NamedNode::new_unchecked(RDF_NIL)));
+            triples.push(Triple::new(node.clone(), sh("ignoredProperties"), ignored));
+        }
+
+        for property in &shape.properties {
+            let name = shape_name(&property.predicate);
+            let property_node = iri(&format!("{}{class_name}-{name}", options.namespace))?;
+            triples.push(Triple::new(node.clone(), sh("property"), property_node.clone()));
+            triples.push(Triple::new(property_node.clone(), rdf_type.clone(), sh("PropertyShape")));
+            triples.push(Triple::new(property_node.clone(), sh("path"), iri(&property.predicate)?));
+            triples.push(Triple::new(property_node.clone(), sh("name"), Literal::new_simple_literal(name)));
+
+            let mut description = format!(
+                "Used by {:.1}% of the instances, at most {} values per instance",
+                property.frequency * 100.0,
+                property.max_per_instance
+            );
+            for stat in ["uniqueness", "entropy", "score"] {
+                if let Some(value) = property.stats.get(stat) {
+                    description += &format!(", {stat} {value:.3}");
+                }
+            }
+            if property.keep == Some(false) {
+                description += ", dropped by the predicate analysis";
+            }
+            triples.push(
+                Triple::new(property_node.clone(), sh("description"), Literal::new_simple_literal(description))
+            );
+
+            if let Some(min) = property.min_count {
+                triples.push(Triple::new(property_node.clone(), sh("minCount"), Literal::from(min as i64)));
+            }
+            if let Some(max) = property.max_count {
+                triples.push(Triple::new(property_node.clone(), sh("maxCount"), Literal::from(max as i64)));
+            }
+            if let Some(kind) = &property.node_kind {
+                triples.push(Triple::new(property_node.clone(), sh("nodeKind"), sh(kind)));
+            }
+            if let Some(datatype) = &property.datatype {
+                triples.push(Triple::new(property_node.clone(), sh("datatype"), iri(datatype)?));
+            }
+            match property.classes.as_slice() {
+                [] => {}
+                [class] => triples.push(Triple::new(property_node.clone(), sh("class"), iri(class)?)),
+                classes => {
+                    let mut head: Term = NamedNode::new_unchecked(RDF_NIL).into();
+                    for class in classes.iter().rev() {
+                        let alternative = BlankNode::default();
+                        triples.push(Triple::new(alternative.clone(), sh("class"), iri(class)?));
+                        let item = BlankNode::default();
+                        triples.push(Triple::new(item.clone(), NamedNode::new_unchecked(RDF_FIRST), alternative));
+                        triples.push(Triple::new(item.clone(), NamedNode::new_unchecked(RDF_REST), head));
+                        head = item.into();
+                    }
+                    triples.push(Triple::new(property_node.clone(), sh("or"), head));
+                }
+            }
+        }
+    }
+
+    let mut serializer = RdfSerializer::from_format(RdfFormat::Turtle);
+    for (prefix, namespace) in [
+        ("sh", SH),
+        ("xsd", "http://www.w3.org/2001/XMLSchema#"),
+        ("schema", "http://schema.org/"),
+        ("shapes", options.namespace.as_str()),
+    ] {
+        serializer = serializer.with_prefix(prefix, namespace).map_err(|e| e.to_string())?;
+    }
+    let mut writer = serializer.for_writer(Vec::new());
+    for triple in &triples {
+        writer.serialize_triple(triple).map_err(|e| e.to_string())?;
+    }
+    let bytes = writer.finish().map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| e.to_string())
+}
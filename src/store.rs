@@ -9,6 +9,8 @@
 //! - **Version Control**: Dump and revert the store to specific versions, maintaining a history of operations.
 //! - **Entity Management**: Merge entities based on shared predicates, retrieve entity details, and fetch associated images.
 //! - **History Replay**: Replay operations from a history file or routine files.
+//! - **Inference**: Query the asserted triples alone or together with the inferred graph (see `inference`).
+//! - **Analysis caches**: Log what every update may change, to recompute only the affected statistics (see `cache`).
 //!
 //! ## Structs and Enums
 //! - `KG`: Represents the knowledge graph store and provides methods for dataset handling and SPARQL operations.
@@ -18,7 +20,8 @@ use core::option::Option::None;
 use core::panic;
 use core::result::Result;
 
-use std::collections::{ HashMap };
+use std::cell::RefCell;
+use std::collections::{ BTreeSet, HashMap, HashSet, VecDeque };
 //Working with files
 use std::path::Path;
 use std::fs::{ read_to_string, File };
@@ -30,43 +33,43 @@ use std::str::FromStr;
 use std::time::Instant;
 
 // Mulithread handling
-use std::sync::atomic::{ AtomicUsize, Ordering };
-use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
 
 // Tar-gz decoder
 use flate2::read::GzDecoder;
 
 // Oxigraph imports
-use oxigraph::model::{ GraphNameRef, NamedNode, Term };
+use oxigraph::model::{ GraphName, GraphNameRef, NamedNode, NamedNodeRef, Quad, Term };
 use oxigraph::model::Term::Literal;
 use oxigraph::store::Store;
-use oxigraph::sparql::{ QueryResults, QuerySolution };
+use oxigraph::sparql::{ Query, QueryResults, QuerySolution };
 use oxigraph::io::{ RdfParser, RdfFormat };
 
 // Petgraph
 
-use petgraph::graph::EdgeIndex;
 use petgraph::graph::NodeIndex;
-use petgraph::visit::{ EdgeRef };
 use petgraph::Direction::{ Incoming, Outgoing };
 use petgraph::{ self, data, Graph };
 use rayon::iter::{ IntoParallelRefIterator, ParallelIterator };
 use rayon::result;
 // Create imports
+use crate::cache::{ cache_file_name, Cached, ChangeSet };
+use crate::predicate_labels::PredicateLabel;
 use crate::utils::{
     self,
     calculate_probabilities_for_graph,
-    choice,
     compute_scores,
     extract_literal,
-    load_predicate_analysis,
-    load_relations,
     normalize_column,
     remove_disconnected,
-    save_predicate_anlaysis,
-    save_relations,
 };
+use crate::inference::INFERRED_GRAPH;
 use crate::item;
+use crate::jobs;
+use crate::page_rank::{ page_rank, PageRankOptions };
+
+use serde::{ Deserialize, Serialize };
 
 /// # Enumerates possible errors during store operations.
 ///
@@ -78,17 +81,175 @@ pub enum StoreError {
     UnsupportedError,
 }
 
+impl std::fmt::Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::EvaluationError(e) => write!(f, "{}", e),
+            StoreError::UnsupportedError => write!(f, "Query Not Supported"),
+        }
+    }
+}
+
+/// Cached statistics of the predicates of a class (see `KG::stat_anal_predicates`).
+///
+/// ## Fields
+/// * `class` - The class, in its SPARQL form.
+/// * `object_count` - Number of entities of the class.
+/// * `raw` - Frequency, uniqueness, entropy and entity quality of its predicates, before normalization.
+/// * `analysis` - Last analysis of the class: the statistics normalized, with the edge ranks.
+#[derive(Serialize, Deserialize)]
+struct PredicateStatsCache {
+    class: String,
+    object_count: f64,
+    raw: Vec<(String, HashMap<String, f64>)>,
+    analysis: Vec<(String, HashMap<String, f64>)>,
+}
+
+/// Class relations cached by `KG::class_relations`: class, predicate, class of the objects
+/// (`Literal` for literals and untyped IRIs) and number of triples.
+pub(crate) type ClassRelations = Vec<(String, String, String, f64)>;
+
+/// Cache of the class relations, relative to `cache_dir`.
+const CLASS_GRAPH_CACHE: &str = "class_graph.json";
+
+/// Web Data Commons crawl the WDC datasets are downloaded from.
+pub const WDC_CRAWL: &str = "2024-12";
+
+/// Location of the class-specific subsets of the WDC crawls.
+const WDC_URL: &str = "https://data.dws.informatik.uni-mannheim.de/structureddata";
+
+/// Web Data Commons origin of a WDC dataset.
+///
+/// ## Fields
+/// * `crawl` - Crawl the dataset comes from (see `WDC_CRAWL`).
+/// * `subset` - URL of the class-specific subset of the crawl.
+/// * `parts` - URLs of the parts of the subset that were loaded.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct WdcSource {
+    pub crawl: String,
+    pub subset: String,
+    pub parts: Vec<String>,
+}
+
+/// # An operation recorded in the history file.
+///
+/// ## Variants:
+/// * `Sparql`: A SPARQL update, or an advanced query (`SELECT`, `#` line, update template).
+/// * `Routine`: A routine procedure (`file::procedure`).
+/// * `Dump`: A dump of the store; `version` is the file name to pass to `/restore/`.
+/// * `PlanUndo`: The undo of a predicate plan; `file` holds the triples it put back.
+///
+/// `user` is the authenticated user that made the change, if any.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HistoryEntry {
+    Sparql {
+        query: String,
+        user: Option<String>,
+    },
+    Routine {
+        file: String,
+        procedure: String,
+        user: Option<String>,
+    },
+    Dump {
+        file: String,
+        version: String,
+        user: Option<String>,
+    },
+    PlanUndo {
+        plan: u32,
+        file: String,
+        user: Option<String>,
+    },
+}
+
+/// Prefix of the history lines naming the user behind the following entry.
+const USER_PREFIX: &str = "@user ";
+
+/// Prefix of the history lines recording the undo of a predicate plan: `<prefix><id> from <path>`.
+pub(crate) const PLAN_UNDO_PREFIX: &str = "Undoing predicate plan ";
+
+/// Parses the plan id and the file of an undo history line.
+fn parse_plan_undo(line: &str) -> Option<(u32, &str)> {
+    let (id, file) = line.strip_prefix(PLAN_UNDO_PREFIX)?.split_once(" from ")?;
+    Some((id.trim().parse().ok()?, file.trim()))
+}
+
+thread_local! {
+    static ACTING_USER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Sets the user on whose behalf the current thread modifies the store.
+///
+/// Every entry written to the history afterwards is preceded by a `@user <name>` line, until it is reset
+/// with `None`.
+pub fn set_acting_user(user: Option<String>) {
+    ACTING_USER.with(|u| *u.borrow_mut() = user);
+}
+
+/// Returns the user on whose behalf the current thread modifies the store.
+pub fn acting_user() -> Option<String> {
+    ACTING_USER.with(|u| u.borrow().clone())
+}
+
+/// # Outcome of running a list of routine procedures.
+///
+/// ## Fields
+/// * `executed` - Procedures that ran successfully.
+/// * `failed` - Procedure that failed, if any.
+/// * `skipped` - Procedures that were not run because of the failure.
+/// * `error` - Error message of the failure.
+/// * `triple_diff` - Number of triples inserted (positive) or deleted (negative).
+#[derive(Serialize)]
+pub struct RunReport {
+    pub executed: Vec<String>,
+    pub failed: Option<String>,
+    pub skipped: Vec<String>,
+    pub error: Option<String>,
+    pub triple_diff: i64,
+}
+
+/// # Side of an entity the triples are read from.
+///
+/// ## Variants:
+/// * `Outgoing`: Triples whose subject is the entity, the other end is their object.
+/// * `Incoming`: Triples whose object is the entity, the other end is their subject.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum TripleDirection {
+    Outgoing,
+    Incoming,
+}
+
+/// # Part of the graph around an entity, as reached by `KG::neighbourhood`.
+///
+/// ## Fields
+/// * `nodes` - Reached nodes with their simple properties (objects that have no properties of their own).
+/// * `links` - `(source, target, predicate)` links between reached nodes.
+/// * `frontier` - Reached nodes whose own neighbours were not loaded, because of the depth or the node budget.
+/// * `truncated` - Whether the node budget stopped the traversal.
+pub struct Neighbourhood {
+    pub nodes: Vec<(String, Vec<QuerySolution>)>,
+    pub links: Vec<(String, String, String)>,
+    pub frontier: HashSet<String>,
+    pub truncated: bool,
+}
+
 /// # Configuration and storage handler for a knowledge graph dataset.
 /// ## Fields
 /// * `dataset` - Name of the WDC dataset or path to a local dataset file.
 /// * `nb_parts` - Number of parts to download when fetching a WDC dataset.
 /// * `history_path` - File path where download history is recorded.
 /// * `store` - Store for managing and persisting the dataset.
+/// * `inferences_stale` - Whether the store changed since the inferred graph was last refreshed (see `inference`).
+/// * `inference_lock` - Serializes the refreshes of the inferred graph.
 pub struct KG {
     dataset: String,
     nb_parts: u32,
     history_path: String,
     store: Option<Store>,
+    inferences_stale: AtomicBool,
+    inference_lock: Mutex<()>,
 }
 
 impl KG {
@@ -108,6 +269,9 @@ impl KG {
             nb_parts,
             store: None,
             history_path: String::new(),
+            // The vocabulary may have changed since the last run
+            inferences_stale: AtomicBool::new(true),
+            inference_lock: Mutex::new(()),
         };
 
         //Check if the store is not yet created and download the dataset if needed
@@ -132,12 +296,30 @@ impl KG {
             nb_parts: 0,
             store: None,
             history_path: String::new(),
+            // The vocabulary may have changed since the last run
+            inferences_stale: AtomicBool::new(true),
+            inference_lock: Mutex::new(()),
         };
         created.load_file(dataset_path);
 
         created
     }
 
+    /// Constructs a `KG` on an in-memory store holding the given TriG data, for the unit tests.
+    #[cfg(test)]
+    pub(crate) fn in_memory(trig: &str) -> KG {
+        let store = Store::new().expect("Failed to create the store");
+        store.load_from_reader(RdfFormat::TriG, trig.as_bytes()).expect("Invalid test data");
+        KG {
+            dataset: "test".to_string(),
+            nb_parts: 0,
+            store: Some(store),
+            history_path: String::new(),
+            inferences_stale: AtomicBool::new(false),
+            inference_lock: Mutex::new(()),
+        }
+    }
+
     // # Loading procedures
 
     /// Downloads, unpacks, and preprocesses parts of a WDC dataset.
@@ -149,10 +331,7 @@ impl KG {
     fn download_dataset(&self) {
         let mut now = Instant::now();
 
-        let download_path = format!(
-            "https://data.dws.informatik.uni-mannheim.de/structureddata/2024-12/quads/classspecific/{}/part_",
-            self.dataset
-        );
+        let download_path = format!("{WDC_URL}/{WDC_CRAWL}/quads/classspecific/{}/part_", self.dataset);
 
         //Path to the directory where the dataset rdfs will be stored
         let path = format!("./data/{}", self.dataset);
@@ -280,6 +459,9 @@ impl KG {
             println!("Graph loaded");
         }
         self.store = Some(store);
+        if is_empty {
+            self.invalidate_caches();
+        }
 
         self.history_path = format!("./data/{}.db/history.txt", self.dataset.to_lowercase());
         // Set up history file
@@ -335,6 +517,9 @@ impl KG {
             println!("Graph loaded");
         }
         self.store = Some(store);
+        if is_empty {
+            self.invalidate_caches();
+        }
 
         // Set up the history file
         self.history_path = format!("./data/{}.db/history.txt", filename);
@@ -361,13 +546,43 @@ impl KG {
         }
     }
 
+    /// Web Data Commons origin of the dataset, `None` for datasets loaded from a file.
+    pub fn wdc_source(&self) -> Option<WdcSource> {
+        if self.nb_parts == 0 {
+            return None;
+        }
+        let subset = format!("{WDC_URL}/{WDC_CRAWL}/quads/classspecific/{}/", self.dataset);
+        let parts = (0..self.nb_parts).map(|i| format!("{subset}part_{i}.gz")).collect();
+        Some(WdcSource { crawl: WDC_CRAWL.to_string(), subset, parts })
+    }
+
+    /// Flushes the pending writes of the store to disk.
+    ///
+    /// Called on shutdown so that no update is lost.
+    pub fn flush(&self) {
+        if let Some(store) = &self.store {
+            if let Err(e) = store.flush() {
+                eprintln!("Failed to flush the store: {}", e);
+            }
+        }
+    }
+
     // # History
 
     /// Appends an operation to the history file.
     ///
     /// - Ensures the history file exists.
+    /// - Writes a `@user <name>` line first when an acting user is set (see `set_acting_user`).
     /// - Writes the provided content as a new line.
     pub fn write_to_history(&self, content: String) {
+        if let Some(user) = acting_user() {
+            self.append_history(&format!("{USER_PREFIX}{user}"));
+        }
+        self.append_history(&content);
+    }
+
+    fn append_history(&self, content: &str) {
+        self.mark_inferences_stale();
         if
             let Ok(mut file) = std::fs::OpenOptions
                 ::new()
@@ -387,6 +602,62 @@ impl KG {
         read_to_string(self.history_path.clone()).unwrap()
     }
 
+    /// Number of lines of the history file (0 if it does not exist yet).
+    ///
+    /// Grows with every change made to the store, so caches use it as a version.
+    pub fn history_len(&self) -> usize {
+        read_to_string(&self.history_path)
+            .map(|history| history.lines().count())
+            .unwrap_or(0)
+    }
+
+    /// Parses the history file into a list of entries.
+    ///
+    /// - ```sparql blocks become `HistoryEntry::Sparql`.
+    /// - `file::procedure` lines become `HistoryEntry::Routine`.
+    /// - `Dumping store to ...` lines become `HistoryEntry::Dump`, with the version that can be restored.
+    /// - `Undoing predicate plan ...` lines become `HistoryEntry::PlanUndo`.
+    /// - `@user <name>` lines set the user of the entries that follow, up to the next `@user` line.
+    pub fn history_entries(&self) -> Vec<HistoryEntry> {
+        let dump_prefix = format!("Dumping store to ./data/{}/", self.get_name());
+        let mut entries = vec![];
+        let mut sparql_block = String::new();
+        let mut in_sparql = false;
+        // An `@user` line only applies to the entry following it
+        let mut user: Option<String> = None;
+
+        let history = read_to_string(&self.history_path).unwrap_or_default();
+        for line in history.lines().map(str::trim) {
+            if line.starts_with("```sparql") {
+                in_sparql = true;
+                sparql_block.clear();
+            } else if line.starts_with("```") && in_sparql {
+                in_sparql = false;
+                entries.push(HistoryEntry::Sparql { query: sparql_block.clone(), user: user.take() });
+            } else if in_sparql {
+                sparql_block.push_str(line);
+                sparql_block.push('\n');
+            } else if let Some(name) = line.strip_prefix(USER_PREFIX) {
+                user = Some(name.trim().to_string());
+            } else if let Some((plan, file)) = parse_plan_undo(line) {
+                entries.push(HistoryEntry::PlanUndo { plan, file: file.to_string(), user: user.take() });
+            } else if let Some((file, procedure)) = line.split_once("::") {
+                entries.push(HistoryEntry::Routine {
+                    file: file.to_string(),
+                    procedure: procedure.to_string(),
+                    user: user.take(),
+                });
+            } else if line.starts_with("Dumping") {
+                entries.push(HistoryEntry::Dump {
+                    file: line.replace("Dumping store to", "").trim().to_string(),
+                    version: line.replace(&dump_prefix, ""),
+                    user: user.take(),
+                });
+            }
+        }
+        entries
+    }
+
     // # Store operations
 
     /// Executes a SPARQL `SELECT`('CONSTRUCT', `ASK`, or `DESCRIBE` to be implemented) query against the store.
@@ -397,7 +668,33 @@ impl KG {
     /// - `StoreError::EvaluationError` if the query fails to evaluate.
     /// - `StoreError::UnsupportedError` if the query result type is not supported.
     pub fn query(&self, query: &str) -> Result<Vec<QuerySolution>, StoreError> {
+        self.query_with_inferred(query, false)
+    }
+
+    /// Executes a SPARQL `SELECT` query, over the asserted and inferred triples if `include_inferred` is set.
+    ///
+    /// The inferred graph is only added when inference is enabled and the query has no `FROM` clause; it is
+    /// refreshed first if the store changed since (see `inference`).
+    ///
+    /// # Errors
+    /// Same as `query`, plus `StoreError::EvaluationError` if the inferred graph cannot be refreshed.
+    pub fn query_with_inferred(
+        &self,
+        query: &str,
+        include_inferred: bool
+    ) -> Result<Vec<QuerySolution>, StoreError> {
         if let Some(store) = &self.store {
+            let mut query = Query::parse(query, None).map_err(|e|
+                StoreError::EvaluationError(e.to_string())
+            )?;
+            if include_inferred && query.dataset().is_default_dataset() && self.inference_enabled() {
+                self.refresh_inferences_if_stale()?;
+                query
+                    .dataset_mut()
+                    .set_default_graph(
+                        vec![GraphName::DefaultGraph, NamedNode::new_unchecked(INFERRED_GRAPH).into()]
+                    );
+            }
             let result = store.query(query);
             match result {
                 Ok(QueryResults::Solutions(query_solution_iter)) => {
@@ -422,13 +719,19 @@ impl KG {
 
     /// Executes a SPARQL update (`INSERT`/`DELETE`) query against the store.
     ///
+    /// Logs what the update may have changed, for the caches of the analyses (see `cache`).
+    ///
     /// # Errors
     /// Returns `StoreError::EvaluationError` if the update fails.
     pub fn update(&self, query: &str) -> Result<(), StoreError> {
         if let Some(store) = &self.store {
+            let changes = ChangeSet::from_update(query);
             let r = store.update(query);
             match r {
-                Ok(_) => Ok(()),
+                Ok(_) => {
+                    self.record_changes(changes);
+                    Ok(())
+                }
                 Err(e) => Err(StoreError::EvaluationError(e.to_string())),
             }
         } else {
@@ -463,7 +766,9 @@ impl KG {
                     .map(|v| v.as_str())
                     .collect::<Vec<&str>>();
 
-                for r in &result {
+                let total = result.len() as u64;
+                for (i, r) in result.iter().enumerate() {
+                    jobs::progress(i as u64, total);
                     let mut uq = update_query.to_string();
                     for v in &vars {
                         let var = r.get(*v).unwrap().to_string();
@@ -479,6 +784,8 @@ impl KG {
                     }
                 }
                 println!("Ran {} queries", result.len());
+                jobs::progress(total, total);
+                jobs::log(format!("Ran {} updates", result.len()));
 
                 Ok(())
             }
@@ -486,6 +793,37 @@ impl KG {
         }
     }
 
+    // # Inferred graph
+
+    /// Whether the store has the named graph `graph` (an IRI, without brackets), even if it is empty.
+    pub fn has_named_graph(&self, graph: &str) -> bool {
+        match (&self.store, NamedNodeRef::new(graph)) {
+            (Some(store), Ok(graph)) => store.contains_named_graph(graph).unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    /// Marks the inferred graph as out of date; every change recorded in the history does.
+    pub fn mark_inferences_stale(&self) {
+        self.inferences_stale.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether the store changed since the inferred graph was last refreshed.
+    pub fn inferences_stale(&self) -> bool {
+        self.inferences_stale.load(Ordering::SeqCst)
+    }
+
+    /// Clears the stale flag of the inferred graph, returning whether it was set.
+    pub fn take_inferences_stale(&self) -> bool {
+        self.inferences_stale.swap(false, Ordering::SeqCst)
+    }
+
+    /// Runs `f` while no other thread enables, refreshes or drops the inferred graph.
+    pub fn with_inference_lock<T>(&self, f: impl FnOnce() -> T) -> T {
+        let _guard = self.inference_lock.lock().unwrap_or_else(|e| e.into_inner());
+        f()
+    }
+
     // # Version management
 
     /// Dumps the current graph state to a new N-Triples file.
@@ -493,7 +831,7 @@ impl KG {
     /// - Creates a `data/<dataset>/` directory if missing.
     /// - Names the dump file `version_<N>.nt`, where `N` is the next available version number.
     /// - Appends a dump record to the history file.
-    /// - Serializes the default graph to N-Triples.
+    /// - Serializes the default graph to N-Triples, described by `version_<N>.void.ttl` (see `void`).
     pub fn dump_store(&self) {
         if let Some(store) = &self.store {
             let dir_path = format!(
@@ -537,6 +875,9 @@ impl KG {
                         )
                     )
             {
+                if let Some(user) = acting_user() {
+                    let _ = writeln!(file, "{USER_PREFIX}{user}");
+                }
                 let _ = writeln!(file, "Dumping store to {}", file_path);
             }
 
@@ -550,6 +891,11 @@ impl KG {
             );
 
             let _ = file.write(&buffer);
+
+            let void_path = file_path.replace(".nt", ".void.ttl");
+            if let Err(e) = self.export_void(&void_path) {
+                eprintln!("Failed to describe the dump in {void_path}: {e}");
+            }
         }
     }
 
@@ -557,11 +903,13 @@ impl KG {
     ///
     /// - Clears the current store.
     /// - Loads `version_<version>.nt` from the dataset directory.
+    /// - Keeps inference enabled if it was: the inferred graph is recreated and refreshed on its next use.
     /// - Truncates the history file to the revert point.
-    /// - Removes any newer dump files.
+    /// - Removes any newer dump files, and their descriptions.
     pub fn revert(&self, version: u32) {
         if let Some(store) = &self.store {
             let dataset = self.dataset.split("/").last().unwrap_or(&self.dataset);
+            let inference = self.has_named_graph(INFERRED_GRAPH);
             let _ = store.clear();
             let dir_path = format!(
                 "./data/{}/",
@@ -569,13 +917,20 @@ impl KG {
             );
 
             let file_path = format!("{}version_{}.nt", dir_path, version);
+            jobs::log(format!("Loading {file_path}"));
             let parser = File::open(file_path).unwrap();
             store
                 .bulk_loader()
                 .with_num_threads(16)
                 .load_from_reader(RdfParser::from_format(RdfFormat::NTriples), parser)
                 .expect("Failed to load file");
+            if inference {
+                let _ = store.insert_named_graph(NamedNodeRef::new_unchecked(INFERRED_GRAPH));
+            }
+            self.mark_inferences_stale();
+            self.invalidate_caches();
 
+            jobs::log("Truncating the history and removing the newer dumps".to_string());
             let history_path = format!("./data/{}.db/history.txt", dataset.to_lowercase());
             if let Ok(content) = std::fs::read_to_string(&history_path) {
                 let target_line = format!(
@@ -602,6 +957,7 @@ impl KG {
                     break;
                 }
                 let _ = std::fs::remove_file(&file_path);
+                let _ = std::fs::remove_file(file_path.replace(".nt", ".void.ttl"));
                 v += 1;
             }
         }
@@ -612,18 +968,27 @@ impl KG {
     /// - Parses SPARQL blocks delimited by ```sparql ... ``` and executes them.
     /// - Supports advanced queries with a `#\n` separator for `SELECT` + `UPDATE`.
     /// - Executes routine files referenced as `file::procedure` lines.
-    /// - Logs each replayed line back to the history file.
+    /// - Puts back the triples of the predicate plans undone by `Undoing predicate plan ...` lines.
+    /// - Logs each replayed line back to the history file, every entry under the acting user if one is set. The
+    ///   `@user` lines of the replayed content are dropped: the replay is attributed to whoever runs it.
     pub fn execute(&self, content: String) -> Result<(), (StoreError, i32)> {
-        let lines = content.lines().map(str::trim);
+        let lines = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with(USER_PREFIX));
         let mut in_sparql = false;
         let mut sparql_block = String::new();
         let mut count = 0;
         for line in lines {
+            let is_routine = line.contains("::") && !line.starts_with("Dumping");
+            let starts_entry =
+                line.starts_with("```sparql") || (!in_sparql && (is_routine || line.starts_with(PLAN_UNDO_PREFIX)));
             if line.starts_with("```sparql") {
                 in_sparql = true;
                 sparql_block.clear();
             } else if line.starts_with("```") && in_sparql {
                 in_sparql = false;
+                jobs::log("Running a SPARQL block".to_string());
 
                 // Execute the SPARQL block
                 if sparql_block.contains("#\n") {
@@ -664,8 +1029,19 @@ impl KG {
             } else if in_sparql {
                 sparql_block.push_str(line);
                 sparql_block.push('\n');
+            } else if let Some((plan, _)) = parse_plan_undo(line) {
+                jobs::log(format!("Undoing predicate plan {plan}"));
+                match self.restore_predicate_plan(plan) {
+                    Ok(_) => {
+                        count += 1;
+                    }
+                    Err(e) => {
+                        return Err((e, count));
+                    }
+                };
             } else if line.contains("::") && !line.starts_with("Dumping") {
                 // Executing a routine
+                jobs::log(format!("Running {line}"));
                 let (file, proc) = line.split_once("::").unwrap();
                 let path = Path::new("routines").join(file);
 
@@ -767,15 +1143,63 @@ impl KG {
                 }
             }
             if !line.starts_with("Dumping") {
-                self.write_to_history(format!("{}", line));
+                if let Some(user) = acting_user().filter(|_| starts_entry) {
+                    self.append_history(&format!("{USER_PREFIX}{user}"));
+                }
+                self.append_history(line);
             }
         }
 
         Ok(())
     }
 
+    /// Runs routine procedures (`file::procedure`) in order, stopping at the first failure.
+    ///
+    /// Returns a `RunReport` listing the executed, failed and skipped procedures and the triple difference.
+    pub fn run_routines(&self, routines: Vec<String>) -> RunReport {
+        let initial_count = self.count_lines();
+        let result = self.execute(routines.join("\n"));
+        let triple_diff = (self.count_lines() as i64) - (initial_count as i64);
+
+        match result {
+            Ok(()) =>
+                RunReport {
+                    executed: routines,
+                    failed: None,
+                    skipped: vec![],
+                    error: None,
+                    triple_diff,
+                },
+            Err((e, cnt)) => {
+                // `cnt` procedures succeeded before the failing one
+                let cnt = (cnt.max(0) as usize).min(routines.len());
+                let mut skipped = routines[cnt..].to_vec();
+                let failed = if skipped.is_empty() { None } else { Some(skipped.remove(0)) };
+                RunReport {
+                    executed: routines[..cnt].to_vec(),
+                    failed,
+                    skipped,
+                    error: Some(e.to_string()),
+                    triple_diff,
+                }
+            }
+        }
+    }
+
     // # Useful procedures
 
+    /// Calls `f` on every triple of the default graph, streaming them from the store.
+    ///
+    /// Meant for full scans (e.g. building the search index) where collecting query solutions would not fit
+    /// in memory. Triples that fail to be read are skipped.
+    pub fn for_each_triple(&self, mut f: impl FnMut(Quad)) {
+        if let Some(store) = &self.store {
+            for quad in store.quads_for_pattern(None, None, None, Some(GraphNameRef::DefaultGraph)).flatten() {
+                f(quad);
+            }
+        }
+    }
+
     /// Counts the number of triples in the default graph.
     ///
     /// Executes:
@@ -857,6 +1281,174 @@ WHERE  { {{s2}} ?p ?o }
         r
     }
 
+    /// Counts the entities of every class, most populated classes first.
+    ///
+    /// # Panics
+    /// Panics if the count query fails.
+    pub fn class_counts(&self) -> Vec<(String, u32)> {
+        let q =
+            r#"
+SELECT ?t (COUNT(?s) AS ?count)
+WHERE {
+    ?s a ?t .
+}
+GROUP BY ?t
+ORDER BY DESC(?count)
+"#;
+
+        let mut class_counts = vec![];
+        match self.query(q) {
+            Ok(result) => {
+                for r in result {
+                    let class = r.get("t").unwrap().to_string();
+                    let cnt = match r.get("count").unwrap() {
+                        Literal(literal) => literal.value().parse::<u32>().unwrap(),
+                        _ => 0,
+                    };
+                    class_counts.push((class, cnt));
+                }
+            }
+            Err(_) => panic!("SPARQL query failed"),
+        }
+        class_counts
+    }
+
+    /// Retrieves the triples around an entity.
+    ///
+    /// Returns the outgoing `(predicate, object)` pairs and the incoming `(subject, predicate)` pairs.
+    pub fn get_triples(&self, entity: &str) -> (Vec<(String, String)>, Vec<(String, String)>) {
+        let outgoing_query = format!(
+            r#"
+      SELECT ?pred ?obj WHERE {{
+        {entity} ?pred ?obj .
+      }}
+      "#
+        );
+        let incoming_query = format!(
+            r#"
+      SELECT ?sub ?pred WHERE {{
+        ?sub ?pred {entity} .
+      }}
+      "#
+        );
+        let outgoing = self
+            .query(&outgoing_query)
+            .unwrap_or(vec![])
+            .iter()
+            .map(|row| (row.get("pred").unwrap().to_string(), row.get("obj").unwrap().to_string()))
+            .collect();
+        let incoming = self
+            .query(&incoming_query)
+            .unwrap_or(vec![])
+            .iter()
+            .map(|row| (row.get("sub").unwrap().to_string(), row.get("pred").unwrap().to_string()))
+            .collect();
+        (outgoing, incoming)
+    }
+
+    /// Counts the triples on one side of an entity, per predicate.
+    ///
+    /// Returns `(predicate, count)` pairs ordered by predicate, the order used by `triples_page`.
+    pub fn triple_groups(&self, entity: &str, direction: TripleDirection) -> Vec<(String, u64)> {
+        let pattern = match direction {
+            TripleDirection::Outgoing => format!("{entity} ?pred ?other ."),
+            TripleDirection::Incoming => format!("?other ?pred {entity} ."),
+        };
+        let q = format!("SELECT ?pred (COUNT(*) AS ?count) WHERE {{ {pattern} }} GROUP BY ?pred ORDER BY ?pred");
+        self.query(&q)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| {
+                let count = extract_literal(row.get("count"))?.parse::<u64>().ok()?;
+                Some((row.get("pred")?.to_string(), count))
+            })
+            .collect()
+    }
+
+    /// Retrieves a page of the triples on one side of an entity, ordered by predicate then by the other end.
+    ///
+    /// Returns `(predicate, other end)` pairs: the objects of outgoing triples, the subjects of incoming ones.
+    pub fn triples_page(
+        &self,
+        entity: &str,
+        direction: TripleDirection,
+        limit: u64,
+        offset: u64
+    ) -> Vec<(String, String)> {
+        let pattern = match direction {
+            TripleDirection::Outgoing => format!("{entity} ?pred ?other ."),
+            TripleDirection::Incoming => format!("?other ?pred {entity} ."),
+        };
+        let q = format!(
+            "SELECT ?pred ?other WHERE {{ {pattern} }} ORDER BY ?pred ?other LIMIT {limit} OFFSET {offset}"
+        );
+        self.query(&q)
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|row| Some((row.get("pred")?.to_string(), row.get("other")?.to_string())))
+            .collect()
+    }
+
+    /// Traverses the neighbourhood of an entity breadth first.
+    ///
+    /// - Only objects that have properties of their own are followed, up to `depth` hops from the entity.
+    /// - At most `max_nodes` nodes are reached; the nodes that could not be expanded are in the frontier.
+    /// - Every reached node comes with its simple properties (objects that have no properties of their own).
+    /// - Every link between two reached nodes is returned as `(source, target, predicate)`.
+    pub fn neighbourhood(&self, entity: &str, depth: u32, max_nodes: usize) -> Neighbourhood {
+        let max_nodes = max_nodes.max(1);
+        let mut reached: HashSet<String> = HashSet::from([entity.to_string()]);
+        let mut queue = VecDeque::from([(entity.to_string(), 0)]);
+        let mut neighbourhood = Neighbourhood {
+            nodes: vec![],
+            links: vec![],
+            frontier: HashSet::new(),
+            truncated: false,
+        };
+
+        while let Some((ent, hops)) = queue.pop_front() {
+            let simple_connections_query = format!(
+                r#"PREFIX schema: <http://schema.org/>
+                    SELECT DISTINCT ?predicate ?object
+                    WHERE {{
+                {ent} ?predicate ?object .
+                    FILTER NOT EXISTS {{
+                        ?object ?otherPredicate ?otherSubject .
+                }}
+                }}"#
+            );
+            neighbourhood.nodes.push((ent.clone(), self.query(&simple_connections_query).unwrap_or_default()));
+
+            if hops >= depth {
+                neighbourhood.frontier.insert(ent);
+                continue;
+            }
+            let complex_connections_query = format!(
+                r#"PREFIX schema: <http://schema.org/>
+                    SELECT DISTINCT ?predicate ?object
+                    WHERE {{
+                        {ent} ?predicate ?object .
+                        FILTER EXISTS {{ ?object ?p ?oo . }}
+                }}"#
+            );
+            for row in self.query(&complex_connections_query).unwrap_or_default() {
+                let cur = row.get("object").unwrap().to_string();
+                if !reached.contains(&cur) {
+                    if reached.len() >= max_nodes {
+                        // The budget is spent: the node is left for an expansion from the UI
+                        neighbourhood.truncated = true;
+                        neighbourhood.frontier.insert(ent.clone());
+                        continue;
+                    }
+                    reached.insert(cur.clone());
+                    queue.push_back((cur.clone(), hops + 1));
+                }
+                neighbourhood.links.push((ent.clone(), cur, row.get("predicate").unwrap().to_string()));
+            }
+        }
+        neighbourhood
+    }
+
     /// Retrieves a page of entity IRIs of a given type.
     ///
     /// - `object_type`: IRI of the RDF type to filter on.
@@ -1028,103 +1620,169 @@ SELECT DISTINCT ?p WHERE {{
         res
     }
 
+    /// Cache of the predicate statistics of `otype`, relative to `cache_dir` (see `stat_anal_predicates`).
+    fn predicate_cache_name(otype: &str) -> String {
+        format!("predicates/{}.json", cache_file_name(otype))
+    }
+
+    /// Last predicate analysis of `otype`: the statistics of its predicates, normalized, with their edge rank.
+    pub fn cached_predicate_analysis(&self, otype: &str) -> Option<Vec<(String, HashMap<String, f64>)>> {
+        self.load_cache::<PredicateStatsCache>(&Self::predicate_cache_name(otype))
+            .map(|cached| cached.data)
+            .filter(|cache| cache.class == otype)
+            .map(|cache| cache.analysis)
+    }
+
+    /// Statistics of the predicates of `otype`: frequency, uniqueness, entropy and entity quality (the last two
+    /// normalized), the edge rank of the predicate, and the scores of `compute_scores`.
+    ///
+    /// The raw statistics are cached (see `cache`): after an update, only the predicates it may have changed are
+    /// recomputed, along with the entity quality of the class, which takes a single query.
+    /// Returns `None` if the class has no predicate worth analysing.
     pub fn stat_anal_predicates(
         &self,
         otype: &str,
         edge_rank: &HashMap<String, f64>
     ) -> Option<Vec<(String, HashMap<String, f64>)>> {
-        let mut data = vec![];
-        let mut recalculate = true;
-        match
-            load_predicate_analysis(
-                &format!(
-                    "./data/{}/stat_anal/{}",
-                    self.dataset,
-                    otype.replace("<", "").replace(">", "").replace(":", "_").replace("/", "\\")
-                )
-            )
-        {
-            Ok((version, cached_data)) => {
-                if version == self.get_history().lines().count() {
-                    data = cached_data;
-                    recalculate = false;
-                    println!("{otype} analysis loaded");
-                }
+        let name = Self::predicate_cache_name(otype);
+        let version = self.cache_version();
+        let cached = self
+            .load_cache::<PredicateStatsCache>(&name)
+            .filter(|cached| cached.data.class == otype)
+            .and_then(|Cached { data, changes }| {
+                let known = data.raw
+                    .iter()
+                    .map(|(p, _)| p.clone())
+                    .collect::<BTreeSet<_>>();
+                self.touched_predicates(&changes, otype, &known).map(|touched| (data, touched))
+            });
+
+        let mut cache = match cached {
+            Some((cache, touched)) if touched.is_empty() => {
+                println!("{otype} analysis loaded");
+                cache
+            }
+            Some((cache, touched)) => {
+                println!("{otype} analysis: recomputing {} predicates", touched.len());
+                self.refresh_predicate_stats(cache, &touched)
             }
-            Err(_) => (),
+            None => self.compute_predicate_stats(otype),
+        };
+
+        let mut data = cache.raw
+            .iter()
+            .filter(|r| { (r.1["uniqueness"] - 1.0).abs() > 0.0000000000000000001 })
+            .cloned()
+            .collect::<Vec<_>>();
+        for (pred, scores) in data.iter_mut() {
+            scores.insert("edge_rank".to_string(), *edge_rank.get(pred).unwrap_or(&0.0));
+        }
+        if !data.is_empty() {
+            normalize_column(&mut data, "entropy");
+            normalize_column(&mut data, "quality");
         }
+        cache.analysis = data.clone();
+        self.save_cache(&name, version, &cache);
+        println!("{otype} analysis saved");
 
-        if recalculate {
-            let overall_count_query = format!(
-                r#"
+        if data.len() == 0 {
+            return None;
+        }
+        compute_scores(&mut data);
+
+        return Some(data);
+    }
+
+    /// Computes the raw statistics of every predicate of `otype`.
+    fn compute_predicate_stats(&self, otype: &str) -> PredicateStatsCache {
+        let overall_count_query = format!(
+            r#"
 SELECT (COUNT (DISTINCT ?s) as ?cnt)
 WHERE {{
         ?s a {otype}.
 }}
 "#
-            );
+        );
 
-            let object_count = *self.get_counts(&overall_count_query, "cnt").first().unwrap();
+        let object_count = *self.get_counts(&overall_count_query, "cnt").first().unwrap();
+        let mut cache = PredicateStatsCache {
+            class: otype.to_string(),
+            object_count,
+            raw: vec![],
+            analysis: vec![],
+        };
+        self.refresh_predicate_stats_of(&mut cache, |_| true);
+        cache
+    }
 
-            let predicates = self.get_predicates(otype);
-            let plen = predicates.len();
-            let filtered_predicates: Vec<_> = predicates
-                .iter()
-                .filter(|p| { *p != "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>" })
-                .collect();
-            data = filtered_predicates
-                .par_iter()
-                .map(|p| {
-                    (p.to_string(), self.stat_anal_single_predicate(otype, p, plen, object_count))
-                })
-                .filter(|r| { (r.1["uniqueness"] - 1.0).abs() > 0.0000000000000000001 })
-                .collect::<Vec<_>>();
-            if data.len() == 0 {
-                return None;
-            }
-            for (pred, scores) in data.iter_mut() {
-                scores.insert("edge_rank".to_string(), *edge_rank.get(pred).unwrap_or(&0.0));
-            }
-            normalize_column(&mut data, "entropy");
-            normalize_column(&mut data, "quality");
+    /// Recomputes the raw statistics of the `touched` predicates of a cached class, and of the predicates it gained.
+    ///
+    /// The instances of the class are unchanged (see `KG::touched_predicates`), and so is its entity count.
+    fn refresh_predicate_stats(
+        &self,
+        mut cache: PredicateStatsCache,
+        touched: &BTreeSet<String>
+    ) -> PredicateStatsCache {
+        self.refresh_predicate_stats_of(&mut cache, |p| touched.contains(p));
+        cache
+    }
 
-            match
-                save_predicate_anlaysis(
-                    &format!(
-                        "./data/{}/stat_anal/{}",
-                        self.dataset,
-                        otype.replace("<", "").replace(">", "").replace(":", "_").replace("/", "\\")
-                    ),
-                    &data,
-                    self.get_history().lines().count()
-                )
-            {
-                Ok(_) => println!("{otype} analysis saved"),
-                Err(e) => {
-                    println!("error caching {otype} analysis: \n{}", e);
+    /// Recomputes the raw statistics of the predicates of the class matching `stale` (and of the ones missing from
+    /// the cache), drops the predicates it lost, and recomputes the entity quality of every predicate.
+    fn refresh_predicate_stats_of(&self, cache: &mut PredicateStatsCache, stale: impl Fn(&str) -> bool + Sync) {
+        let otype = cache.class.clone();
+        let object_count = cache.object_count;
+        let predicates = self.get_predicates(&otype);
+        let plen = predicates.len();
+        let known = cache.raw.drain(..).collect::<HashMap<_, _>>();
+        let filtered_predicates: Vec<_> = predicates
+            .iter()
+            .filter(|p| { *p != "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>" })
+            .collect();
+        let mut raw = filtered_predicates
+            .par_iter()
+            .map(|p| {
+                match known.get(p.as_str()) {
+                    Some(stats) if !stale(p) => (p.to_string(), stats.clone()),
+                    _ => (p.to_string(), self.stat_anal_single_predicate(&otype, p, object_count)),
                 }
-            }
+            })
+            .collect::<Vec<_>>();
+        let quality = self.predicate_quality(&otype, plen);
+        for (pred, stats) in raw.iter_mut() {
+            stats.insert("quality".to_string(), *quality.get(pred).unwrap_or(&0.0));
         }
-        compute_scores(&mut data);
+        cache.raw = raw;
+    }
 
-        return Some(data);
+    /// Frequency, uniqueness and entropy of a predicate of a class of `object_count` entities, `predicate_used` of
+    /// which have it, from the number of times each of its distinct values is used.
+    ///
+    /// Shared by the predicate analysis and the class profiles (see `profiling`).
+    pub fn usage_stats(object_count: f64, predicate_used: f64, value_counts: &[f64]) -> HashMap<String, f64> {
+        let distinct_objects = value_counts.len() as f64;
+        let total_uses: f64 = value_counts.iter().sum();
+
+        let mut ent: f64 = 0.0;
+        for e in value_counts {
+            let p = e / total_uses;
+            ent -= p * p.log2();
+        }
+
+        let mut result = HashMap::new();
+        result.insert("frequency".to_string(), predicate_used / object_count);
+        result.insert("uniqueness".to_string(), distinct_objects / total_uses);
+        result.insert("entropy".to_string(), ent);
+
+        result
     }
 
     fn stat_anal_single_predicate(
         &self,
         otype: &str,
         predicate: &str,
-        total_predicates: usize,
         object_count: f64
     ) -> HashMap<String, f64> {
-        //         let overall_count_query = format!(
-        //             r#"
-        // SELECT (COUNT (DISTINCT ?s) as ?cnt)
-        // WHERE {{
-        //         ?s a {otype}.
-        // }}
-        // "#
-        //         );
         let frequency_query = format!(
             r#"
 SELECT (COUNT(DISTINCT ?s) as ?cnt)
@@ -1134,15 +1792,8 @@ WHERE {{
 }}
         "#
         );
-        let distinct_objects_query = format!(
-            r#"
-SELECT (COUNT(DISTINCT ?o) as ?cnt){{
-        ?s a {otype};
-        {predicate} ?o.
-}}        
-"#
-        );
 
+        // The distinct objects and the total uses follow from the uses of every value
         let entropy_query = format!(
             r#"
 SELECT (COUNT(?s) AS ?cnt) 
@@ -1154,59 +1805,62 @@ GROUP BY ?v
             
             "#
         );
-        let used_query = format!(
-            r#"
-SELECT (COUNT(?o) AS ?cnt) 
-WHERE {{
-    ?s a {otype}.
-    ?s {predicate} ?o.
-}}
-            
-            "#
-        );
 
+        let predicate_used = *self.get_counts(&frequency_query, "cnt").first().unwrap();
+        let entropy_vals = self.get_counts(&entropy_query, "cnt");
+
+        Self::usage_stats(object_count, predicate_used, &entropy_vals)
+    }
+
+    /// Entity quality of every predicate of `otype`, in one query: the sum, over the entities using the predicate,
+    /// of `total_predicates` divided by the number of other predicates of the entity.
+    fn predicate_quality(&self, otype: &str, total_predicates: usize) -> HashMap<String, f64> {
         let entity_quality_query = format!(
             r#"
-        SELECT (COUNT(DISTINCT ?p2) as ?cnt) WHERE {{
-            ?s a {otype}.
-            ?s {predicate} ?o1.
-            ?s ?p2 ?o2.
-            FILTER(?p2!={predicate})
+        SELECT ?p (SUM(1.0 / (?n - 1)) as ?cnt) WHERE {{
+            {{
+                SELECT ?s (COUNT(DISTINCT ?p2) as ?n) WHERE {{
+                    ?s a {otype}.
+                    ?s ?p2 ?o2.
+                }}
+                GROUP BY ?s
+            }}
+            {{
+                SELECT DISTINCT ?s ?p WHERE {{
+                    ?s a {otype}.
+                    ?s ?p ?o1.
+                }}
+            }}
+            FILTER(?n > 1)
         }}
-        GROUP BY ?s
+        GROUP BY ?p
         "#
         );
 
-        // let object_count = *self.get_counts(&overall_count_query, "cnt").first().unwrap();
-        let predicate_used = *self.get_counts(&frequency_query, "cnt").first().unwrap();
-        let distinct_objects = *self.get_counts(&distinct_objects_query, "cnt").first().unwrap();
-
-        let entropy_vals = self.get_counts(&entropy_query, "cnt");
-        let total_uses = *self.get_counts(&used_query, "cnt").first().unwrap();
-
-        let mut ent: f64 = 0.0;
-        for e in entropy_vals {
-            let p = e / total_uses;
-            ent -= p * p.log2();
-        }
-
-        let entity_quality = self.get_counts(&entity_quality_query, "cnt");
-        let mut quality = 0.0;
-        for q in entity_quality {
-            quality += (total_predicates as f64) / q;
+        let mut quality = HashMap::new();
+        match self.query(&entity_quality_query) {
+            Ok(result) => {
+                for r in result {
+                    let (Some(p), Some(q)) = (r.get("p"), extract_literal(r.get("cnt"))) else {
+                        continue;
+                    };
+                    let q = q.parse::<f64>().unwrap_or(0.0);
+                    quality.insert(p.to_string(), (total_predicates as f64) * q);
+                }
+            }
+            Err(e) => eprintln!("Entity quality query failed for {otype}: {e}"),
         }
-        let mut result = HashMap::new();
-        result.insert("frequency".to_string(), predicate_used / object_count);
-        result.insert("uniqueness".to_string(), distinct_objects / total_uses);
-        result.insert("entropy".to_string(), ent);
-        result.insert("quality".to_string(), quality);
-
-        result
+        quality
     }
 
+    /// Scores the classes reachable from `schema:{start_with}` over 3 elimination rounds, ranking them with `options`.
+    ///
+    /// Returns, per class, its count, depth, PageRank, reverse PageRank, last round, whether it survived every round
+    /// and its score, the survivors first. Nothing is changed in the store: see `KG::apply_class_plan`.
     pub fn stat_anal_types(
         &self,
-        start_with: &str
+        start_with: &str,
+        options: &PageRankOptions
     ) -> Vec<(String, (f64, f64, f64, f64, i32, bool, f64))> {
         let (mut graph, mut node_map) = self.calculate_class_relations_graph();
         // let literal = node_map["Literal"];
@@ -1238,10 +1892,12 @@ WHERE {{
         let mut overall_stats = HashMap::new();
 
         for i in 0..level {
+            jobs::progress(i as u64, level as u64);
+            jobs::log(format!("Elimination round {} of {level}", i + 1));
             calculate_probabilities_for_graph(&mut graph);
 
-            let (fpr, _) = self.page_rank(&graph, &node_map, &node_counts, Outgoing);
-            let (rpr, _) = self.page_rank(&graph, &node_map, &node_counts, Incoming);
+            let (fpr, _) = page_rank(&graph, &node_counts, Outgoing, options);
+            let (rpr, _) = page_rank(&graph, &node_counts, Incoming, options);
 
             let mut stats = vec![];
             for (t, depth) in &order {
@@ -1329,18 +1985,16 @@ WHERE {{
             .iter()
             .map(|a| { (a.0.to_string(), *a.1) })
             .collect::<Vec<_>>();
-        result.sort_by(|a, b| { b.1.4.cmp(&a.1.4).then_with(|| b.1.6.total_cmp(&a.1.6)) });
-
-        let mut scores = HashMap::new();
-        result.iter().for_each(|(n, (_, _, _, _, _, _, s))| {
-            scores.insert(n.to_string(), *s);
+        result.sort_by(|a, b| {
+            b.1.4
+                .cmp(&a.1.4)
+                .then_with(|| b.1.6.total_cmp(&a.1.6))
+                .then_with(|| a.0.cmp(&b.0))
         });
-        self.keep_types(keep);
-        self.fix_types(scores);
 
-        return result;
+        println!("Proposed to keep {} classes", keep.len());
 
-        // self.keep_types(keep);
+        return result;
     }
 
     fn rank(&self, stats: &Vec<(String, f64, f64, f64, f64)>, limit: f64) -> HashMap<String, f64> {
@@ -1362,7 +2016,8 @@ WHERE {{
             *score = *score / s;
         }
 
-        scores.sort_by(|a, b| { b.1.total_cmp(&a.1) });
+        // Ties are broken by name, so that the same graph always keeps the same classes
+        scores.sort_by(|a, b| { b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)) });
 
         // for s in &scores {
         //     println!("{}: {}", s.0, s.1);
@@ -1382,11 +2037,17 @@ WHERE {{
         println!("Kept: {i}, Removed: {}", scores.len() - i);
         results
     }
-    pub fn keep_types(&self, keep: Vec<String>) {
+    /// Removes every type not in `keep`, then the entities left without a type.
+    ///
+    /// `note` is written at the top of the update in the history (as SPARQL comments).
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of the update, or of the routine removing the entities without a type.
+    pub fn keep_types(&self, keep: &[String], note: &str) -> Result<(), StoreError> {
         let filter = keep.join(",");
 
         let q = format!(
-            "
+            "{note}
 DELETE {{
     ?s a ?t .
         }}
@@ -1402,22 +2063,15 @@ WHERE {{
         "
         );
 
-        println!("{}", q);
-
-        match self.update(&q) {
-            Ok(_) => {
-                self.write_to_history(format!("```sparql\n{}\n```", q));
-                match
-                    self.execute("general.sparql::Remove entities withot type@advanced".to_string())
-                {
-                    Ok(_) => println!("Yeah"),
-                    Err(_) => println!("Noo"),
-                }
-            }
-            Err(_) => println!("NOOO"),
-        };
+        self.update(&q)?;
+        self.write_to_history(format!("```sparql\n{}\n```", q));
+        self.execute("general.sparql::Remove entities withot type@advanced".to_string()).map_err(|(e, _)| e)
     }
 
+    /// Builds the class relations graph: one node per class (and `Literal`), one edge per predicate linking the
+    /// entities of a class to the entities of another, weighted by the number of triples.
+    ///
+    /// Only reads the store (see `KG::class_relations`).
     pub fn calculate_class_relations_graph(
         &self
     ) -> (Graph<String, (String, f64, Option<f64>, Option<f64>)>, HashMap<String, NodeIndex>) {
@@ -1425,24 +2079,29 @@ WHERE {{
         let mut graph: Graph<String, (String, f64, Option<f64>, Option<f64>)> = Graph::new();
         let mut node_map: HashMap<String, NodeIndex> = HashMap::new();
         node_map.insert("Literal".to_string(), graph.add_node("Literal".to_string()));
-        let mut adj_list = vec![];
 
-        // Checking for a cached version
-        let mut recalculate = false;
-        match load_relations(&format!("./data/{}.db/relation_counts", self.dataset.to_lowercase())) {
-            Ok((version, result)) => {
-                if version == self.get_history().lines().count() {
-                    adj_list = result;
-                } else {
-                    recalculate = true;
-                }
-            }
-            Err(_) => {
-                recalculate = true;
-            }
+        let (types, adj_list) = self.class_relations();
+        for t in types {
+            let nid = graph.add_node(t.clone());
+            node_map.insert(t.clone(), nid);
+        }
+
+        // Loading to a graph from the adjecency list
+        for e in adj_list {
+            graph.add_edge(node_map[&e.0], node_map[&e.2], (e.1, e.3, None, None));
         }
+        (graph, node_map)
+    }
 
-        // Slower when cached, but acceptable
+    /// Classes of the dataset and their relations: class, predicate, class of the objects (`Literal` for literals and
+    /// untyped IRIs) and number of triples.
+    ///
+    /// The relations are cached (see `cache`): after an update, only the outgoing relations of the classes it may
+    /// have changed are recomputed, unless it added or removed instances, which can change the class of any object.
+    /// Nothing is written to the store: the legacy graph `<urn:class_relations>` is only built by the
+    /// `class_graph.sparql` routines.
+    pub(crate) fn class_relations(&self) -> (Vec<String>, ClassRelations) {
+        let version = self.cache_version();
         let classes_query = "SELECT DISTINCT ?t WHERE {
             ?s a ?t.
         }";
@@ -1455,15 +2114,52 @@ WHERE {{
             Err(_) => panic!("Failed to fetch types. Failed miserably"),
         };
 
-        //Doing the computation if no cached version
-        if recalculate {
-            let _ = self.execute("class_graph.sparql::Clear class relations graph".to_string());
-            for t in types {
-                let nid = graph.add_node(t.clone());
-                node_map.insert(t.clone(), nid);
+        // Checking for a cached version
+        let adj_list = match self.load_cache::<ClassRelations>(CLASS_GRAPH_CACHE) {
+            Some(Cached { data, changes }) if changes.is_empty() => data,
+            Some(Cached { data, changes }) if changes.types.is_empty() && !changes.everything => {
+                let mut touched = vec![];
+                for t in &types {
+                    let predicates = data
+                        .iter()
+                        .filter(|e| e.0 == *t)
+                        .map(|e| e.1.clone())
+                        .collect::<BTreeSet<_>>();
+                    match self.touched_predicates(&changes, t, &predicates) {
+                        Some(p) if p.is_empty() => (),
+                        _ => touched.push(t.clone()),
+                    }
+                }
+                println!("class graph: recomputing the relations of {} classes", touched.len());
+                let mut adj_list = data
+                    .into_iter()
+                    .filter(|e| !touched.contains(&e.0))
+                    .collect::<Vec<_>>();
+                for t in &touched {
+                    adj_list.extend(self.class_relations_of(t));
+                }
+                self.save_cache(CLASS_GRAPH_CACHE, version, &adj_list);
+                adj_list
+            }
+            _ => {
+                //Doing the computation if no cached version
+                let mut adj_list = vec![];
+                for t in &types {
+                    adj_list.extend(self.class_relations_of(t));
+                }
+                self.save_cache(CLASS_GRAPH_CACHE, version, &adj_list);
+                println!("class graph saved");
+                adj_list
+            }
+        };
+        (types, adj_list)
+    }
 
-                let outgoing_edges_query = format!(
-                    r#"
+    /// Outgoing relations of the class `t`.
+    fn class_relations_of(&self, t: &str) -> ClassRelations {
+        let mut adj_list = vec![];
+        let outgoing_edges_query = format!(
+            r#"
 SELECT ?p ?t2 (COUNT(?o) as ?cnt) WHERE {{
     ?s ?p ?o.
     ?s a {t}.
@@ -1471,156 +2167,36 @@ SELECT ?p ?t2 (COUNT(?o) as ?cnt) WHERE {{
 }}
 GROUP BY ?p ?t2
             "#
-                );
-                match self.query(&outgoing_edges_query) {
-                    Ok(result) =>
-                        result.iter().for_each(|r| {
-                            let itm = (
-                                t.clone(),
-                                r.get("p").unwrap().to_string(),
-                                match r.get("t2") {
-                                    Some(v) => v.to_string(),
-                                    None => "Literal".to_string(),
-                                },
-                                match r.get("cnt").unwrap() {
-                                    Literal(literal) => literal.value().parse::<f64>().unwrap(),
-                                    _ => panic!("Count is not a literal!!! Not possible"),
-                                },
-                            );
-                            // Keeping legacy class graph in the store
-                            if itm.2 != "Literal".to_string() {
-                                let q = &format!(
-                                    r#"
-INSERT DATA {{   
-    GRAPH <urn:class_relations> {{
-        {} {} {}.
-    }}
-}}"#,
-                                    itm.0,
-                                    itm.1,
-                                    itm.2
-                                );
-                                match self.update(q) {
-                                    Ok(_) => (),
-                                    Err(e) =>
-                                        match e {
-                                            StoreError::EvaluationError(err) => {
-                                                println!("{}", q);
-                                                println!("{}", err);
-                                            }
-                                            StoreError::UnsupportedError => (),
-                                        }
-                                }
-                            }
-                            if !(itm.1 == "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>") {
-                                adj_list.push(itm);
-                            }
-                        }),
-                    Err(_) => panic!("Something went wronnnnng!"),
-                };
-            }
-            match
-                save_relations(
-                    &format!("./data/{}.db/relation_counts", self.dataset.to_lowercase()),
-                    &adj_list,
-                    self.get_history().lines().count()
-                )
-            {
-                Ok(_) => println!("class graph saved"),
-                Err(_) => println!("error caching class graph"),
-            };
-        } else {
-            for t in types {
-                let nid = graph.add_node(t.clone());
-                node_map.insert(t.clone(), nid);
-            }
-        }
-
-        // Loading to a graph from the adjecency list
-        for e in adj_list {
-            graph.add_edge(node_map[&e.0], node_map[&e.2], (e.1, e.3, None, None));
-        }
-        (graph, node_map)
-    }
-
-    pub fn page_rank(
-        &self,
-        graph: &Graph<String, (String, f64, Option<f64>, Option<f64>)>,
-        node_map: &HashMap<String, NodeIndex>,
-        node_counts: &HashMap<String, f64>,
-        direction: petgraph::Direction
-    ) -> (HashMap<String, f64>, HashMap<String, HashMap<String, f64>>) {
-        let mut page_rank = HashMap::new();
-        let mut edge_rank = HashMap::new();
-        let mut ertotal = 0.0;
-        let mut total = 0.0;
-
-        let literal = node_map["Literal"];
-
-        for n in node_map.keys() {
-            page_rank.insert(n.clone(), 0.0);
-        }
-
-        for _ in 0..10000 {
-            let node = choice(&node_counts).unwrap();
-            let mut current = *node_map.get(&node).unwrap();
-            *page_rank.get_mut(&node).unwrap() += 1.0;
-            total += 1.0;
-
-            for _ in 0..10 {
-                let neighbors = graph.edges_directed(current, direction);
-                let mut edge_map: HashMap<EdgeIndex, f64> = HashMap::new();
-                for edge in neighbors {
-                    let id = edge.id();
-                    if direction == Outgoing {
-                        edge_map.insert(id, edge.weight().2.unwrap());
-                    } else {
-                        edge_map.insert(id, edge.weight().3.unwrap());
+        );
+        match self.query(&outgoing_edges_query) {
+            Ok(result) =>
+                result.iter().for_each(|r| {
+                    let itm = (
+                        t.to_string(),
+                        r.get("p").unwrap().to_string(),
+                        match r.get("t2") {
+                            Some(v) => v.to_string(),
+                            None => "Literal".to_string(),
+                        },
+                        match r.get("cnt").unwrap() {
+                            Literal(literal) => literal.value().parse::<f64>().unwrap(),
+                            _ => panic!("Count is not a literal!!! Not possible"),
+                        },
+                    );
+                    if !(itm.1 == "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>") {
+                        adj_list.push(itm);
                     }
-                }
-                if edge_map.is_empty() {
-                    break;
-                }
-                let follow = choice(&edge_map).unwrap();
-                let key = (graph[current].clone(), graph.edge_weight(follow).unwrap().0.clone());
-                if !edge_rank.contains_key(&key.0) {
-                    edge_rank.insert(key.0.clone(), HashMap::new());
-                }
-                if edge_rank.get(&key.0).unwrap().contains_key(&key.1) {
-                    *edge_rank.get_mut(&key.0).unwrap().get_mut(&key.1).unwrap() += 1.0;
-                } else {
-                    edge_rank.get_mut(&key.0).unwrap().insert(key.1, 1.0);
-                }
-                ertotal += 1.0;
-                let last = current.clone();
-                if direction == Outgoing {
-                    current = graph.edge_endpoints(follow).unwrap().1;
-                } else {
-                    current = graph.edge_endpoints(follow).unwrap().0;
-                }
-                if current == literal {
-                    *page_rank.get_mut(&graph[last]).unwrap() += graph
-                        .edge_weight(follow)
-                        .unwrap()
-                        .2.unwrap();
-                    break;
-                }
-                *page_rank.get_mut(&graph[current]).unwrap() += 1.0;
-                total += 1.0;
-            }
-        }
-        for (_, v) in &mut page_rank {
-            *v /= total;
-        }
-        for (_, hm) in &mut edge_rank {
-            for (_, v) in hm.iter_mut() {
-                *v /= ertotal;
-            }
-        }
-        (page_rank, edge_rank)
+                }),
+            Err(_) => panic!("Something went wronnnnng!"),
+        };
+        adj_list
     }
 
-    fn fix_types(&self, scores: HashMap<String, f64>) {
+    /// Keeps a single type per entity: the type with the best score, the others becoming `schema:additionalType`.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of the first failed update.
+    pub fn fix_types(&self, scores: &HashMap<String, f64>) -> Result<(), StoreError> {
         let q =
             "
         SELECT DISTINCT ?t1 ?t2  {{
@@ -1640,14 +2216,15 @@ INSERT DATA {{
                     let r = result.get(0).unwrap();
                     let t1 = r.get("t1").unwrap().to_string();
                     let t2 = r.get("t2").unwrap().to_string();
-                    let (keep, skip) = if scores[&t1] > scores[&t2] { (t1, t2) } else { (t2, t1) };
+                    let score = |t: &String| scores.get(t).copied().unwrap_or(0.0);
+                    let (keep, skip) = if score(&t1) > score(&t2) { (t1, t2) } else { (t2, t1) };
                     let query = format!(
                         r#"
                         DELETE {{
                             ?s a {skip}.
                         }}
                         INSERT {{
-                            ?s <http://schema.org/additionaltype> {skip}.
+                            ?s <http://schema.org/additionalType> {skip}.
                         }}
                         WHERE {{
                             ?s a {skip}.
@@ -1656,20 +2233,15 @@ INSERT DATA {{
                     
                     "#
                     );
-                    match self.update(&query) {
-                        Ok(_) => {
-                            self.write_to_history(format!("```sparql\n{}\n```", query));
-                        }
-                        Err(_) => {
-                            panic!("ERROR");
-                        }
-                    }
+                    self.update(&query)?;
+                    self.write_to_history(format!("```sparql\n{}\n```", query));
                 }
                 Err(_) => {
                     break;
                 }
             }
         }
+        Ok(())
     }
     pub fn delete_predicate(&self, otype: &str, pred: &str) {
         let q = format!(
@@ -1687,6 +2259,16 @@ INSERT DATA {{
         match self.update(&q) {
             Ok(_) => {
                 self.write_to_history(format!("```sparql\n{}\n```", q));
+                // The decision is a label for the predicate keep model, once the values are actually deleted
+                let label = PredicateLabel {
+                    class: otype.to_string(),
+                    predicate: pred.to_string(),
+                    keep: false,
+                    stats: HashMap::new(),
+                };
+                if let Err(e) = self.record_predicate_label(label) {
+                    eprintln!("Failed to record the label of {pred} for {otype}: {e}");
+                }
             }
             Err(_) => panic!("failed to delete predicate {pred} for type {otype}"),
         }
@@ -1695,22 +2277,11 @@ INSERT DATA {{
     pub fn analyse_objects(&self, otype: &str) -> i64 {
         let mut cnt = 0;
         let mut scores = HashMap::new();
-        match
-            load_predicate_analysis(
-                &format!(
-                    "./data/{}/stat_anal/{}",
-                    self.dataset,
-                    otype.replace("<", "").replace(">", "").replace(":", "_").replace("/", "\\")
-                )
-            )
-        {
-            Ok((_, mut data)) => {
-                compute_scores(&mut data);
-                data.iter().for_each(|(k, v)| {
-                    scores.insert(k.clone(), v.get("score").unwrap().clone());
-                });
-            }
-            Err(_) => (),
+        if let Some(mut data) = self.cached_predicate_analysis(otype) {
+            compute_scores(&mut data);
+            data.iter().for_each(|(k, v)| {
+                scores.insert(k.clone(), v.get("score").unwrap().clone());
+            });
         }
         let mut sm = 0.0;
         for (_, s) in &scores {
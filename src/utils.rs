@@ -24,8 +24,15 @@ use oxigraph::model::Term::{ self, NamedNode, Literal };
 use oxigraph::sparql::{ QuerySolution };
 use rand::Rng;
 
+use crate::keep_model;
+use crate::vocabulary::{ self, TermKind };
+
+const RDF_TYPE: &str = "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>";
+
 /// Preprocesses an N-Quads file by performing a series of normalization and cleanup steps:
-/// - Removes invalid Unicode replacement characters and standardizes schema.org IRIs.
+/// - Removes invalid Unicode replacement characters and standardizes schema.org IRIs to `http://schema.org/`,
+///   with the canonical casing of the term when the vocabulary knows it (see `vocabulary`): a predicate is cased as a
+///   property, the object of `rdf:type` as a class.
 /// - Converts inline JSON‐LD constructs wrapped in `<…{…}…>` into quoted literals.
 /// - Rewrites `<@type:>` tokens to the standard RDF type IRI.
 /// - Strips named graph annotations, ending each triple with a simple `.`.
@@ -55,13 +62,32 @@ pub fn preprocess(nquads_file: &str) {
     let bnode_regex = Regex::new(r"_:([A-Za-z0-9]+)").unwrap();
 
     let schema = Regex::new(r"<https?:\/\/schema\.org\/([^>]*)>").unwrap();
+    // Predicate and IRI object of a statement, to fix the casing of a term as a property or as a class
+    let statement = Regex::new(r"^\s*\S+\s+(<[^>]*>)\s+(<[^>]*>)?").unwrap();
 
     while reader.read_line(&mut line).expect("Failed to read the file") != 0 {
         line = line.replace("\\uFFFD", "");
 
+        let (predicate, object) = match statement.captures(&line) {
+            Some(caps) => {
+                let is_type = caps
+                    .get(1)
+                    .is_some_and(|p| p.as_str() == "<@type:>" || p.as_str() == RDF_TYPE);
+                (caps.get(1).map(|p| p.start()), caps.get(2).filter(|_| is_type).map(|o| o.start()))
+            }
+            None => (None, None),
+        };
         line = schema
             .replace_all(&line, |caps: &regex::Captures| {
-                format!("<http://schema.org/{}>", caps.get(1).unwrap().as_str().to_lowercase())
+                let start = caps.get(0).unwrap().start();
+                let expected = if Some(start) == predicate {
+                    Some(TermKind::Property)
+                } else if Some(start) == object {
+                    Some(TermKind::Class)
+                } else {
+                    None
+                };
+                vocabulary::schema_iri_as(caps.get(1).unwrap().as_str(), expected)
             })
             .into_owned();
 
@@ -169,29 +195,6 @@ pub fn url_decode(input: &str) -> String {
     result
 }
 
-/// Retrieves a query parameter value from a URL-encoded query string.
-///
-/// # Arguments
-///
-/// * `query` – The raw query string (e.g. `"a=1&b=2"`).
-/// * `key` – The parameter name to extract.
-///
-/// # Returns
-///
-/// * `Some(String)` of the decoded parameter value, or `None` if the key is not present.
-pub fn extract_query_param(query: &str, key: &str) -> Option<String> {
-    for pair in query.split('&') {
-        let p = percent_encoding::percent_decode_str(pair).decode_utf8().unwrap().to_string();
-        let mut parts = p.splitn(2, '=');
-        let k = parts.next()?;
-        let v = parts.next()?;
-        if k == key {
-            return Some(v.replace("+", " ").replace("%20", " ").replace("%23", ""));
-        }
-    }
-    None
-}
-
 /// Escapes HTML-special characters in a string to their entity equivalents.
 ///
 /// # Arguments
@@ -366,21 +369,25 @@ pub fn calculate_probabilities_for_graph(
     }
 }
 
-pub fn choice<T: Clone>(map: &HashMap<T, f64>) -> Option<T> {
+/// Picks a key of `map` at random, with a probability proportional to its weight.
+///
+/// Keys are drawn in sorted order, so that a seeded `rng` always picks the same keys.
+pub fn choice<T: Clone + Ord, R: Rng>(map: &HashMap<T, f64>, rng: &mut R) -> Option<T> {
     if map.is_empty() {
         println!("EMPTY MAP ");
         return None;
     }
 
-    let total: f64 = map.values().sum();
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+    let total: f64 = entries.iter().map(|(_, weight)| *weight).sum();
     if total == 0.0 {
         println!("NUL TOTAL");
         return None;
     }
 
-    let mut rng = rand::rng();
     let mut rand: f64 = rng.random();
-    for (key, weight) in map {
+    for (key, weight) in entries {
         if rand < *weight / total {
             return Some(key.clone());
         }
@@ -392,45 +399,6 @@ pub fn choice<T: Clone>(map: &HashMap<T, f64>) -> Option<T> {
 // #[derive(Serialize, Deserialize, Debug)]
 // struct RelationCounts(String, String, String, f64);
 
-pub fn save_relations(
-    path: &str,
-    data: &Vec<(String, String, String, f64)>,
-    version: usize
-) -> std::io::Result<()> {
-    let file = File::create(path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, &(version, data))?;
-    Ok(())
-}
-
-pub fn load_relations(path: &str) -> std::io::Result<(usize, Vec<(String, String, String, f64)>)> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let data = serde_json::from_reader(reader)?;
-    Ok(data)
-}
-
-pub fn save_predicate_anlaysis(
-    path: &str,
-    data: &Vec<(String, HashMap<String, f64>)>,
-    version: usize
-) -> std::io::Result<()> {
-    println!("{}", path);
-    let file = File::create(path)?;
-    let writer = BufWriter::new(file);
-    serde_json::to_writer(writer, &(version, data))?;
-    Ok(())
-}
-
-pub fn load_predicate_analysis(
-    path: &str
-) -> std::io::Result<(usize, Vec<(String, HashMap<String, f64>)>)> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let data = serde_json::from_reader(reader)?;
-    Ok(data)
-}
-
 pub fn normalize_column(data: &mut Vec<(String, HashMap<String, f64>)>, col: &str) {
     let min_val = data
         .iter()
@@ -482,12 +450,22 @@ pub fn compute_scores(data: &mut Vec<(String, HashMap<String, f64>)>) {
             row.insert("score".to_string(), 0.0);
         }
         println!("{}", name);
-        let nn_keep = nn_interface(f, u, h, q, r);
-        row.insert("keep".to_string(), nn_keep);
-        // if nn_keep > 0.5 {
-        // } else {
-        //     row.insert("keep".to_string(), 1.0 - nn_keep);
-        // }
+    }
+
+    // All of the predicates go through the model at once
+    let model = keep_model::get();
+    let rows = data
+        .iter()
+        .map(|(_, row)| row)
+        .collect::<Vec<_>>();
+    let keep = model.predict(&rows).unwrap_or_else(|e| {
+        eprintln!("{e}: falling back to the heuristic");
+        rows.iter()
+            .map(|row| keep_model::fallback_score(row))
+            .collect()
+    });
+    for ((_, row), keep) in data.iter_mut().zip(keep) {
+        row.insert("keep".to_string(), keep);
     }
 
     println!("{}", s / inv_temp);
@@ -544,40 +522,3 @@ pub fn remove_disconnected(
     // }
     return order;
 }
-
-use tract_onnx::prelude::*;
-
-type Model = RunnableModel<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
-
-pub fn nn_interface(freq: f64, uniqueness: f64, entropy: f64, quality: f64, edge_rank: f64) -> f64 {
-    let model: Model = tract_onnx
-        ::onnx()
-        .model_for_path("./ml/model.onnx")
-        .unwrap()
-        .with_input_fact(0, f32::fact(&[1, 5]).into())
-        .unwrap()
-        .into_optimized()
-        .unwrap()
-        .into_runnable()
-        .unwrap();
-
-    // println!("Model loaded successfully.");
-
-    let input_vec = [
-        freq as f32,
-        uniqueness as f32,
-        entropy as f32,
-        quality as f32,
-        edge_rank as f32,
-    ];
-    let input: Tensor = tract_ndarray::arr1(&input_vec).to_shape((1, 5)).unwrap().to_owned().into();
-
-    // println!("Running inference with input: {:?}", input_vec);
-
-    let result = model.run(tvec!(input.into())).unwrap();
-
-    let output: &[f32] = result[0].as_slice().unwrap();
-
-    println!("Model output: {:?}", output);
-    return output[1] as f64;
-}
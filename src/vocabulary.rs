@@ -0,0 +1,524 @@
+//! # Schema.org vocabulary
+//!
+//! Local copy of the schema.org vocabulary (e.g. `schemaorg-current-https.nt` from
+//! <https://schema.org/docs/developers.html>), loaded once at startup.
+//!
+//! - Resolves the canonical casing of terms (`addresscountry` → `addressCountry`), used by `preprocess`.
+//! - Gives the label and comment of classes and properties, shown in the UI.
+//! - Knows the `rdfs:subClassOf` and `rdfs:subPropertyOf` hierarchies, and the `schema:inverseOf` properties.
+//! - Flags the schema.org classes and predicates of a dataset that do not exist in the vocabulary.
+//!
+//! Lookups fall back to ignoring the case of local names, so datasets preprocessed before the vocabulary was available
+//! still get labels, and their lowercased terms are reported with the canonical term to use instead. Schema.org has
+//! terms that only differ by their case (the class `Brand` and the property `brand`): the exact name always wins, and
+//! otherwise the kind expected at the position of the term (class or property) picks one.
+
+use std::collections::{ BTreeSet, HashMap };
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use oxigraph::io::{ RdfFormat, RdfParser };
+use serde::Serialize;
+
+use crate::explore::parse_iri;
+use crate::jobs;
+use crate::store::{ StoreError, KG };
+use crate::utils::extract_literal;
+
+/// Namespace of the schema.org terms, as written in the store.
+pub const SCHEMA_NS: &str = "http://schema.org/";
+
+/// Vocabulary file loaded when none is given on the command line, if it exists.
+pub const DEFAULT_VOCABULARY_PATH: &str = "./data/schemaorg-current-https.nt";
+
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const RDF_PROPERTY: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#Property";
+const RDFS_CLASS: &str = "http://www.w3.org/2000/01/rdf-schema#Class";
+const RDFS_LABEL: &str = "http://www.w3.org/2000/01/rdf-schema#label";
+const RDFS_COMMENT: &str = "http://www.w3.org/2000/01/rdf-schema#comment";
+const RDFS_SUBCLASS_OF: &str = "http://www.w3.org/2000/01/rdf-schema#subClassOf";
+const RDFS_SUBPROPERTY_OF: &str = "http://www.w3.org/2000/01/rdf-schema#subPropertyOf";
+
+static VOCABULARY: OnceLock<Vocabulary> = OnceLock::new();
+
+/// # Kinds of schema.org terms.
+///
+/// ## Variants:
+/// * `Class`: An `rdfs:Class` (including enumerations).
+/// * `DataType`: A `schema:DataType` (`Text`, `Number`, `Date`, ...), or a subclass of one (`URL`, `Integer`).
+/// * `Property`: An `rdf:Property`.
+/// * `Member`: A member of an enumeration (e.g. `InStock`, an `ItemAvailability`).
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TermKind {
+    Class,
+    DataType,
+    Property,
+    Member,
+}
+
+/// A term of the vocabulary.
+///
+/// ## Fields
+/// * `name` - Canonical local name (`addressCountry`).
+/// * `kind` - Class, property or enumeration member.
+/// * `label`, `comment` - `rdfs:label` and `rdfs:comment` (HTML, as published by schema.org).
+/// * `parents` - Local names of the direct super classes (or super properties).
+/// * `domains`, `ranges` - Local names of the `schema:domainIncludes` and `schema:rangeIncludes` of a property.
+/// * `inverse` - Local name of the `schema:inverseOf` of a property (`hasPart` for `isPartOf`).
+#[derive(Serialize, Clone)]
+pub struct VocabularyTerm {
+    pub name: String,
+    pub kind: TermKind,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub parents: Vec<String>,
+    pub domains: Vec<String>,
+    pub ranges: Vec<String>,
+    pub inverse: Option<String>,
+}
+
+impl VocabularyTerm {
+    /// IRI of the term, in its SPARQL form.
+    pub fn iri(&self) -> String {
+        format!("<{SCHEMA_NS}{}>", self.name)
+    }
+}
+
+/// The schema.org vocabulary.
+///
+/// ## Fields
+/// * `terms` - Terms by exact local name.
+/// * `casings` - Local names of the terms by lowercased local name, only used to fix the casing of a name.
+pub struct Vocabulary {
+    terms: HashMap<String, VocabularyTerm>,
+    casings: HashMap<String, Vec<String>>,
+}
+
+/// Whether a term of kind `kind` can be used where a `expected` is: data types are classes too.
+fn fits(kind: TermKind, expected: TermKind) -> bool {
+    kind == expected || (expected == TermKind::Class && kind == TermKind::DataType)
+}
+
+/// Returns the local name of a schema.org IRI (given with or without brackets, over http or https).
+pub fn local_name(iri: &str) -> Option<&str> {
+    let iri = iri.trim_start_matches('<').trim_end_matches('>');
+    iri.strip_prefix(SCHEMA_NS)
+        .or_else(|| iri.strip_prefix("https://schema.org/"))
+        .filter(|name| !name.is_empty())
+}
+
+/// Loads the vocabulary used by the rest of the explorer. Only the first call has an effect.
+///
+/// # Errors
+/// Returns a message if the file cannot be read or parsed.
+pub fn init(path: &str) -> Result<(), String> {
+    let vocabulary = Vocabulary::load(path)?;
+    println!("Loaded {} schema.org terms from {path}", vocabulary.len());
+    let _ = VOCABULARY.set(vocabulary);
+    Ok(())
+}
+
+/// Returns the loaded vocabulary, if any.
+pub fn get() -> Option<&'static Vocabulary> {
+    VOCABULARY.get()
+}
+
+/// Builds the IRI of a schema.org term from a local name, with its canonical casing when the vocabulary knows it.
+pub fn schema_iri(name: &str) -> String {
+    schema_iri_as(name, None)
+}
+
+/// Builds the IRI of a schema.org term from a local name, like `schema_iri`, for a position where a term of the
+/// `expected` kind is used (a predicate, or the object of `rdf:type`).
+pub fn schema_iri_as(name: &str, expected: Option<TermKind>) -> String {
+    match get().and_then(|v| v.lookup(name, expected)) {
+        Some(term) => term.iri(),
+        None => format!("<{SCHEMA_NS}{name}>"),
+    }
+}
+
+/// Resolves a term given as a schema.org local name (`Book`, with any casing) or as an IRI, to its SPARQL form.
+///
+/// # Errors
+/// Returns a message if the IRI is invalid.
+pub fn resolve_term(term: &str) -> Result<String, String> {
+    let term = term.trim();
+    if term.contains(':') { parse_iri(term) } else { Ok(schema_iri(term)) }
+}
+
+/// Short name of a term (in its SPARQL form or not), for the labels, columns and file names of the exports: the
+/// canonical schema.org name, or the last segment of the IRI.
+pub fn short_name(term: &str) -> String {
+    let iri = term.trim_start_matches('<').trim_end_matches('>');
+    if let Some(name) = local_name(iri) {
+        return get()
+            .and_then(|v| v.canonical_name(name))
+            .unwrap_or(name)
+            .to_string();
+    }
+    iri.rsplit(['/', '#'])
+        .find(|s| !s.is_empty())
+        .unwrap_or(iri)
+        .to_string()
+}
+
+impl Vocabulary {
+    /// Parses a vocabulary file; the RDF format is guessed from its extension (`.nt`, `.ttl`, `.rdf`, ...).
+    ///
+    /// # Errors
+    /// Returns a message if the file cannot be opened, its format is unknown or it is not valid RDF.
+    pub fn load(path: &str) -> Result<Vocabulary, String> {
+        let format = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(RdfFormat::from_extension)
+            .ok_or_else(|| format!("Unknown RDF format of the vocabulary file {path}"))?;
+        let file = File::open(path).map_err(|e| format!("Cannot open the vocabulary file {path}: {e}"))?;
+
+        let mut kinds: HashMap<String, TermKind> = HashMap::new();
+        let mut labels: HashMap<String, String> = HashMap::new();
+        let mut comments: HashMap<String, String> = HashMap::new();
+        let mut parents: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut members: Vec<String> = vec![];
+        let mut data_types: Vec<String> = vec![];
+        let mut domains: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut ranges: HashMap<String, BTreeSet<String>> = HashMap::new();
+        let mut inverses: HashMap<String, String> = HashMap::new();
+
+        for quad in RdfParser::from_format(format).for_reader(BufReader::new(file)) {
+            let quad = quad.map_err(|e| format!("Invalid vocabulary file {path}: {e}"))?;
+            let subject = quad.subject.to_string();
+            let Some(name) = local_name(&subject) else {
+                continue;
+            };
+            let name = name.to_string();
+            let object = quad.object;
+            let object_name = || local_name(&object.to_string()).map(|n| n.to_string());
+            match quad.predicate.as_str() {
+                RDF_TYPE =>
+                    match object.to_string().trim_matches(['<', '>']) {
+                        RDFS_CLASS => {
+                            kinds.insert(name, TermKind::Class);
+                        }
+                        RDF_PROPERTY => {
+                            kinds.insert(name, TermKind::Property);
+                        }
+                        _ =>
+                            match object_name().as_deref() {
+                                Some("DataType") => data_types.push(name),
+                                Some(_) => members.push(name),
+                                None => {}
+                            }
+                    }
+                RDFS_LABEL => {
+                    if let Some(label) = extract_literal(Some(&object)) {
+                        labels.insert(name, label);
+                    }
+                }
+                RDFS_COMMENT => {
+                    if let Some(comment) = extract_literal(Some(&object)) {
+                        comments.insert(name, comment);
+                    }
+                }
+                RDFS_SUBCLASS_OF | RDFS_SUBPROPERTY_OF => {
+                    if let Some(parent) = object_name() {
+                        parents.entry(name).or_default().insert(parent);
+                    }
+                }
+                _ =>
+                    match (local_name(quad.predicate.as_str()), object_name()) {
+                        (Some("domainIncludes"), Some(domain)) => {
+                            domains.entry(name).or_default().insert(domain);
+                        }
+                        (Some("rangeIncludes"), Some(range)) => {
+                            ranges.entry(name).or_default().insert(range);
+                        }
+                        (Some("inverseOf"), Some(inverse)) => {
+                            inverses.insert(name, inverse);
+                        }
+                        _ => {}
+                    }
+            }
+        }
+        for name in data_types {
+            kinds.insert(name, TermKind::DataType);
+        }
+        // Enumeration members typed as classes too (rare) stay classes
+        for name in members {
+            kinds.entry(name).or_insert(TermKind::Member);
+        }
+
+        let terms: HashMap<String, VocabularyTerm> = kinds
+            .into_iter()
+            .map(|(name, kind)| {
+                let term = VocabularyTerm {
+                    label: labels.remove(&name),
+                    comment: comments.remove(&name),
+                    parents: parents.remove(&name).unwrap_or_default().into_iter().collect(),
+                    domains: domains.remove(&name).unwrap_or_default().into_iter().collect(),
+                    ranges: ranges.remove(&name).unwrap_or_default().into_iter().collect(),
+                    inverse: inverses.remove(&name),
+                    name,
+                    kind,
+                };
+                (term.name.clone(), term)
+            })
+            .collect();
+        let mut casings: HashMap<String, Vec<String>> = HashMap::new();
+        for name in terms.keys() {
+            casings.entry(name.to_lowercase()).or_default().push(name.clone());
+        }
+        casings.values_mut().for_each(|names| names.sort());
+        Ok(Vocabulary { terms, casings })
+    }
+
+    /// Number of terms of the vocabulary.
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Looks a term up by local name or IRI: the exact name, or else a term with another casing.
+    pub fn term(&self, name_or_iri: &str) -> Option<&VocabularyTerm> {
+        self.lookup(name_or_iri, None)
+    }
+
+    /// Looks a term up by local name or IRI, like `term`, where a term of the `expected` kind is used.
+    ///
+    /// The exact name wins if it has the expected kind, then any other casing that has it (`brand` as a class is
+    /// `Brand`), then the exact name or the first other casing whatever their kind.
+    pub fn lookup(&self, name_or_iri: &str, expected: Option<TermKind>) -> Option<&VocabularyTerm> {
+        let name = local_name(name_or_iri).unwrap_or(name_or_iri);
+        let mut candidates = self.casings
+            .get(&name.to_lowercase())?
+            .iter()
+            .filter_map(|n| self.terms.get(n))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|t| t.name != name);
+        expected
+            .and_then(|kind| candidates.iter().find(|t| fits(t.kind, kind)))
+            .or(candidates.first())
+            .copied()
+    }
+
+    /// Canonical local name of a term (`addresscountry` → `addressCountry`).
+    pub fn canonical_name(&self, name: &str) -> Option<&str> {
+        self.term(name).map(|t| t.name.as_str())
+    }
+
+    /// Every ancestor of a class (or property), closest first, following `rdfs:subClassOf` (or `rdfs:subPropertyOf`).
+    pub fn ancestors(&self, name_or_iri: &str) -> Vec<&VocabularyTerm> {
+        let mut ancestors: Vec<&VocabularyTerm> = vec![];
+        let mut queue = self
+            .term(name_or_iri)
+            .map(|t| t.parents.clone())
+            .unwrap_or_default();
+        while !queue.is_empty() {
+            let parent = queue.remove(0);
+            if let Some(term) = self.term(&parent) {
+                if ancestors.iter().all(|a| a.name != term.name) {
+                    ancestors.push(term);
+                    queue.extend(term.parents.iter().cloned());
+                }
+            }
+        }
+        ancestors
+    }
+
+    /// Whether `name_or_iri` is `ancestor` or one of its descendants.
+    pub fn is_a(&self, name_or_iri: &str, ancestor: &str) -> bool {
+        let (Some(term), Some(ancestor)) = (self.term(name_or_iri), self.term(ancestor)) else {
+            return false;
+        };
+        term.name == ancestor.name || self.ancestors(&term.name).iter().any(|a| a.name == ancestor.name)
+    }
+
+    /// Whether a class is a data type or a subclass of one (`URL` is a `Text`).
+    pub fn is_data_type(&self, name_or_iri: &str) -> bool {
+        self.term(name_or_iri).is_some_and(|t| {
+            t.kind == TermKind::DataType ||
+                self
+                    .ancestors(&t.name)
+                    .iter()
+                    .any(|a| a.kind == TermKind::DataType)
+        })
+    }
+
+    /// Direct children of a class (or property).
+    pub fn children(&self, name_or_iri: &str) -> Vec<&VocabularyTerm> {
+        let Some(parent) = self.term(name_or_iri) else {
+            return vec![];
+        };
+        let mut children = self.terms
+            .values()
+            .filter(|t| t.parents.contains(&parent.name))
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+        children
+    }
+}
+
+/// # Status of a dataset term against the vocabulary.
+///
+/// ## Variants:
+/// * `Known`: The term exists with this exact casing.
+/// * `WrongCase`: The term exists with another casing (see `canonical`).
+/// * `WrongKind`: The term exists but is used as something else (e.g. a property used as a class).
+/// * `Unknown`: No such term in the vocabulary.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TermStatus {
+    Known,
+    WrongCase,
+    WrongKind,
+    Unknown,
+}
+
+/// A schema.org class or predicate of the dataset, checked against the vocabulary.
+///
+/// ## Fields
+/// * `iri` - Term as used in the dataset.
+/// * `count` - Number of instances (classes) or triples (predicates).
+/// * `status` - Result of the check.
+/// * `canonical` - Canonical IRI of the term, if the vocabulary knows it.
+/// * `label`, `comment` - From the vocabulary.
+/// * `ancestors` - Local names of the super classes (or super properties), closest first.
+#[derive(Serialize, Clone)]
+pub struct TermUsage {
+    pub iri: String,
+    pub count: u64,
+    pub status: TermStatus,
+    pub canonical: Option<String>,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+    pub ancestors: Vec<String>,
+}
+
+/// Schema.org terms used by the dataset, checked against the vocabulary.
+///
+/// ## Fields
+/// * `vocabulary_size` - Number of terms of the vocabulary.
+/// * `classes`, `predicates` - Schema.org terms used as classes (`rdf:type` objects) and as predicates, most used first.
+/// * `other_namespaces` - Number of classes and predicates outside of schema.org, which are not checked.
+#[derive(Serialize, Clone)]
+pub struct VocabularyReport {
+    pub vocabulary_size: usize,
+    pub classes: Vec<TermUsage>,
+    pub predicates: Vec<TermUsage>,
+    pub other_namespaces: u64,
+}
+
+impl VocabularyReport {
+    /// Number of classes and predicates that are not `Known`.
+    pub fn issues(&self) -> usize {
+        self.classes
+            .iter()
+            .chain(&self.predicates)
+            .filter(|t| t.status != TermStatus::Known)
+            .count()
+    }
+}
+
+impl Vocabulary {
+    fn check(&self, iri: &str, count: u64, expected: TermKind) -> TermUsage {
+        let name = local_name(iri).unwrap_or_default();
+        let term = self.lookup(name, Some(expected));
+        let status = match term {
+            None => TermStatus::Unknown,
+            Some(t) if t.name != name => TermStatus::WrongCase,
+            Some(t) if !fits(t.kind, expected) => TermStatus::WrongKind,
+            Some(_) => TermStatus::Known,
+        };
+        TermUsage {
+            iri: iri.to_string(),
+            count,
+            status,
+            canonical: term.map(|t| t.iri()),
+            label: term.and_then(|t| t.label.clone()),
+            comment: term.and_then(|t| t.comment.clone()),
+            ancestors: term
+                .map(|t| self.ancestors(&t.name))
+                .unwrap_or_default()
+                .iter()
+                .map(|a| a.name.clone())
+                .collect(),
+        }
+    }
+}
+
+impl KG {
+    /// Checks the schema.org classes and predicates of the dataset against the vocabulary.
+    pub fn vocabulary_report(&self, vocabulary: &Vocabulary) -> VocabularyReport {
+        let mut other_namespaces = 0;
+        let mut usages = |query: &str, kind: TermKind| {
+            let mut found = vec![];
+            for row in self.query(query).unwrap_or_default() {
+                let (Some(term), Some(count)) = (row.get("term"), extract_literal(row.get("count"))) else {
+                    continue;
+                };
+                let count = count.parse::<u64>().unwrap_or(0);
+                let iri = term.to_string();
+                if local_name(&iri).is_some() {
+                    found.push(vocabulary.check(&iri, count, kind));
+                } else {
+                    other_namespaces += 1;
+                }
+            }
+            found
+        };
+        let classes = usages(
+            "SELECT ?term (COUNT(?s) AS ?count) WHERE { ?s a ?term . FILTER(isIRI(?term)) }
+            GROUP BY ?term ORDER BY DESC(?count)",
+            TermKind::Class
+        );
+        let predicates = usages(
+            "SELECT ?term (COUNT(*) AS ?count) WHERE { ?s ?term ?o . } GROUP BY ?term ORDER BY DESC(?count)",
+            TermKind::Property
+        );
+        VocabularyReport { vocabulary_size: vocabulary.len(), classes, predicates, other_namespaces }
+    }
+}
+
+impl KG {
+    /// Renames the schema.org terms of the dataset that only differ from the vocabulary by their casing
+    /// (e.g. the `addresscountry` of a dataset lowercased by an older `preprocess`).
+    ///
+    /// A class is only renamed as the object of `rdf:type`, and a predicate as a predicate, so that a class and a
+    /// property that share their lowercased name (`brand`) are renamed apart. Every rename is recorded in the
+    /// history. Returns the number of renamed terms.
+    ///
+    /// # Errors
+    /// Returns `StoreError::EvaluationError` if an update fails; the previous renames are kept.
+    pub fn canonicalize_schema_terms(&self, vocabulary: &Vocabulary) -> Result<usize, StoreError> {
+        let report = self.vocabulary_report(vocabulary);
+        let mut renames = vec![];
+        for (usages, is_class) in [(&report.classes, true), (&report.predicates, false)] {
+            for usage in usages.iter().filter(|t| t.status == TermStatus::WrongCase) {
+                let (old, Some(new)) = (&usage.iri, &usage.canonical) else {
+                    continue;
+                };
+                let q = if is_class {
+                    format!("DELETE {{ ?s a {old} . }} INSERT {{ ?s a {new} . }} WHERE {{ ?s a {old} . }}")
+                } else {
+                    format!("DELETE {{ ?s {old} ?o . }} INSERT {{ ?s {new} ?o . }} WHERE {{ ?s {old} ?o . }}")
+                };
+                renames.push((format!("{old} to {new}"), q));
+            }
+        }
+
+        let total = renames.len() as u64;
+        for (i, (rename, q)) in renames.iter().enumerate() {
+            jobs::log(format!("Renaming {rename}"));
+            self.update(q)?;
+            self.write_to_history(format!("```sparql\n{}\n```", q));
+            jobs::progress((i as u64) + 1, total);
+        }
+        Ok(renames.len())
+    }
+}
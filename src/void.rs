@@ -0,0 +1,287 @@
+//! # VoID dataset description
+//!
+//! Describes the dataset with the VoID vocabulary, typed as a DCAT dataset too, for the consumers of the published
+//! subsets. Served at `/.well-known/void` and written next to every dump of the store.
+//!
+//! - Statistics: triples, entities (typed subjects), distinct subjects and objects, classes and properties.
+//! - Class partitions (`void:classPartition`) with their entities, property partitions (`void:propertyPartition`)
+//!   with their triples, distinct subjects and objects.
+//! - Linksets between the class partitions, one per relation between two classes (see `KG::class_relations`), with
+//!   the triples linking their instances.
+//! - Provenance of WDC datasets: the crawl, its class-specific subset and the parts loaded.
+//!
+//! The description is cached (see `cache`) until the next update.
+
+use oxigraph::io::{ RdfFormat, RdfSerializer };
+use oxigraph::model::{ Literal, NamedNode, Triple };
+use oxigraph::sparql::QuerySolution;
+use serde::{ Deserialize, Serialize };
+
+use crate::store::{ StoreError, WdcSource, KG };
+use crate::utils::extract_literal;
+
+const VOID: &str = "http://rdfs.org/ns/void#";
+const DCAT: &str = "http://www.w3.org/ns/dcat#";
+const DCTERMS: &str = "http://purl.org/dc/terms/";
+const PROV: &str = "http://www.w3.org/ns/prov#";
+const RDF_TYPE: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#type";
+const XSD_INTEGER: &str = "http://www.w3.org/2001/XMLSchema#integer";
+
+/// Cache of the description, relative to `cache_dir`.
+const VOID_CACHE: &str = "void.json";
+
+/// Base of the IRIs of the descriptions written to files, followed by the dataset name.
+const EXPORT_BASE: &str = "urn:kg-explorer:void:";
+
+/// Instances of a class.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ClassPartition {
+    pub class: String,
+    pub entities: u64,
+}
+
+/// Use of a property.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PropertyPartition {
+    pub property: String,
+    pub triples: u64,
+    pub distinct_subjects: u64,
+    pub distinct_objects: u64,
+}
+
+/// Triples of a predicate linking the instances of two classes.
+///
+/// ## Fields
+/// * `subjects` - Class of the subjects.
+/// * `predicate` - Linking predicate.
+/// * `objects` - Class of the objects.
+/// * `triples` - Number of linking triples.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Linkset {
+    pub subjects: String,
+    pub predicate: String,
+    pub objects: String,
+    pub triples: u64,
+}
+
+/// VoID description of the dataset. Classes and properties are in their SPARQL form.
+///
+/// ## Fields
+/// * `dataset` - Name of the dataset.
+/// * `triples` - Triples of the default graph.
+/// * `entities` - Subjects having a type.
+/// * `distinct_subjects`, `distinct_objects` - Distinct subjects and objects of the triples.
+/// * `classes` - Class partitions, the largest first.
+/// * `properties` - Property partitions, the most used first.
+/// * `linksets` - Links between the classes, the largest first.
+/// * `source` - Web Data Commons origin of the dataset, if it was downloaded from there.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VoidDescription {
+    pub dataset: String,
+    pub triples: u64,
+    pub entities: u64,
+    pub distinct_subjects: u64,
+    pub distinct_objects: u64,
+    pub classes: Vec<ClassPartition>,
+    pub properties: Vec<PropertyPartition>,
+    pub linksets: Vec<Linkset>,
+    pub source: Option<WdcSource>,
+}
+
+fn count(row: &QuerySolution, var: &str) -> u64 {
+    extract_literal(row.get(var))
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(0)
+}
+
+fn integer(value: u64) -> Literal {
+    Literal::new_typed_literal(value.to_string(), NamedNode::new_unchecked(XSD_INTEGER))
+}
+
+/// Parses a class or property in its SPARQL form.
+fn iri(term: &str) -> Result<NamedNode, String> {
+    NamedNode::new(term.trim_matches(['<', '>'])).map_err(|e| format!("Invalid IRI {term}: {e}"))
+}
+
+impl KG {
+    /// Describes the dataset, or loads its cached description if the store did not change since.
+    ///
+    /// # Errors
+    /// Returns the `StoreError` of a failed count.
+    pub fn void_description(&self) -> Result<VoidDescription, StoreError> {
+        let version = self.cache_version();
+        if let Some(cached) = self.load_cache::<VoidDescription>(VOID_CACHE).filter(|c| c.changes.is_empty()) {
+            // The same store may be loaded with more parts
+            return Ok(VoidDescription { source: self.wdc_source(), ..cached.data });
+        }
+
+        let totals = self.query(
+            "SELECT (COUNT(*) AS ?triples) (COUNT(DISTINCT ?s) AS ?subjects) (COUNT(DISTINCT ?o) AS ?objects)
+            WHERE { ?s ?p ?o }"
+        )?;
+        let totals = totals.first();
+        let entities = self
+            .query("SELECT (COUNT(DISTINCT ?s) AS ?cnt) WHERE { ?s a ?t }")?
+            .first()
+            .map(|row| count(row, "cnt"))
+            .unwrap_or(0);
+
+        let classes = self
+            .class_counts()
+            .into_iter()
+            .map(|(class, entities)| ClassPartition { class, entities: entities as u64 })
+            .collect();
+
+        let properties = self
+            .query(
+                "SELECT ?p (COUNT(*) AS ?triples) (COUNT(DISTINCT ?s) AS ?subjects) (COUNT(DISTINCT ?o) AS ?objects)
+                WHERE { ?s ?p ?o }
+                GROUP BY ?p ORDER BY DESC(?triples)"
+            )?
+            .iter()
+            .filter_map(|row| {
+                Some(PropertyPartition {
+                    property: row.get("p")?.to_string(),
+                    triples: count(row, "triples"),
+                    distinct_subjects: count(row, "subjects"),
+                    distinct_objects: count(row, "objects"),
+                })
+            })
+            .collect();
+
+        let (_, relations) = self.class_relations();
+        let mut linksets = relations
+            .into_iter()
+            .filter(|(_, _, objects, _)| objects != "Literal")
+            .map(|(subjects, predicate, objects, triples)| Linkset {
+                subjects,
+                predicate,
+                objects,
+                triples: triples as u64,
+            })
+            .collect::<Vec<_>>();
+        linksets.sort_by(|a, b| b.triples.cmp(&a.triples));
+
+        let description = VoidDescription {
+            dataset: self.get_name(),
+            triples: totals.map(|row| count(row, "triples")).unwrap_or(0),
+            entities,
+            distinct_subjects: totals.map(|row| count(row, "subjects")).unwrap_or(0),
+            distinct_objects: totals.map(|row| count(row, "objects")).unwrap_or(0),
+            classes,
+            properties,
+            linksets,
+            source: self.wdc_source(),
+        };
+        self.save_cache(VOID_CACHE, version, &description);
+        Ok(description)
+    }
+
+    /// Writes the VoID description of the dataset to `path`, in Turtle.
+    ///
+    /// # Errors
+    /// Returns a message if the description cannot be computed or written.
+    pub fn export_void(&self, path: &str) -> Result<(), String> {
+        let description = self.void_description().map_err(|e| e.to_string())?;
+        let turtle = description.to_rdf(&format!("{EXPORT_BASE}{}", self.get_name()), RdfFormat::Turtle)?;
+        std::fs::write(path, turtle).map_err(|e| format!("Cannot write the description to {path}: {e}"))
+    }
+}
+
+impl VoidDescription {
+    /// Serializes the description, the dataset being `<base#dataset>`, its partitions and linksets
+    /// `<base#class-N>`, `<base#property-N>` and `<base#linkset-N>`.
+    ///
+    /// # Errors
+    /// Returns a message if an IRI is invalid or the format does not support triples.
+    pub fn to_rdf(&self, base: &str, format: RdfFormat) -> Result<String, String> {
+        let node = |name: &str| NamedNode::new(format!("{base}#{name}")).map_err(|e| e.to_string());
+        let void = |name: &str| NamedNode::new_unchecked(format!("{VOID}{name}"));
+        let dcat = |name: &str| NamedNode::new_unchecked(format!("{DCAT}{name}"));
+        let dcterms = |name: &str| NamedNode::new_unchecked(format!("{DCTERMS}{name}"));
+        let rdf_type = NamedNode::new_unchecked(RDF_TYPE);
+
+        let mut triples = vec![];
+        let dataset = node("dataset")?;
+        triples.push(Triple::new(dataset.clone(), rdf_type.clone(), void("Dataset")));
+        triples.push(Triple::new(dataset.clone(), rdf_type.clone(), dcat("Dataset")));
+        triples.push(Triple::new(dataset.clone(), dcterms("title"), Literal::new_simple_literal(&self.dataset)));
+        for (property, value) in [
+            ("triples", self.triples),
+            ("entities", self.entities),
+            ("distinctSubjects", self.distinct_subjects),
+            ("distinctObjects", self.distinct_objects),
+            ("classes", self.classes.len() as u64),
+            ("properties", self.properties.len() as u64),
+        ] {
+            triples.push(Triple::new(dataset.clone(), void(property), integer(value)));
+        }
+
+        let mut partitions = vec![];
+        for (i, partition) in self.classes.iter().enumerate() {
+            let class = iri(&partition.class)?;
+            let subject = node(&format!("class-{i}"))?;
+            triples.push(Triple::new(dataset.clone(), void("classPartition"), subject.clone()));
+            triples.push(Triple::new(subject.clone(), void("class"), class));
+            triples.push(Triple::new(subject.clone(), void("entities"), integer(partition.entities)));
+            partitions.push((partition.class.as_str(), subject));
+        }
+        for (i, partition) in self.properties.iter().enumerate() {
+            let subject = node(&format!("property-{i}"))?;
+            triples.push(Triple::new(dataset.clone(), void("propertyPartition"), subject.clone()));
+            triples.push(Triple::new(subject.clone(), void("property"), iri(&partition.property)?));
+            triples.push(Triple::new(subject.clone(), void("triples"), integer(partition.triples)));
+            triples.push(Triple::new(subject.clone(), void("distinctSubjects"), integer(partition.distinct_subjects)));
+            triples.push(Triple::new(subject.clone(), void("distinctObjects"), integer(partition.distinct_objects)));
+        }
+
+        let partition = |class: &str| partitions.iter().find(|(c, _)| *c == class).map(|(_, node)| node.clone());
+        for (i, linkset) in self.linksets.iter().enumerate() {
+            let (Some(subjects), Some(objects)) = (partition(&linkset.subjects), partition(&linkset.objects)) else {
+                continue;
+            };
+            let subject = node(&format!("linkset-{i}"))?;
+            triples.push(Triple::new(dataset.clone(), void("subset"), subject.clone()));
+            triples.push(Triple::new(subject.clone(), rdf_type.clone(), void("Linkset")));
+            triples.push(Triple::new(subject.clone(), void("subjectsTarget"), subjects));
+            triples.push(Triple::new(subject.clone(), void("objectsTarget"), objects));
+            triples.push(Triple::new(subject.clone(), void("linkPredicate"), iri(&linkset.predicate)?));
+            triples.push(Triple::new(subject.clone(), void("triples"), integer(linkset.triples)));
+        }
+
+        if let Some(source) = &self.source {
+            let subset = iri(&source.subset)?;
+            triples.push(Triple::new(dataset.clone(), dcterms("source"), subset.clone()));
+            triples.push(
+                Triple::new(
+                    subset.clone(),
+                    dcterms("title"),
+                    Literal::new_simple_literal(
+                        format!("Web Data Commons {} schema.org crawl, class-specific subset {}", source.crawl, self.dataset)
+                    )
+                )
+            );
+            triples.push(
+                Triple::new(subset, dcterms("publisher"), NamedNode::new_unchecked("http://webdatacommons.org/"))
+            );
+            for part in &source.parts {
+                triples.push(
+                    Triple::new(dataset.clone(), NamedNode::new_unchecked(format!("{PROV}wasDerivedFrom")), iri(part)?)
+                );
+            }
+        }
+
+        let serializer = RdfSerializer::from_format(format)
+            .with_prefix("void", VOID)
+            .and_then(|s| s.with_prefix("dcat", DCAT))
+            .and_then(|s| s.with_prefix("dcterms", DCTERMS))
+            .and_then(|s| s.with_prefix("prov", PROV))
+            .map_err(|e| e.to_string())?;
+        let mut writer = serializer.for_writer(Vec::new());
+        for triple in &triples {
+            writer.serialize_triple(triple).map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.finish().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,1262 @@
+//! # JSON API
+//!
+//! Versioned API (`/api/v1/...`) mirroring the pages of the web UI, for tools that need the data rather than HTML.
+//! Entities and classes are given in their SPARQL form (`<http://schema.org/Book>`); the brackets may be omitted.
+//!
+//! | Route                                   | Method | Content                                          |
+//! |-----------------------------------------|--------|--------------------------------------------------|
+//! | `/api/v1/stats`                         | GET    | Dataset name and entity count of every class     |
+//! | `/api/v1/void`                          | GET    | VoID description of the dataset (`format=json`, `turtle` or `ntriples`) |
+//! | `/api/v1/explore?id=&page=&sort=`       | GET    | A page of 50 filtered entities of a class        |
+//! | `/api/v1/explore/facets?id=`            | GET    | Facets of the filtered entities of a class       |
+//! | `/api/v1/profile?class=`                | GET    | Profile of every predicate of a class            |
+//! | `/api/v1/entity?iri=`                   | GET    | Details, outgoing and incoming triples of entity |
+//! | `/api/v1/entity/triples?iri=&direction=` | GET   | A page of triples of an entity, per predicate    |
+//! | `/api/v1/entity/neighbourhood?iri=`     | GET    | Nodes and links of the entity neighbourhood      |
+//! | `/api/v1/query?query=&inferred=`        | GET    | Results of a SPARQL `SELECT`                     |
+//! | `/api/v1/query`                         | POST   | SPARQL update (`mode=update` or `advanced`)      |
+//! | `/api/v1/inference`                     | GET    | State of the inferred graph                      |
+//! | `/api/v1/inference`                     | POST   | Enables, refreshes or disables inference (`action`) |
+//! | `/api/v1/history`                       | GET    | Entries of the history                           |
+//! | `/api/v1/routines`                      | GET    | Routine files and their procedures               |
+//! | `/api/v1/routines/run`                  | POST   | Runs the `procedures` (`file::procedure`) given  |
+//! | `/api/v1/routines/merge`                | POST   | Merges the `entity` instances sharing `mergeby`  |
+//! | `/api/v1/analysis/graph?start_with=`    | GET    | Class relations graph                            |
+//! | `/api/v1/analysis/graph/export?format=` | GET    | Whole class relations graph with probabilities and ranks (`graphml`, `dot` or `json`), PageRank options below |
+//! | `/api/v1/export/neo4j?file=`            | GET    | Property graph for Neo4j: `nodes` or `relationships` CSV (admin import), or `cypher` script; filters below |
+//! | `/api/v1/export/flatten?class=`         | GET    | Instances of a class as a table (`path`, repeatable, `multi_value`, `format=csv`, `jsonl` or `parquet`) |
+//! | `/api/v1/export/flatten/paths?class=`   | GET    | Predicate paths suggested for the table of a class |
+//! | `/api/v1/analysis/classes?start_with=`  | GET    | Class analysis and proposed plan, PageRank options below |
+//! | `/api/v1/analysis/classes/apply`        | POST   | Applies a reviewed class plan (`start_with`, `keep`, `drop`) |
+//! | `/api/v1/analysis/predicates?start_with=` | GET  | Predicate analysis                               |
+//! | `/api/v1/analysis/features?start_with=` | GET    | Training table of the keep model (`format=csv` or `columns`) |
+//! | `/api/v1/analysis/labels`               | GET    | Curator keep/drop labels of the predicates       |
+//! | `/api/v1/analysis/labels`               | POST   | Labels a predicate (`class`, `predicate`, `label=keep`, `drop` or `clear`) |
+//! | `/api/v1/analysis/labels/report?start_with=` | GET | Agreement of the decisions with the labels, predicates to label next |
+//! | `/api/v1/predicate_plans`               | GET    | Predicate deletion plans applied to the dataset  |
+//! | `/api/v1/predicate_plans/impact?pair=`  | GET    | Triples and entities a deletion plan would remove |
+//! | `/api/v1/predicate_plans/apply`         | POST   | Applies a deletion plan (`pair`, repeatable, and `archive`) |
+//! | `/api/v1/predicate_plans/undo`          | POST   | Undoes an applied deletion plan (`id`)           |
+//! | `/api/v1/analysis/conformance`          | GET    | Domain/range conformance of the predicates       |
+//! | `/api/v1/analysis/conformance/fix`      | POST   | Fixes non-conforming values (`class`, `predicate`, `range`, `fix`) |
+//! | `/api/v1/analysis/shapes?start_with=`   | GET    | SHACL shapes inferred from the predicate analysis (`format=json` or `turtle`) |
+//! | `/api/v1/shacl`                         | GET    | Shape files of the `shapes/` directory           |
+//! | `/api/v1/shacl/validate?file=&graph=`   | GET    | SHACL validation report (`format=json`, `turtle` or `ntriples`) |
+//! | `/api/v1/search?q=&class=&lang=`        | GET    | Full-text search, ranked, with snippets          |
+//! | `/api/v1/vocabulary`                    | GET    | Schema.org terms of the dataset, checked         |
+//! | `/api/v1/vocabulary/term?iri=`          | GET    | Label, comment and hierarchy of a schema.org term |
+//! | `/api/v1/jobs`                          | GET    | Running and finished background jobs             |
+//! | `/api/v1/jobs/{id}`                     | GET    | Status, progress, logs and result of a job       |
+//! | `/api/v1/session`                       | GET    | CSRF token and read-only flag of the server      |
+//!
+//! The neighbourhood is limited to `depth` hops (2 by default, at most 5) and `max_nodes` nodes (40 by default, at
+//! most 500); the nodes listed in `frontier` can be expanded by requesting their own neighbourhood.
+//!
+//! Both explore endpoints accept the filters of the explore page: `has` (predicate, repeatable), `value`
+//! (`<predicate> object`, repeatable), `datatype` and `lang`.
+//!
+//! The class analysis ranks the classes by power iteration by default (`damping`, 0.85 by default, and
+//! `tolerance`); `pagerank=sampling` estimates the ranks with `walks` random walks seeded with `seed` instead.
+//! It only proposes a plan: the classes are dropped once the plan, possibly amended, is sent back to
+//! `/api/v1/analysis/classes/apply`. Kept classes come best first, the first one winning when an entity has several.
+//! The graph export ranks its classes with the same options.
+//!
+//! The Neo4j export takes the `types` (with their subclasses), `predicates` and `namespaces` to export, comma
+//! separated (schema.org terms may be given by local name), and `multi_value` (`array`, `first` or `join`).
+//!
+//! The flattened table has a row per entity and a column per predicate path (`author/name` follows `author`, then
+//! `name`); cells holding several values keep the `first` one (default), `join` them, or `explode` the row. Without
+//! `path`, the suggested paths are used.
+//!
+//! A deletion plan is a list of `pair` parameters, `<class> <predicate>` each. It is applied in a single
+//! transaction, recorded as one history entry; with `archive=true`, the removed triples are moved to a named graph
+//! rather than deleted.
+//!
+//! Queries include the inferred triples when inference is enabled, unless they opt out with `inferred=false`.
+//!
+//! POST requests must carry the CSRF token given by `/api/v1/session` in the `X-CSRF-Token` header (unless they
+//! are authenticated with an API token), and are rejected when the server runs in read-only mode. Except for the
+//! labels, they run as background jobs, like in the web UI: the answer is `202 Accepted` with the job, followed at
+//! `/api/v1/jobs/{id}`, whose `result` is the JSON result once the job succeeded.
+//! Errors are returned as `{"error": "..."}` with the matching status code.
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use oxigraph::io::RdfFormat;
+use oxigraph::model::NamedNode;
+use serde::Serialize;
+use tiny_http::Method;
+
+use crate::analysis::ClassPlan;
+use crate::explore::{ ExploreFilters, SortOrder };
+use crate::feature_export::{ self, ExportFormat };
+use crate::flatten::{ FlattenFormat, FlattenOptions };
+use crate::graph_export::{ self, GraphFormat };
+use crate::conformance::ConformanceFix;
+use crate::item::Item;
+use crate::neo4j_export::Neo4jOptions;
+use crate::jobs::{ JobInfo, JobManager };
+use crate::page_rank::{ self, PageRankMethod, PageRankOptions };
+use crate::predicate_labels::{ self, PredicateLabel };
+use crate::predicate_plan::PlanPair;
+use crate::search::{ self, SearchEngine, SearchQuery };
+use crate::shacl::{ self, Shapes };
+use crate::shape_inference::{ self, ShapeInferenceOptions };
+use crate::routine::{ list_routines, Routine };
+use crate::store::{ self, StoreError, TripleDirection, KG };
+use crate::utils::extract_literal;
+use crate::vocabulary::{ self, VocabularyTerm };
+use crate::web_ui::auth::Auth;
+use crate::web_ui::http::{ HttpRequest, HttpResponse, Params };
+use crate::web_ui::security;
+
+/// Prefix of every API route.
+pub(crate) const API_PREFIX: &str = "/api/v1/";
+
+/// Size of the pages returned by `/api/v1/explore`, same as the explore page.
+const PAGE_SIZE: u32 = 50;
+
+/// Hops of the neighbourhood graph when no `depth` is given.
+pub(crate) const DEFAULT_GRAPH_DEPTH: u32 = 2;
+
+/// Largest `depth` accepted for the neighbourhood graph.
+pub(crate) const MAX_GRAPH_DEPTH: u32 = 5;
+
+/// Node budget of the neighbourhood graph when no `max_nodes` is given.
+pub(crate) const DEFAULT_GRAPH_NODES: usize = 40;
+
+/// Largest `max_nodes` accepted for the neighbourhood graph.
+pub(crate) const MAX_GRAPH_NODES: usize = 500;
+
+/// Largest page of `/api/v1/entity/triples`.
+const MAX_TRIPLES_PAGE: u64 = 500;
+
+const ENDPOINTS: &[&str] = &[
+    "session",
+    "stats",
+    "void",
+    "explore",
+    "explore/facets",
+    "profile",
+    "entity",
+    "entity/triples",
+    "entity/neighbourhood",
+    "query",
+    "inference",
+    "history",
+    "routines",
+    "routines/run",
+    "routines/merge",
+    "analysis/graph",
+    "analysis/graph/export",
+    "export/neo4j",
+    "export/flatten",
+    "export/flatten/paths",
+    "analysis/classes",
+    "analysis/classes/apply",
+    "analysis/predicates",
+    "analysis/features",
+    "analysis/labels",
+    "analysis/labels/report",
+    "predicate_plans",
+    "predicate_plans/impact",
+    "predicate_plans/apply",
+    "predicate_plans/undo",
+    "analysis/conformance",
+    "analysis/conformance/fix",
+    "analysis/shapes",
+    "shacl",
+    "shacl/validate",
+    "search",
+    "vocabulary",
+    "vocabulary/term",
+    "jobs",
+];
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+#[derive(Serialize)]
+struct Session {
+    csrf_token: String,
+    read_only: bool,
+}
+
+#[derive(Serialize)]
+struct ClassCount {
+    class: String,
+    count: u32,
+}
+
+#[derive(Serialize)]
+struct Stats {
+    dataset: String,
+    classes: Vec<ClassCount>,
+}
+
+#[derive(Serialize)]
+struct EntitySummary {
+    id: String,
+    types: Vec<String>,
+    name: Option<String>,
+    description: Option<String>,
+    images: Vec<String>,
+}
+
+impl From<Item> for EntitySummary {
+    fn from(item: Item) -> Self {
+        EntitySummary {
+            id: item.id(),
+            types: item.entity_types
+                .iter()
+                .map(|t| t.to_string())
+                .collect(),
+            name: item.name,
+            description: item.description,
+            images: item.images,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ExplorePage {
+    class: String,
+    total: u64,
+    page: u32,
+    page_size: u32,
+    entities: Vec<EntitySummary>,
+}
+
+#[derive(Serialize)]
+struct Outgoing {
+    predicate: String,
+    object: String,
+}
+
+#[derive(Serialize)]
+struct Incoming {
+    subject: String,
+    predicate: String,
+}
+
+#[derive(Serialize)]
+struct EntityDetails {
+    #[serde(flatten)]
+    summary: EntitySummary,
+    outgoing: Vec<Outgoing>,
+    incoming: Vec<Incoming>,
+}
+
+#[derive(Serialize)]
+struct Property {
+    predicate: String,
+    value: String,
+}
+
+#[derive(Serialize)]
+struct NeighbourhoodNode {
+    id: String,
+    properties: Vec<Property>,
+}
+
+#[derive(Serialize)]
+struct NeighbourhoodLink {
+    source: String,
+    target: String,
+    predicate: String,
+}
+
+#[derive(Serialize)]
+struct Neighbourhood {
+    nodes: Vec<NeighbourhoodNode>,
+    links: Vec<NeighbourhoodLink>,
+    frontier: Vec<String>,
+    truncated: bool,
+}
+
+#[derive(Serialize)]
+struct TermDetails<'a> {
+    #[serde(flatten)]
+    term: &'a VocabularyTerm,
+    ancestors: Vec<String>,
+    children: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PredicateGroup {
+    predicate: String,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct TriplesPage {
+    direction: &'static str,
+    total: u64,
+    offset: u64,
+    limit: u64,
+    groups: Vec<PredicateGroup>,
+    /// Objects of outgoing triples, subjects of incoming ones
+    triples: Vec<Property>,
+}
+
+#[derive(Serialize)]
+struct QueryResults {
+    variables: Vec<String>,
+    rows: Vec<Vec<Option<String>>>,
+}
+
+#[derive(Serialize)]
+struct UpdateResult {
+    triple_diff: i64,
+}
+
+#[derive(Serialize)]
+struct JobDetails {
+    #[serde(flatten)]
+    job: JobInfo,
+    result: Option<serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct SearchResult {
+    id: String,
+    name: Option<String>,
+    score: f32,
+    snippet: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SearchPage {
+    total: usize,
+    hits: Vec<SearchResult>,
+}
+
+fn ok<T: Serialize>(body: &T) -> HttpResponse {
+    HttpResponse::json(200, body)
+}
+
+pub(crate) fn error(status: u16, message: &str) -> HttpResponse {
+    HttpResponse::json(status, &(ErrorBody { error: message.to_string() }))
+}
+
+fn missing(param: &str) -> HttpResponse {
+    error(400, &format!("Missing parameter: {param}"))
+}
+
+/// VoID description of the dataset, in the `format` asked for or else the one of the `Accept` header (Turtle by
+/// default). Also serves `/.well-known/void`.
+///
+/// The IRIs of the description are relative to the `/.well-known/void` URL of the server.
+pub(crate) fn void_response(kg: &KG, req: &HttpRequest) -> HttpResponse {
+    let format = req.params.get("format").unwrap_or_else(|| {
+        match req.header("Accept") {
+            Some(accept) if accept.contains("application/n-triples") => "ntriples".to_string(),
+            Some(accept) if accept.contains("application/json") => "json".to_string(),
+            _ => "turtle".to_string(),
+        }
+    });
+    let description = match kg.void_description() {
+        Ok(description) => description,
+        Err(e) => {
+            return error(500, &e.to_string());
+        }
+    };
+    let (format, content_type) = match format.as_str() {
+        "json" => {
+            return ok(&description);
+        }
+        "turtle" => (RdfFormat::Turtle, "text/turtle"),
+        "ntriples" => (RdfFormat::NTriples, "application/n-triples"),
+        other => {
+            return error(400, &format!("Unknown format: {other}"));
+        }
+    };
+    let base = format!("http://{}/.well-known/void", req.header("Host").unwrap_or("localhost"));
+    match description.to_rdf(&base, format) {
+        Ok(rdf) =>
+            HttpResponse {
+                status: 200,
+                content_type: content_type.to_string(),
+                headers: vec![],
+                body: rdf.into_bytes(),
+            },
+        Err(e) => error(500, &e),
+    }
+}
+
+/// Reads an IRI parameter and returns it in its SPARQL form (`<iri>`).
+///
+/// # Errors
+/// Returns a `400` response if the parameter is missing or is not a valid IRI.
+fn iri_param(req: &HttpRequest, name: &str) -> Result<String, HttpResponse> {
+    let raw = req.params.get(name).ok_or_else(|| missing(name))?;
+    let raw = raw.trim();
+    let iri = if raw.starts_with('<') { raw.to_string() } else { format!("<{raw}>") };
+    match NamedNode::from_str(&iri) {
+        Ok(node) => Ok(node.to_string()),
+        Err(_) => Err(error(400, &format!("Invalid IRI: {raw}"))),
+    }
+}
+
+/// Reads the filters of the explore endpoints.
+///
+/// # Errors
+/// Returns a `400` response if the class is missing or a filter is invalid.
+fn explore_filters(req: &HttpRequest) -> Result<ExploreFilters, HttpResponse> {
+    let params = &req.params;
+    let class = params.get("id").ok_or_else(|| missing("id"))?;
+    ExploreFilters::parse(
+        &class,
+        params.get_all("has"),
+        params.get_all("value"),
+        params.get("datatype"),
+        params.get("lang")
+    ).map_err(|e| error(400, &e))
+}
+
+/// Reads the `depth` and `max_nodes` of a neighbourhood graph, clamped to their maximum.
+pub(crate) fn graph_limits(params: &Params) -> (u32, usize) {
+    let depth = params
+        .get("depth")
+        .and_then(|d| d.parse::<u32>().ok())
+        .unwrap_or(DEFAULT_GRAPH_DEPTH)
+        .min(MAX_GRAPH_DEPTH);
+    let max_nodes = params
+        .get("max_nodes")
+        .and_then(|n| n.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_GRAPH_NODES)
+        .clamp(1, MAX_GRAPH_NODES);
+    (depth, max_nodes)
+}
+
+/// Whether a query includes the inferred triples (`inferred`); by default when inference is enabled.
+pub(crate) fn include_inferred(kg: &KG, params: &Params) -> bool {
+    match params.get("inferred") {
+        Some(inferred) => inferred == "1" || inferred == "true",
+        None => kg.inference_enabled(),
+    }
+}
+
+/// Reads the PageRank options of the class analysis (`pagerank`, `damping`, `tolerance`, `walks`, `seed`).
+///
+/// # Errors
+/// Returns a message for unknown methods and out of range values.
+pub(crate) fn page_rank_options(params: &Params) -> Result<PageRankOptions, String> {
+    fn parse<T: FromStr>(params: &Params, name: &str, default: T) -> Result<T, String> {
+        match params.get(name).filter(|v| !v.trim().is_empty()) {
+            Some(value) => value.trim().parse().map_err(|_| format!("Invalid {name}: {value}")),
+            None => Ok(default),
+        }
+    }
+    let method = match params.get("pagerank").as_deref() {
+        None | Some("") | Some("power") => PageRankMethod::PowerIteration,
+        Some("sampling") =>
+            PageRankMethod::Sampling {
+                walks: parse(params, "walks", page_rank::DEFAULT_WALKS)?,
+                seed: parse(params, "seed", page_rank::DEFAULT_SEED)?,
+            },
+        Some(other) => {
+            return Err(format!("Unknown PageRank method: {other}"));
+        }
+    };
+    let damping = parse(params, "damping", page_rank::DEFAULT_DAMPING)?;
+    if !(0.0..1.0).contains(&damping) {
+        return Err(format!("The damping must be in [0, 1): {damping}"));
+    }
+    let tolerance = parse(params, "tolerance", page_rank::DEFAULT_TOLERANCE)?;
+    if tolerance.is_nan() || tolerance <= 0.0 {
+        return Err(format!("The tolerance must be positive: {tolerance}"));
+    }
+    Ok(PageRankOptions { method, damping, tolerance })
+}
+
+/// Reads a reviewed class plan: `start_with`, the classes to `keep` (repeatable, best first) and to `drop`
+/// (repeatable). Classes listed in `class` (repeatable) and not kept are dropped too, as sent by the review form.
+///
+/// # Errors
+/// Returns a message if `start_with` is missing or no class is kept.
+pub(crate) fn class_plan(params: &Params) -> Result<ClassPlan, String> {
+    let start_with = params.get("start_with").ok_or("Missing parameter: start_with")?;
+    let clean = |values: Vec<String>| {
+        values
+            .into_iter()
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+    };
+    let keep = clean(params.get_all("keep"));
+    if keep.is_empty() {
+        return Err("The plan keeps no class".to_string());
+    }
+    let mut drop = clean(params.get_all("drop"));
+    for class in clean(params.get_all("class")) {
+        if !keep.contains(&class) && !drop.contains(&class) {
+            drop.push(class);
+        }
+    }
+    Ok(ClassPlan { start_with, keep, drop })
+}
+
+/// Records or removes the label of a class-predicate pair (`class`, `predicate`, `label`: `keep`, `drop` or `clear`).
+///
+/// Returns the label of the pair afterwards.
+///
+/// # Errors
+/// Returns a message if a parameter is missing or invalid, or if the labels cannot be saved.
+pub(crate) fn label_predicate(kg: &KG, params: &Params) -> Result<Option<bool>, String> {
+    let (Some(class), Some(predicate), Some(label)) = (params.get("class"), params.get("predicate"), params.get("label")) else {
+        return Err("Missing parameters: class, predicate and label".to_string());
+    };
+    let sparql = |iri: String| {
+        let iri = iri.trim();
+        let iri = if iri.starts_with('<') { iri.to_string() } else { format!("<{iri}>") };
+        NamedNode::from_str(&iri)
+            .map(|node| node.to_string())
+            .map_err(|_| format!("Invalid IRI: {iri}"))
+    };
+    let (class, predicate) = (sparql(class)?, sparql(predicate)?);
+    let keep = match label.as_str() {
+        "keep" => true,
+        "drop" => false,
+        "clear" => {
+            kg.remove_predicate_label(&class, &predicate).map_err(|e| format!("Cannot save the labels: {e}"))?;
+            return Ok(None);
+        }
+        other => {
+            return Err(format!("Unknown label: {other}"));
+        }
+    };
+    let label = PredicateLabel { class, predicate, keep, stats: HashMap::new() };
+    kg.record_predicate_label(label).map_err(|e| format!("Cannot save the labels: {e}"))?;
+    Ok(Some(keep))
+}
+
+/// Reads the class-predicate pairs of a deletion plan (`pair`, repeatable, `<class> <predicate>`).
+///
+/// # Errors
+/// Returns a message if a pair is invalid or if there is none.
+pub(crate) fn plan_pairs(params: &Params) -> Result<Vec<PlanPair>, String> {
+    let mut pairs: Vec<PlanPair> = vec![];
+    for value in params.get_all("pair") {
+        let pair = PlanPair::parse(&value).ok_or_else(|| format!("Invalid class-predicate pair: {value}"))?;
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+    if pairs.is_empty() {
+        return Err("The plan has no class-predicate pair".to_string());
+    }
+    Ok(pairs)
+}
+
+/// Reads the options of the shape inference (`required`, `dominant`, `dropped`, `closed`, `namespace`).
+pub(crate) fn shape_inference_options(params: &Params) -> ShapeInferenceOptions {
+    let defaults = ShapeInferenceOptions::default();
+    let share = |name: &str, default: f64| {
+        params
+            .get(name)
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| (0.0..=1.0).contains(v))
+            .unwrap_or(default)
+    };
+    ShapeInferenceOptions {
+        required_frequency: share("required", defaults.required_frequency),
+        dominant_share: share("dominant", defaults.dominant_share),
+        include_dropped: params.get("dropped").is_some_and(|v| v == "1" || v == "true"),
+        closed: params.get("closed").is_some_and(|v| v == "1" || v == "true"),
+        namespace: params
+            .get("namespace")
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty())
+            .unwrap_or(defaults.namespace),
+    }
+}
+
+/// Runs `work` on a background job attributed to the acting user, and answers `202 Accepted` with the job.
+///
+/// The job is followed at `/api/v1/jobs/{id}` (the `Location` header), whose `result` holds the JSON result of
+/// `work` once it succeeded.
+fn spawn_job<T, F>(kg: &Arc<KG>, jobs: &Arc<JobManager>, title: &str, work: F) -> HttpResponse
+    where T: Serialize, F: FnOnce(&KG) -> Result<T, String> + Send + 'static
+{
+    let kg = kg.clone();
+    // Without a key: the job modifies the store, see `JobManager::shutdown`
+    let id = jobs.spawn(title.to_string(), None, store::acting_user(), move || {
+        work(&kg).and_then(|result| serde_json::to_string(&result).map_err(|e| e.to_string()))
+    });
+    HttpResponse::json(202, &jobs.get(id)).with_header("Location", &format!("{API_PREFIX}jobs/{id}"))
+}
+
+/// Runs an update on a background job (see `spawn_job`), whose result is the number of triples it inserted (or
+/// deleted, if negative).
+fn run_update<F>(kg: &Arc<KG>, jobs: &Arc<JobManager>, title: &str, update: F) -> HttpResponse
+    where F: FnOnce(&KG) -> Result<(), StoreError> + Send + 'static
+{
+    spawn_job(kg, jobs, title, move |kg| {
+        let before = kg.count_lines() as i64;
+        update(kg).map_err(|e| e.to_string())?;
+        Ok(UpdateResult { triple_diff: (kg.count_lines() as i64) - before })
+    })
+}
+
+/// Dispatches a request whose path starts with `API_PREFIX`.
+pub(crate) fn route(
+    kg: &Arc<KG>,
+    jobs: &Arc<JobManager>,
+    search: &Arc<SearchEngine>,
+    auth: Option<&Auth>,
+    req: &HttpRequest
+) -> HttpResponse {
+    let params = &req.params;
+    let endpoint = &req.path[API_PREFIX.len()..];
+    let is_post = req.method == Method::Post;
+
+    // Every POST endpoint modifies the store
+    if is_post && ENDPOINTS.contains(&endpoint) {
+        if security::is_read_only() {
+            return error(403, "The explorer is served in read-only mode");
+        }
+        if !security::check_csrf(req, auth) {
+            return error(403, "Invalid or missing CSRF token");
+        }
+    }
+
+    match (endpoint, is_post) {
+        ("session", false) =>
+            ok(
+                &(Session {
+                    csrf_token: security::csrf_token().to_string(),
+                    read_only: security::is_read_only(),
+                })
+            ),
+        ("stats", false) => {
+            let classes = kg
+                .class_counts()
+                .into_iter()
+                .map(|(class, count)| ClassCount { class, count })
+                .collect();
+            ok(&(Stats { dataset: kg.get_name(), classes }))
+        }
+        ("void", false) => void_response(kg, req),
+        ("explore", false) => {
+            let filters = match explore_filters(req) {
+                Ok(filters) => filters,
+                Err(response) => {
+                    return response;
+                }
+            };
+            let page = params
+                .get("page")
+                .and_then(|p| p.parse::<u32>().ok())
+                .filter(|p| *p > 0)
+                .unwrap_or(1);
+            let sort = SortOrder::parse(&params.get("sort").unwrap_or_default());
+            let total = kg.explore_facets(&filters).total;
+            let entities = kg
+                .explore_entities(&filters, sort, PAGE_SIZE, (page - 1) * PAGE_SIZE)
+                .iter()
+                .map(|o| kg.get_details(o).into())
+                .collect();
+            ok(&(ExplorePage { class: filters.class, total, page, page_size: PAGE_SIZE, entities }))
+        }
+        ("explore/facets", false) =>
+            match explore_filters(req) {
+                Ok(filters) => ok(&*kg.explore_facets(&filters)),
+                Err(response) => response,
+            }
+        ("profile", false) =>
+            match iri_param(req, "class") {
+                Ok(class) =>
+                    match kg.class_profile(&class) {
+                        Ok(profile) => ok(&profile),
+                        Err(e) => error(400, &e.to_string()),
+                    }
+                Err(response) => response,
+            }
+        ("entity", false) => {
+            let iri = match iri_param(req, "iri") {
+                Ok(iri) => iri,
+                Err(response) => {
+                    return response;
+                }
+            };
+            let (outgoing, incoming) = kg.get_triples(&iri);
+            ok(
+                &(EntityDetails {
+                    summary: kg.get_details(&iri).into(),
+                    outgoing: outgoing
+                        .into_iter()
+                        .map(|(predicate, object)| Outgoing { predicate, object })
+                        .collect(),
+                    incoming: incoming
+                        .into_iter()
+                        .map(|(subject, predicate)| Incoming { subject, predicate })
+                        .collect(),
+                })
+            )
+        }
+        ("entity/neighbourhood", false) => {
+            let iri = match iri_param(req, "iri") {
+                Ok(iri) => iri,
+                Err(response) => {
+                    return response;
+                }
+            };
+            let (depth, max_nodes) = graph_limits(params);
+            let neighbourhood = kg.neighbourhood(&iri, depth, max_nodes);
+            let nodes = neighbourhood.nodes
+                .into_iter()
+                .map(|(id, props)| NeighbourhoodNode {
+                    id,
+                    properties: props
+                        .iter()
+                        .map(|sol| Property {
+                            predicate: sol.get("predicate").unwrap().to_string(),
+                            value: extract_literal(sol.get("object")).unwrap_or_else(||
+                                sol.get("object").unwrap().to_string()
+                            ),
+                        })
+                        .collect(),
+                })
+                .collect();
+            let links = neighbourhood.links
+                .into_iter()
+                .map(|(source, target, predicate)| NeighbourhoodLink { source, target, predicate })
+                .collect();
+            let mut frontier = neighbourhood.frontier.into_iter().collect::<Vec<_>>();
+            frontier.sort();
+            ok(&(Neighbourhood { nodes, links, frontier, truncated: neighbourhood.truncated }))
+        }
+        ("entity/triples", false) => {
+            let iri = match iri_param(req, "iri") {
+                Ok(iri) => iri,
+                Err(response) => {
+                    return response;
+                }
+            };
+            let (direction, name) = match params.get("direction").as_deref() {
+                None | Some("outgoing") => (TripleDirection::Outgoing, "outgoing"),
+                Some("incoming") => (TripleDirection::Incoming, "incoming"),
+                Some(other) => {
+                    return error(400, &format!("Invalid direction: {other}"));
+                }
+            };
+            let limit = params
+                .get("limit")
+                .and_then(|l| l.parse::<u64>().ok())
+                .unwrap_or(PAGE_SIZE as u64)
+                .min(MAX_TRIPLES_PAGE);
+            let offset = params
+                .get("offset")
+                .and_then(|o| o.parse::<u64>().ok())
+                .unwrap_or(0);
+            let groups = kg
+                .triple_groups(&iri, direction)
+                .into_iter()
+                .map(|(predicate, count)| PredicateGroup { predicate, count })
+                .collect::<Vec<_>>();
+            let triples = kg
+                .triples_page(&iri, direction, limit, offset)
+                .into_iter()
+                .map(|(predicate, value)| Property { predicate, value })
+                .collect();
+            ok(
+                &(TriplesPage {
+                    direction: name,
+                    total: groups.iter().map(|g| g.count).sum(),
+                    offset,
+                    limit,
+                    groups,
+                    triples,
+                })
+            )
+        }
+        ("query", false) => {
+            let Some(query) = params.get("query") else {
+                return missing("query");
+            };
+            match kg.query_with_inferred(&query, include_inferred(kg, params)) {
+                Ok(solutions) => {
+                    let variables = solutions
+                        .first()
+                        .map(|s|
+                            s
+                                .variables()
+                                .iter()
+                                .map(|v| v.as_str().to_string())
+                                .collect()
+                        )
+                        .unwrap_or_default();
+                    let rows = solutions
+                        .iter()
+                        .map(|s|
+                            s
+                                .values()
+                                .iter()
+                                .map(|v| v.as_ref().map(|t| t.to_string()))
+                                .collect()
+                        )
+                        .collect();
+                    ok(&(QueryResults { variables, rows }))
+                }
+                Err(e) => error(400, &e.to_string()),
+            }
+        }
+        ("query", true) => {
+            let Some(query) = params.get("query") else {
+                return missing("query");
+            };
+            match params.get("mode").unwrap_or("update".to_string()).as_str() {
+                "update" =>
+                    run_update(kg, jobs, "SPARQL update", move |kg| {
+                        kg.update(&query)?;
+                        kg.write_to_history(format!("```sparql\n{}\n```", query));
+                        Ok(())
+                    }),
+                "advanced" => {
+                    let Some(secondary) = params.get("secondary") else {
+                        return missing("secondary");
+                    };
+                    run_update(kg, jobs, "Iterative SPARQL update", move |kg| {
+                        kg.iterative_update(&secondary, &query)?;
+                        kg.write_to_history(format!("```sparql\n{}\n#\n{}\n```", secondary, query));
+                        Ok(())
+                    })
+                }
+                mode => error(400, &format!("Unknown mode: {mode}")),
+            }
+        }
+        ("inference", false) => ok(&kg.inference_status()),
+        ("inference", true) =>
+            match params.get("action").as_deref() {
+                Some("enable") =>
+                    spawn_job(kg, jobs, "Enable inference", |kg| kg.enable_inference().map_err(|e| e.to_string())),
+                Some("refresh") =>
+                    spawn_job(kg, jobs, "Refresh the inferred triples", |kg| {
+                        kg.refresh_inferences().map_err(|e| e.to_string())
+                    }),
+                Some("disable") =>
+                    spawn_job(kg, jobs, "Disable inference", |kg| {
+                        kg.disable_inference()
+                            .map(|()| kg.inference_status())
+                            .map_err(|e| e.to_string())
+                    }),
+                Some(action) => error(400, &format!("Unknown action: {action}")),
+                None => missing("action"),
+            }
+        ("history", false) => ok(&kg.history_entries()),
+        ("routines", false) => {
+            let routines: Vec<Routine> = list_routines()
+                .into_iter()
+                .filter(|r| !r.hidden)
+                .collect();
+            ok(&routines)
+        }
+        ("routines/run", true) => {
+            let procedures = params
+                .iter()
+                .filter(|(k, _)| k == "procedures")
+                .map(|(_, v)| v.clone())
+                .collect::<Vec<_>>();
+            if procedures.is_empty() {
+                return missing("procedures");
+            }
+            spawn_job(kg, jobs, "Run routines", move |kg| {
+                let report = kg.run_routines(procedures);
+                match &report.error {
+                    Some(e) => Err(format!("{} procedures run, then: {e}", report.executed.len())),
+                    None => Ok(report),
+                }
+            })
+        }
+        ("routines/merge", true) => {
+            let entity = match iri_param(req, "entity") {
+                Ok(entity) => entity,
+                Err(response) => {
+                    return response;
+                }
+            };
+            let Some(mergeby) = params.get("mergeby") else {
+                return missing("mergeby");
+            };
+            let mergeby = mergeby
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .collect();
+            run_update(kg, jobs, "Merge entities", move |kg| kg.merge_entities(entity, mergeby))
+        }
+        ("analysis/graph", false) | ("analysis/classes", false) | ("analysis/predicates", false) => {
+            let Some(start_with) = params.get("start_with") else {
+                return missing("start_with");
+            };
+            match endpoint {
+                "analysis/graph" => ok(&kg.class_graph(&start_with)),
+                "analysis/classes" =>
+                    match page_rank_options(params) {
+                        Ok(options) => ok(&kg.class_analysis(&start_with, &options)),
+                        Err(e) => error(400, &e),
+                    }
+                _ => ok(&kg.predicate_scores(&start_with)),
+            }
+        }
+        ("analysis/graph/export", false) => {
+            let format = match params.get("format").as_deref() {
+                None | Some("") => GraphFormat::NodeLink,
+                Some(name) =>
+                    match GraphFormat::parse(name) {
+                        Some(format) => format,
+                        None => {
+                            return error(400, &format!("Unknown format: {name}"));
+                        }
+                    }
+            };
+            let options = match page_rank_options(params) {
+                Ok(options) => options,
+                Err(e) => {
+                    return error(400, &e);
+                }
+            };
+            match graph_export::export_class_graph(&kg.class_relations_export(&options), format, &options) {
+                Ok(graph) =>
+                    HttpResponse {
+                        status: 200,
+                        content_type: format.content_type().to_string(),
+                        headers: vec![],
+                        body: graph.into_bytes(),
+                    },
+                Err(e) => error(500, &e),
+            }
+        }
+        ("export/neo4j", false) => {
+            let options = match
+                Neo4jOptions::parse(
+                    params.get("types").as_deref(),
+                    params.get("predicates").as_deref(),
+                    params.get("namespaces").as_deref(),
+                    params.get("multi_value").as_deref()
+                )
+            {
+                Ok(options) => options,
+                Err(e) => {
+                    return error(400, &e);
+                }
+            };
+            let file = params.get("file").unwrap_or_else(|| "nodes".to_string());
+            if !["nodes", "relationships", "cypher"].contains(&file.as_str()) {
+                return error(400, &format!("Unknown file: {file}"));
+            }
+            let graph = match kg.property_graph(&options) {
+                Ok(graph) => graph,
+                Err(e) => {
+                    return error(500, &e.to_string());
+                }
+            };
+            let (body, content_type) = match file.as_str() {
+                "cypher" => (graph.cypher(options.multi_value), "text/plain; charset=utf-8"),
+                "relationships" => (graph.relationships_csv(), "text/csv; charset=utf-8"),
+                _ => (graph.nodes_csv(options.multi_value), "text/csv; charset=utf-8"),
+            };
+            HttpResponse { status: 200, content_type: content_type.to_string(), headers: vec![], body: body.into_bytes() }
+        }
+        ("export/flatten", false) => {
+            let Some(class) = params.get("class") else {
+                return missing("class");
+            };
+            let format = match params.get("format").as_deref() {
+                None | Some("") => FlattenFormat::Csv,
+                Some(name) =>
+                    match FlattenFormat::parse(name) {
+                        Some(format) => format,
+                        None => {
+                            return error(400, &format!("Unknown format: {name}"));
+                        }
+                    }
+            };
+            let mut paths = params.get_all("path");
+            if paths.iter().all(|p| p.trim().is_empty()) {
+                paths = match
+                    vocabulary::resolve_term(&class).map(|class| kg.suggested_flatten_paths(&class))
+                {
+                    Ok(Ok(paths)) => paths,
+                    Ok(Err(e)) => {
+                        return error(500, &e.to_string());
+                    }
+                    Err(e) => {
+                        return error(400, &e);
+                    }
+                };
+            }
+            let options = match FlattenOptions::parse(&class, &paths, params.get("multi_value").as_deref()) {
+                Ok(options) => options,
+                Err(e) => {
+                    return error(400, &e);
+                }
+            };
+            let body = match kg.flatten_class(&options).map_err(|e| e.to_string()).and_then(|t| t.export(format)) {
+                Ok(body) => body,
+                Err(e) => {
+                    return error(500, &e);
+                }
+            };
+            let file_name = format!("{}.{}", vocabulary::short_name(&options.class).replace('"', ""), format.extension());
+            HttpResponse {
+                status: 200,
+                content_type: format.content_type().to_string(),
+                headers: vec![],
+                body,
+            }.with_header("Content-Disposition", &format!("attachment; filename=\"{file_name}\""))
+        }
+        ("export/flatten/paths", false) =>
+            match params.get("class").map(|class| vocabulary::resolve_term(&class)) {
+                Some(Ok(class)) =>
+                    match kg.suggested_flatten_paths(&class) {
+                        Ok(paths) => ok(&paths),
+                        Err(e) => error(500, &e.to_string()),
+                    }
+                Some(Err(e)) => error(400, &e),
+                None => missing("class"),
+            }
+        ("analysis/classes/apply", true) =>
+            match class_plan(params) {
+                Ok(plan) => run_update(kg, jobs, "Apply the class plan", move |kg| kg.apply_class_plan(&plan)),
+                Err(e) => error(400, &e),
+            }
+        ("analysis/labels", false) => ok(&kg.predicate_labels()),
+        ("analysis/labels", true) =>
+            match label_predicate(kg, params) {
+                Ok(label) => ok(&label),
+                Err(e) => error(400, &e),
+            }
+        ("analysis/labels/report", false) => {
+            let Some(start_with) = params.get("start_with") else {
+                return missing("start_with");
+            };
+            ok(&predicate_labels::label_report(&kg.predicate_scores(&start_with), &kg.predicate_labels()))
+        }
+        ("predicate_plans", false) => ok(&kg.predicate_plans()),
+        ("predicate_plans/impact", false) =>
+            match plan_pairs(params) {
+                Ok(pairs) =>
+                    match kg.predicate_plan_impact(&pairs) {
+                        Ok(impact) => ok(&impact),
+                        Err(e) => error(400, &e.to_string()),
+                    }
+                Err(e) => error(400, &e),
+            }
+        ("predicate_plans/apply", true) =>
+            match plan_pairs(params) {
+                Ok(pairs) => {
+                    let archive = params.get("archive").is_some_and(|v| v == "1" || v == "true");
+                    spawn_job(kg, jobs, "Apply the predicate plan", move |kg| {
+                        kg.apply_predicate_plan(&pairs, archive).map_err(|e| e.to_string())
+                    })
+                }
+                Err(e) => error(400, &e),
+            }
+        ("predicate_plans/undo", true) =>
+            match params.get("id").and_then(|id| id.parse::<u32>().ok()) {
+                Some(id) =>
+                    spawn_job(kg, jobs, &format!("Undo the predicate plan {id}"), move |kg| {
+                        kg.undo_predicate_plan(id).map_err(|e| e.to_string())
+                    }),
+                None => missing("id"),
+            }
+        ("analysis/features", false) => {
+            let Some(start_with) = params.get("start_with") else {
+                return missing("start_with");
+            };
+            let format = match params.get("format").as_deref() {
+                None | Some("") => ExportFormat::Csv,
+                Some(name) =>
+                    match ExportFormat::parse(name) {
+                        Some(format) => format,
+                        None => {
+                            return error(400, &format!("Unknown format: {name}"));
+                        }
+                    }
+            };
+            match feature_export::export_training_table(&kg.training_table(&start_with), format) {
+                Ok(table) =>
+                    HttpResponse {
+                        status: 200,
+                        content_type: format.content_type().to_string(),
+                        headers: vec![],
+                        body: table.into_bytes(),
+                    },
+                Err(e) => error(500, &e),
+            }
+        }
+        ("analysis/conformance", false) =>
+            match vocabulary::get() {
+                Some(vocabulary) => ok(&kg.conformance_report(vocabulary)),
+                None => error(404, "No schema.org vocabulary is loaded"),
+            }
+        ("analysis/conformance/fix", true) => {
+            let Some(vocabulary) = vocabulary::get() else {
+                return error(404, "No schema.org vocabulary is loaded");
+            };
+            let (Some(class), Some(predicate), Some(range), Some(fix)) = (
+                params.get("class"),
+                params.get("predicate"),
+                params.get("range"),
+                params.get("fix"),
+            ) else {
+                return missing("class, predicate, range and fix");
+            };
+            let Some(fix) = ConformanceFix::parse(&fix) else {
+                return error(400, &format!("Invalid fix: {fix}"));
+            };
+            run_update(kg, jobs, "Fix non-conforming values", move |kg| {
+                kg.apply_conformance_fix(vocabulary, &class, &predicate, &range, &fix)
+            })
+        }
+        ("analysis/shapes", false) => {
+            let Some(start_with) = params.get("start_with") else {
+                return missing("start_with");
+            };
+            let options = shape_inference_options(params);
+            let shapes = kg.infer_shapes(&start_with, &options);
+            match params.get("format").as_deref() {
+                None | Some("json") => ok(&shapes),
+                Some("turtle") =>
+                    match shape_inference::shapes_to_turtle(&shapes, &options) {
+                        Ok(turtle) =>
+                            HttpResponse {
+                                status: 200,
+                                content_type: "text/turtle".to_string(),
+                                headers: vec![],
+                                body: turtle.into_bytes(),
+                            },
+                        Err(e) => error(400, &e),
+                    }
+                Some(other) => error(400, &format!("Unknown format: {other}")),
+            }
+        }
+        ("shacl", false) => ok(&shacl::list_shape_files()),
+        ("shacl/validate", false) => {
+            let graph = params.get("graph").filter(|g| !g.trim().is_empty());
+            let report = match
+                Shapes::load_selected(kg, &params.get_all("file"), graph.as_deref()).and_then(|shapes|
+                    kg.validate_shacl(&shapes)
+                )
+            {
+                Ok(report) => report,
+                Err(e) => {
+                    return error(400, &e);
+                }
+            };
+            let (format, content_type) = match params.get("format").as_deref() {
+                None | Some("json") => {
+                    return ok(&report);
+                }
+                Some("turtle") => (RdfFormat::Turtle, "text/turtle"),
+                Some("ntriples") => (RdfFormat::NTriples, "application/n-triples"),
+                Some(other) => {
+                    return error(400, &format!("Unknown format: {other}"));
+                }
+            };
+            match report.to_rdf(format) {
+                Ok(rdf) =>
+                    HttpResponse {
+                        status: 200,
+                        content_type: content_type.to_string(),
+                        headers: vec![],
+                        body: rdf.into_bytes(),
+                    },
+                Err(e) => error(500, &e),
+            }
+        }
+        ("vocabulary", false) =>
+            match vocabulary::get() {
+                Some(vocabulary) => ok(&kg.vocabulary_report(vocabulary)),
+                None => error(404, "No schema.org vocabulary is loaded"),
+            }
+        ("vocabulary/term", false) => {
+            let Some(vocabulary) = vocabulary::get() else {
+                return error(404, "No schema.org vocabulary is loaded");
+            };
+            let Some(iri) = params.get("iri") else {
+                return missing("iri");
+            };
+            let Some(term) = vocabulary.term(&iri) else {
+                return error(404, &format!("Not a schema.org term: {iri}"));
+            };
+            let names = |terms: Vec<&VocabularyTerm>| -> Vec<String> {
+                terms
+                    .iter()
+                    .map(|t| t.name.clone())
+                    .collect()
+            };
+            ok(
+                &(TermDetails {
+                    term,
+                    ancestors: names(vocabulary.ancestors(&term.name)),
+                    children: names(vocabulary.children(&term.name)),
+                })
+            )
+        }
+        ("search", false) => {
+            let Some(text) = params.get("q") else {
+                return missing("q");
+            };
+            let Some(index) = search.index() else {
+                return error(503, "The search index is being built, try again in a moment");
+            };
+            let limit = params
+                .get("limit")
+                .and_then(|l| l.parse::<usize>().ok())
+                .unwrap_or(20)
+                .min(100);
+            let offset = params
+                .get("offset")
+                .and_then(|o| o.parse::<usize>().ok())
+                .unwrap_or(0);
+            let class = params
+                .get("class")
+                .filter(|c| !c.is_empty())
+                .map(|c| if c.starts_with('<') { c } else { format!("<{c}>") });
+            let results = index.search(
+                &(SearchQuery {
+                    text: text.clone(),
+                    class,
+                    lang: params.get("lang").filter(|l| !l.is_empty()),
+                    limit,
+                    offset,
+                })
+            );
+            let hits = results.hits
+                .into_iter()
+                .map(|hit| SearchResult {
+                    snippet: search::snippet(kg, &hit.id, &text, 200),
+                    id: hit.id,
+                    name: hit.name,
+                    score: hit.score,
+                })
+                .collect();
+            ok(&(SearchPage { total: results.total, hits }))
+        }
+        ("jobs", false) => ok(&jobs.list()),
+        (endpoint, false) if endpoint.starts_with("jobs/") => {
+            let id = endpoint["jobs/".len()..].parse::<u64>().ok();
+            match id.and_then(|id| jobs.get(id)) {
+                Some(job) => {
+                    // The results of the jobs of the web UI are HTML pages, only the API ones are given
+                    let result = id
+                        .and_then(|id| jobs.result(id))
+                        .and_then(|result| serde_json::from_str(&result).ok());
+                    ok(&(JobDetails { job, result }))
+                }
+                None => error(404, "Unknown job"),
+            }
+        }
+        (endpoint, _) if ENDPOINTS.contains(&endpoint) => {
+            let allowed = if is_post { "GET" } else { "POST" };
+            error(405, &format!("Method not allowed, use {allowed}")).with_header("Allow", allowed)
+        }
+        _ => error(404, "Unknown API endpoint"),
+    }
+}
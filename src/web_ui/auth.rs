@@ -0,0 +1,147 @@
+//! Local authentication of the web UI.
+//!
+//! Users and API tokens are read from an htpasswd-style file, with a role appended to each line:
+//!
+//! ```text
+//! # name:bcrypt hash:role (as produced by `htpasswd -nB name`, followed by `:role`)
+//! alice:$2y$05$...:admin
+//! # token:name:sha256 of the token (hex):role
+//! token:ci:9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08:viewer
+//! ```
+//!
+//! Users authenticate with HTTP Basic authentication, tools with an `Authorization: Bearer <token>` header.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::read_to_string;
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{ Digest, Sha256 };
+
+use crate::web_ui::http::HttpRequest;
+
+/// # Roles of the users, from the least to the most privileged.
+///
+/// ## Variants:
+/// * `Viewer`: Can browse, query and run the analyses that do not modify the store.
+/// * `Curator`: Can also run updates, routines, merges, predicate deletions and dumps.
+/// * `Admin`: Can also revert the store and replay history files.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub(crate) enum Role {
+    Viewer,
+    Curator,
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "viewer" => Ok(Role::Viewer),
+            "curator" => Ok(Role::Curator),
+            "admin" => Ok(Role::Admin),
+            other => Err(format!("Unknown role: {other}")),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Viewer => write!(f, "viewer"),
+            Role::Curator => write!(f, "curator"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// An authenticated user (or API token) and its role.
+#[derive(Clone)]
+pub(crate) struct User {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Users and API tokens allowed to access the explorer.
+///
+/// ## Fields
+/// * `users` - Bcrypt password hash and role of each user name.
+/// * `tokens` - SHA-256 hash (hex) of each token, with the token name and role.
+pub(crate) struct Auth {
+    users: HashMap<String, (String, Role)>,
+    tokens: HashMap<String, User>,
+}
+
+impl Auth {
+    /// Loads the users and API tokens of an auth file.
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    ///
+    /// # Errors
+    /// Returns a message pointing to the faulty line if the file cannot be read or a line is malformed.
+    pub fn load(path: &str) -> Result<Auth, String> {
+        let content = read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let mut auth = Auth { users: HashMap::new(), tokens: HashMap::new() };
+
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| format!("{path}:{}: {reason}", i + 1);
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                ["token", name, hash, role] => {
+                    let role = role.parse::<Role>().map_err(invalid)?;
+                    auth.tokens.insert(hash.to_lowercase(), User { name: name.to_string(), role });
+                }
+                [name, hash, role] => {
+                    let role = role.parse::<Role>().map_err(invalid)?;
+                    auth.users.insert(name.to_string(), (hash.to_string(), role));
+                }
+                _ =>
+                    {
+                        return Err(
+                            invalid("expected `name:hash:role` or `token:name:sha256:role`".to_string())
+                        );
+                    }
+            }
+        }
+        Ok(auth)
+    }
+
+    /// Identifies the user sending the request from its `Authorization` header.
+    ///
+    /// Returns `None` if the header is missing or the credentials are wrong.
+    pub fn authenticate(&self, req: &HttpRequest) -> Option<User> {
+        let header = req.header("Authorization")?.trim();
+        let (scheme, credentials) = header.split_once(' ')?;
+
+        if scheme.eq_ignore_ascii_case("Bearer") {
+            let hash = format!("{:x}", Sha256::digest(credentials.trim().as_bytes()));
+            return self.tokens.get(&hash).cloned();
+        }
+        if scheme.eq_ignore_ascii_case("Basic") {
+            let decoded = STANDARD.decode(credentials.trim()).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (name, password) = decoded.split_once(':')?;
+            let (hash, role) = self.users.get(name)?;
+            if bcrypt::verify(password, hash).unwrap_or(false) {
+                return Some(User { name: name.to_string(), role: *role });
+            }
+        }
+        None
+    }
+
+    /// Whether the request is authenticated by one of the API tokens (rather than by a password).
+    pub fn is_token_authenticated(&self, req: &HttpRequest) -> bool {
+        let bearer = req
+            .header("Authorization")
+            .and_then(|h| h.trim().split_once(' '))
+            .is_some_and(|(scheme, _)| scheme.eq_ignore_ascii_case("Bearer"));
+        bearer && self.authenticate(req).is_some()
+    }
+}
@@ -1,12 +1,47 @@
-use std::{ env, fs };
-use std::path::Path;
+use std::env;
 
-use crate::{ named_args, utils::escape_html, web_ui::templetization::Template };
+use crate::{
+    conformance::ConformanceReport,
+    explore::ValueCount,
+    inference::{ InferenceReport, InferenceStatus },
+    jobs::{ JobInfo, JobStatus },
+    named_args,
+    predicate_labels::{ Agreement, LabelReport },
+    predicate_plan::{ AppliedPlan, PlanImpact, PlanPair },
+    profiling::{ ClassProfile, ValueRange },
+    routine::{ list_routines, Routine },
+    shacl::{ Severity, ValidationReport },
+    shape_inference::{ InferredShape, ShapeInferenceOptions },
+    utils::{ escape_html, to_link },
+    vocabulary::{ self, TermStatus, TermUsage, VocabularyReport },
+    web_ui::{ security, templetization::Template },
+};
 use crate::web_ui::templetization::include_str;
 
-const NAV: &str = include_str!("../../templates/parts/nav.html");
 const DEBUG: bool = true;
 
+/// Results shown per shape on the SHACL page; the downloadable report has all of them.
+const MAX_SHACL_ROWS: usize = 200;
+
+/// Renders the navigation bar, which also carries the CSRF token used by the forms and scripts of every page.
+fn nav() -> String {
+    let file = if DEBUG {
+        include_str("./templates/parts/nav.html").to_string()
+    } else {
+        include_str!("../../templates/parts/nav.html").to_string()
+    };
+    let actions = if security::is_read_only() {
+        r#"<span class="badge bg-warning text-dark">Read-only</span>"#
+    } else {
+        r#"<form method="post" action="/dump" class="d-inline">
+          <button type="submit" class="nav-link btn btn-outline-success ms-3">Save current state</button>
+        </form>"#
+    };
+
+    let template = Template::new(&file, &["csrf_token", "actions"]);
+    template.render(named_args!(csrf_token = security::csrf_token(), actions = actions))
+}
+
 pub(crate) fn index_page(dataset_name: &str, class_counts: &[(String, u32)]) -> String {
     let mut all_cards = String::new();
 
@@ -23,31 +58,56 @@ pub(crate) fn index_page(dataset_name: &str, class_counts: &[(String, u32)]) ->
 
     let template = Template::new(&file, &["nav", "ds_name", "all_cards", "total_cards"]);
     let ds_low = &dataset_name.to_lowercase();
+    let nav = nav();
     template.render(
-        named_args!(nav = NAV, ds_name = ds_low, all_cards = &all_cards, total_cards = &total_cards)
+        named_args!(nav = &nav, ds_name = ds_low, all_cards = &all_cards, total_cards = &total_cards)
     )
 }
 
-pub(crate) fn explore_page(id: &str, page_num: u32, data: &str) -> String {
+/// Renders the explore page.
+///
+/// `page_link` gives the URL of a page with the current filters, `hidden_inputs` carries these filters in the
+/// sort and jump-to-page forms.
+pub(crate) fn explore_page(
+    page_num: u32,
+    pages: u32,
+    page_link: &dyn Fn(u32) -> String,
+    hidden_inputs: &str,
+    toolbar: &str,
+    facets: &str,
+    data: &str
+) -> String {
     let mut navigation = String::new();
 
-    navigation += r#"<div class="d-flex justify-content-between gap-2 mt-4">"#;
+    navigation += r#"<div class="d-flex justify-content-between align-items-center gap-2 mt-4">"#;
 
     // Previous button
     if page_num > 1 {
         navigation += &format!(
-            r#"<a class="btn btn-outline-primary" href="/explore?id={id}&page={}">Previous</a>"#,
-            page_num - 1
+            r#"<a class="btn btn-outline-primary" href="{}">Previous</a>"#,
+            page_link(page_num - 1)
         );
     } else {
         navigation += r#"<button class="btn btn-outline-secondary" disabled>Previous</button>"#;
     }
-    navigation += &format!("<p>Page {page_num}</p>");
-    // Next button
     navigation += &format!(
-        r#"<a class="btn btn-outline-primary" href="/explore?id={id}&page={}">Next</a>"#,
-        page_num + 1
+        r#"<form method="get" action="/explore" class="d-flex align-items-center gap-2">
+          {hidden_inputs}
+          <span>Page</span>
+          <input type="number" name="page" min="1" max="{pages}" value="{page_num}" class="form-control form-control-sm" style="width: 90px" />
+          <span>of {pages}</span>
+          <button type="submit" class="btn btn-sm btn-outline-primary">Go</button>
+        </form>"#
     );
+    // Next button
+    if page_num < pages {
+        navigation += &format!(
+            r#"<a class="btn btn-outline-primary" href="{}">Next</a>"#,
+            page_link(page_num + 1)
+        );
+    } else {
+        navigation += r#"<button class="btn btn-outline-secondary" disabled>Next</button>"#;
+    }
 
     navigation += "</div>";
     let file = if DEBUG {
@@ -56,16 +116,20 @@ pub(crate) fn explore_page(id: &str, page_num: u32, data: &str) -> String {
         include_str!("../../templates/explore.html").to_string()
     };
 
-    let template = Template::new(&file, &["nav", "navigation", "data"]);
+    let template = Template::new(&file, &["nav", "toolbar", "facets", "navigation", "data"]);
 
-    template.render(named_args!(nav = NAV, navigation = navigation, data = data))
+    let nav = nav();
+    template.render(
+        named_args!(nav = &nav, toolbar = toolbar, facets = facets, navigation = navigation, data = data)
+    )
 }
 
 pub(crate) fn query_page(
     nb_results: usize,
     table_rows_js_array: &str,
     table_headers_js_array: &str,
-    message: &str
+    message: &str,
+    inferred: Option<bool>
 ) -> String {
     let file = if DEBUG {
         include_str("./templates/query.html").to_string()
@@ -73,7 +137,7 @@ pub(crate) fn query_page(
         include_str!("../../templates/query.html").to_string()
     };
 
-    let html_template = Template::new(&file, &["nav", "message", "nb_results", "js"]);
+    let html_template = Template::new(&file, &["nav", "message", "nb_results", "js", "inferred"]);
 
     let jscode = if DEBUG {
         include_str("./templates/query.js").to_string()
@@ -96,22 +160,54 @@ pub(crate) fn query_page(
         )
     );
     let nb_results = &nb_results.to_string();
+    // `None` when inference is disabled: there is nothing to opt in to
+    let inferred = &inferred
+        .map(|checked| {
+            format!(
+                r#"<div class="form-check form-check-inline me-3">
+                <input class="form-check-input" type="checkbox" id="inferredInput" {} />
+                <label class="form-check-label" for="inferredInput">Include inferred triples</label>
+              </div>"#,
+                if checked { "checked" } else { "" }
+            )
+        })
+        .unwrap_or_default();
 
+    let nav = nav();
     html_template.render(
-        named_args!(nav = NAV, message = message, nb_results = nb_results, js = js)
+        named_args!(nav = &nav, message = message, nb_results = nb_results, js = js, inferred = inferred)
     )
 }
 
+/// Neighbourhood graph of the entity page.
+///
+/// ## Fields
+/// * `nodes`, `links` - Nodes and links, as JavaScript objects.
+/// * `frontier` - JSON array of the nodes that can be expanded.
+/// * `max_nodes` - Node budget of every expansion.
+/// * `options` - Form changing the depth and node budget.
+/// * `info` - Notice shown when the node budget truncated the graph.
+pub(crate) struct EntityGraph<'a> {
+    pub nodes: &'a str,
+    pub links: &'a str,
+    pub frontier: &'a str,
+    pub max_nodes: usize,
+    pub options: &'a str,
+    pub info: &'a str,
+}
+
+/// Renders the entity page.
+///
+/// `outgoing` and `incoming` are the rows of the triple tables and their pagination.
 pub(crate) fn entity_page(
     uri: &str,
     name: &str,
     description: &str,
     otype: &str,
     image: &str,
-    table_1: &str,
-    table_2: &str,
-    jsons: &str,
-    cons: &str
+    outgoing: (&str, &str),
+    incoming: (&str, &str),
+    graph: &EntityGraph
 ) -> String {
     let (js, html) = if DEBUG {
         let js = include_str("templates/graph_renderer.js");
@@ -133,43 +229,50 @@ pub(crate) fn entity_page(
             "name",
             "description",
             "table_1",
+            "outgoing_nav",
             "table_2",
+            "incoming_nav",
+            "graph_options",
+            "graph_info",
             "js",
             "nodes",
             "cons",
+            "frontier",
+            "max_nodes",
         ]
     );
 
+    let max_nodes = graph.max_nodes.to_string();
+    let nav = nav();
     template.render(
         named_args!(
-            nav = NAV,
+            nav = &nav,
             image = image,
             uri = uri,
             otype = otype,
             name = name,
             description = description,
-            table_1 = table_1,
-            table_2 = table_2,
+            table_1 = outgoing.0,
+            outgoing_nav = outgoing.1,
+            table_2 = incoming.0,
+            incoming_nav = incoming.1,
+            graph_options = graph.options,
+            graph_info = graph.info,
             js = js,
-            nodes = jsons,
-            cons = cons
+            nodes = graph.nodes,
+            cons = graph.links,
+            frontier = graph.frontier,
+            max_nodes = &max_nodes
         )
     )
 }
 
 pub(crate) fn routines_page() -> String {
-    let mut script_cards = String::new();
-
-    if let Ok(entries) = fs::read_dir("routines") {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.extension().map_or(false, |ext| ext == "sparql") {
-                if let Ok(content) = fs::read_to_string(&path) {
-                    script_cards += &script_card(&path, &content);
-                }
-            }
-        }
-    }
+    let script_cards = list_routines()
+        .iter()
+        .filter(|routine| !routine.hidden)
+        .map(script_card)
+        .collect::<String>();
 
     let file = if DEBUG {
         include_str("./templates/routines.html").to_string()
@@ -179,7 +282,8 @@ pub(crate) fn routines_page() -> String {
 
     let template = Template::new(&file, &["nav", "script_cards"]);
 
-    template.render(named_args!(nav = NAV, script_cards = &script_cards))
+    let nav = nav();
+    template.render(named_args!(nav = &nav, script_cards = &script_cards))
 }
 
 pub(crate) fn history_page(inside: String) -> String {
@@ -190,7 +294,119 @@ pub(crate) fn history_page(inside: String) -> String {
     };
     let template = Template::new(&file, &["nav", "inside"]);
 
-    template.render(named_args!(nav = NAV, inside = inside))
+    let nav = nav();
+    template.render(named_args!(nav = &nav, inside = inside))
+}
+
+pub(crate) fn search_page(
+    q: &str,
+    class: &str,
+    lang: &str,
+    class_counts: &[(String, u32)],
+    summary: &str,
+    results: &str,
+    navigation: &str
+) -> String {
+    let class_options = class_counts
+        .iter()
+        .map(|(c, count)| {
+            format!(
+                r#"<option value="{}"{}>{} ({count})</option>"#,
+                escape_html(c),
+                if c == class { " selected" } else { "" },
+                escape_html(&c.split('/').last().unwrap_or_default().replace('>', ""))
+            )
+        })
+        .collect::<String>();
+
+    let file = if DEBUG {
+        include_str("./templates/search.html").to_string()
+    } else {
+        include_str!("../../templates/search.html").to_string()
+    };
+    let template = Template::new(
+        &file,
+        &["nav", "q", "class_options", "lang", "summary", "results", "navigation"]
+    );
+
+    let nav = nav();
+    let q = &escape_html(&q.to_string());
+    let lang = &escape_html(&lang.to_string());
+    template.render(
+        named_args!(
+            nav = &nav,
+            q = q,
+            class_options = &class_options,
+            lang = lang,
+            summary = summary,
+            results = results,
+            navigation = navigation
+        )
+    )
+}
+
+pub(crate) fn jobs_page(jobs: &[JobInfo]) -> String {
+    let mut rows = String::new();
+    for job in jobs {
+        let (badge, class) = match job.status {
+            JobStatus::Queued => ("bg-secondary", "job-running"),
+            JobStatus::Running => ("bg-primary", "job-running"),
+            JobStatus::Succeeded => ("bg-success", ""),
+            JobStatus::Failed => ("bg-danger", ""),
+        };
+        let progress = match job.progress {
+            Some(p) => format!("{} / {}", p.done, p.total),
+            None => String::new(),
+        };
+        let duration = match job.finished_at {
+            Some(end) => format!("{}s", end.saturating_sub(job.started_at)),
+            None => String::new(),
+        };
+        rows += &format!(
+            r#"<tr class="{class}">
+            <td><a href="/jobs/{id}">{id}</a></td>
+            <td>{}</td>
+            <td>{}</td>
+            <td><span class="badge {badge}">{:?}</span></td>
+            <td>{progress}</td>
+            <td data-timestamp="{}"></td>
+            <td>{duration}</td>
+          </tr>"#,
+            escape_html(&job.title),
+            escape_html(&job.user.clone().unwrap_or_default()),
+            job.status,
+            job.started_at,
+            id = job.id
+        );
+    }
+    let empty = if jobs.is_empty() { r#"<p class="text-center text-muted">No jobs yet</p>"# } else { "" };
+
+    let file = if DEBUG {
+        include_str("./templates/jobs.html").to_string()
+    } else {
+        include_str!("../../templates/jobs.html").to_string()
+    };
+    let template = Template::new(&file, &["nav", "rows", "empty"]);
+
+    let nav = nav();
+    template.render(named_args!(nav = &nav, rows = &rows, empty = empty))
+}
+
+pub(crate) fn job_page(job: &JobInfo) -> String {
+    // The state is embedded in a script, so `</script>` in the logs must not close it
+    let job_json = serde_json::to_string(job).unwrap_or("null".to_string()).replace('<', "\\u003c");
+    let job_id = &job.id.to_string();
+    let title = &escape_html(&job.title);
+
+    let file = if DEBUG {
+        include_str("./templates/job.html").to_string()
+    } else {
+        include_str!("../../templates/job.html").to_string()
+    };
+    let template = Template::new(&file, &["nav", "job_id", "title", "job_json"]);
+
+    let nav = nav();
+    template.render(named_args!(nav = &nav, job_id = job_id, title = title, job_json = &job_json))
 }
 
 pub(crate) fn analysis_page(start_with: &str) -> String {
@@ -202,84 +418,673 @@ pub(crate) fn analysis_page(start_with: &str) -> String {
 
     let template = Template::new(&file, &["nav", "start_with"]);
 
-    template.render(named_args!(nav = NAV, start_with = start_with))
+    let nav = nav();
+    template.render(named_args!(nav = &nav, start_with = start_with))
 }
 
-pub(crate) fn class_analysis_page(class_anal: &str) -> String {
+pub(crate) fn conformance_page(report: &ConformanceReport) -> String {
+    let mut rows = String::new();
+    for pair in &report.pairs {
+        let mut observed = String::new();
+        for range in &pair.observed {
+            let share = (range.count as f64) * 100.0 / (pair.total.max(1) as f64);
+            let fixes = range.fixes
+                .iter()
+                .filter(|_| !security::is_read_only())
+                .map(|fix| {
+                    format!(
+                        r#"<form method="post" action="/analysis/conformance/fix" class="d-inline">
+                  <input type="hidden" name="class" value="{}" />
+                  <input type="hidden" name="predicate" value="{}" />
+                  <input type="hidden" name="range" value="{}" />
+                  <input type="hidden" name="fix" value="{}" />
+                  <button type="submit" class="btn btn-sm btn-outline-{}">{}</button>
+                </form>"#,
+                        escape_html(&pair.class),
+                        escape_html(&pair.predicate),
+                        escape_html(&range.range),
+                        escape_html(&fix.as_param()),
+                        if fix.as_param() == "drop" { "danger" } else { "warning" },
+                        escape_html(&fix.describe())
+                    )
+                })
+                .collect::<String>();
+            observed += &format!(
+                r#"<div class="d-flex align-items-center gap-2 mb-1">
+              <span class="badge {}">{}</span> {} <small class="text-muted">{share:.1}%</small> {fixes}
+            </div>"#,
+                if range.conforms { "bg-success" } else { "bg-danger" },
+                range.count,
+                escape_html(&range.range)
+            );
+        }
+        let domain = if pair.domain_conforms {
+            r#"<span class="badge bg-success">ok</span>"#.to_string()
+        } else {
+            format!(
+                r#"<span class="badge bg-warning text-dark" title="Expected: {}">unexpected</span>"#,
+                escape_html(&pair.expected_domains.join(", "))
+            )
+        };
+        rows += &format!(
+            r#"<tr class="{}">
+            <td>{}</td>
+            <td>{}</td>
+            <td>{domain}</td>
+            <td><small>{}</small></td>
+            <td>{observed}</td>
+          </tr>"#,
+            if pair.non_conforming == 0 && pair.domain_conforms { "conforming" } else { "" },
+            vocabulary_label(&pair.class),
+            vocabulary_label(&pair.predicate),
+            escape_html(&pair.expected_ranges.join(", "))
+        );
+    }
+
+    let non_conforming = report.pairs
+        .iter()
+        .filter(|p| p.non_conforming > 0)
+        .count();
+    let summary = format!(
+        r#"<p>{non_conforming} of {} class-predicate pairs have non-conforming values. {} predicates are not schema.org
+        properties and were not checked.</p>"#,
+        report.pairs.len(),
+        report.skipped_predicates.len()
+    );
+
+    let file = if DEBUG {
+        include_str("./templates/analysis/conformance.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/conformance.html").to_string()
+    };
+    let template = Template::new(&file, &["nav", "summary", "rows"]);
+    let nav = nav();
+    template.render(named_args!(nav = &nav, summary = summary, rows = rows))
+}
+
+/// Renders the SHACL page: the shapes form, and the results of a validation grouped by shape and focus node.
+///
+/// `validation` is the report along with its Turtle serialization, or the error that stopped it.
+pub(crate) fn shacl_page(
+    files: &[String],
+    selected: &[String],
+    graph: &str,
+    validation: Option<Result<(&ValidationReport, &str), &str>>
+) -> String {
+    let files_html = if files.is_empty() {
+        r#"<p class="text-muted">No shape files: add <code>.ttl</code> files to the <code>shapes/</code> directory.</p>"#.to_string()
+    } else {
+        files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| {
+                format!(
+                    r#"<div class="form-check">
+              <input class="form-check-input" type="checkbox" name="file" value="{0}" id="file-{i}" {1} />
+              <label class="form-check-label" for="file-{i}">{0}</label>
+            </div>"#,
+                    escape_html(file),
+                    if selected.contains(file) { "checked" } else { "" }
+                )
+            })
+            .collect::<String>()
+    };
+
+    let term_link = |term: &dyn std::fmt::Display| to_link(escape_html(&term.to_string()));
+    let results = match validation {
+        None => String::new(),
+        Some(Err(e)) => format!(r#"<div class="alert alert-danger">{}</div>"#, escape_html(&e.to_string())),
+        Some(Ok((report, rdf))) => {
+            let summary = if report.conforms {
+                format!(
+                    r#"<div class="alert alert-success">The data conforms: {} focus nodes checked against {} shapes.</div>"#,
+                    report.focus_nodes,
+                    report.shapes
+                )
+            } else {
+                format!(
+                    r#"<div class="alert alert-danger">{} violations, {} warnings and {} infos on {} focus nodes checked
+                    against {} shapes.</div>"#,
+                    report.count(Severity::Violation),
+                    report.count(Severity::Warning),
+                    report.count(Severity::Info),
+                    report.focus_nodes,
+                    report.shapes
+                )
+            };
+
+            // Results are grouped by shape, in order of appearance, then by focus node
+            let mut shapes: Vec<(&str, Vec<usize>)> = vec![];
+            for (i, result) in report.results.iter().enumerate() {
+                match shapes.iter_mut().find(|(shape, _)| *shape == result.shape) {
+                    Some((_, results)) => results.push(i),
+                    None => shapes.push((result.shape.as_str(), vec![i])),
+                }
+            }
+            let mut groups = String::new();
+            for (shape, mut indices) in shapes {
+                let total = indices.len();
+                indices.sort_by_key(|i| report.results[*i].focus_node.to_string());
+                indices.truncate(MAX_SHACL_ROWS);
+                let mut rows = String::new();
+                for (n, i) in indices.iter().enumerate() {
+                    let result = &report.results[*i];
+                    let first = n == 0 || report.results[indices[n - 1]].focus_node != result.focus_node;
+                    let focus = if first {
+                        let span = indices[n..]
+                            .iter()
+                            .take_while(|j| report.results[**j].focus_node == result.focus_node)
+                            .count();
+                        format!(r#"<td rowspan="{span}">{}</td>"#, term_link(&result.focus_node))
+                    } else {
+                        String::new()
+                    };
+                    rows += &format!(
+                        r#"<tr>
+              {focus}
+              <td><small>{}</small></td>
+              <td>{}</td>
+              <td><span class="badge {}">{}</span> <small class="text-muted">{}</small></td>
+              <td>{}</td>
+            </tr>"#,
+                        result.path.as_ref().map(|p| escape_html(&p.to_string())).unwrap_or_default(),
+                        result.value.as_ref().map(|v| term_link(v)).unwrap_or_default(),
+                        match result.severity {
+                            Severity::Violation => "bg-danger",
+                            Severity::Warning => "bg-warning text-dark",
+                            Severity::Info => "bg-info text-dark",
+                        },
+                        result.severity.name(),
+                        escape_html(&result.component.trim_end_matches("ConstraintComponent").to_string()),
+                        escape_html(&result.message)
+                    );
+                }
+                let more = if total > MAX_SHACL_ROWS {
+                    format!(
+                        r#"<p class="text-muted">{} more results in the downloadable report.</p>"#,
+                        total - MAX_SHACL_ROWS
+                    )
+                } else {
+                    String::new()
+                };
+                groups += &format!(
+                    r#"<details class="mb-3" open>
+          <summary class="fs-5">{} <span class="badge bg-secondary">{total}</span></summary>
+          <table class="table table-sm table-bordered align-middle mt-2">
+            <thead class="table-light">
+              <tr><td>Focus node</td><td>Path</td><td>Value</td><td>Constraint</td><td>Message</td></tr>
+            </thead>
+            <tbody>{rows}</tbody>
+          </table>
+          {more}
+        </details>"#,
+                    term_link(&shape)
+                );
+            }
+            format!(
+                r#"{summary}
+        <div class="mb-3">
+          <button type="button" class="btn btn-outline-secondary" data-download="shacl-report">Download report (Turtle)</button>
+          <textarea id="shacl-report" class="d-none">{}</textarea>
+        </div>
+        {groups}"#,
+                escape_html(&rdf.to_string())
+            )
+        }
+    };
+
+    let file = if DEBUG {
+        include_str("./templates/shacl.html").to_string()
+    } else {
+        include_str!("../../templates/shacl.html").to_string()
+    };
+    let template = Template::new(&file, &["nav", "files", "graph", "results"]);
+    let nav = nav();
+    let graph = escape_html(&graph.to_string());
+    template.render(named_args!(nav = &nav, files = files_html, graph = graph, results = results))
+}
+
+/// Renders the shapes inferred from the predicate analysis: a summary per class and the Turtle to download.
+pub(crate) fn shapes_page(
+    start_with: &str,
+    options: &ShapeInferenceOptions,
+    shapes: &[InferredShape],
+    turtle: &str
+) -> String {
+    let mut classes = String::new();
+    for shape in shapes {
+        let rows = shape.properties
+            .iter()
+            .map(|p| {
+                let mut constraints = vec![];
+                if let Some(min) = p.min_count {
+                    constraints.push(format!("min {min}"));
+                }
+                if let Some(max) = p.max_count {
+                    constraints.push(format!("max {max}"));
+                }
+                if let Some(datatype) = &p.datatype {
+                    constraints.push(datatype.rsplit(['#', '/']).next().unwrap_or(datatype).to_string());
+                }
+                if !p.classes.is_empty() {
+                    constraints.push(p.classes.join(" | "));
+                } else if p.datatype.is_none() {
+                    constraints.extend(p.node_kind.clone());
+                }
+                format!(
+                    r#"<tr class="{}"><td>{}</td><td>{:.1}%</td><td><small>{}</small></td></tr>"#,
+                    if p.keep == Some(false) { "text-muted" } else { "" },
+                    vocabulary_label(&p.predicate),
+                    p.frequency * 100.0,
+                    escape_html(&constraints.join(", "))
+                )
+            })
+            .collect::<String>();
+        classes += &format!(
+            r#"<details class="mb-3" open>
+          <summary class="fs-5">{} <span class="badge bg-secondary">{}</span></summary>
+          <table class="table table-sm table-bordered mt-2">
+            <thead class="table-light"><tr><td>Predicate</td><td>Used by</td><td>Constraints</td></tr></thead>
+            <tbody>{rows}</tbody>
+          </table>
+        </details>"#,
+            vocabulary_label(&shape.class),
+            shape.count
+        );
+    }
+
+    let file = if DEBUG {
+        include_str("./templates/analysis/shapes.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/shapes.html").to_string()
+    };
+    let template = Template::new(
+        &file,
+        &["nav", "start_with", "required", "dominant", "namespace", "dropped", "closed", "classes", "turtle"]
+    );
+    let nav = nav();
+    let start_with = escape_html(&start_with.to_string());
+    let required = options.required_frequency.to_string();
+    let dominant = options.dominant_share.to_string();
+    let namespace = escape_html(&options.namespace);
+    let turtle = escape_html(&turtle.to_string());
+    template.render(
+        named_args!(
+            nav = &nav,
+            start_with = start_with,
+            required = required,
+            dominant = dominant,
+            namespace = namespace,
+            dropped = if options.include_dropped { "checked" } else { "" },
+            closed = if options.closed { "checked" } else { "" },
+            classes = classes,
+            turtle = turtle
+        )
+    )
+}
+
+/// Renders the class analysis as a review form: every class can be kept or dropped before the plan is applied.
+pub(crate) fn class_analysis_page(class_anal: &str, page_rank: &str, start_with: &str) -> String {
     let file = if DEBUG {
         include_str("./templates/analysis/class_analysis.html").to_string()
     } else {
         include_str!("../../templates/analysis/class_analysis.html").to_string()
     };
 
-    let template = Template::new(&file, &["nav", "class_anal"]);
+    let template = Template::new(&file, &["nav", "class_anal", "page_rank", "start_with"]);
 
-    template.render(named_args!(nav = NAV, class_anal = class_anal))
+    let nav = nav();
+    let page_rank = &escape_html(&page_rank.to_string());
+    let start_with = &escape_html(&start_with.to_string());
+    template.render(
+        named_args!(nav = &nav, class_anal = class_anal, page_rank = page_rank, start_with = start_with)
+    )
 }
 
-pub(crate) fn predicate_analysis_page(
-    classes: &str,
-    preds_to_delete: Vec<(String, String)>
-) -> String {
+pub(crate) fn predicate_analysis_page(classes: &str, report: &LabelReport) -> String {
     let file = if DEBUG {
         include_str("./templates/analysis/predicate_analysis.html").to_string()
     } else {
         include_str!("../../templates/analysis/predicate_analysis.html").to_string()
     };
 
-    let mut preds_list = String::new();
-    for p in preds_to_delete {
-        preds_list += &format!("{{class: \"{}\", pred: \"{}\"}},", p.0, p.1);
-    }
+    let template = Template::new(&file, &["nav", "labels", "classes"]);
+
+    let nav = nav();
+    let labels = label_card(report);
+    template.render(named_args!(nav = &nav, labels = &labels, classes = classes))
+}
 
-    let template = Template::new(&file, &["nav", "classes", "preds_to_delete"]);
+/// Impact of a predicate deletion plan, with the form applying it.
+pub(crate) fn predicate_plan_page(pairs: &[PlanPair], impact: &PlanImpact) -> String {
+    let file = if DEBUG {
+        include_str("./templates/analysis/predicate_plan.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/predicate_plan.html").to_string()
+    };
 
+    let attribute = |value: &str| escape_html(&value.to_string()).replace('"', "&quot;");
+    let rows = impact.pairs
+        .iter()
+        .map(|p| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&p.class),
+                escape_html(&p.predicate),
+                p.triples,
+                p.entities
+            )
+        })
+        .collect::<String>();
+    let hidden = pairs
+        .iter()
+        .map(|p| format!(r#"<input type="hidden" name="pair" value="{}" />"#, attribute(&p.to_param())))
+        .collect::<String>();
+
+    let template = Template::new(&file, &["nav", "rows", "pairs", "triples", "entities", "disabled"]);
+
+    let nav = nav();
+    let (triples, entities) = (impact.triples.to_string(), impact.entities.to_string());
+    let disabled = if pairs.is_empty() || security::is_read_only() { "disabled" } else { "" };
     template.render(
-        named_args!(nav = NAV, classes = classes, preds_to_delete = preds_list.as_str())
+        named_args!(
+            nav = &nav,
+            rows = &rows,
+            pairs = &hidden,
+            triples = &triples,
+            entities = &entities,
+            disabled = disabled
+        )
     )
 }
 
-pub(crate) fn class_relation_graph(nodes: &str, edges: &str) -> String {
+/// Predicate deletion plans applied to the dataset, the latest first, with the forms undoing them.
+pub(crate) fn predicate_plans_page(plans: &[AppliedPlan]) -> String {
     let file = if DEBUG {
-        include_str("./templates/analysis/graph.html").to_string()
+        include_str("./templates/analysis/predicate_plans.html").to_string()
     } else {
-        include_str!("../../templates/analysis/graph.html").to_string()
+        include_str!("../../templates/analysis/predicate_plans.html").to_string()
     };
 
-    let template = Template::new(&file, &["nav", "nodes", "edges"]);
+    let rows = plans
+        .iter()
+        .rev()
+        .map(|plan| {
+            let pairs = plan.pairs
+                .iter()
+                .map(|p| format!("<li>{} {}</li>", escape_html(&p.class), escape_html(&p.predicate)))
+                .collect::<String>();
+            let action = if plan.undone {
+                "Undone".to_string()
+            } else if security::is_read_only() {
+                String::new()
+            } else {
+                format!(
+                    r#"<form method="post" action="/predicate_plans/undo" onsubmit="return confirm('Put back the {} triples of plan {}?')">
+                    <input type="hidden" name="id" value="{}" />
+                    <button type="submit" class="btn btn-sm btn-outline-warning">Undo</button>
+                  </form>"#,
+                    plan.triples,
+                    plan.id,
+                    plan.id
+                )
+            };
+            format!(
+                "<tr><td>{}</td><td><ul class=\"mb-0\">{pairs}</ul></td><td>{}</td><td>{}</td><td>{action}</td></tr>",
+                plan.id,
+                plan.triples,
+                plan.archive
+                    .as_ref()
+                    .map(|g| format!("<code>{}</code>", escape_html(g)))
+                    .unwrap_or_else(|| "Deleted".to_string())
+            )
+        })
+        .collect::<String>();
+
+    let template = Template::new(&file, &["nav", "rows"]);
 
-    template.render(named_args!(nav = NAV, nodes = nodes, edges = edges))
+    let nav = nav();
+    template.render(named_args!(nav = &nav, rows = &rows))
 }
-fn script_card(path: &Path, content: &str) -> String {
-    let file_name = path.file_name().unwrap().to_string_lossy();
-    let mut lines = content.lines();
-    let description = lines.next().unwrap_or("").trim_start_matches("###").trim();
-    if description.ends_with("@hidden") {
-        return String::new();
-    }
 
-    let mut body = String::new();
-    let mut current_proc_name = String::new();
-    let mut current_proc_query = String::new();
-    let mut in_proc = false;
+/// Profile of a class: a summary table of its predicates, then a card per predicate with its value distributions.
+pub(crate) fn class_profile_page(profile: &ClassProfile) -> String {
+    let file = if DEBUG {
+        include_str("./templates/analysis/profile.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/profile.html").to_string()
+    };
 
-    for line in lines {
-        if line.starts_with("##") {
-            if in_proc {
-                body += &procedure_section(&file_name, &current_proc_name, &current_proc_query);
-                current_proc_query.clear();
-            }
-            current_proc_name = line.trim_start_matches("##").trim().to_string();
-            in_proc = true;
-        } else if in_proc {
-            current_proc_query.push_str(line);
-            current_proc_query.push('\n');
+    // Horizontal bars, relative to the largest count of the list
+    let bars = |counts: &[ValueCount], link: bool| {
+        if counts.is_empty() {
+            return "<p class=\"text-muted\">None</p>".to_string();
         }
-    }
+        let max = counts
+            .iter()
+            .map(|c| c.count)
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let rows = counts
+            .iter()
+            .map(|c| {
+                let value = escape_html(&c.value);
+                let value = if link { to_link(value) } else { value };
+                format!(
+                    r#"<tr><td class="text-break">{value}</td><td style="width: 40%"><div class="progress" role="progressbar"><div class="progress-bar" style="width: {:.1}%"></div></div></td><td class="text-end">{}</td></tr>"#,
+                    ((c.count as f64) / (max as f64)) * 100.0,
+                    c.count
+                )
+            })
+            .collect::<String>();
+        format!(r#"<table class="table table-sm mb-0"><tbody>{rows}</tbody></table>"#)
+    };
+    let range = |range: &Option<ValueRange>| match range {
+        Some(r) => format!("{} to {} ({} values)", escape_html(&r.min), escape_html(&r.max), r.count),
+        None => "-".to_string(),
+    };
+
+    let rows = profile.predicates
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            format!(
+                "<tr><td><a href=\"#predicate-{i}\">{}</a></td><td>{}</td><td>{:.1}%</td><td>{}</td><td>{}</td><td>{:.3}</td><td>{:.3}</td></tr>",
+                vocabulary_label(&p.predicate),
+                p.entities,
+                p.fill_rate * 100.0,
+                p.values,
+                p.distinct,
+                p.uniqueness,
+                p.entropy
+            )
+        })
+        .collect::<String>();
+
+    let cards = profile.predicates
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let examples = p.examples
+                .iter()
+                .map(|e| format!("<li>{}</li>", to_link(escape_html(e))))
+                .collect::<String>();
+            format!(
+                r#"<div class="card shadow-sm my-4 px-0" id="predicate-{i}">
+              <div class="card-header bg-primary text-white"><h5 class="mb-0">{}</h5></div>
+              <div class="card-body">
+                <p>
+                  Used by <b>{}</b> of {} entities ({:.1}%), <b>{}</b> values of which <b>{}</b> distinct.
+                  Numbers: {}. Dates: {}.
+                </p>
+                <div class="row">
+                  <div class="col-md-6"><h6>Top values</h6>{}</div>
+                  <div class="col-md-6">
+                    <h6>Kinds</h6>{}
+                    <h6 class="mt-3">Datatypes</h6>{}
+                    <h6 class="mt-3">Languages</h6>{}
+                    <h6 class="mt-3">Lengths of the literals</h6>{}
+                  </div>
+                </div>
+                <h6 class="mt-3">Examples</h6><ul>{examples}</ul>
+              </div>
+            </div>"#,
+                vocabulary_label(&p.predicate),
+                p.entities,
+                profile.entities,
+                p.fill_rate * 100.0,
+                p.values,
+                p.distinct,
+                range(&p.numeric),
+                range(&p.dates),
+                bars(&p.top_values, true),
+                bars(&p.kinds, false),
+                bars(&p.datatypes, false),
+                bars(&p.languages, false),
+                bars(&p.lengths, false)
+            )
+        })
+        .collect::<String>();
+
+    let template = Template::new(&file, &["nav", "class", "class_param", "entities", "rows", "cards"]);
+
+    let nav = nav();
+    let class = vocabulary_label(&profile.class);
+    let class_param = escape_html(&profile.class).replace('"', "&quot;").replace('#', "%23");
+    let entities = profile.entities.to_string();
+    template.render(
+        named_args!(
+            nav = &nav,
+            class = &class,
+            class_param = &class_param,
+            entities = &entities,
+            rows = &rows,
+            cards = &cards
+        )
+    )
+}
+
+/// Form flattening a class into a table, prefilled with the suggested predicate paths.
+pub(crate) fn flatten_page(class: &str, paths: &[String], analysed: bool) -> String {
+    let file = if DEBUG {
+        include_str("./templates/analysis/flatten.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/flatten.html").to_string()
+    };
+    let template = Template::new(&file, &["nav", "class", "class_param", "source", "paths"]);
 
-    if in_proc {
-        body += &procedure_section(&file_name, &current_proc_name, &current_proc_query);
+    let nav = nav();
+    let label = vocabulary_label(class);
+    let class_param = escape_html(&class.to_string()).replace('"', "&quot;");
+    let source = if analysed {
+        "the predicate analysis of the class, with the labels of the curators"
+    } else {
+        "the profile of the class (predicates used by at least half of the entities); run the predicate analysis for better ones"
+    }.to_string();
+    let paths = escape_html(&paths.join("\n"));
+    template.render(
+        named_args!(nav = &nav, class = &label, class_param = &class_param, source = &source, paths = &paths)
+    )
+}
+
+/// Keep/drop/clear buttons labelling a predicate of a class, the current label highlighted.
+pub(crate) fn label_buttons(class: &str, predicate: &str, label: Option<bool>) -> String {
+    if security::is_read_only() {
+        return match label {
+            Some(true) => "Keep".to_string(),
+            Some(false) => "Drop".to_string(),
+            None => String::new(),
+        };
     }
+    let attribute = |value: &str| escape_html(&value.to_string()).replace('"', "&quot;");
+    let button = |value: &str, text: &str, style: &str, active: bool| {
+        format!(
+            r#"<button type="button" class="btn label-btn btn-{}{style}" data-label="{value}">{text}</button>"#,
+            if active { "" } else { "outline-" }
+        )
+    };
+    format!(
+        r#"<div class="btn-group btn-group-sm" data-class="{}" data-predicate="{}">{}{}{}</div>"#,
+        attribute(class),
+        attribute(predicate),
+        button("keep", "Keep", "success", label == Some(true)),
+        button("drop", "Drop", "danger", label == Some(false)),
+        button("clear", "×", "secondary", false)
+    )
+}
+
+/// Agreement of the automatic decisions with the curator labels, and the predicates to label next.
+fn label_card(report: &LabelReport) -> String {
+    let agreement = |name: &str, a: &Agreement| {
+        if a.compared == 0 {
+            return format!("<li>{name}: no labelled predicate to compare with</li>");
+        }
+        format!(
+            "<li>{name}: agrees on {} of {} labelled predicates ({:.0}%); keeps {} the curators drop, drops {} they keep</li>",
+            a.agreed,
+            a.compared,
+            a.rate * 100.0,
+            a.kept_by_model_only,
+            a.kept_by_curator_only
+        )
+    };
+    let suggestions = report.suggestions
+        .iter()
+        .map(|s| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{:.3}</td><td>{}</td></tr>",
+                escape_html(&s.class),
+                escape_html(&s.predicate),
+                s.model_keep,
+                label_buttons(&s.class, &s.predicate, None)
+            )
+        })
+        .collect::<String>();
+    let suggestions = if suggestions.is_empty() {
+        String::new()
+    } else {
+        format!(
+            r#"<h6 class="mt-3">Label next (the model is the least sure about these)</h6>
+            <table class="table table-sm mb-0">
+              <thead><tr><th>Class</th><th>Predicate</th><th>NN Confidence</th><th>Curator</th></tr></thead>
+              <tbody>{suggestions}</tbody>
+            </table>"#
+        )
+    };
+    format!(
+        r#"<div class="card my-4 px-0">
+        <div class="card-body">
+          <h5 class="card-title">Curator labels</h5>
+          <p class="card-text">{} labels. They override the hybrid decision, and are exported as training labels
+          (<code>/api/v1/analysis/features</code>).</p>
+          <ul class="mb-0">{}{}</ul>
+          {suggestions}
+        </div>
+      </div>"#,
+        report.labels,
+        agreement("NN Keep", &report.model),
+        agreement("Hybrid Decision", &report.hybrid)
+    )
+}
+
+pub(crate) fn class_relation_graph(nodes: &str, edges: &str) -> String {
+    let file = if DEBUG {
+        include_str("./templates/analysis/graph.html").to_string()
+    } else {
+        include_str!("../../templates/analysis/graph.html").to_string()
+    };
+
+    let template = Template::new(&file, &["nav", "nodes", "edges"]);
+
+    let nav = nav();
+    template.render(named_args!(nav = &nav, nodes = nodes, edges = edges))
+}
+fn script_card(routine: &Routine) -> String {
+    let body = routine.procedures
+        .iter()
+        .map(|p| procedure_section(&routine.file, &p.name, &p.query))
+        .collect::<String>();
 
     format!(
         r#"<div class="card mb-4">
@@ -295,9 +1100,9 @@ fn script_card(path: &Path, content: &str) -> String {
     </div>
     <div class="card-body">{}</div>
 </div>"#,
-        file_name,
-        description,
-        file_name,
+        routine.file,
+        routine.description,
+        routine.file,
         body
     )
 }
@@ -347,3 +1152,204 @@ pub(crate) fn class_card(index: usize, name: &str, count: u32) -> String {
         named_args!(index = index, name = name, entity_name = entity_name, count = count)
     )
 }
+
+/// Removes the HTML tags of a schema.org comment, for a `title` attribute.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+            }
+            '>' => {
+                in_tag = false;
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Renders a term with its schema.org label and comment (as a tooltip).
+///
+/// Schema.org terms missing from the vocabulary get an "unknown" badge, lowercased ones a "casing" badge.
+/// Other terms (and every term when no vocabulary is loaded) are rendered as is.
+pub(crate) fn vocabulary_label(iri: &str) -> String {
+    let escaped = escape_html(&iri.to_string());
+    let (Some(vocabulary), Some(name)) = (vocabulary::get(), vocabulary::local_name(iri)) else {
+        return escaped;
+    };
+    match vocabulary.term(name) {
+        None =>
+            format!(
+                r#"{escaped} <span class="badge bg-danger" title="Not a schema.org term">unknown</span>"#
+            ),
+        Some(term) => {
+            let casing = if term.name != name {
+                format!(
+                    r#" <span class="badge bg-warning text-dark" title="schema.org spells it {}">casing</span>"#,
+                    escape_html(&term.name)
+                )
+            } else {
+                String::new()
+            };
+            format!(
+                r#"<span title="{}">{escaped}</span> <small class="text-muted">{}</small>{casing}"#,
+                escape_html(&strip_tags(&term.comment.clone().unwrap_or_default())),
+                escape_html(&term.label.clone().unwrap_or_default())
+            )
+        }
+    }
+}
+
+/// Status and controls of the inferred graph, with the outcome of the last refresh if any.
+fn inference_card(status: &InferenceStatus, refreshed: Option<&InferenceReport>) -> String {
+    let description = if status.enabled {
+        format!(
+            r#"Enabled: {} triples inferred from the class and property hierarchies are kept in
+            <code>{}</code>{}. Queries include them unless they opt out."#,
+            status.triples,
+            escape_html(&status.graph),
+            if status.stale { " (out of date, refreshed on the next query)" } else { "" }
+        )
+    } else {
+        r#"Disabled: queries only see the asserted triples. Enabling materializes the <code>rdfs:subClassOf</code>,
+        <code>rdfs:subPropertyOf</code> and <code>schema:inverseOf</code> entailments into a separate graph."#.to_string()
+    };
+    let refreshed = refreshed
+        .map(|r| {
+            format!(
+                r#"<div class="alert alert-success py-2">{} class, {} property and {} inverse rules applied:
+                {} triples inserted, {} deleted, {} inferred.</div>"#,
+                r.class_rules,
+                r.property_rules,
+                r.inverse_rules,
+                r.inserted,
+                r.deleted,
+                r.triples
+            )
+        })
+        .unwrap_or_default();
+    let button = |action: &str, label: &str, style: &str| {
+        format!(
+            r#"<form method="post" action="/vocabulary/inference" class="d-inline">
+              <input type="hidden" name="action" value="{action}" />
+              <button type="submit" class="btn btn-sm btn-outline-{style}">{label}</button>
+            </form>"#
+        )
+    };
+    let actions = match (security::is_read_only(), status.enabled) {
+        (true, _) => String::new(),
+        (false, true) => button("refresh", "Refresh", "primary") + &button("disable", "Disable", "danger"),
+        (false, false) => button("enable", "Enable inference", "primary"),
+    };
+    format!(
+        r#"<div class="card mb-4">
+        <div class="card-body">
+          <h5 class="card-title">Inference</h5>
+          <p class="card-text">{description}</p>
+          {refreshed}
+          {actions}
+        </div>
+      </div>"#
+    )
+}
+
+pub(crate) fn vocabulary_page(
+    report: Option<&VocabularyReport>,
+    inference: &InferenceStatus,
+    refreshed: Option<&InferenceReport>
+) -> String {
+    let status_badge = |status: TermStatus| match status {
+        TermStatus::Known => r#"<span class="badge bg-success">known</span>"#,
+        TermStatus::WrongCase => r#"<span class="badge bg-warning text-dark">wrong casing</span>"#,
+        TermStatus::WrongKind => r#"<span class="badge bg-warning text-dark">wrong kind</span>"#,
+        TermStatus::Unknown => r#"<span class="badge bg-danger">unknown</span>"#,
+    };
+    let rows = |usages: &[TermUsage]| {
+        usages
+            .iter()
+            .map(|usage| {
+                let canonical = match (&usage.canonical, usage.status) {
+                    (Some(canonical), TermStatus::WrongCase) =>
+                        format!("<br /><small>Should be {}</small>", escape_html(canonical)),
+                    _ => String::new(),
+                };
+                format!(
+                    r#"<tr>
+            <td><span title="{}">{}</span> <small class="text-muted">{}</small>{canonical}</td>
+            <td>{}</td>
+            <td>{}</td>
+            <td><small>{}</small></td>
+          </tr>"#,
+                    escape_html(&strip_tags(&usage.comment.clone().unwrap_or_default())),
+                    escape_html(&usage.iri),
+                    escape_html(&usage.label.clone().unwrap_or_default()),
+                    usage.count,
+                    status_badge(usage.status),
+                    escape_html(&usage.ancestors.join(" → "))
+                )
+            })
+            .collect::<String>()
+    };
+
+    let (summary, classes, predicates, actions) = match report {
+        None =>
+            (
+                r#"<div class="alert alert-warning">No schema.org vocabulary is loaded: start the explorer with
+                <code>--vocabulary</code> (or put <code>schemaorg-current-https.nt</code> in <code>./data</code>).</div>"#.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ),
+        Some(report) => {
+            let wrong_case = report.classes
+                .iter()
+                .chain(&report.predicates)
+                .any(|t| t.status == TermStatus::WrongCase);
+            let actions = if wrong_case && !security::is_read_only() {
+                r#"<form method="post" action="/vocabulary/canonicalize">
+              <button type="submit" class="btn btn-outline-warning">Fix the casing of the dataset terms</button>
+            </form>"#.to_string()
+            } else {
+                String::new()
+            };
+            (
+                format!(
+                    r#"<p>{} terms in the vocabulary, {} issues among the schema.org classes and predicates of the
+                    dataset ({} terms of other namespaces are not checked).</p>"#,
+                    report.vocabulary_size,
+                    report.issues(),
+                    report.other_namespaces
+                ),
+                rows(&report.classes),
+                rows(&report.predicates),
+                actions,
+            )
+        }
+    };
+
+    let file = if DEBUG {
+        include_str("./templates/vocabulary.html").to_string()
+    } else {
+        include_str!("../../templates/vocabulary.html").to_string()
+    };
+    let template = Template::new(
+        &file,
+        &["nav", "actions", "summary", "inference", "classes", "predicates"]
+    );
+    let nav = nav();
+    let inference = inference_card(inference, refreshed);
+    template.render(
+        named_args!(
+            nav = &nav,
+            actions = actions,
+            summary = summary,
+            inference = inference,
+            classes = classes,
+            predicates = predicates
+        )
+    )
+}
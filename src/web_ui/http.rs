@@ -0,0 +1,280 @@
+//! HTTP layer of the web UI.
+//!
+//! Turns raw `tiny_http` requests into `HttpRequest`s and `HttpResponse`s back into `tiny_http` responses.
+//!
+//! - Parameters are merged from the query string, `application/x-www-form-urlencoded` bodies and
+//!   `application/json` bodies, so every route accepts both GET and POST.
+//! - Bodies are capped at `MAX_BODY_SIZE` bytes.
+//! - Responses carry a proper status code and content type.
+
+use std::io::{ Cursor, Read };
+
+use serde::Serialize;
+use serde_json::Value;
+use tiny_http::{ Header, Method, Request, Response, StatusCode };
+
+use crate::utils::url_decode;
+
+/// Largest request body accepted by the server (64 MiB, enough for a replayed history file).
+pub(crate) const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+/// # Errors raised while reading an incoming request.
+///
+/// ## Variants:
+/// * `BadRequest(String)`: The request is malformed (invalid body encoding, invalid JSON, ...).
+/// * `PayloadTooLarge`: The body exceeds `MAX_BODY_SIZE`.
+pub(crate) enum RequestError {
+    BadRequest(String),
+    PayloadTooLarge,
+}
+
+/// Ordered list of request parameters.
+///
+/// The order is kept as sent by the client, which matters for routines that must run in the selected order.
+#[derive(Default)]
+pub(crate) struct Params(Vec<(String, String)>);
+
+impl Params {
+    /// Parses a URL-encoded string (`a=1&b=2`).
+    pub fn from_urlencoded(query: &str) -> Params {
+        let mut params = Params::default();
+        params.extend_urlencoded(query.as_bytes());
+        params
+    }
+
+    /// Returns the first value sent for `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    }
+
+    /// Returns every value sent for `key`, in order.
+    pub fn get_all(&self, key: &str) -> Vec<String> {
+        self.0
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    /// Iterates over all of the `(key, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = &(String, String)> {
+        self.0.iter()
+    }
+
+    fn push(&mut self, key: String, value: String) {
+        self.0.push((key, value));
+    }
+
+    /// Appends the pairs of a URL-encoded string (`a=1&b=2`).
+    fn extend_urlencoded(&mut self, data: &[u8]) {
+        for (k, v) in url::form_urlencoded::parse(data) {
+            self.push(k.into_owned(), v.into_owned());
+        }
+    }
+
+    /// Appends the fields of a flat JSON object. Arrays yield one pair per element.
+    fn extend_json(&mut self, body: &str) -> Result<(), RequestError> {
+        let value: Value = serde_json
+            ::from_str(body)
+            .map_err(|e| RequestError::BadRequest(format!("Invalid JSON body: {e}")))?;
+        let Value::Object(fields) = value else {
+            return Err(RequestError::BadRequest("JSON body must be an object".to_string()));
+        };
+        for (k, v) in fields {
+            match v {
+                Value::Array(items) => {
+                    for item in items {
+                        self.push(k.clone(), json_to_param(item));
+                    }
+                }
+                v => self.push(k, json_to_param(v)),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn json_to_param(value: Value) -> String {
+    match value {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        v => v.to_string(),
+    }
+}
+
+/// A fully read HTTP request.
+///
+/// ## Fields
+/// * `method` - HTTP method of the request.
+/// * `url` - Raw request target, including the query string.
+/// * `path` - Percent-decoded path, without the query string.
+/// * `params` - Merged query string and body parameters.
+/// * `body` - Raw body decoded as UTF-8.
+/// * `headers` - Request headers as `(name, value)` pairs.
+pub(crate) struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub path: String,
+    pub params: Params,
+    pub body: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    /// Reads the head and body of a `tiny_http` request.
+    ///
+    /// # Errors
+    /// - `RequestError::PayloadTooLarge` if the body is bigger than `MAX_BODY_SIZE`.
+    /// - `RequestError::BadRequest` if the body is not UTF-8 or not valid for its content type.
+    pub fn read(request: &mut Request) -> Result<HttpRequest, RequestError> {
+        if request.body_length().is_some_and(|len| len > MAX_BODY_SIZE) {
+            return Err(RequestError::PayloadTooLarge);
+        }
+
+        let headers = request
+            .headers()
+            .iter()
+            .map(|h| (h.field.as_str().to_string(), h.value.as_str().to_string()))
+            .collect::<Vec<_>>();
+
+        let (raw_path, query_string) = match request.url().split_once('?') {
+            Some((p, q)) => (p.to_string(), Some(q.to_string())),
+            None => (request.url().to_string(), None),
+        };
+
+        let mut bytes = vec![];
+        request
+            .as_reader()
+            .take((MAX_BODY_SIZE as u64) + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|e| RequestError::BadRequest(format!("Failed to read the body: {e}")))?;
+        if bytes.len() > MAX_BODY_SIZE {
+            return Err(RequestError::PayloadTooLarge);
+        }
+        let body = String::from_utf8(bytes).map_err(|_|
+            RequestError::BadRequest("Body is not valid UTF-8".to_string())
+        )?;
+
+        let mut params = Params::default();
+        if let Some(qs) = query_string {
+            params.extend_urlencoded(qs.as_bytes());
+        }
+
+        let content_type = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("Content-Type"))
+            .map(|(_, v)| v.to_ascii_lowercase())
+            .unwrap_or_default();
+        if content_type.starts_with("application/x-www-form-urlencoded") {
+            params.extend_urlencoded(body.as_bytes());
+        } else if content_type.starts_with("application/json") && !body.trim().is_empty() {
+            params.extend_json(&body)?;
+        }
+
+        Ok(HttpRequest {
+            method: request.method().clone(),
+            url: request.url().to_string(),
+            path: url_decode(&raw_path),
+            params,
+            body,
+            headers,
+        })
+    }
+
+    /// Returns the value of the header `name` (case insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Response produced by a route handler.
+///
+/// ## Fields
+/// * `status` - HTTP status code.
+/// * `content_type` - Value of the `Content-Type` header.
+/// * `headers` - Additional headers.
+/// * `body` - Response body.
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub content_type: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    /// Creates an HTML response.
+    pub fn html(status: u16, body: String) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type: "text/html; charset=UTF-8".to_string(),
+            headers: vec![],
+            body: body.into_bytes(),
+        }
+    }
+
+    /// Creates a JSON response by serializing `body`.
+    ///
+    /// Falls back to a `500` error if the body cannot be serialized.
+    pub fn json<T: Serialize>(status: u16, body: &T) -> HttpResponse {
+        match serde_json::to_vec(body) {
+            Ok(bytes) =>
+                HttpResponse {
+                    status,
+                    content_type: "application/json".to_string(),
+                    headers: vec![],
+                    body: bytes,
+                },
+            Err(e) => HttpResponse::error(500, &format!("Failed to serialize the response: {e}")),
+        }
+    }
+
+    /// Creates a `303 See Other` response pointing to `location`.
+    ///
+    /// The body still contains the HTML redirect page for clients that do not follow redirects.
+    pub fn redirect(location: &str) -> HttpResponse {
+        let mut response = HttpResponse::html(
+            303,
+            include_str!("../../templates/redirect.html").to_string()
+        );
+        response.headers.push(("Location".to_string(), location.to_string()));
+        response
+    }
+
+    /// Creates an HTML error page with the given status and message.
+    pub fn error(status: u16, message: &str) -> HttpResponse {
+        let reason = StatusCode(status).default_reason_phrase();
+        HttpResponse::html(
+            status,
+            format!(
+                "<html><body><h1>{status} - {reason}</h1><p>{}</p></body></html>",
+                crate::utils::escape_html(&message.to_string())
+            )
+        )
+    }
+
+    /// Adds a header to the response.
+    pub fn with_header(mut self, name: &str, value: &str) -> HttpResponse {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Converts the response into a `tiny_http` response.
+    pub fn into_response(self) -> Response<Cursor<Vec<u8>>> {
+        let mut response = Response::from_data(self.body).with_status_code(self.status);
+        if let Ok(h) = Header::from_bytes("Content-Type", self.content_type.as_bytes()) {
+            response.add_header(h);
+        }
+        for (k, v) in self.headers {
+            if let Ok(h) = Header::from_bytes(k.as_bytes(), v.as_bytes()) {
+                response.add_header(h);
+            }
+        }
+        response
+    }
+}
@@ -1,5 +1,10 @@
 //! Module for managing the webui
 
 pub mod server;
+pub mod http;
+pub mod api;
+pub mod security;
+pub mod auth;
+pub mod sse;
 pub mod html_templates;
 mod templetization;
@@ -0,0 +1,64 @@
+//! Protection of the mutating routes.
+//!
+//! - Mutations are only accepted as POST requests carrying the CSRF token of the server, either in the
+//!   `X-CSRF-Token` header or in a `csrf_token` parameter. The token is generated at startup and embedded in
+//!   every page through the navigation bar.
+//! - Requests authenticated by a valid `Authorization: Bearer` API token are exempt from the CSRF check: browsers
+//!   never attach such a header on their own, so it cannot be forged by another site. Without authentication, or
+//!   with an unknown token, the CSRF token is required.
+//! - In read-only mode every mutation is rejected.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{ AtomicBool, Ordering };
+
+use rand::Rng;
+
+use crate::web_ui::auth::Auth;
+use crate::web_ui::http::HttpRequest;
+
+/// Name of the header carrying the CSRF token.
+pub(crate) const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// Name of the form parameter carrying the CSRF token.
+pub(crate) const CSRF_PARAM: &str = "csrf_token";
+
+static CSRF_TOKEN: OnceLock<String> = OnceLock::new();
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Returns the CSRF token of the server, generating it on first use.
+pub(crate) fn csrf_token() -> &'static str {
+    CSRF_TOKEN.get_or_init(|| format!("{:032x}", rand::rng().random::<u128>()))
+}
+
+/// Checks that the request carries the CSRF token of the server, or an API token `auth` accepts.
+pub(crate) fn check_csrf(req: &HttpRequest, auth: Option<&Auth>) -> bool {
+    if auth.is_some_and(|auth| auth.is_token_authenticated(req)) {
+        return true;
+    }
+    let sent = req
+        .header(CSRF_HEADER)
+        .map(str::to_string)
+        .or_else(|| req.params.get(CSRF_PARAM));
+    match sent {
+        Some(sent) => constant_time_eq(sent.as_bytes(), csrf_token().as_bytes()),
+        None => false,
+    }
+}
+
+/// Switches the server to read-only mode.
+pub(crate) fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::SeqCst);
+}
+
+/// Whether the server rejects every mutation.
+pub(crate) fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() &&
+        a
+            .iter()
+            .zip(b)
+            .fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,19 +1,28 @@
 use core::option::Option::None;
-use std::collections::{ HashMap, HashSet };
-use std::io::{ prelude::*, BufReader };
-use std::net::{ TcpListener, TcpStream };
+use std::collections::HashMap;
+use std::panic::{ self, AssertUnwindSafe };
 use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
 use std::thread;
-
-use oxigraph::model::Term::Literal;
-use petgraph::Direction::Outgoing;
-
-use crate::store::KG;
+use std::time::Duration;
+
+use oxigraph::io::RdfFormat;
+use tiny_http::{ Method, Request, Server };
+
+use crate::analysis::{ ClassEdge, ClassNode, ClassPredicates, PredicateScore };
+use crate::conformance::ConformanceFix;
+use crate::explore::{ self, ExploreFilters, Facets, SortOrder };
+use crate::jobs::{ JobManager, JobStatus };
+use crate::page_rank::PageRankOptions;
+use crate::predicate_labels;
+use crate::search::{ self, SearchEngine, SearchQuery };
+use crate::shacl::{ self, Shapes, ValidationReport };
+use crate::shape_inference;
+use crate::store::{ self, HistoryEntry, TripleDirection, KG };
+use crate::vocabulary;
 use crate::utils::{
-    calculate_probabilities_for_graph,
     escape_html,
     external_link,
-    extract_query_param,
     format_json,
     to_link,
     url_decode,
@@ -21,295 +30,990 @@ use crate::utils::{
 use crate::web_ui::html_templates::{
     analysis_page,
     class_analysis_page,
+    class_profile_page,
     class_relation_graph,
+    conformance_page,
     entity_page,
+    EntityGraph,
     explore_page,
+    flatten_page,
     history_page,
     index_page,
+    job_page,
+    label_buttons,
+    jobs_page,
     predicate_analysis_page,
+    predicate_plan_page,
+    predicate_plans_page,
     query_page,
+    object_card,
     routines_page,
+    search_page,
+    shacl_page,
+    shapes_page,
+    vocabulary_label,
+    vocabulary_page,
 };
+use crate::web_ui::api::{ self, API_PREFIX };
+use crate::web_ui::auth::{ Auth, Role };
+use crate::web_ui::security;
+use crate::web_ui::sse;
+use crate::web_ui::http::{ HttpRequest, HttpResponse, Params, RequestError };
 use crate::store::StoreError;
-use std::io::Write;
 
 enum Page {
     Index,
-    Explore(String, u32),
-    Query(Option<String>, Option<String>, Option<String>),
-    Entity(String),
-    Run(Vec<String>),
+    Explore(ExploreFilters, SortOrder, u32),
+    Query(Option<String>, Option<String>, Option<String>, bool),
+    Entity(String, EntityOptions),
     Scripts,
-    Error,
+    Error(String),
     Redirect,
     History,
-    ClassRelations(i32, String),
+    Search,
+    Jobs,
+    Vocabulary,
+    PredicatePlans,
+    Shacl(Vec<String>, String),
+    JobDetails(u64),
+    Job(u64),
 }
 
 pub(crate) struct WebServer {
     dataset: Arc<KG>,
     port: u32,
+    workers: usize,
+    auth: Option<Arc<Auth>>,
+    jobs: Arc<JobManager>,
+    search: Arc<SearchEngine>,
+}
+
+/// Number of triples per page of the tables of the entity page.
+const TRIPLES_PAGE_SIZE: u64 = 50;
+
+/// Options of the entity page, given in the query string following the IRI.
+///
+/// ## Fields
+/// * `depth`, `max_nodes` - Limits of the neighbourhood graph.
+/// * `outgoing_page`, `incoming_page` - Pages of the triple tables, from 1.
+struct EntityOptions {
+    depth: u32,
+    max_nodes: usize,
+    outgoing_page: u64,
+    incoming_page: u64,
 }
 
+impl EntityOptions {
+    /// Splits the decoded path following `/entity/` into the entity and its options.
+    ///
+    /// IRIs may contain a '?' but never a '>', so the options of `<iri>` start after its closing bracket.
+    fn split(path: &str) -> (&str, EntityOptions) {
+        let end = match path.starts_with('<') {
+            true => path.find('>').map(|i| i + 1),
+            false => path.find('?'),
+        };
+        let (entity, query) = match end {
+            Some(end) => (&path[..end], path[end..].trim_start_matches('?')),
+            None => (path, ""),
+        };
+        let params = Params::from_urlencoded(query);
+        let (depth, max_nodes) = api::graph_limits(&params);
+        let page = |key: &str| {
+            params
+                .get(key)
+                .and_then(|p| p.parse::<u64>().ok())
+                .filter(|p| *p > 0)
+                .unwrap_or(1)
+        };
+        let options = EntityOptions {
+            depth,
+            max_nodes,
+            outgoing_page: page("outgoing_page"),
+            incoming_page: page("incoming_page"),
+        };
+        (entity, options)
+    }
+
+    /// URL of the entity page with these options, escaped for an HTML attribute.
+    fn link(&self, entity: &str, anchor: &str) -> String {
+        escape_html(
+            &format!(
+                "/entity/{}?depth={}&max_nodes={}&outgoing_page={}&incoming_page={}#{anchor}",
+                entity.replace('#', "%23"),
+                self.depth,
+                self.max_nodes,
+                self.outgoing_page,
+                self.incoming_page
+            )
+        )
+    }
+}
+
+/// Number of entities per page of `/explore`.
+const EXPLORE_PAGE_SIZE: u32 = 50;
+
+/// Number of results per page of `/search`.
+const SEARCH_PAGE_SIZE: usize = 30;
+
 impl WebServer {
-    pub fn new(kg: KG, port: u32) -> WebServer {
+    /// Creates the server.
+    ///
+    /// - With `read_only`, every operation modifying the store is rejected.
+    /// - With `auth`, every request must be authenticated and the user's role must allow the operation.
+    pub fn new(kg: KG, port: u32, workers: usize, read_only: bool, auth: Option<Auth>) -> WebServer {
         let kg = Arc::new(kg);
-        WebServer { dataset: kg, port }
+        security::set_read_only(read_only);
+        let jobs = Arc::new(JobManager::new());
+        WebServer {
+            search: Arc::new(SearchEngine::new(kg.clone(), jobs.clone())),
+            dataset: kg,
+            port,
+            workers: workers.max(1),
+            auth: auth.map(Arc::new),
+            jobs,
+        }
+    }
+
+    /// Creates a handler sharing the store, the users and the jobs of this server.
+    fn handler(&self) -> WebServer {
+        WebServer {
+            dataset: self.dataset.clone(),
+            port: 0,
+            workers: 0,
+            auth: self.auth.clone(),
+            jobs: self.jobs.clone(),
+            search: self.search.clone(),
+        }
     }
 
+    /// Serves the web UI until Ctrl-C is pressed.
+    ///
+    /// - Requests are handled by a fixed pool of `workers` threads; connections are kept alive by `tiny_http`.
+    /// - On Ctrl-C, the workers finish their current request, the jobs modifying the store finish their update (see
+    ///   `JobManager::shutdown`) and the store is flushed before returning.
     pub fn serve(&self) {
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", self.port)).unwrap();
-        println!("Listening on http://127.0.0.1:{}", self.port);
-
-        for stream in listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let dataset_clone = self.dataset.clone();
-                    thread::spawn(move || {
-                        let server = WebServer {
-                            dataset: dataset_clone,
-                            port: 0,
-                        };
-                        server.handle_connection(stream);
-                    });
-                }
-                Err(e) => {
-                    eprintln!("Failed to accept connection: {}", e);
+        let server = Arc::new(
+            Server::http(format!("127.0.0.1:{}", self.port)).expect("Failed to bind the server")
+        );
+        println!("Listening on http://127.0.0.1:{} with {} workers", self.port, self.workers);
+        // Starts building the search index
+        self.search.index();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let running_clone = running.clone();
+        ctrlc
+            ::set_handler(move || {
+                println!("Shutting down, waiting for running requests to finish...");
+                running_clone.store(false, Ordering::SeqCst);
+            })
+            .expect("Failed to set the Ctrl-C handler");
+
+        let handles = (0..self.workers)
+            .map(|_| {
+                let server = server.clone();
+                let running = running.clone();
+                let handler = self.handler();
+                thread::spawn(move || {
+                    while running.load(Ordering::SeqCst) {
+                        match server.recv_timeout(Duration::from_millis(200)) {
+                            Ok(Some(request)) => handler.handle_connection(request),
+                            Ok(None) => (),
+                            Err(e) => eprintln!("Failed to accept connection: {}", e),
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            let _ = handle.join();
+        }
+        self.jobs.shutdown();
+        self.dataset.flush();
+        println!("Store flushed, bye!");
+    }
+
+    fn handle_connection(&self, mut request: Request) {
+        let response = match HttpRequest::read(&mut request) {
+            Ok(req) => {
+                println!("{} {}", req.method, req.url);
+                match (self.authorize(&req), Self::job_events(&req)) {
+                    (Ok(_), Some(id)) if self.jobs.get(id).is_some() =>
+                        match sse::StreamSlot::acquire() {
+                            Some(slot) => {
+                                // The stream lasts as long as the job, it gets a thread of its own
+                                let jobs = self.jobs.clone();
+                                thread::spawn(move || {
+                                    sse::stream_job(request.into_writer(), jobs, id);
+                                    drop(slot);
+                                });
+                                return;
+                            }
+                            None =>
+                                HttpResponse::error(503, "Too many progress streams are open").with_header(
+                                    "Retry-After",
+                                    "5"
+                                ),
+                        }
+                    (Ok(_), Some(_)) => HttpResponse::error(404, "Job not found"),
+                    (Ok(user), None) => {
+                        store::set_acting_user(user);
+                        // A failing page must not take the worker down with it
+                        let response = panic
+                            ::catch_unwind(AssertUnwindSafe(|| self.route(&req)))
+                            .unwrap_or_else(|_| {
+                                HttpResponse::error(500, "The request could not be processed")
+                            });
+                        store::set_acting_user(None);
+                        response
+                    }
+                    (Err(response), _) => response,
                 }
             }
+            Err(RequestError::PayloadTooLarge) =>
+                HttpResponse::error(413, "The request body is too large"),
+            Err(RequestError::BadRequest(message)) => HttpResponse::error(400, &message),
+        };
+
+        if let Err(e) = request.respond(response.into_response()) {
+            eprintln!("Failed to send the response: {}", e);
         }
     }
 
-    fn handle_connection(&self, mut stream: TcpStream) {
-        let mut reader = BufReader::new(&mut stream);
-        let mut request = String::new();
-
-        loop {
-            let mut line = String::new();
-            let bytes_read = reader.read_line(&mut line).unwrap();
-            if bytes_read == 0 || line == "\r\n" {
-                break;
+    /// Checks the credentials of the request against the auth file, if any.
+    ///
+    /// Returns the name of the authenticated user (`None` when authentication is disabled).
+    ///
+    /// # Errors
+    /// - `401` with a `WWW-Authenticate` challenge if the credentials are missing or wrong.
+    /// - `403` if the role of the user does not allow the operation (see `required_role`).
+    fn authorize(&self, req: &HttpRequest) -> Result<Option<String>, HttpResponse> {
+        let Some(auth) = &self.auth else {
+            return Ok(None);
+        };
+        // API clients get their errors as JSON
+        let deny = |status: u16, message: &str| {
+            if req.path.starts_with(API_PREFIX) {
+                api::error(status, message)
+            } else {
+                HttpResponse::error(status, message)
             }
-            request.push_str(&line);
+        };
+        let Some(user) = auth.authenticate(req) else {
+            return Err(
+                deny(401, "Authentication required").with_header(
+                    "WWW-Authenticate",
+                    r#"Basic realm="kg-explorer", charset="UTF-8""#
+                )
+            );
+        };
+        let required = Self::required_role(req);
+        if user.role < required {
+            return Err(
+                deny(
+                    403,
+                    &format!("{} is a {}, this operation requires the {} role", user.name, user.role, required)
+                )
+            );
         }
+        Ok(Some(user.name))
+    }
 
-        let first_line = request.lines().next().unwrap_or("");
+    /// Role needed to perform the request.
+    ///
+    /// - Reverting the store and replaying history files require `Admin`.
+    /// - Any other mutation, from the UI or the JSON API, requires `Curator`.
+    /// - Everything else only requires `Viewer`.
+    fn required_role(req: &HttpRequest) -> Role {
+        if req.path.starts_with(API_PREFIX) {
+            return if req.method == Method::Post { Role::Curator } else { Role::Viewer };
+        }
+        match req.path.as_str() {
+            "/replay_history" => Role::Admin,
+            route if route.starts_with("/restore/") => Role::Admin,
+            _ if Self::is_mutation(req) => Role::Curator,
+            _ => Role::Viewer,
+        }
+    }
 
-        let full_path = first_line.split_whitespace().nth(1).unwrap_or("/");
+    /// ID of the job whose progress is requested, for `GET /jobs/{id}/events`.
+    fn job_events(req: &HttpRequest) -> Option<u64> {
+        if req.method != Method::Get {
+            return None;
+        }
+        req.path.strip_prefix("/jobs/")?.strip_suffix("/events")?.parse::<u64>().ok()
+    }
 
-        let (route, query_string) = match full_path.split_once('?') {
-            Some((r, q)) => (r, Some(q)),
-            _ => (full_path, None),
-        };
-        println!("{}", first_line);
+    /// Runs `work` on a background job attributed to the acting user, and returns the ID of the job.
+    ///
+    /// - `work` gets a handler of its own and returns the HTML page of the result.
+    /// - Requests that do not modify the store share their job with the identical requests still queued or running
+    ///   (same route and parameters), so that reloading a page does not start the same analysis again.
+    fn spawn_job<F>(&self, req: &HttpRequest, title: String, work: F) -> u64
+        where F: FnOnce(&WebServer) -> Result<String, String> + Send + 'static
+    {
+        let key = (!Self::is_mutation(req)).then(|| {
+            let mut params = req.params
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>();
+            params.sort();
+            format!("{}?{}", req.path, params.join("&"))
+        });
+        let handler = self.handler();
+        self.jobs.spawn(title, key, store::acting_user(), move || work(&handler))
+    }
+
+    fn route(&self, req: &HttpRequest) -> HttpResponse {
+        if !matches!(req.method, Method::Get | Method::Head | Method::Post) {
+            return HttpResponse::error(405, "Only GET and POST are supported").with_header(
+                "Allow",
+                "GET, HEAD, POST"
+            );
+        }
+        if req.path.starts_with(API_PREFIX) {
+            return api::route(&self.dataset, &self.jobs, &self.search, self.auth.as_deref(), req);
+        }
+        if Self::is_mutation(req) {
+            if req.method != Method::Post {
+                return HttpResponse::error(405, "This operation must be sent with POST").with_header(
+                    "Allow",
+                    "POST"
+                );
+            }
+            if security::is_read_only() {
+                return HttpResponse::error(403, "The explorer is served in read-only mode");
+            }
+            if !security::check_csrf(req, self.auth.as_deref()) {
+                return HttpResponse::error(403, "Invalid or missing CSRF token");
+            }
+        }
+        let params = &req.params;
 
-        let (status_line, page) = match route {
-            "/" => ("HTTP/1.1 200 OK", Page::Index),
+        let (status, page) = match req.path.as_str() {
+            "/" => (200, Page::Index),
             "/query" =>
-                match query_string {
-                    Some(qs) => {
-                        (
-                            "HTTP/1.1 200 OK",
-                            Page::Query(
-                                extract_query_param(qs, "query"),
-                                extract_query_param(qs, "mode"),
-                                extract_query_param(qs, "secondary")
-                            ),
-                        )
+                match (params.get("query"), params.get("mode"), params.get("secondary")) {
+                    // Iterative updates run one update per row, which can take a while
+                    (Some(q), Some(mode), sq) if mode == "advanced" && !q.is_empty() => {
+                        let id = self.spawn_job(req, "Advanced update".to_string(), move |server| {
+                            Ok(server.generate_query(&q, &mode, sq, false))
+                        });
+                        (200, Page::Job(id))
                     }
-                    None => ("HTTP/1.1 200 OK", Page::Query(None, None, None)),
+                    (q, mode, sq) => (200, Page::Query(q, mode, sq, api::include_inferred(&self.dataset, params))),
                 }
             "/explore" =>
-                match query_string {
-                    Some(qs) =>
-                        match extract_query_param(qs, "id") {
-                            Some(id) => {
-                                let page: u32 = match
-                                    extract_query_param(qs, "page")
-                                        .unwrap_or("1".to_string())
-                                        .parse()
-                                {
-                                    Ok(num) => num,
-                                    Err(_) => 1,
-                                };
-                                ("HTTP/1.1 200 OK", Page::Explore(id, page))
-                            }
-                            None => ("HTTP/1.1 400 BAD REQUEST", Page::Error),
+                match params.get("id") {
+                    Some(id) => {
+                        let page = params
+                            .get("page")
+                            .and_then(|p| p.parse::<u32>().ok())
+                            .filter(|p| *p > 0)
+                            .unwrap_or(1);
+                        let sort = SortOrder::parse(&params.get("sort").unwrap_or_default());
+                        match
+                            ExploreFilters::parse(
+                                &id,
+                                params.get_all("has"),
+                                params.get_all("value"),
+                                params.get("datatype"),
+                                params.get("lang")
+                            )
+                        {
+                            Ok(filters) => (200, Page::Explore(filters, sort, page)),
+                            Err(e) => (400, Page::Error(e)),
                         }
-                    None => ("HTTP/1.1 400 BAD REQUEST", Page::Error),
+                    }
+                    None => (400, Page::Error("Missing parameter: id".to_string())),
                 }
 
+            "/profile" =>
+                match params.get("class").map(|class| explore::parse_iri(&class)) {
+                    Some(Ok(class)) => {
+                        let id = self.spawn_job(req, format!("Profile of {class}"), move |server| {
+                            server.dataset
+                                .class_profile(&class)
+                                .map(|profile| class_profile_page(&profile))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Some(Err(e)) => (400, Page::Error(e)),
+                    None => (400, Page::Error("Missing parameter: class".to_string())),
+                }
+            "/flatten" =>
+                match params.get("class").map(|class| explore::parse_iri(&class)) {
+                    Some(Ok(class)) => {
+                        let id = self.spawn_job(req, format!("Paths to flatten {class}"), move |server| {
+                            let analysed = server.dataset.cached_predicate_analysis(&class).is_some();
+                            server.dataset
+                                .suggested_flatten_paths(&class)
+                                .map(|paths| flatten_page(&class, &paths, analysed))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Some(Err(e)) => (400, Page::Error(e)),
+                    None => (400, Page::Error("Missing parameter: class".to_string())),
+                }
             route if route.starts_with("/entity/") => {
-                let fp = url_decode(full_path);
-                let entity_name = &fp["/entity/".len()..];
+                // The raw url is used since IRIs may contain a '?'
+                let fp = url_decode(&req.url);
+                let (entity_name, options) = EntityOptions::split(&fp["/entity/".len()..]);
 
-                (
-                    "HTTP/1.1 200 OK",
-                    Page::Entity(entity_name.to_string().replace("%3C", "<").replace("%3E", ">")),
-                )
+                (200, Page::Entity(entity_name.to_string(), options))
             }
             "/routines" => {
-                if let Some(qs) = query_string {
-                    if qs.starts_with("entity") {
-                        let ent = extract_query_param(qs, "entity").unwrap();
-                        let mergeby_param = extract_query_param(qs, "mergeby").unwrap();
-                        let mergeby: Vec<String> = mergeby_param
-                            .split(',')
-                            .map(|s| s.trim().to_string())
-                            .collect();
-                        match self.dataset.merge_entities(ent, mergeby) {
-                            Ok(()) => ("HTTP/1.1 200 OK", Page::Scripts),
-                            Err(_) => ("HTTP/1.1 400 ERROR", Page::Error),
+                if let Some(ent) = params.get("entity") {
+                    match params.get("mergeby") {
+                        Some(mergeby_param) => {
+                            let mergeby: Vec<String> = mergeby_param
+                                .split(',')
+                                .map(|s| s.trim().to_string())
+                                .collect();
+                            let title = format!("Merge {ent} by {mergeby_param}");
+                            let id = self.spawn_job(req, title, move |server| {
+                                server.dataset
+                                    .merge_entities(ent, mergeby)
+                                    .map(|_| server.generate_scripts())
+                                    .map_err(|e| e.to_string())
+                            });
+                            (200, Page::Job(id))
                         }
-                    } else {
-                        let queries = Self::parse_procedures(qs);
-
-                        ("HTTP/1.1 200 OK", Page::Run(queries))
+                        None => (400, Page::Error("Missing parameter: mergeby".to_string())),
                     }
                 } else {
-                    ("HTTP/1.1 200 OK", Page::Scripts)
+                    let queries = Self::parse_procedures(params);
+                    if queries.is_empty() {
+                        (200, Page::Scripts)
+                    } else {
+                        let title = format!("Run {}", queries.join(", "));
+                        let id = self.spawn_job(req, title, move |server| {
+                            Ok(server.generate_run_results(queries))
+                        });
+                        (200, Page::Job(id))
+                    }
                 }
             }
             "/dump" => {
                 self.dataset.dump_store();
-                ("HTTP/1.1 200 OK", Page::Redirect)
+                (200, Page::Redirect)
             }
             "/delete_predicate" => {
-                match query_string {
-                    Some(qs) => {
-                        let otype = extract_query_param(qs, "otype").unwrap();
-                        let pred = extract_query_param(qs, "pred").unwrap();
-
+                match (params.get("otype"), params.get("pred")) {
+                    (Some(otype), Some(pred)) => {
                         self.dataset.delete_predicate(&otype, &pred);
-                        ("HTTP/1.1 200 OK", Page::Redirect)
+                        (200, Page::Redirect)
                     }
-                    None => ("HTTP/1.1 400 BAD REQUEST", Page::Error),
+                    _ => (400, Page::Error("Missing parameters: otype and pred".to_string())),
                 }
             }
-            "/history" => { ("HTTP/1.1 200 OK", Page::History) }
+            "/history" => (200, Page::History),
             route if route.starts_with("/restore/") => {
-                let v = route
-                    .replace("/restore/version_", "")
-                    .replace(".nt", "")
-                    .parse::<u32>()
-                    .unwrap();
-                self.dataset.revert(v);
-                ("HTTP/1.1 200 OK", Page::Redirect)
+                match
+                    route
+                        .replace("/restore/version_", "")
+                        .replace(".nt", "")
+                        .parse::<u32>()
+                {
+                    Ok(v) => {
+                        let id = self.spawn_job(req, format!("Revert to version {v}"), move |server| {
+                            server.dataset.revert(v);
+                            Ok(include_str!("../../templates/redirect.html").to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Err(_) => (400, Page::Error(format!("Invalid version: {route}"))),
+                }
             }
             "/replay_history" => {
-                let mut content_length = 0;
-
-                for header in request.lines() {
-                    if let Some(cl) = header.strip_prefix("Content-Length:") {
-                        content_length = cl.trim().parse::<usize>().unwrap_or(0);
+                let history = req.body.clone();
+                let id = self.spawn_job(req, "Replay history file".to_string(), move |server| {
+                    match server.dataset.execute(history) {
+                        Ok(()) => Ok(include_str!("../../templates/redirect.html").to_string()),
+                        Err((e, _)) => {
+                            eprintln!("Error during replay_history");
+                            Err(e.to_string())
+                        }
+                    }
+                });
+                (200, Page::Job(id))
+            }
+            "/analysis" => {
+                match params.get("start_with") {
+                    Some(start_with) => {
+                        let (page, name) = match params.get("page").unwrap_or_default().as_str() {
+                            "graph" => (1, "Class relations graph"),
+                            "classes" => (2, "Class analysis"),
+                            "predicates" => (3, "Predicate analysis"),
+                            _ => (0, "Analysis"),
+                        };
+                        let options = match api::page_rank_options(params) {
+                            Ok(options) => options,
+                            Err(e) => {
+                                return HttpResponse::error(400, &e);
+                            }
+                        };
+                        let title = format!("{name} from {start_with}");
+                        let id = self.spawn_job(req, title, move |server| {
+                            Ok(server.generate_analytics(page, &start_with, &options))
+                        });
+                        (200, Page::Job(id))
                     }
+                    None => (400, Page::Error("Missing parameter: start_with".to_string())),
                 }
-                let mut body_buf = vec![0; content_length];
-                reader.read_exact(&mut body_buf).unwrap();
-
-                let payload = String::from_utf8(body_buf).unwrap();
-                if let Err(_) = self.dataset.execute(payload) {
-                    eprintln!("Error during replay_history");
+            }
+            "/analysis/classes/apply" =>
+                match api::class_plan(params) {
+                    Ok(plan) => {
+                        let title = format!(
+                            "Apply the class plan from {} ({} kept, {} dropped)",
+                            plan.start_with,
+                            plan.keep.len(),
+                            plan.drop.len()
+                        );
+                        let id = self.spawn_job(req, title, move |server| {
+                            server.dataset
+                                .apply_class_plan(&plan)
+                                .map(|_| include_str!("../../templates/redirect.html").to_string())
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Err(e) => (400, Page::Error(e)),
                 }
-                ("HTTP/1.1 200 OK", Page::Redirect)
+            "/analysis/labels" => {
+                return match api::label_predicate(&self.dataset, params) {
+                    Ok(label) => HttpResponse::json(200, &label),
+                    Err(e) => HttpResponse::json(400, &e),
+                };
             }
-            "/analysis" => {
-                match query_string {
-                    Some(qs) => {
-                        (
-                            "HTTP/1.1 200 OK",
-                            Page::ClassRelations(
-                                match
-                                    extract_query_param(qs, "page")
-                                        .unwrap_or("".to_string())
-                                        .as_str()
-                                {
-                                    "graph" => 1,
-                                    "classes" => 2,
-                                    "predicates" => 3,
-                                    _ => 0,
-                                },
-                                extract_query_param(qs, "start_with").unwrap().to_string()
-                            ),
+            "/predicate_plans" => (200, Page::PredicatePlans),
+            "/predicate_plans/preview" | "/predicate_plans/apply" =>
+                match api::plan_pairs(params) {
+                    Ok(pairs) if req.path == "/predicate_plans/preview" => {
+                        let title = format!("Impact of a predicate deletion plan ({} pairs)", pairs.len());
+                        let id = self.spawn_job(req, title, move |server| {
+                            server.dataset
+                                .predicate_plan_impact(&pairs)
+                                .map(|impact| predicate_plan_page(&pairs, &impact))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Ok(pairs) => {
+                        let archive = params.get("archive").is_some_and(|v| v == "1" || v == "true");
+                        let title = format!("Apply a predicate deletion plan ({} pairs)", pairs.len());
+                        let id = self.spawn_job(req, title, move |server| {
+                            server.dataset
+                                .apply_predicate_plan(&pairs, archive)
+                                .map(|_| predicate_plans_page(&server.dataset.predicate_plans()))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    Err(e) => (400, Page::Error(e)),
+                }
+            "/predicate_plans/undo" =>
+                match params.get("id").and_then(|id| id.parse::<u32>().ok()) {
+                    Some(plan) => {
+                        let id = self.spawn_job(req, format!("Undo predicate deletion plan {plan}"), move |server| {
+                            server.dataset
+                                .undo_predicate_plan(plan)
+                                .map(|_| predicate_plans_page(&server.dataset.predicate_plans()))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    None => (400, Page::Error("Missing or invalid parameter: id".to_string())),
+                }
+            "/analysis/conformance" =>
+                match vocabulary::get() {
+                    Some(vocabulary) => {
+                        let id = self.spawn_job(req, "Domain/range conformance".to_string(), move |server| {
+                            Ok(conformance_page(&server.dataset.conformance_report(vocabulary)))
+                        });
+                        (200, Page::Job(id))
+                    }
+                    None => (400, Page::Error("No schema.org vocabulary is loaded".to_string())),
+                }
+            "/analysis/shapes" =>
+                match params.get("start_with") {
+                    Some(start_with) => {
+                        let options = api::shape_inference_options(params);
+                        let id = self.spawn_job(req, format!("SHACL shapes from {start_with}"), move |server| {
+                            let shapes = server.dataset.infer_shapes(&start_with, &options);
+                            let turtle = shape_inference::shapes_to_turtle(&shapes, &options)?;
+                            Ok(shapes_page(&start_with, &options, &shapes, &turtle))
+                        });
+                        (200, Page::Job(id))
+                    }
+                    None => (400, Page::Error("Missing parameter: start_with".to_string())),
+                }
+            "/analysis/conformance/fix" => {
+                let fix = params.get("fix").and_then(|f| ConformanceFix::parse(&f));
+                match (vocabulary::get(), params.get("class"), params.get("predicate"), params.get("range"), fix) {
+                    (None, ..) => (400, Page::Error("No schema.org vocabulary is loaded".to_string())),
+                    (Some(vocabulary), Some(class), Some(predicate), Some(range), Some(fix)) => {
+                        let title = format!("{} {range} objects of {predicate} for {class}", fix.describe());
+                        let id = self.spawn_job(req, title, move |server| {
+                            server.dataset
+                                .apply_conformance_fix(vocabulary, &class, &predicate, &range, &fix)
+                                .map(|_| conformance_page(&server.dataset.conformance_report(vocabulary)))
+                                .map_err(|e| e.to_string())
+                        });
+                        (200, Page::Job(id))
+                    }
+                    _ => (400, Page::Error("Missing parameters: class, predicate, range and fix".to_string())),
+                }
+            }
+            "/.well-known/void" => {
+                return api::void_response(&self.dataset, req);
+            }
+            "/search" => (200, Page::Search),
+            "/search/suggest" => {
+                return HttpResponse::json(200, &self.suggest(&params.get("q").unwrap_or_default()));
+            }
+            "/jobs" => (200, Page::Jobs),
+            "/vocabulary" => (200, Page::Vocabulary),
+            "/shacl" => {
+                let files = params.get_all("file");
+                let graph = params.get("graph").unwrap_or_default().trim().to_string();
+                if params.get("run").is_some() {
+                    let id = self.spawn_job(req, "SHACL validation".to_string(), move |server| {
+                        Ok(server.generate_shacl(&files, &graph))
+                    });
+                    (200, Page::Job(id))
+                } else {
+                    (200, Page::Shacl(files, graph))
+                }
+            }
+            "/vocabulary/canonicalize" =>
+                match vocabulary::get() {
+                    Some(vocabulary) => {
+                        let id = self.spawn_job(
+                            req,
+                            "Fix the casing of the schema.org terms".to_string(),
+                            move |server| {
+                                server.dataset
+                                    .canonicalize_schema_terms(vocabulary)
+                                    .map(|_| {
+                                        vocabulary_page(
+                                            Some(&server.dataset.vocabulary_report(vocabulary)),
+                                            &server.dataset.inference_status(),
+                                            None
+                                        )
+                                    })
+                                    .map_err(|e| e.to_string())
+                            }
+                        );
+                        (200, Page::Job(id))
+                    }
+                    None => (400, Page::Error("No schema.org vocabulary is loaded".to_string())),
+                }
+            "/vocabulary/inference" => {
+                let action = params.get("action").unwrap_or_default();
+                let title = match action.as_str() {
+                    "enable" => "Enable inference",
+                    "refresh" => "Refresh the inferred triples",
+                    "disable" => "Disable inference",
+                    _ => {
+                        return HttpResponse::error(400, &format!("Unknown action: {action}"));
+                    }
+                };
+                let id = self.spawn_job(req, title.to_string(), move |server| {
+                    let refreshed = (
+                        match action.as_str() {
+                            "enable" => server.dataset.enable_inference().map(Some),
+                            "refresh" => server.dataset.refresh_inferences().map(Some),
+                            _ => server.dataset.disable_inference().map(|()| None),
+                        }
+                    ).map_err(|e| e.to_string())?;
+                    Ok(
+                        vocabulary_page(
+                            vocabulary::get()
+                                .map(|v| server.dataset.vocabulary_report(v))
+                                .as_ref(),
+                            &server.dataset.inference_status(),
+                            refreshed.as_ref()
                         )
+                    )
+                });
+                (200, Page::Job(id))
+            }
+            route if route.starts_with("/jobs/") => {
+                let rest = &route["/jobs/".len()..];
+                let (id, result) = match rest.strip_suffix("/result") {
+                    Some(id) => (id, true),
+                    None => (rest, false),
+                };
+                match id.parse::<u64>().ok().and_then(|id| self.jobs.get(id)) {
+                    Some(job) if result => {
+                        return match (job.status, self.jobs.result(job.id)) {
+                            (JobStatus::Succeeded, Some(html)) => HttpResponse::html(200, html),
+                            (JobStatus::Failed, _) =>
+                                HttpResponse::error(500, &job.error.unwrap_or_default()),
+                            _ => HttpResponse::redirect(&format!("/jobs/{}", job.id)),
+                        };
                     }
-                    None => ("HTTP/1.1 400 BAD REQUEST", Page::Error),
+                    Some(job) => (200, Page::JobDetails(job.id)),
+                    None => (404, Page::Error("Job not found".to_string())),
                 }
             }
-            _ => ("HTTP/1.1 404 NOT FOUND", Page::Error),
+            _ => (404, Page::Error("Page Not Found".to_string())),
         };
 
         let contents: String = match page {
             Page::Index => self.generate_index(),
-            Page::Explore(id, page) => self.generate_explore(&id, page),
-            Page::Query(Some(q), Some(mode), sq) => self.generate_query(&q, &mode, sq),
-            Page::Query(None, _, _) => self.generate_query("", "query", None),
-            Page::Query(Some(q), None, _) => self.generate_query(&q, "query", None),
-            Page::Entity(uri) => self.generate_entity(&uri),
+            Page::Explore(filters, sort, page) => self.generate_explore(&filters, sort, page),
+            Page::Query(Some(q), Some(mode), sq, inferred) => self.generate_query(&q, &mode, sq, inferred),
+            Page::Query(None, _, _, inferred) => self.generate_query("", "query", None, inferred),
+            Page::Query(Some(q), None, _, inferred) => self.generate_query(&q, "query", None, inferred),
+            Page::Entity(uri, options) => self.generate_entity(&uri, &options),
             Page::Scripts => self.generate_scripts(),
-            Page::Run(scripts) => self.generate_run_results(scripts),
-            Page::Error => "<html><body><h1>404 - Page Not Found</h1></body></html>".to_string(),
-            Page::Redirect => include_str!("../../templates/redirect.html").to_string(),
+            Page::Error(message) => {
+                return HttpResponse::error(status, &message);
+            }
+            Page::Redirect => {
+                return HttpResponse::redirect("/");
+            }
             Page::History => self.generate_history(),
-            Page::ClassRelations(page, uri) => self.generate_analytics(page, &uri),
+            Page::Search => self.generate_search(params),
+            Page::Jobs => jobs_page(&self.jobs.list()),
+            Page::PredicatePlans => predicate_plans_page(&self.dataset.predicate_plans()),
+            Page::Vocabulary =>
+                vocabulary_page(
+                    vocabulary::get().map(|v| self.dataset.vocabulary_report(v)).as_ref(),
+                    &self.dataset.inference_status(),
+                    None
+                ),
+            Page::Shacl(files, graph) => shacl_page(&shacl::list_shape_files(), &files, &graph, None),
+            Page::JobDetails(id) =>
+                match self.jobs.get(id) {
+                    Some(job) => job_page(&job),
+                    None => {
+                        return HttpResponse::error(404, "Job not found");
+                    }
+                }
+            Page::Job(id) => {
+                return HttpResponse::redirect(&format!("/jobs/{id}"));
+            }
         };
 
-        let response = format!(
-            "{status_line}\r\nContent-Type: text/html; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-            contents.len(),
-            contents
-        );
-
-        let _ = stream.write_all(response.as_bytes());
+        HttpResponse::html(status, contents)
     }
 
     fn generate_index(&self) -> String {
-        let q =
-            r#"
-SELECT ?t (COUNT(?s) AS ?count)
-WHERE {
-    ?s a ?t .
-}
-GROUP BY ?t
-ORDER BY DESC(?count)
-"#;
-
-        let mut class_counts = vec![];
-
-        let res = self.dataset.query(q);
-        match res {
-            Ok(result) => {
-                for r in result {
-                    let class = r.get("t").unwrap().to_string();
-                    let cnt = match r.get("count").unwrap() {
-                        Literal(literal) => literal.value().parse::<u32>().unwrap(),
-                        _ => 0,
-                    };
-                    class_counts.push((class, cnt));
-                }
-            }
-            Err(_) => panic!("SPARQL query failed"),
-        }
-
-        index_page(&self.dataset.get_name(), &class_counts)
+        index_page(&self.dataset.get_name(), &self.dataset.class_counts())
     }
 
-    fn generate_explore(&self, id: &str, page_num: u32) -> String {
-        let objs = self.dataset.get_objects(
-            &id.replace("%3C", "<").replace("%3E", ">"),
-            50,
-            (page_num - 1) * 50
-        );
-        let data = objs
+    fn generate_explore(&self, filters: &ExploreFilters, sort: SortOrder, page_num: u32) -> String {
+        let facets = self.dataset.explore_facets(filters);
+        let pages = (facets.total.div_ceil(EXPLORE_PAGE_SIZE as u64) as u32).max(1);
+        let page_num = page_num.min(pages);
+
+        let data = self.dataset
+            .explore_entities(filters, sort, EXPLORE_PAGE_SIZE, (page_num - 1) * EXPLORE_PAGE_SIZE)
             .iter()
-            .map(|o| self.dataset.get_details(&o.to_string()).html_rep())
+            .map(|o| self.dataset.get_details(o).html_rep())
             .collect::<Vec<String>>()
             .join("");
 
-        explore_page(id, page_num, &data)
+        let page_link = |page: u32| Self::explore_link(filters, sort, page);
+        let hidden_inputs = Self::hidden_inputs(&Self::explore_params(filters, sort));
+        let toolbar = Self::explore_toolbar(filters, sort, facets.total);
+        let facets_html = Self::explore_facets_html(filters, sort, &facets);
+
+        explore_page(page_num, pages, &page_link, &hidden_inputs, &toolbar, &facets_html, &data)
     }
 
-    fn generate_query(&self, q: &str, mode: &str, sq: Option<String>) -> String {
+    /// Query parameters carrying the filters (and the sort order, unless `Default`).
+    fn explore_params(filters: &ExploreFilters, sort: SortOrder) -> Vec<(String, String)> {
+        let mut params = vec![("id".to_string(), filters.class.clone())];
+        for p in &filters.has {
+            params.push(("has".to_string(), p.clone()));
+        }
+        for (p, o) in &filters.values {
+            params.push(("value".to_string(), format!("{p} {o}")));
+        }
+        if let Some(datatype) = &filters.datatype {
+            params.push(("datatype".to_string(), datatype.clone()));
+        }
+        if let Some(lang) = &filters.lang {
+            params.push(("lang".to_string(), lang.clone()));
+        }
+        if sort != SortOrder::Default {
+            params.push(("sort".to_string(), sort.as_str().to_string()));
+        }
+        params
+    }
+
+    fn hidden_inputs(params: &[(String, String)]) -> String {
+        params
+            .iter()
+            .map(|(k, v)| format!(r#"<input type="hidden" name="{k}" value="{}" />"#, escape_html(v)))
+            .collect()
+    }
+
+    /// URL of a page of the explore page, escaped for an HTML attribute.
+    fn explore_link(filters: &ExploreFilters, sort: SortOrder, page: u32) -> String {
+        let mut query = url::form_urlencoded::Serializer::new(String::new());
+        for (k, v) in Self::explore_params(filters, sort) {
+            query.append_pair(&k, &v);
+        }
+        query.append_pair("page", &page.to_string());
+        escape_html(&format!("/explore?{}", query.finish()))
+    }
+
+    /// Total count, active filters and sort order.
+    fn explore_toolbar(filters: &ExploreFilters, sort: SortOrder, total: u64) -> String {
+        let mut active = String::new();
+        let mut remove = |label: String, without: ExploreFilters| {
+            active += &format!(
+                r#"<a class="badge bg-info text-dark text-decoration-none me-1" href="{}">{} ✕</a>"#,
+                Self::explore_link(&without, sort, 1),
+                escape_html(&label)
+            );
+        };
+        for p in &filters.has {
+            let mut without = filters.clone();
+            without.has.retain(|h| h != p);
+            remove(format!("has {}", short_name(p)), without);
+        }
+        for value in &filters.values {
+            let mut without = filters.clone();
+            without.values.retain(|v| v != value);
+            remove(format!("{} = {}", short_name(&value.0), value.1), without);
+        }
+        if let Some(datatype) = &filters.datatype {
+            remove(format!("datatype {}", short_name(datatype)), ExploreFilters { datatype: None, ..filters.clone() });
+        }
+        if let Some(lang) = &filters.lang {
+            remove(format!("language {lang}"), ExploreFilters { lang: None, ..filters.clone() });
+        }
+        if filters.is_filtered() {
+            let all = ExploreFilters { class: filters.class.clone(), ..Default::default() };
+            active += &format!(r#"<a class="small ms-2" href="{}">Clear all</a>"#, Self::explore_link(&all, sort, 1));
+        }
+
+        let options = [
+            (SortOrder::Default, "Default order"),
+            (SortOrder::Name, "Name"),
+            (SortOrder::Triples, "Number of triples"),
+        ]
+            .iter()
+            .map(|(order, label)| {
+                format!(
+                    r#"<option value="{}"{}>{label}</option>"#,
+                    order.as_str(),
+                    if *order == sort { " selected" } else { "" }
+                )
+            })
+            .collect::<String>();
+        let hidden_inputs = Self::hidden_inputs(&Self::explore_params(filters, SortOrder::Default));
+
+        format!(
+            r#"<div class="d-flex justify-content-between align-items-center flex-wrap gap-2">
+          <div>
+            <h4 class="mb-1">{} <span class="text-muted fs-6">{total} entities</span></h4>
+            <div>{active}</div>
+          </div>
+          <form method="get" action="/explore" class="d-flex align-items-center gap-2">
+            {hidden_inputs}
+            <label for="sort" class="text-nowrap">Sort by</label>
+            <select id="sort" name="sort" class="form-select form-select-sm" onchange="this.form.submit()">{options}</select>
+          </form>
+        </div>"#,
+            escape_html(&short_name(&filters.class))
+        )
+    }
+
+    /// Sidebar of the explore page: predicate presence, top values, datatypes and languages.
+    fn explore_facets_html(filters: &ExploreFilters, sort: SortOrder, facets: &Facets) -> String {
+        let item = |label: &str, count: u64, active: bool, link: String| {
+            format!(
+                r#"<a class="list-group-item list-group-item-action d-flex justify-content-between align-items-center{}" href="{link}">
+              <span class="text-truncate" title="{label}">{label}</span><span class="badge bg-secondary rounded-pill">{count}</span>
+            </a>"#,
+                if active { " active" } else { "" },
+                label = escape_html(&label.to_string())
+            )
+        };
+        let card = |title: &str, items: String| {
+            if items.is_empty() {
+                return String::new();
+            }
+            format!(
+                r#"<div class="card mb-3"><div class="card-header">{title}</div>
+            <div class="list-group list-group-flush" style="max-height: 320px; overflow-y: auto">{items}</div></div>"#
+            )
+        };
+
+        let mut html = String::new();
+        let predicates = facets.predicates
+            .iter()
+            .filter(|f| f.predicate != "<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>")
+            .map(|f| {
+                let active = filters.has.contains(&f.predicate);
+                let mut toggled = filters.clone();
+                if active {
+                    toggled.has.retain(|h| h != &f.predicate);
+                } else {
+                    toggled.has.push(f.predicate.clone());
+                }
+                item(&short_name(&f.predicate), f.count, active, Self::explore_link(&toggled, sort, 1))
+            })
+            .collect::<String>();
+        html += &card("Has predicate", predicates);
+
+        for facet in facets.predicates.iter().filter(|f| !f.top_values.is_empty()) {
+            let values = facet.top_values
+                .iter()
+                .map(|v| {
+                    let pair = (facet.predicate.clone(), v.value.clone());
+                    let active = filters.values.contains(&pair);
+                    let mut toggled = filters.clone();
+                    if active {
+                        toggled.values.retain(|p| p != &pair);
+                    } else {
+                        toggled.values.push(pair);
+                    }
+                    item(&short_name(&v.value), v.count, active, Self::explore_link(&toggled, sort, 1))
+                })
+                .collect::<String>();
+            html += &card(&escape_html(&short_name(&facet.predicate)), values);
+        }
+
+        let datatypes = facets.datatypes
+            .iter()
+            .map(|v| {
+                let active = filters.datatype.as_ref() == Some(&v.value);
+                let toggled = ExploreFilters {
+                    datatype: if active { None } else { Some(v.value.clone()) },
+                    ..filters.clone()
+                };
+                item(&short_name(&v.value), v.count, active, Self::explore_link(&toggled, sort, 1))
+            })
+            .collect::<String>();
+        html += &card("Literal datatype", datatypes);
+
+        let languages = facets.languages
+            .iter()
+            .map(|v| {
+                let active = filters.lang.as_ref() == Some(&v.value);
+                let toggled = ExploreFilters {
+                    lang: if active { None } else { Some(v.value.clone()) },
+                    ..filters.clone()
+                };
+                item(&v.value, v.count, active, Self::explore_link(&toggled, sort, 1))
+            })
+            .collect::<String>();
+        html += &card("Literal language", languages);
+        html
+    }
+
+    fn generate_query(&self, q: &str, mode: &str, sq: Option<String>, inferred: bool) -> String {
         let mut table_data = vec![];
         let mut headers = vec![];
         let mut message = "Query successfully executed".to_string();
@@ -317,7 +1021,7 @@ ORDER BY DESC(?count)
         if !q.is_empty() {
             match mode {
                 "query" => {
-                    let query_result = self.dataset.query(q);
+                    let query_result = self.dataset.query_with_inferred(q, inferred);
                     match query_result {
                         Ok(res) => {
                             if !res.is_empty() {
@@ -423,58 +1127,36 @@ ORDER BY DESC(?count)
                 message
             )
         };
-        query_page(result_rows, &table_rows_js_array, &table_headers_js_array, message_box)
+        query_page(
+            result_rows,
+            &table_rows_js_array,
+            &table_headers_js_array,
+            message_box,
+            self.dataset.inference_enabled().then_some(inferred)
+        )
     }
 
-    fn generate_entity(&self, entity: &str) -> String {
+    fn generate_entity(&self, entity: &str, options: &EntityOptions) -> String {
         let itm = self.dataset.get_details(entity);
-        //Table 1 generation
-        let table_1_query = format!(
-            r#"
-      SELECT ?pred ?obj WHERE {{
-        {entity} ?pred ?obj .
-      }}
-      "#
-        );
-        let table_1_data = self.dataset.query(&table_1_query).unwrap_or(vec![]);
-        let mut table_1 = String::new();
-        for row in table_1_data {
-            table_1 += &format!(
-                "<tr>
-          <td>{}</td>
-          <td>{}</td>
-        </tr>",
-                escape_html(&row.get("pred").unwrap().to_string()),
-                to_link(escape_html(&row.get("obj").unwrap().to_string()))
-            );
-        }
-        //Table 2 generation
-        let table_2_query = format!(
-            r#"
-      SELECT ?sub ?pred WHERE {{
-        ?sub ?pred {entity} .
-      }}
-      "#
-        );
-        let table_2_data = self.dataset.query(&table_2_query).unwrap_or(vec![]);
-        let mut table_2 = String::new();
-        for row in table_2_data {
-            table_2 += &format!(
-                "<tr>
-          <td>{}</td>
-          <td>{}</td>
-        </tr>",
-                to_link(escape_html(&row.get("sub").unwrap().to_string())),
-                escape_html(&row.get("pred").unwrap().to_string())
-            );
-        }
+        let outgoing = self.triples_table(entity, TripleDirection::Outgoing, options);
+        let incoming = self.triples_table(entity, TripleDirection::Incoming, options);
 
         let mut entity_types = String::new();
         for tp in itm.entity_types {
+            let ancestors = vocabulary::get()
+                .map(|v| {
+                    v.ancestors(&tp.to_string())
+                        .iter()
+                        .map(|a| a.name.clone())
+                        .collect::<Vec<_>>()
+                })
+                .filter(|a| !a.is_empty())
+                .map(|a| format!(r#" <small class="text-muted">({})</small>"#, escape_html(&a.join(" → "))))
+                .unwrap_or_default();
             entity_types += &format!(
-                "<a href=\"/explore?id={}\">{}</a>",
+                "<a href=\"/explore?id={}\">{}</a>{ancestors} ",
                 tp,
-                escape_html(&tp.to_string())
+                vocabulary_label(&tp.to_string())
             );
         }
 
@@ -492,194 +1174,169 @@ ORDER BY DESC(?count)
             )
         };
 
-        let mut seen: HashSet<String> = HashSet::new();
-        let mut items = vec![entity.to_string()];
-        let mut connections = vec![];
+        let neighbourhood = self.dataset.neighbourhood(entity, options.depth, options.max_nodes);
         let mut cons = String::new();
         let mut jsons = String::new();
-
-        while items.len() > 0 {
-            let ent = items.get(0).unwrap().clone();
-            if seen.contains(&ent) {
-                items.remove(0);
-                continue;
-            }
-            seen.insert(ent.clone());
-            let simple_connections_query = format!(
-                r#"PREFIX schema: <http://schema.org/>
-                    SELECT DISTINCT ?predicate ?object
-                    WHERE {{
-                {ent} ?predicate ?object .
-                    FILTER NOT EXISTS {{
-                        ?object ?otherPredicate ?otherSubject .
-                }}
-                }}"#
-            );
-            let simple_connections = self.dataset
-                .query(&simple_connections_query)
-                .unwrap_or_default();
-            let jsonrep = format_json(format!("{ent}"), simple_connections);
-            jsons += &jsonrep;
-            let complex_connections_query = format!(
-                r#"PREFIX schema: <http://schema.org/>
-                    SELECT DISTINCT ?predicate ?object
-                    WHERE {{
-                        {ent} ?predicate ?object .
-                        ?object ?p ?oo .
-                }}"#
-            );
-
-            let complex_connections = self.dataset
-                .query(&complex_connections_query)
-                .unwrap_or_default();
-            for row in complex_connections {
-                let cur = row.get("object").unwrap().to_string();
-                connections.push((
-                    ent.clone(),
-                    cur.clone(),
-                    row.get("predicate").unwrap().to_string(),
-                ));
-                items.push(cur);
-            }
-            items.remove(0);
-        }
-        for (s, t, l) in connections {
+        for (ent, simple_connections) in neighbourhood.nodes {
+            jsons += &format_json(ent, simple_connections);
+        }
+        for (s, t, l) in neighbourhood.links {
             cons += &format!("{{source: \"{}\", target: \"{}\", label: \"{}\"}},", s, t, l);
         }
+        let mut frontier = neighbourhood.frontier.into_iter().collect::<Vec<_>>();
+        frontier.sort();
+        // Embedded in a script: "<" is escaped so that no IRI can close the tag
+        let frontier = serde_json::to_string(&frontier).unwrap_or_default().replace('<', "\\u003c");
+
+        let graph_options = format!(
+            r#"<form method="get" action="/entity/{}" class="d-flex align-items-center gap-2">
+          <label for="depth">Depth</label>
+          <input type="number" id="depth" name="depth" min="0" max="{}" value="{}" class="form-control form-control-sm" style="width: 80px" />
+          <label for="max_nodes" class="text-nowrap">Max nodes</label>
+          <input type="number" id="max_nodes" name="max_nodes" min="1" max="{}" value="{}" class="form-control form-control-sm" style="width: 90px" />
+          <button type="submit" class="btn btn-sm btn-outline-primary">Apply</button>
+        </form>"#,
+            escape_html(&entity.replace('#', "%23")),
+            api::MAX_GRAPH_DEPTH,
+            options.depth,
+            api::MAX_GRAPH_NODES,
+            options.max_nodes
+        );
+        let graph_info = if neighbourhood.truncated {
+            format!(
+                r#"<div class="alert alert-warning py-2">The graph was cut at {} nodes: use <em>Expand</em> on a node to load its neighbours.</div>"#,
+                options.max_nodes
+            )
+        } else {
+            String::new()
+        };
+
         entity_page(
             &external_link(&entity),
             &name,
             &itm.description.unwrap_or("No description found".to_string()),
             &entity_types,
             &img,
-            &table_1,
-            &table_2,
-            &jsons,
-            &cons
+            (&outgoing.0, &outgoing.1),
+            (&incoming.0, &incoming.1),
+            &(EntityGraph {
+                nodes: &jsons,
+                links: &cons,
+                frontier: &frontier,
+                max_nodes: options.max_nodes,
+                options: &graph_options,
+                info: &graph_info,
+            })
         )
     }
 
-    fn generate_analytics(&self, page: i32, start_with: &str) -> String {
-        self.dataset.calculate_class_relations_graph();
-        if page == 0 {
-            return analysis_page(start_with);
+    /// Renders a page of the triples on one side of an entity, grouped by predicate, and its pagination.
+    fn triples_table(&self, entity: &str, direction: TripleDirection, options: &EntityOptions) -> (String, String) {
+        let groups = self.dataset.triple_groups(entity, direction);
+        let total = groups.iter().map(|(_, count)| count).sum::<u64>();
+        let pages = total.div_ceil(TRIPLES_PAGE_SIZE).max(1);
+        let page = match direction {
+            TripleDirection::Outgoing => options.outgoing_page,
+            TripleDirection::Incoming => options.incoming_page,
+        }.min(pages);
+        let offset = (page - 1) * TRIPLES_PAGE_SIZE;
+        let counts = groups.into_iter().collect::<HashMap<_, _>>();
+
+        let mut rows = String::new();
+        let mut current: Option<String> = None;
+        for (pred, other) in self.dataset.triples_page(entity, direction, TRIPLES_PAGE_SIZE, offset) {
+            if current.as_ref() != Some(&pred) {
+                let continued = if current.is_none() && offset > 0 { " (continued)" } else { "" };
+                rows += &format!(
+                    r#"<tr class="table-secondary"><td><strong>{}</strong>{continued} <span class="badge bg-secondary">{}</span></td></tr>"#,
+                    vocabulary_label(&pred),
+                    counts.get(&pred).copied().unwrap_or(0)
+                );
+                current = Some(pred);
+            }
+            rows += &format!("<tr>\n          <td>{}</td>\n        </tr>", to_link(escape_html(&other)));
         }
 
-        let mut seen: HashSet<String> = HashSet::new();
-        let mut items = vec![(format!("<http://schema.org/{}>", start_with), 0)];
-        let mut connections = vec![];
-        let mut cons = String::new();
-        let mut jsons = String::new();
-
-        let mut order = vec![];
-
-        while items.len() > 0 {
-            let (ent, depth) = items.get(0).unwrap().clone();
-            if seen.contains(&ent) {
-                items.remove(0);
-                continue;
+        if pages <= 1 {
+            return (rows, String::new());
+        }
+        let (anchor, link) = (
+            match direction {
+                TripleDirection::Outgoing => "outgoing",
+                TripleDirection::Incoming => "incoming",
+            },
+            |p: u64| match direction {
+                TripleDirection::Outgoing => EntityOptions { outgoing_page: p, ..*options },
+                TripleDirection::Incoming => EntityOptions { incoming_page: p, ..*options },
+            },
+        );
+        let button = |label: &str, target: u64, enabled: bool| {
+            if enabled {
+                format!(
+                    r#"<a class="btn btn-sm btn-outline-primary" href="{}">{label}</a>"#,
+                    link(target).link(entity, anchor)
+                )
+            } else {
+                format!(r#"<button class="btn btn-sm btn-outline-secondary" disabled>{label}</button>"#)
             }
-            seen.insert(ent.clone());
-
-            let count_query = format!(
-                r#"
-SELECT (COUNT(DISTINCT ?s) as ?cnt)
-WHERE {{
-    ?s a {ent}.
+        };
+        let navigation = format!(
+            r#"<div class="d-flex justify-content-between align-items-center gap-2">{}<span>Page {page} of {pages} ({total} triples)</span>{}</div>"#,
+            button("Previous", page - 1, page > 1),
+            button("Next", page + 1, page < pages)
+        );
+        (rows, navigation)
+    }
 
-}}    
-"#
-            );
-            let count = match self.dataset.query(&count_query) {
-                Ok(res) => {
-                    match res.first().unwrap().get("cnt").unwrap() {
-                        Literal(l) => l.value().parse::<u64>().unwrap(),
-                        _ => 0,
-                    }
+    fn generate_analytics(&self, page: i32, start_with: &str, options: &PageRankOptions) -> String {
+        match page {
+            1 => {
+                let graph = self.dataset.class_graph(start_with);
+                let mut jsons = String::new();
+                let mut cons = String::new();
+                for ClassNode { class, .. } in graph.nodes {
+                    jsons += &format!("{{id: \"{class}\", label: \"{class}\"}},");
                 }
-                Err(_) => panic!("Failed to fetch use count"),
-            };
-
-            order.push((ent.clone(), count, depth));
-            if page == 1 {
-                jsons += &format!("{{id: \"{ent}\", label: \"{ent}\"}},");
-            }
-            let complex_connections_query = format!(
-                r#"PREFIX schema: <http://schema.org/>
-                    SELECT DISTINCT ?predicate ?object
-                    FROM <urn:class_relations>
-                    WHERE {{
-                        {ent} ?predicate ?object .
-                        ?object ?p ?oo .
-                }}"#
-            );
-
-            let complex_connections = self.dataset
-                .query(&complex_connections_query)
-                .unwrap_or_default();
-            for row in complex_connections {
-                let cur = row.get("object").unwrap().to_string();
-                if page == 1 {
-                    connections.push((
-                        ent.clone(),
-                        cur.clone(),
-                        row.get("predicate").unwrap().to_string(),
-                    ));
+                for ClassEdge { from, to, predicate } in graph.edges {
+                    cons += &format!("{{from: \"{}\", to: \"{}\", label: \"{}\"}},", from, to, predicate);
                 }
-                items.push((cur, depth + 1));
+                class_relation_graph(&jsons, &cons)
             }
-            items.remove(0);
-        }
-        for (s, t, l) in connections {
-            cons += &format!("{{from: \"{}\", to: \"{}\", label: \"{}\"}},", s, t, l);
-        }
-
-        let mut classes = String::new();
-        let mut class_analysis = String::new();
-
-        order.reverse();
-
-        // let c = order.len();
-        if page == 2 {
-            let scores = self.dataset.stat_anal_types(start_with);
-            for (otype, (cnt, depth, pr, rpr, round, keep, score)) in scores {
-                class_analysis += &format!(
-                    "<tr class='{}-row'><td>{}</td><td>{cnt}</td><td>{depth}</td><td>{pr}</td><td>{rpr}</td><td>{round}</td><td>{score}</td></tr>",
-                    if keep {
-                        "green"
-                    } else {
-                        "red"
-                    },
-                    escape_html(&otype)
-                );
-            }
-        }
-        let mut preds_to_delete = vec![];
-
-        if page == 3 {
-            let (mut graph, node_map) = self.dataset.calculate_class_relations_graph();
-            calculate_probabilities_for_graph(&mut graph);
-            let mut node_counts: HashMap<String, f64> = HashMap::new();
-
-            for node in node_map.keys() {
-                if node == "Literal" {
-                    continue;
+            2 => {
+                let analysis = self.dataset.class_analysis(start_with, options);
+                let mut class_analysis = String::new();
+                for score in analysis.classes {
+                    let class = escape_html(&score.class);
+                    let value = class.replace('\'', "&#39;");
+                    class_analysis += &format!(
+                        "<tr class='{}-row'><td>{class}<input type='hidden' name='class' value='{value}'></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><input class='form-check-input' type='checkbox' name='keep' value='{value}'{}></td></tr>",
+                        if score.keep {
+                            "green"
+                        } else {
+                            "red"
+                        },
+                        score.count,
+                        score.depth,
+                        score.page_rank,
+                        score.reverse_page_rank,
+                        score.elimination_round,
+                        score.score,
+                        if score.keep {
+                            " checked"
+                        } else {
+                            ""
+                        }
+                    );
                 }
-                let q = format!(
-                    r#"
-            SELECT (COUNT(?s) as ?cnt) WHERE {{
-                ?s a {node}.
-            }}
-            "#
-                );
-                let cnt = *self.dataset.get_counts(&q, "cnt").get(0).unwrap();
-                node_counts.insert(node.clone(), cnt);
+                class_analysis_page(&class_analysis, &analysis.page_rank, start_with)
             }
-
-            let (_, edge_rank) = self.dataset.page_rank(&graph, &node_map, &node_counts, Outgoing);
-            for (o, count, _) in order {
-                let mut table =
-                    r#"<table class="table table-bordered table-hover" style="width:100%">
+            3 => {
+                let mut classes = String::new();
+                let scores = self.dataset.predicate_scores(start_with);
+                let report = predicate_labels::label_report(&scores, &self.dataset.predicate_labels());
+                for ClassPredicates { class, count, good_entities, predicates } in scores {
+                    let mut table =
+                        r#"<table class="table table-bordered table-hover" style="width:100%">
               <thead class="table-light">
                 <tr>
                     <th>Predicate</th>
@@ -693,122 +1350,223 @@ WHERE {{
                     <th>NN Keep</th>
                     <th>Score Based Keep</th>
                     <th>Hybrid Decision</td>
+                    <th>Curator</th>
+                    <th>Delete</th>
                 </tr>
               </thead>
               <tbody>"#.to_string();
 
-                let data = self.dataset
-                    .stat_anal_predicates(&o, edge_rank.get(&o).unwrap())
-                    .unwrap_or(vec![]);
-                let mut thres = 60.0;
-
-                let mut mean_passed_score = 0.0;
-                let mut passed_count = 0;
-                for (_, stats) in &data {
-                    if thres > 0.0 {
-                        mean_passed_score += stats["score"];
-                        passed_count += 1;
-                    }
-                    thres -= stats["score"];
-                }
-                mean_passed_score = mean_passed_score / (passed_count as f64);
-                thres = 60.0;
-                for (p, stats) in data {
-                    // let color = if thres > 0.0 { "green" } else { "red" };
-                    // table += &format!("<tr class=\"{color}-row\"><td>{}</td>", escape_html(&p));
-                    table += &format!("<tr><td>{}</td>", escape_html(&p));
-
-                    for key in [
-                        "frequency",
-                        "uniqueness",
-                        "entropy",
-                        "quality",
-                        "edge_rank",
-                        "score",
-                        "keep",
-                        // "score_ratio",
-                    ] {
-                        table += &format!("<td>{}</td>", stats.get(key).unwrap_or(&0.0));
-                    }
-                    let mut keep_nn = false;
-                    let mut keep_score = false;
-                    if *stats.get("keep").unwrap() > 0.5 {
-                        table += "<td>✅</td>";
-                        keep_nn = true;
-                    } else {
-                        table += "<td>❌</td>";
-                    }
-                    if thres > 0.0 {
-                        table += "<td>✅</td>";
-                        keep_score = true;
-                    } else {
-                        table += "<td>❌</td>";
-                    }
-                    if !keep_nn && keep_score {
-                        let s =
-                            stats["keep"] + (stats["keep"] * stats["score"]) / mean_passed_score;
-                        table += &format!("<td>{}</td>", if s >= 0.5 { "✅" } else { "❌" });
-                        if s < 0.5 {
-                            preds_to_delete.push((o.clone(), p.clone()));
+                    for PredicateScore { predicate, stats, nn_keep, score_keep, keep, label, decision } in predicates {
+                        let overridden = label.is_some_and(|l| l != keep);
+                        table += &format!(
+                            "<tr{}><td>{}</td>",
+                            if overridden {
+                                " class='table-warning'"
+                            } else {
+                                ""
+                            },
+                            escape_html(&predicate)
+                        );
+                        for key in [
+                            "frequency",
+                            "uniqueness",
+                            "entropy",
+                            "quality",
+                            "edge_rank",
+                            "score",
+                            "keep",
+                        ] {
+                            table += &format!("<td>{}</td>", stats.get(key).unwrap_or(&0.0));
                         }
-                    }
-                    if !keep_nn && !keep_score {
-                        table += "<td>❌</td>";
-                        preds_to_delete.push((o.clone(), p));
-                    }
-                    if keep_nn {
-                        table += "<td>✅</td>";
+                        for decision in [nn_keep, score_keep, keep] {
+                            table += if decision { "<td>✅</td>" } else { "<td>❌</td>" };
+                        }
+                        let pair = escape_html(&format!("{class} {predicate}")).replace('\'', "&#39;");
+                        table += &format!(
+                            "<td>{}</td><td><input class='form-check-input' type='checkbox' name='pair' value='{pair}'{}></td></tr>",
+                            label_buttons(&class, &predicate, label),
+                            if decision {
+                                ""
+                            } else {
+                                " checked"
+                            }
+                        );
                     }
 
-                    // table += &format!("<td>{}</td>", stats[""]);
-                    thres -= stats["score"];
-                    table += "</tr>";
-                }
-
-                let keep = self.dataset.analyse_objects(&o);
-                classes += &format!(
-                    r#"<div class="card shadow-sm my-5 px-0"><div class="card-header bg-primary text-white ">
+                    classes += &format!(
+                        r#"<div class="card shadow-sm my-5 px-0"><div class="card-header bg-primary text-white ">
                   <h3 class="mb-0 text-center">{}</h3>
                 </div>
                 <div class="card-body">
                 <p>Found {count} entities</p>
-                <b>{keep} good ones</b>
+                <b>{good_entities} good ones</b>
                 {table}</tbody></table></div>
                 </div>
                 "#,
-                    escape_html(&o)
+                        escape_html(&class)
+                    );
+                }
+                predicate_analysis_page(&classes, &report)
+            }
+            _ => {
+                self.dataset.calculate_class_relations_graph();
+                analysis_page(start_with)
+            }
+        }
+    }
+
+    fn generate_search(&self, params: &Params) -> String {
+        let q = params.get("q").unwrap_or_default();
+        let class = params.get("class").unwrap_or_default();
+        let lang = params.get("lang").unwrap_or_default();
+        let page = params
+            .get("page")
+            .and_then(|p| p.parse::<usize>().ok())
+            .filter(|p| *p > 0)
+            .unwrap_or(1);
+        let class_counts = self.dataset.class_counts();
+        let render = |summary: &str, results: &str, navigation: &str| {
+            search_page(&q, &class, &lang, &class_counts, summary, results, navigation)
+        };
+
+        if q.trim().is_empty() {
+            return render("", "", "");
+        }
+        let Some(index) = self.search.index() else {
+            return render(
+                r#"<div class="alert alert-info">The search index is being built, see the <a href="/jobs">jobs</a> page and try again in a moment.</div>"#,
+                "",
+                ""
+            );
+        };
+
+        let results = index.search(
+            &(SearchQuery {
+                text: q.clone(),
+                class: Some(class.clone()).filter(|c| !c.is_empty()),
+                lang: Some(lang.clone()).filter(|l| !l.is_empty()),
+                limit: SEARCH_PAGE_SIZE,
+                offset: (page - 1) * SEARCH_PAGE_SIZE,
+            })
+        );
+
+        let mut cards = String::new();
+        for hit in &results.hits {
+            let item = self.dataset.get_details(&hit.id);
+            let name = item.name.clone().or(hit.name.clone()).unwrap_or(hit.id.clone());
+            let description = search
+                ::snippet(&self.dataset, &hit.id, &q, 200)
+                .or(item.description.as_ref().map(|d| search::highlight(d, &q, Some(200))))
+                .unwrap_or("No description available".to_string());
+            let id = hit.id.trim_start_matches('<').trim_end_matches('>').replace("#", "%23");
+            cards += &object_card(
+                &search::highlight(&name, &q, None),
+                &description,
+                item.images.first().map(String::as_str).unwrap_or(""),
+                &id
+            );
+        }
+
+        let pages = results.total.div_ceil(SEARCH_PAGE_SIZE);
+        let link = |page: usize| {
+            let query = url::form_urlencoded::Serializer
+                ::new(String::new())
+                .append_pair("q", &q)
+                .append_pair("class", &class)
+                .append_pair("lang", &lang)
+                .append_pair("page", &page.to_string())
+                .finish();
+            format!("/search?{}", escape_html(&query))
+        };
+        let mut navigation = String::new();
+        if pages > 1 {
+            navigation += r#"<div class="d-flex justify-content-between gap-2 mt-4">"#;
+            if page > 1 {
+                navigation += &format!(
+                    r#"<a class="btn btn-outline-primary" href="{}">Previous</a>"#,
+                    link(page - 1)
                 );
+            } else {
+                navigation += r#"<button class="btn btn-outline-secondary" disabled>Previous</button>"#;
             }
+            navigation += &format!("<p>Page {page} of {pages}</p>");
+            if page < pages {
+                navigation += &format!(
+                    r#"<a class="btn btn-outline-primary" href="{}">Next</a>"#,
+                    link(page + 1)
+                );
+            } else {
+                navigation += r#"<button class="btn btn-outline-secondary" disabled>Next</button>"#;
+            }
+            navigation += "</div>";
         }
 
-        println!("Finita la comedia");
-        match page {
-            1 => class_relation_graph(&jsons, &cons),
-            2 => class_analysis_page(&class_analysis),
-            3 => predicate_analysis_page(&classes, preds_to_delete),
-            _ => analysis_page(start_with),
+        let summary = format!(
+            r#"<p class="text-muted">{} result{} for <b>{}</b></p>"#,
+            results.total,
+            if results.total == 1 { "" } else { "s" },
+            escape_html(&q)
+        );
+        render(&summary, &cards, &navigation)
+    }
+
+    /// Typeahead suggestions of the search box: the best matching entities and their names.
+    fn suggest(&self, q: &str) -> Vec<search::SearchHit> {
+        match self.search.index() {
+            Some(index) =>
+                index.search(
+                    &(SearchQuery {
+                        text: q.to_string(),
+                        class: None,
+                        lang: None,
+                        limit: 8,
+                        offset: 0,
+                    })
+                ).hits,
+            None => vec![],
         }
     }
 
+    /// Validates the store against the selected shape files and/or named graph, and renders the results.
+    fn generate_shacl(&self, files: &[String], graph: &str) -> String {
+        let graph_iri = Some(graph).filter(|g| !g.is_empty());
+        let validation = Shapes::load_selected(&self.dataset, files, graph_iri)
+            .and_then(|shapes| self.dataset.validate_shacl(&shapes))
+            .and_then(|report| {
+                let rdf = report.to_rdf(RdfFormat::Turtle)?;
+                Ok::<(ValidationReport, String), String>((report, rdf))
+            });
+        shacl_page(
+            &shacl::list_shape_files(),
+            files,
+            graph,
+            Some(
+                validation
+                    .as_ref()
+                    .map(|(report, rdf)| (report, rdf.as_str()))
+                    .map_err(|e| e.as_str())
+            )
+        )
+    }
+
     fn generate_scripts(&self) -> String {
         routines_page()
     }
 
     fn generate_run_results(&self, routines: Vec<String>) -> String {
-        let initial_count = self.dataset.count_lines();
-
-        let result = self.dataset.execute(routines.join("\n"));
-
-        let final_count = self.dataset.count_lines();
-        let diff = (final_count as i64) - (initial_count as i64);
-        let action = if diff >= 0 { "Inserted" } else { "Deleted" };
-        let count = diff.abs();
-        match result {
-            Ok(_) => {
-                let script_list = routines
-                    .iter()
-                    .map(|name| format!("<li>{}</li>", name))
-                    .collect::<String>();
+        let report = self.dataset.run_routines(routines);
+
+        let action = if report.triple_diff >= 0 { "Inserted" } else { "Deleted" };
+        let count = report.triple_diff.abs();
+        let list = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| format!("<li>{}</li>", escape_html(name)))
+                .collect::<String>()
+        };
+        match report.error {
+            None => {
+                let script_list = list(&report.executed);
 
                 return format!(
                     r#"
@@ -834,37 +1592,11 @@ WHERE {{
     "#
                 );
             }
-            Err((e, cnt)) => {
-                let cnt_usize = cnt as usize;
-                let ran_scripts = if cnt_usize > 0 {
-                    routines[0..cnt_usize - 1]
-                        .iter()
-                        .map(|name| format!("<li>{}</li>", name))
-                        .collect::<String>()
-                } else {
-                    String::new()
-                };
-                let skipped_scripts = if cnt_usize > 0 && cnt_usize <= routines.len() {
-                    routines[cnt_usize - 1..]
-                        .iter()
-                        .map(|name| format!("<li>{}</li>", name))
-                        .collect::<String>()
-                } else {
-                    routines
-                        .iter()
-                        .map(|name| format!("<li>{}</li>", name))
-                        .collect::<String>()
-                };
-
-                let failed_name = if cnt_usize > 0 && cnt_usize <= routines.len() {
-                    routines.get(cnt_usize - 1).unwrap()
-                } else {
-                    "Unknown"
-                };
-                let err_message = match e {
-                    StoreError::EvaluationError(e) => e,
-                    StoreError::UnsupportedError => "Query Not Supported".to_string(),
-                };
+            Some(err_message) => {
+                let ran_scripts = list(&report.executed);
+                let skipped_scripts = list(&report.skipped);
+                let failed_name = escape_html(&report.failed.unwrap_or("Unknown".to_string()));
+                let err_message = escape_html(&err_message);
                 return format!(
                     r#"
     <!DOCTYPE html>
@@ -897,82 +1629,122 @@ WHERE {{
         }
     }
 
-    fn parse_procedures(query_string: &str) -> Vec<String> {
-        let query_string = url_decode(query_string);
-
-        let mut selections: Vec<String> = vec![];
-        for pair in query_string.split('&') {
-            if let Some((key, _value)) = pair.split_once('=') {
-                if let Some((file, proc)) = key.split_once("::") {
-                    let file = url_decode(file);
-                    let proc = url_decode(proc);
-                    selections.push(format!("{}::{}", file, proc));
-                    println!("{}->{}", file, proc);
-                }
-            }
+    /// Whether the request modifies the store.
+    ///
+    /// Covers updates from the query page, routines and merges, dumps and reverts, history replays,
+    /// predicate deletions, plans and labels, the inferred graph and class plans.
+    fn is_mutation(req: &HttpRequest) -> bool {
+        let params = &req.params;
+        match req.path.as_str() {
+            "/dump" | "/delete_predicate" | "/replay_history" | "/vocabulary/canonicalize" => true,
+            "/vocabulary/inference" => true,
+            "/analysis/conformance/fix" | "/analysis/classes/apply" | "/analysis/labels" => true,
+            "/predicate_plans/apply" | "/predicate_plans/undo" => true,
+            route if route.starts_with("/restore/") => true,
+            "/routines" => params.get("entity").is_some() || !Self::parse_procedures(params).is_empty(),
+            "/query" =>
+                params.get("query").is_some_and(|q| !q.is_empty()) &&
+                    params.get("mode").is_some_and(|m| m != "query"),
+            _ => false,
         }
-        return selections;
+    }
+
+    /// Collects the selected `file::procedure` routines, in the order they were sent.
+    fn parse_procedures(params: &Params) -> Vec<String> {
+        params
+            .iter()
+            .filter(|(key, _)| key.contains("::"))
+            .map(|(key, _)| key.clone())
+            .collect()
     }
 
     fn generate_history(&self) -> String {
-        let input = self.dataset.get_history();
-        let lines = input.lines().map(str::trim);
         let mut inside = String::new();
-        let mut sparql_block = String::new();
-        let mut in_sparql = false;
-
-        for line in lines {
-            if line.starts_with("```sparql") {
-                in_sparql = true;
-                sparql_block.clear();
-            } else if line.starts_with("```") && in_sparql {
-                in_sparql = false;
-                inside.push_str(
-                    r#"<div class="card mb-3 shadow-sm">
-          <div class="card-header bg-light text-dark">SPARQL Script</div>
+
+        for entry in self.dataset.history_entries() {
+            match entry {
+                HistoryEntry::Sparql { query, user } => {
+                    inside.push_str(
+                        &format!(
+                            r#"<div class="card mb-3 shadow-sm">
+          <div class="card-header bg-light text-dark">SPARQL Script{}</div>
           <div class="card-body">
-            <pre class="bg-dark border p-3"><code>"#
-                );
-                inside.push_str(&escape_html(&sparql_block));
-                inside.push_str("</code></pre>\n  </div>\n</div>\n");
-            } else if in_sparql {
-                sparql_block.push_str(line);
-                sparql_block.push('\n');
-            } else if let Some((file, desc)) = line.split_once("::") {
-                inside.push_str(
-                    &format!(
-                        r#"<div class="card mb-3 shadow-sm">
-          <div class="card-header bg-secondary text-white">{}</div>
+            <pre class="bg-dark border p-3"><code>"#,
+                            Self::user_badge(&user)
+                        )
+                    );
+                    inside.push_str(&escape_html(&query));
+                    inside.push_str("</code></pre>\n  </div>\n</div>\n");
+                }
+                HistoryEntry::Routine { file, procedure, user } => {
+                    inside.push_str(
+                        &format!(
+                            r#"<div class="card mb-3 shadow-sm">
+          <div class="card-header bg-secondary text-white">{}{}</div>
           <div class="card-body">
             <span class="badge bg-info text-dark">Change</span> {}
           </div>
         </div>
         "#,
-                        escape_html(&file.to_string()),
-                        escape_html(&desc.to_string())
-                    )
-                );
-            } else if line.starts_with("Dumping") {
-                inside.push_str(
-                    &format!(
-                        r#"<div class="card mb-3 shadow-sm">
-          <div class="card-header bg-success text-white">Dump created!</div>
+                            escape_html(&file),
+                            Self::user_badge(&user),
+                            escape_html(&procedure)
+                        )
+                    );
+                }
+                HistoryEntry::Dump { file, version, user } => {
+                    inside.push_str(
+                        &format!(
+                            r#"<div class="card mb-3 shadow-sm">
+          <div class="card-header bg-success text-white">Dump created!{}</div>
           <div class="card-body">
             <span class="badge bg-info text-dark">Dump file</span> {}
           </div>
-          <div class='d-flex justify-content-center py-2'>
-          <a href="/restore/{}" class='btn btn-danger'>Revert to this version(all of the following changes and dumps will be lost)</a>
-          </div>
+          <form method="post" action="/restore/{}" class='d-flex justify-content-center py-2'>
+          <button type="submit" class='btn btn-danger'>Revert to this version(all of the following changes and dumps will be lost)</button>
+          </form>
         </div>"#,
-                        line.replace("Dumping store to", ""),
-                        line.replace(
-                            &format!("Dumping store to ./data/{}/", self.dataset.get_name()),
-                            ""
+                            Self::user_badge(&user),
+                            file,
+                            version
                         )
-                    )
-                );
+                    );
+                }
+                HistoryEntry::PlanUndo { plan, file, user } => {
+                    inside.push_str(
+                        &format!(
+                            r#"<div class="card mb-3 shadow-sm">
+          <div class="card-header bg-warning text-dark">Predicate plan {} undone{}</div>
+          <div class="card-body">
+            <span class="badge bg-info text-dark">Restored triples</span> {}
+          </div>
+        </div>
+        "#,
+                            plan,
+                            Self::user_badge(&user),
+                            escape_html(&file)
+                        )
+                    );
+                }
             }
         }
         history_page(inside)
     }
+
+    /// Badge naming the user behind a history entry, empty for anonymous entries.
+    fn user_badge(user: &Option<String>) -> String {
+        match user {
+            Some(user) =>
+                format!(r#" <span class="badge bg-dark float-end">{}</span>"#, escape_html(user)),
+            None => String::new(),
+        }
+    }
+}
+
+/// Last segment of an IRI (`<http://schema.org/Book>` → `Book`); other terms are returned as is.
+fn short_name(term: &str) -> String {
+    match term.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+        Some(iri) => iri.rsplit(['/', '#']).next().unwrap_or(iri).to_string(),
+        None => term.to_string(),
+    }
 }
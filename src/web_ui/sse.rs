@@ -0,0 +1,69 @@
+//! Server-Sent Events streams of the job progress.
+//!
+//! `tiny_http` buffers chunked bodies, so the events are written directly to the socket of the request, on a
+//! thread of their own to keep the workers free. At most `MAX_STREAMS` streams are open at once; the job page polls
+//! `/api/v1/jobs/{id}` when it is refused one.
+
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::Duration;
+
+use crate::jobs::{ JobManager, JobStatus };
+
+/// Delay after which a comment is sent to keep idle connections open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Number of event streams open at once.
+const MAX_STREAMS: usize = 32;
+
+static OPEN_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// One of the `MAX_STREAMS` event streams, released when dropped.
+pub(crate) struct StreamSlot;
+
+impl StreamSlot {
+    /// Takes a slot, or returns `None` if every slot is taken.
+    pub(crate) fn acquire() -> Option<StreamSlot> {
+        OPEN_STREAMS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < MAX_STREAMS).then_some(open + 1))
+            .ok()
+            .map(|_| StreamSlot)
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        OPEN_STREAMS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Streams the state of job `id` as `update` events until it finishes or the client disconnects.
+///
+/// Every event carries the full `JobInfo` as JSON.
+pub(crate) fn stream_job(mut writer: Box<dyn Write + Send>, jobs: Arc<JobManager>, id: u64) {
+    let head =
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n";
+    if writer.write_all(head.as_bytes()).and_then(|_| writer.flush()).is_err() {
+        return;
+    }
+
+    // No job has this version, so the first state is sent right away
+    let mut version = u64::MAX;
+    while let Some((info, new_version)) = jobs.wait_for_change(id, version, KEEP_ALIVE) {
+        let event = if new_version == version {
+            ": keep-alive\n\n".to_string()
+        } else {
+            match serde_json::to_string(&info) {
+                Ok(data) => format!("event: update\ndata: {data}\n\n"),
+                Err(_) => break,
+            }
+        };
+        version = new_version;
+        if writer.write_all(event.as_bytes()).and_then(|_| writer.flush()).is_err() {
+            break;
+        }
+        if !matches!(info.status, JobStatus::Queued | JobStatus::Running) {
+            break;
+        }
+    }
+}
@@ -1,9 +1,45 @@
+/**
+ * Converts a node of `/api/v1/entity/neighbourhood` to the format of the rendered nodes.
+ * @param {object} apiNode - Node with its `id` and `properties`.
+ */
+function toGraphNode(apiNode) {
+  const escape = (text) =>
+    String(text)
+      .replaceAll("&", "&amp;")
+      .replaceAll("<", "&lt;")
+      .replaceAll(">", "&gt;");
+  const node = {
+    id: apiNode.id,
+    name: apiNode.id,
+    url: `/entity/${apiNode.id}`,
+    attributes: [],
+  };
+  for (const { predicate, value } of apiNode.properties) {
+    if (predicate === "<http://schema.org/name>") {
+      node.name = value;
+    }
+    if (value === "<http://schema.org/ImageObject>") {
+      node.image = apiNode.id.replace("<", "").replace(">", "");
+    }
+    const key = predicate
+      .replace("<http://www.w3.org/1999/02/22-rdf-syntax-ns#type>", "type")
+      .replace("<http://schema.org/", ":")
+      .replace(">", "");
+    node.attributes.push([escape(key), escape(value)]);
+  }
+  return node;
+}
+
 /**
  * Renders a D3.js force-directed graph with HTML content in nodes.
+ *
+ * Nodes listed in `graphData.frontier` get an "Expand" button loading their neighbours from the API.
  * @param {string} containerId - The ID of the div where the graph will be rendered.
- * @param {object} graphData - An object containing nodes and links.
+ * @param {object} graphData - An object containing nodes, links and the frontier.
+ * @param {string} currentEntity - URL of the entity of the page, highlighted.
+ * @param {number} maxNodes - Node budget of every expansion.
  */
-function renderD3Graph(containerId, graphData, currentEntity) {
+function renderD3Graph(containerId, graphData, currentEntity, maxNodes) {
   const container = document.getElementById(containerId);
   if (!container) {
     console.error(`Container with id "${containerId}" not found.`);
@@ -35,13 +71,17 @@ function renderD3Graph(containerId, graphData, currentEntity) {
 
   svg.call(zoom);
 
+  const nodes = graphData.nodes;
+  const links = graphData.links;
+  const frontier = new Set(graphData.frontier || []);
+
   // --- Force Simulation ---
   const simulation = d3
-    .forceSimulation(graphData.nodes)
+    .forceSimulation(nodes)
     .force(
       "link",
       d3
-        .forceLink(graphData.links)
+        .forceLink(links)
         .id((d) => d.id)
         .distance(1000)
         .strength(0.2)
@@ -51,99 +91,168 @@ function renderD3Graph(containerId, graphData, currentEntity) {
     .force("center", d3.forceCenter(width / 2, height / 2))
     .alphaDecay(0.05); // faster stabilization
 
-  const link = g
+  const linkLayer = g
     .append("g")
     .attr("stroke", "#999")
-    .attr("stroke-opacity", 0.8)
-    .selectAll("line")
-    .data(graphData.links)
-    .join("line")
-    .attr("stroke-width", 2.5);
-
-  // --- Draw Edges ---
-  const linkLabelGroup = g
-    .append("g")
-    .selectAll(".link-label-group")
-    .data(graphData.links)
-    .join("g")
-    .attr("class", "link-label-group");
-
-  const linkLabelText = linkLabelGroup
-    .append("text")
-    .attr("class", "edge-label-text")
-    .text((d) => d.label);
-
-  linkLabelGroup
-    .insert("rect", "text")
-    .attr("class", "edge-label-box")
-    .each(function (d) {
-      const textNode = d3.select(this.parentNode).select("text").node();
-      if (textNode) {
-        const bbox = textNode.getBBox();
-        const padding = 10;
-
-        d3.select(this)
-          .attr("x", bbox.x - padding / 2)
-          .attr("y", bbox.y - padding / 2)
-          .attr("width", bbox.width + padding)
-          .attr("height", bbox.height + padding)
-          .attr("rx", 4) // rounded corners
-          .attr("ry", 4);
-      }
-    });
+    .attr("stroke-opacity", 0.8);
+  const labelLayer = g.append("g");
+  const nodeLayer = g.append("g");
+  let link = linkLayer.selectAll("line");
+  let linkLabelGroup = labelLayer.selectAll(".link-label-group");
+  let node = nodeLayer.selectAll("g");
 
-  // --- Draw Nodes ---
-  const node = g
-    .append("g")
-    .selectAll("g")
-    .data(graphData.nodes)
-    .join("g")
-    .call(drag(simulation));
-
-  // Use foreignObject to embed HTML content in each node
-  const foreignObject = node
-    .append("foreignObject")
-    .attr("width", nodeWidth)
-    .attr("height", nodeHeight)
-    .style("overflow", "visible");
-
-  const htmlContent = foreignObject
-    .append("xhtml:div")
-    .attr("class", (d) =>
-      d.url === currentEntity ? "active node-html-content" : "node-html-content"
-    );
+  // --- Draw (or redraw after an expansion) ---
+  function update() {
+    const linkKey = (d) =>
+      `${d.source.id || d.source}|${d.target.id || d.target}|${d.label}`;
+
+    link = link
+      .data(links, linkKey)
+      .join("line")
+      .attr("stroke-width", 2.5);
+
+    // --- Draw Edges ---
+    linkLabelGroup = linkLabelGroup
+      .data(links, linkKey)
+      .join((enter) => {
+        const group = enter.append("g").attr("class", "link-label-group");
+        group
+          .append("text")
+          .attr("class", "edge-label-text")
+          .text((d) => d.label);
+        group
+          .insert("rect", "text")
+          .attr("class", "edge-label-box")
+          .each(function (d) {
+            const textNode = d3.select(this.parentNode).select("text").node();
+            if (textNode) {
+              const bbox = textNode.getBBox();
+              const padding = 10;
+
+              d3.select(this)
+                .attr("x", bbox.x - padding / 2)
+                .attr("y", bbox.y - padding / 2)
+                .attr("width", bbox.width + padding)
+                .attr("height", bbox.height + padding)
+                .attr("rx", 4) // rounded corners
+                .attr("ry", 4);
+            }
+          });
+        return group;
+      });
 
-  htmlContent
-    .append("xhtml:a")
-    .attr("href", (d) => d.url)
-    .attr("target", "_blank")
-    .text((d) => d.name);
-
-  htmlContent.append("xhtml:div").attr("class", "separator");
-
-  htmlContent
-    .append("xhtml:img")
-    .attr("src", (d) => d.image)
-    .style("display", (d) => {
-      if (d.hasOwnProperty("image")) {
-        return "block";
-      } else {
-        return "none";
+    // --- Draw Nodes ---
+    node = node
+      .data(nodes, (d) => d.id)
+      .join((enter) => {
+        const group = enter.append("g").call(drag(simulation));
+
+        // Use foreignObject to embed HTML content in each node
+        const htmlContent = group
+          .append("foreignObject")
+          .attr("width", nodeWidth)
+          .attr("height", nodeHeight)
+          .style("overflow", "visible")
+          .append("xhtml:div")
+          .attr("class", (d) =>
+            d.url === currentEntity
+              ? "active node-html-content"
+              : "node-html-content"
+          );
+
+        htmlContent
+          .append("xhtml:a")
+          .attr("href", (d) => d.url)
+          .attr("target", "_blank")
+          .text((d) => d.name);
+
+        htmlContent
+          .append("xhtml:button")
+          .attr("class", "btn btn-sm btn-outline-warning float-end expand")
+          .text("Expand")
+          .on("click", (event, d) => expand(event.currentTarget, d));
+
+        htmlContent.append("xhtml:div").attr("class", "separator");
+
+        htmlContent
+          .append("xhtml:img")
+          .attr("src", (d) => d.image)
+          .style("display", (d) => {
+            if (d.hasOwnProperty("image")) {
+              return "block";
+            } else {
+              return "none";
+            }
+          })
+          .style("width", "80%")
+          .style("margin-left", "10%");
+
+        htmlContent
+          .append("xhtml:div")
+          .attr("class", "attributes")
+          .html((d) => {
+            let attributeString = "";
+            for (const [_, [key, value]] of Object.entries(d.attributes)) {
+              attributeString += `<p><strong>${key}</strong>: ${value}</p>\n`;
+            }
+            return attributeString;
+          });
+        return group;
+      });
+
+    node
+      .select(".expand")
+      .style("display", (d) => (frontier.has(d.id) ? "inline-block" : "none"));
+
+    simulation.nodes(nodes);
+    simulation.force("link").links(links);
+    simulation.alpha(0.5).restart();
+  }
+
+  // --- Expand on click: loads the neighbours of a frontier node ---
+  async function expand(button, d) {
+    button.disabled = true;
+    button.textContent = "Loading...";
+    const params = new URLSearchParams({
+      iri: d.id,
+      depth: 1,
+      max_nodes: maxNodes,
+    });
+    try {
+      const response = await fetch(`/api/v1/entity/neighbourhood?${params}`);
+      if (!response.ok) {
+        throw new Error((await response.json()).error);
       }
-    })
-    .style("width", "80%")
-    .style("margin-left", "10%");
-
-  htmlContent
-    .append("xhtml:div")
-    .attr("class", "attributes")
-    .html((d) => {
-      let attributeString = "";
-      for (const [_, [key, value]] of Object.entries(d.attributes)) {
-        attributeString += `<p><strong>${key}</strong>: ${value}</p>\n`;
+      const data = await response.json();
+      const known = new Set(nodes.map((n) => n.id));
+      for (const apiNode of data.nodes) {
+        if (!known.has(apiNode.id)) {
+          const newNode = toGraphNode(apiNode);
+          // New nodes start next to the expanded one
+          newNode.x = d.x + (Math.random() - 0.5) * 200;
+          newNode.y = d.y + (Math.random() - 0.5) * 200;
+          nodes.push(newNode);
+          if (data.frontier.includes(apiNode.id)) {
+            frontier.add(apiNode.id);
+          }
+        }
       }
-      return attributeString;
-    });
+      const knownLinks = new Set(
+        links.map((l) => `${l.source.id}|${l.target.id}|${l.label}`)
+      );
+      for (const l of data.links) {
+        if (!knownLinks.has(`${l.source}|${l.target}|${l.predicate}`)) {
+          links.push({ source: l.source, target: l.target, label: l.predicate });
+        }
+      }
+      frontier.delete(d.id);
+      update();
+    } catch (e) {
+      button.disabled = false;
+      button.textContent = "Expand";
+      console.error(`Could not expand ${d.id}: ${e.message}`);
+    }
+  }
 
   // --- Tick Function ---
   simulation.on("tick", () => {
@@ -165,6 +274,8 @@ function renderD3Graph(containerId, graphData, currentEntity) {
     );
   });
 
+  update();
+
   // --- Drag Handlers ---
   function drag(simulation) {
     function dragstarted(event, d) {
@@ -120,13 +120,50 @@ document.getElementById("queryForm").addEventListener("submit", (e) => {
         encodeURIComponent(secondary.value).replaceAll("#", "%23")
       : "";
   // console.log;
+  if (modeInput.value !== "query") {
+    submitUpdate();
+    return;
+  }
   const encodedMode = encodeURIComponent(modeInput.value);
-  const baseUrl = window.location.origin + window.location.pathname;
+  // Only shown when inference is enabled
+  const inferredInput = document.getElementById("inferredInput");
+  const inferred = inferredInput
+    ? "&inferred=" + (inferredInput.checked ? "1" : "0")
+    : "";
+  // The page may be the result of an advanced update job, served from /jobs/...
+  const baseUrl = window.location.origin + "/query";
   window.location.href =
-    `${baseUrl}?query=${encodedQuery}&mode=${encodedMode}` + secondaryQuery;
+    `${baseUrl}?query=${encodedQuery}&mode=${encodedMode}` +
+    secondaryQuery +
+    inferred;
   // console.log(window.location.href);
 });
 
+// Updates modify the store, so they are sent as POST with the CSRF token
+const PENDING_UPDATE_KEY = "sparql_pending_update";
+
+function submitUpdate() {
+  const fields = { query: textarea.value, mode: modeInput.value };
+  if (modeInput.value === "advanced") {
+    fields.secondary = secondary.value;
+  }
+  sessionStorage.setItem(PENDING_UPDATE_KEY, JSON.stringify(fields));
+
+  const form = document.createElement("form");
+  form.method = "post";
+  form.action = "/query";
+  for (const [name, value] of Object.entries(fields)) {
+    const input = document.createElement("input");
+    input.type = "hidden";
+    input.name = name;
+    input.value = value;
+    form.appendChild(input);
+  }
+  addCsrfToken(form);
+  document.body.appendChild(form);
+  form.submit();
+}
+
 function handleModeChange() {
   if (modeInput.value == "advanced") {
     secondary.style = "";
@@ -356,7 +393,13 @@ document
   });
 
 window.addEventListener("DOMContentLoaded", () => {
-  const params = new URLSearchParams(window.location.search);
+  let params = new URLSearchParams(window.location.search);
+  // After a POST update, restore the submitted fields
+  const pending = sessionStorage.getItem(PENDING_UPDATE_KEY);
+  sessionStorage.removeItem(PENDING_UPDATE_KEY);
+  if (pending && !params.has("query")) {
+    params = new URLSearchParams(JSON.parse(pending));
+  }
   const query = params.get("query");
   const sec = params.get("secondary");
   const mode = params.get("mode") || "query";